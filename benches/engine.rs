@@ -0,0 +1,185 @@
+//! Throughput benchmarks.
+//!
+//! ```sh
+//! cargo bench --bench engine
+//! ```
+//!
+//! - `ingest` runs CSV through the whole pipeline, as the command line does.
+//! - `handle` feeds pre-parsed records straight to a `State`, measuring only the handlers.
+//! - `disputes` does the same for a workload which disputes every deposit,
+//!   then resolves or charges back each dispute.
+//! - `channels` runs the pipeline with many handler threads on each channel backend
+//!   enabled, e.g. with `--features crossbeam,flume`.
+
+// With wider ids, conversions to and from fixed-width integers may be identities
+#![cfg_attr(
+    any(feature = "wide-client-ids", feature = "wide-tx-ids"),
+    allow(clippy::useless_conversion, clippy::unnecessary_cast)
+)]
+
+use std::io;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+use payments_engine_example::channel::ChannelBackend;
+use payments_engine_example::input::Inputs;
+use payments_engine_example::pipeline::PipelineConfig;
+use payments_engine_example::policy::Policies;
+use payments_engine_example::rand::{
+    generate_random_valid_transaction_sequence, TransactionWeights,
+};
+use payments_engine_example::state::State;
+use payments_engine_example::types::{ClientId, Currency, TransactionId};
+use payments_engine_example::types::{TransactionRecord, TransactionType};
+use payments_engine_example::{process_inputs, process_transactions};
+
+const NUM_TX: TransactionId = 100_000;
+const MAX_CLIENT: ClientId = 1000;
+
+fn generated_records() -> Vec<TransactionRecord> {
+    generate_random_valid_transaction_sequence(
+        Some(NUM_TX),
+        MAX_CLIENT,
+        Currency::from(100.0),
+        1000,
+        TransactionWeights::default().distribution().unwrap(),
+    )
+    .collect()
+}
+
+fn record(
+    transaction_type: TransactionType,
+    client_id: ClientId,
+    tx_id: TransactionId,
+    amount: Option<Currency>,
+) -> TransactionRecord {
+    TransactionRecord {
+        transaction_type,
+        client_id,
+        tx_id,
+        amount,
+        timestamp: None,
+        currency: None,
+        reason: None,
+        reverses: None,
+        effective: None,
+    }
+}
+
+/// Deposits, followed by a dispute of each, followed by settling each dispute.
+fn dispute_records() -> Vec<TransactionRecord> {
+    let num_deposits = NUM_TX / 3;
+    let client = |tx_id: TransactionId| (tx_id % MAX_CLIENT as TransactionId) as ClientId + 1;
+    let deposits = (1..=num_deposits).map(|tx_id| {
+        record(
+            TransactionType::Deposit,
+            client(tx_id),
+            tx_id,
+            Some(Currency::from(10.0)),
+        )
+    });
+    let disputes = (1..=num_deposits)
+        .map(|tx_id| record(TransactionType::Dispute, client(tx_id), tx_id, None));
+    let settlements = (1..=num_deposits).map(|tx_id| {
+        let settlement = if tx_id % 2 == 0 {
+            TransactionType::Resolve
+        } else {
+            TransactionType::Chargeback
+        };
+        record(settlement, client(tx_id), tx_id, None)
+    });
+    deposits.chain(disputes).chain(settlements).collect()
+}
+
+fn to_csv(records: &[TransactionRecord]) -> Vec<u8> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for record in records {
+        writer.serialize(record).unwrap();
+    }
+    writer.into_inner().unwrap()
+}
+
+fn bench_ingest(c: &mut Criterion) {
+    let input = to_csv(&generated_records());
+    let mut group = c.benchmark_group("ingest");
+    group.throughput(Throughput::Elements(NUM_TX as u64));
+    group.sample_size(20);
+    group.bench_function("generated", |b| {
+        b.iter(|| {
+            process_transactions(
+                io::Cursor::new(input.clone()),
+                &mut io::sink(),
+                1000,
+                true,
+                Policies::default(),
+                None,
+                None,
+            )
+        })
+    });
+    group.finish();
+}
+
+fn bench_handle(c: &mut Criterion) {
+    let workloads = [
+        ("handle", generated_records()),
+        ("disputes", dispute_records()),
+    ];
+    for (name, records) in &workloads {
+        let mut group = c.benchmark_group(*name);
+        group.throughput(Throughput::Elements(records.len() as u64));
+        group.bench_function("state", |b| {
+            b.iter_batched(
+                || records.clone(),
+                |records| {
+                    let mut state = State::new();
+                    for record in records {
+                        let _ = state.handle(record);
+                    }
+                    state
+                },
+                BatchSize::LargeInput,
+            )
+        });
+        group.finish();
+    }
+}
+
+fn bench_channels(c: &mut Criterion) {
+    let input = to_csv(&generated_records());
+    let backends = [
+        ("std", ChannelBackend::Std),
+        #[cfg(feature = "crossbeam")]
+        ("crossbeam", ChannelBackend::Crossbeam),
+        #[cfg(feature = "flume")]
+        ("flume", ChannelBackend::Flume),
+    ];
+    let mut group = c.benchmark_group("channels");
+    group.throughput(Throughput::Elements(NUM_TX as u64));
+    group.sample_size(20);
+    for (name, channel) in backends {
+        let config = PipelineConfig {
+            notrim: true,
+            handler_threads: 16,
+            handler_queue_depth: 100,
+            channel,
+            ..Default::default()
+        };
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                process_inputs(
+                    Inputs::single(io::Cursor::new(input.clone())),
+                    &mut io::sink(),
+                    config,
+                    Policies::default(),
+                    None,
+                    None,
+                )
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_ingest, bench_handle, bench_channels);
+criterion_main!(benches);
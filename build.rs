@@ -0,0 +1,16 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=proto/payments.proto");
+
+    // Generate the gRPC service from its protobuf definition
+    #[cfg(feature = "grpc")]
+    {
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("No vendored protoc");
+        std::env::set_var("PROTOC", protoc);
+        // The generated client needs the 2021 prelude, and isn't used here
+        tonic_prost_build::configure()
+            .build_client(false)
+            .compile_protos(&["proto/payments.proto"], &["proto"])
+            .expect("Failed to compile protobuf definitions");
+    }
+}
@@ -0,0 +1,48 @@
+//! Helpers shared by the examples' tests.
+#![allow(dead_code)]
+
+use payments_engine_example::policy::Policies;
+use payments_engine_example::process_transactions;
+use payments_engine_example::types::{ClientId, Currency, OutputRecord};
+
+/// Process a CSV string with the given policies,
+/// returning the final balances sorted by client id and currency.
+pub fn process_csv(input: &'static str, policies: Policies) -> Vec<OutputRecord> {
+    let mut output = Vec::new();
+    process_transactions(
+        input.as_bytes(),
+        &mut output,
+        1000,
+        false,
+        policies,
+        None,
+        None,
+    );
+    read_balances(&output)
+}
+
+/// Deserialize balances written by the engine, sorted by client id and currency
+/// since the order of rows is not significant.
+pub fn read_balances(output: &[u8]) -> Vec<OutputRecord> {
+    let mut balances: Vec<OutputRecord> = csv::Reader::from_reader(output)
+        .into_deserialize()
+        .collect::<Result<_, _>>()
+        .expect("engine wrote invalid balances");
+    balances.sort_by_key(|record| (record.client, record.currency));
+    balances
+}
+
+/// Shorthand for an expected output row.
+pub fn balance(client: ClientId, available: f64, held: f64, locked: bool) -> OutputRecord {
+    let available = Currency::from(available);
+    let held = Currency::from(held);
+    OutputRecord {
+        client,
+        currency: None,
+        available,
+        held,
+        total: available + held,
+        locked,
+        lock_reason: None,
+    }
+}
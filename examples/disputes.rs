@@ -0,0 +1,60 @@
+//! Dispute, resolve, and charge back transactions.
+//!
+//! ```sh
+//! cargo run --example disputes
+//! ```
+
+#[cfg(test)]
+mod common;
+
+use std::io;
+
+use payments_engine_example::policy::Policies;
+use payments_engine_example::process_transactions;
+
+/// Client 1 disputes and resolves a deposit,
+/// client 2 has part of a deposit charged back,
+/// and client 3 leaves a dispute open.
+const TRANSACTIONS: &str = "\
+type,       client, tx, amount
+deposit,         1,  1,   10.0
+dispute,         1,  1,
+resolve,         1,  1,
+deposit,         2,  2,   10.0
+dispute,         2,  2,    4.0
+chargeback,      2,  2,
+deposit,         2,  3,    1.0
+deposit,         3,  4,    5.0
+dispute,         3,  4,
+";
+
+fn main() {
+    process_transactions(
+        TRANSACTIONS.as_bytes(),
+        &mut io::stdout(),
+        1000,
+        false,
+        Policies::default(),
+        None,
+        None,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TRANSACTIONS;
+    use crate::common::{balance, process_csv};
+    use payments_engine_example::policy::Policies;
+
+    #[test]
+    fn test_disputes() {
+        // Client 2's account is locked by the chargeback,
+        // so their second deposit is rejected.
+        let expected = vec![
+            balance(1, 10.0, 0.0, false),
+            balance(2, 6.0, 0.0, true),
+            balance(3, 0.0, 5.0, false),
+        ];
+        assert_eq!(process_csv(TRANSACTIONS, Policies::default()), expected);
+    }
+}
@@ -0,0 +1,80 @@
+//! Generate random valid transactions, e.g. for load testing.
+//!
+//! ```sh
+//! cargo run --example generate > transactions.csv
+//! ```
+
+use std::io;
+
+use payments_engine_example::rand::{
+    generate_random_valid_transaction_sequence, TransactionWeights,
+};
+use payments_engine_example::types::Currency;
+
+fn main() {
+    let num_tx = Some(100);
+    let max_client = 10;
+    let max_deposit = Currency::from(100.0);
+    let max_attempts = 1000;
+    let mix = TransactionWeights::default().distribution().unwrap();
+
+    let mut writer = csv::Writer::from_writer(io::stdout());
+    for record in generate_random_valid_transaction_sequence(
+        num_tx,
+        max_client,
+        max_deposit,
+        max_attempts,
+        mix,
+    ) {
+        writer.serialize(record).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use payments_engine_example::policy::Policies;
+    use payments_engine_example::process_transactions;
+    use payments_engine_example::rand::{
+        generate_random_valid_transaction_sequence, TransactionWeights,
+    };
+    use payments_engine_example::types::{Currency, OutputRecord};
+    use std::io;
+
+    #[test]
+    fn test_generated_transactions_are_processed() {
+        let mut input = csv::Writer::from_writer(Vec::new());
+        let mix = TransactionWeights::default().distribution().unwrap();
+        for record in generate_random_valid_transaction_sequence(
+            Some(1000),
+            10,
+            Currency::from(100.0),
+            1000,
+            mix,
+        ) {
+            input.serialize(record).unwrap();
+        }
+        let input = input.into_inner().unwrap();
+
+        let mut output = Vec::new();
+        process_transactions(
+            io::Cursor::new(input),
+            &mut output,
+            100,
+            false,
+            Policies::default(),
+            None,
+            None,
+        );
+
+        let balances: Vec<OutputRecord> = csv::Reader::from_reader(&output[..])
+            .into_deserialize()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        // Only generated clients appear
+        assert!(!balances.is_empty());
+        for record in balances {
+            assert!((1..=10).contains(&record.client), "{:?}", record);
+        }
+    }
+}
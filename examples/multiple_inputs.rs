@@ -0,0 +1,87 @@
+//! Process several CSV inputs, e.g. one file per day, in a single run.
+//!
+//! ```sh
+//! cargo run --example multiple_inputs
+//! ```
+
+#[cfg(test)]
+mod common;
+
+use std::io;
+
+use payments_engine_example::input::{InputOrder, Inputs};
+use payments_engine_example::pipeline::PipelineConfig;
+use payments_engine_example::policy::Policies;
+use payments_engine_example::process_inputs;
+
+// Each input has its own header row, which may have different columns.
+const MONDAY: &str = "\
+type,       client, tx, amount, timestamp
+deposit,         1,  1,    5.0,       100
+dispute,         1,  1,       ,       300
+";
+
+const TUESDAY: &str = "\
+type,       client, tx, amount, timestamp
+withdrawal,      1,  2,    4.0,       200
+";
+
+fn main() {
+    // Interleave the inputs by timestamp, so that the withdrawal
+    // comes before the dispute even though it's in a later file.
+    // `InputOrder::Sequential` would read Monday in full first.
+    let inputs = Inputs::new(
+        vec![MONDAY.as_bytes(), TUESDAY.as_bytes()],
+        InputOrder::Timestamp,
+    );
+    process_inputs(
+        inputs,
+        &mut io::stdout(),
+        PipelineConfig::default(),
+        Policies::default(),
+        None,
+        None,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MONDAY, TUESDAY};
+    use crate::common::{balance, read_balances};
+    use payments_engine_example::input::{InputOrder, Inputs};
+    use payments_engine_example::pipeline::PipelineConfig;
+    use payments_engine_example::policy::Policies;
+    use payments_engine_example::process_inputs;
+    use payments_engine_example::types::OutputRecord;
+
+    fn process(order: InputOrder) -> Vec<OutputRecord> {
+        let inputs = Inputs::new(vec![MONDAY.as_bytes(), TUESDAY.as_bytes()], order);
+        let mut output = Vec::new();
+        process_inputs(
+            inputs,
+            &mut output,
+            PipelineConfig::default(),
+            Policies::default(),
+            None,
+            None,
+        );
+        read_balances(&output)
+    }
+
+    #[test]
+    fn test_sequential_inputs() {
+        // The deposit is already held when the withdrawal arrives
+        assert_eq!(
+            process(InputOrder::Sequential),
+            vec![balance(1, 0.0, 5.0, false)]
+        );
+    }
+
+    #[test]
+    fn test_inputs_merged_by_timestamp() {
+        assert_eq!(
+            process(InputOrder::Timestamp),
+            vec![balance(1, -4.0, 5.0, false)]
+        );
+    }
+}
@@ -0,0 +1,94 @@
+//! Pause a running engine with a control file, and inspect a snapshot
+//! of the balances while it's paused.
+//!
+//! ```sh
+//! cargo run --example pause_and_snapshot
+//! ```
+
+#[cfg(test)]
+mod common;
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use payments_engine_example::control::Control;
+use payments_engine_example::policy::Policies;
+use payments_engine_example::process_transactions;
+
+const TRANSACTIONS: &str = "\
+type,    client, tx, amount
+deposit,      1,  1,    1.0
+deposit,      2,  2,    2.0
+";
+
+/// Start the engine paused, wait for its snapshot,
+/// then resume it and return the snapshot and final balances.
+fn run(control_file: PathBuf, snapshot_path: PathBuf) -> (Vec<u8>, Vec<u8>) {
+    fs::write(&control_file, "pause").unwrap();
+    let _ = fs::remove_file(&snapshot_path);
+
+    let control = Control::new(
+        Some(control_file.clone()),
+        Some(snapshot_path.clone()),
+        None,
+    );
+    let engine = thread::spawn(move || {
+        let mut output = Vec::new();
+        process_transactions(
+            TRANSACTIONS.as_bytes(),
+            &mut output,
+            1000,
+            false,
+            Policies::default(),
+            None,
+            Some(control),
+        );
+        output
+    });
+
+    // The snapshot is written as soon as the engine pauses
+    while !snapshot_path.exists() {
+        thread::sleep(Duration::from_millis(10));
+    }
+    let snapshot = fs::read(&snapshot_path).unwrap();
+
+    fs::write(&control_file, "resume").unwrap();
+    let output = engine.join().unwrap();
+
+    fs::remove_file(&control_file).unwrap();
+    fs::remove_file(&snapshot_path).unwrap();
+    (snapshot, output)
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("{}-{}", name, std::process::id()))
+}
+
+fn main() {
+    let (snapshot, output) = run(temp_path("control"), temp_path("snapshot.csv"));
+    println!("Snapshot while paused:");
+    io::Write::write_all(&mut io::stdout(), &snapshot).unwrap();
+    println!("Final balances:");
+    io::Write::write_all(&mut io::stdout(), &output).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run, temp_path};
+    use crate::common::{balance, read_balances};
+
+    #[test]
+    fn test_pause_and_snapshot() {
+        let (snapshot, output) = run(temp_path("test-control"), temp_path("test-snapshot.csv"));
+
+        // The engine is paused before the first batch, so nothing is handled yet
+        assert_eq!(read_balances(&snapshot), vec![]);
+
+        let expected = vec![balance(1, 1.0, 0.0, false), balance(2, 2.0, 0.0, false)];
+        assert_eq!(read_balances(&output), expected);
+    }
+}
@@ -0,0 +1,99 @@
+//! Configure transaction handling with policies,
+//! either in code or from a TOML file.
+//!
+//! ```sh
+//! cargo run --example policies
+//! ```
+
+#[cfg(test)]
+mod common;
+
+use std::io;
+
+use payments_engine_example::policy::Policies;
+use payments_engine_example::process_transactions;
+
+/// Same format accepted by `--policy-file`.
+const POLICIES: &str = r#"
+allow_admin = true
+
+[dispute]
+max_age_secs = 86400
+
+[fees.deposit]
+percent = 1.0
+"#;
+
+/// Client 1 disputes a deposit too late, while client 2's account
+/// is charged back and then unlocked by an administrator.
+/// Each deposit costs a 1% fee.
+const TRANSACTIONS: &str = "\
+type,       client, tx, amount, timestamp
+deposit,         1,  1,    5.0,         0
+dispute,         1,  1,        ,    172800
+deposit,         2,  2,    5.0,         0
+deposit,         2,  3,    3.0,         0
+dispute,         2,  3,        ,      3600
+chargeback,      2,  3,        ,      7200
+unlock,          2,  4,        ,      7300
+";
+
+fn main() {
+    let policies: Policies = toml::from_str(POLICIES).expect("invalid policies");
+    println!("{:?}", policies);
+
+    process_transactions(
+        TRANSACTIONS.as_bytes(),
+        &mut io::stdout(),
+        1000,
+        false,
+        policies,
+        None,
+        None,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{POLICIES, TRANSACTIONS};
+    use crate::common::{balance, process_csv};
+    use payments_engine_example::policy::{DisputePolicy, FeePolicy, FeeSchedule, Policies};
+    use payments_engine_example::types::Currency;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_policies() {
+        let expected = Policies {
+            dispute: DisputePolicy {
+                max_age: Some(Duration::from_secs(24 * 60 * 60)),
+                ..Default::default()
+            },
+            fees: FeePolicy {
+                deposit: FeeSchedule {
+                    flat: Currency::ZERO,
+                    percent: 1.0,
+                },
+                ..Default::default()
+            },
+            allow_admin: true,
+            ..Default::default()
+        };
+        assert_eq!(toml::from_str::<Policies>(POLICIES).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_with_policies() {
+        let policies = toml::from_str(POLICIES).unwrap();
+        // Fees aren't refunded when client 2's deposit is charged back
+        let expected = vec![balance(1, 4.95, 0.0, false), balance(2, 4.92, 0.0, false)];
+        assert_eq!(process_csv(TRANSACTIONS, policies), expected);
+    }
+
+    #[test]
+    fn test_default_policies() {
+        // Without a dispute window, client 1's dispute goes through,
+        // and without admin transactions, client 2 stays locked.
+        let expected = vec![balance(1, 0.0, 5.0, false), balance(2, 5.0, 0.0, true)];
+        assert_eq!(process_csv(TRANSACTIONS, Policies::default()), expected);
+    }
+}
@@ -0,0 +1,90 @@
+//! Process a CSV stream and write the final balances.
+//!
+//! ```sh
+//! cargo run --example process_csv
+//! ```
+
+#[cfg(test)]
+mod common;
+
+use std::io;
+
+use payments_engine_example::policy::Policies;
+use payments_engine_example::process_transactions;
+
+const TRANSACTIONS: &str = "\
+type,       client, tx, amount
+deposit,         1,  1,    1.0
+deposit,         2,  2,    2.0
+deposit,         1,  3,    2.0
+withdrawal,      1,  4,    1.5
+withdrawal,      2,  5,    3.0
+";
+
+fn main() {
+    // Any `io::Read` works as input, and any `io::Write` as output.
+    // Whitespace around fields is trimmed unless `notrim` is set.
+    let batch_size = 1000;
+    let notrim = false;
+    process_transactions(
+        TRANSACTIONS.as_bytes(),
+        &mut io::stdout(),
+        batch_size,
+        notrim,
+        Policies::default(),
+        None,
+        None,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TRANSACTIONS;
+    use crate::common::{balance, process_csv};
+    use payments_engine_example::policy::Policies;
+    use payments_engine_example::process_transactions;
+    use std::io;
+
+    #[test]
+    fn test_process_csv() {
+        // Client 2's withdrawal fails for insufficient funds
+        let expected = vec![balance(1, 1.5, 0.0, false), balance(2, 2.0, 0.0, false)];
+        assert_eq!(process_csv(TRANSACTIONS, Policies::default()), expected);
+    }
+
+    #[test]
+    fn test_invalid_rows_are_skipped() {
+        let input = "\
+type,    client, tx, amount
+deposit,      1,  1,    1.0
+bogus,        1,  2,    1.0
+deposit,      1,  3,    2.0
+";
+        let expected = vec![balance(1, 3.0, 0.0, false)];
+        assert_eq!(process_csv(input, Policies::default()), expected);
+    }
+
+    #[test]
+    fn test_summary() {
+        let input = "\
+type,       client, tx, amount
+deposit,         1,  1,    1.0
+bogus,           1,  2,    1.0
+withdrawal,      1,  3,    5.0
+deposit,         1,  4
+";
+        let state = process_transactions(
+            input.as_bytes(),
+            &mut io::sink(),
+            1000,
+            false,
+            Policies::default(),
+            None,
+            None,
+        );
+        // The withdrawal is rejected, and the other two rows can't be used at all
+        let summary = state.summary();
+        assert_eq!((summary.rejected, summary.skipped), (1, 2));
+        assert!(!summary.is_clean());
+    }
+}
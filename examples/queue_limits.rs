@@ -0,0 +1,69 @@
+//! Limit how many transactions each client may have waiting to be handled.
+//!
+//! ```sh
+//! cargo run --example queue_limits
+//! ```
+
+#[cfg(test)]
+mod common;
+
+use std::io;
+use std::num::NonZeroUsize;
+
+use payments_engine_example::pipeline::{ClientQueueLimit, OverflowStrategy};
+use payments_engine_example::policy::Policies;
+use payments_engine_example::process_transactions;
+
+/// Pause ingestion whenever a client has more than 2 transactions waiting.
+const LIMIT: ClientQueueLimit = ClientQueueLimit {
+    max_in_flight: NonZeroUsize::new(2).unwrap(),
+    overflow: OverflowStrategy::Backpressure,
+};
+
+/// Generate a busy client 1 alongside a quiet client 2.
+fn transactions() -> String {
+    let mut csv = String::from("type,client,tx,amount\n");
+    for tx in 1..=100 {
+        let client = if tx % 10 == 0 { 2 } else { 1 };
+        csv.push_str(&format!("deposit,{},{},1.0\n", client, tx));
+    }
+    csv
+}
+
+fn main() {
+    process_transactions(
+        io::Cursor::new(transactions()),
+        &mut io::stdout(),
+        10,
+        false,
+        Policies::default(),
+        Some(LIMIT),
+        None,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{transactions, LIMIT};
+    use crate::common::{balance, read_balances};
+    use payments_engine_example::policy::Policies;
+    use payments_engine_example::process_transactions;
+    use std::io;
+
+    #[test]
+    fn test_backpressure_loses_nothing() {
+        let mut output = Vec::new();
+        process_transactions(
+            io::Cursor::new(transactions()),
+            &mut output,
+            10,
+            false,
+            Policies::default(),
+            Some(LIMIT),
+            None,
+        );
+
+        let expected = vec![balance(1, 90.0, 0.0, false), balance(2, 10.0, 0.0, false)];
+        assert_eq!(read_balances(&output), expected);
+    }
+}
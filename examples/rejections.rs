@@ -0,0 +1,78 @@
+//! Report which transactions were rejected, and why,
+//! along with any rows which couldn't be read at all.
+//!
+//! ```sh
+//! cargo run --example rejections
+//! ```
+
+use std::io;
+
+use payments_engine_example::input::Inputs;
+use payments_engine_example::pipeline::PipelineConfig;
+use payments_engine_example::policy::Policies;
+use payments_engine_example::{run_inputs, write_rejections};
+
+const TRANSACTIONS: &str = "\
+type,       client, tx, amount
+deposit,         1,  1,    1.0
+withdrawal,      1,  2,    5.0
+deposit,         2,  1,    2.0
+dispute,         2,  7,
+deposit,       two,  8,    1.0
+";
+
+fn main() {
+    let state = run_inputs(
+        Inputs::single(TRANSACTIONS.as_bytes()),
+        PipelineConfig::default(),
+        Policies::default(),
+        None,
+        None,
+    );
+
+    // Each row is the rejected transaction, plus an `error_code`
+    // which is stable across versions, and an `error_message` with details.
+    // Unreadable rows come last, with their `line` and `content` instead.
+    write_rejections(&state.rejections, &state.unreadable_rows, io::stdout());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TRANSACTIONS;
+    use payments_engine_example::input::Inputs;
+    use payments_engine_example::pipeline::PipelineConfig;
+    use payments_engine_example::policy::Policies;
+    use payments_engine_example::{run_inputs, write_rejections};
+
+    #[test]
+    fn test_rejection_codes() {
+        let state = run_inputs(
+            Inputs::single(TRANSACTIONS.as_bytes()),
+            PipelineConfig::default(),
+            Policies::default(),
+            None,
+            None,
+        );
+        let mut output = Vec::new();
+        write_rejections(&state.rejections, &state.unreadable_rows, &mut output);
+
+        let mut reader = csv::Reader::from_reader(&output[..]);
+        let rows: Vec<_> = reader.records().map(Result::unwrap).collect();
+        let codes: Vec<_> = rows
+            .iter()
+            .map(|record| (record[2].to_string(), record[6].to_string()))
+            .collect();
+        assert_eq!(
+            codes,
+            vec![
+                ("2".into(), "INSUFFICIENT_FUNDS".into()),
+                ("1".into(), "DUPLICATE_TX".into()),
+                ("7".into(), "TX_NOT_FOUND".into()),
+                ("".into(), "UNREADABLE".into()),
+            ]
+        );
+        // The line and content of the unreadable row, as it was read
+        let unreadable = rows.last().unwrap();
+        assert_eq!((&unreadable[8], &unreadable[9]), ("6", "deposit,two,8,1.0"));
+    }
+}
@@ -0,0 +1,63 @@
+//! Check the engine's behavior for a sequence of transactions,
+//! including the errors it reports.
+//!
+//! ```sh
+//! cargo test --example scenarios
+//! ```
+
+use std::collections::HashMap;
+
+use payments_engine_example::state::State;
+use payments_engine_example::test_utils::{
+    deposit, dispute, run_test_scenario, withdrawal, AccountBuilder,
+};
+use payments_engine_example::types::{
+    Account, ClientId, Currency, TransactionError, TransactionRecord,
+};
+
+fn scenario() -> (
+    Vec<TransactionRecord>,
+    HashMap<ClientId, Account>,
+    Vec<TransactionError>,
+) {
+    let transactions = vec![
+        deposit(1, 1, 5.0),
+        withdrawal(1, 2, 8.0),
+        dispute(1, 1),
+        dispute(1, 1),
+    ];
+
+    let mut final_accounts = HashMap::new();
+    final_accounts.insert(1, AccountBuilder::new().held(5.0).build());
+
+    let expected_errors = vec![
+        TransactionError::InsufficientFunds {
+            client: 1,
+            tx: 2,
+            requested: Currency::from(8.0),
+            available: Currency::from(5.0),
+        },
+        TransactionError::TxAlreadyDisputed { client: 1, tx: 1 },
+    ];
+
+    (transactions, final_accounts, expected_errors)
+}
+
+fn main() {
+    let (transactions, final_accounts, expected_errors) = scenario();
+    run_test_scenario(State::new(), transactions, final_accounts, expected_errors);
+    println!("Scenario passed");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::scenario;
+    use payments_engine_example::state::State;
+    use payments_engine_example::test_utils::run_test_scenario;
+
+    #[test]
+    fn test_scenario() {
+        let (transactions, final_accounts, expected_errors) = scenario();
+        run_test_scenario(State::new(), transactions, final_accounts, expected_errors);
+    }
+}
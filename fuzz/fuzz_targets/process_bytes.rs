@@ -0,0 +1,26 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use payments_engine_example::policy::Policies;
+use payments_engine_example::types::Currency;
+use payments_engine_example::{invariants, process_bytes, process_bytes_with_policies};
+
+// Whatever the bytes, reading and handling them shouldn't panic,
+// and should leave the state consistent, under the default policies
+// or ones which allow admin transactions, credit and interest.
+fuzz_target!(|data: &[u8]| {
+    let mut permissive = Policies {
+        allow_admin: true,
+        ..Policies::default()
+    };
+    permissive.credit.default_limit = Currency::from(1_000_000.0);
+    permissive.interest.percent = 100.0;
+
+    for state in [
+        process_bytes(data),
+        process_bytes_with_policies(data, permissive),
+    ] {
+        let violations = invariants::check(&state);
+        assert!(violations.is_empty(), "{:?}", violations);
+    }
+});
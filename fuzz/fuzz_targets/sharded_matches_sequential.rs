@@ -0,0 +1,31 @@
+#![no_main]
+
+use std::io;
+
+use libfuzzer_sys::fuzz_target;
+use payments_engine_example::policy::Policies;
+use payments_engine_example::state::AccountOrder;
+use payments_engine_example::{process_bytes, process_transactions, write_balances};
+
+// The full pipeline, with clients sharded across handler threads,
+// should end with the same balances as handling everything in order.
+fuzz_target!(|data: &[u8]| {
+    let mut expected = Vec::new();
+    write_balances(&process_bytes(data), AccountOrder::Client, &mut expected);
+
+    let mut actual = Vec::new();
+    process_transactions(
+        io::Cursor::new(data.to_vec()),
+        &mut actual,
+        100,
+        false,
+        Policies::default(),
+        None,
+        None,
+    );
+
+    assert_eq!(
+        String::from_utf8_lossy(&expected),
+        String::from_utf8_lossy(&actual)
+    );
+});
@@ -1,170 +1,297 @@
+use std::error::Error;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+use crate::currency::Currency;
 use crate::traits::Disputable;
 use crate::types::Account;
-use crate::types::{Deposit, Withdrawal};
+use crate::types::{Deposit, LockInfo, Withdrawal};
+
+/// Mutable access to an account, whether or not it's locked.
+/// Any account can be viewed, disputed, resolved, and charged back.
+pub struct AccountRef<'a>(&'a mut Account);
+
+/// A locked account cannot deposit or withdraw, but can be unlocked.
+pub struct LockedAccount<'a>(AccountRef<'a>);
+
+/// An unlocked account can deposit, withdraw, or be locked.
+pub struct UnlockedAccount<'a>(AccountRef<'a>);
 
-/// A locked account cannot deposit or withdraw.
-pub struct LockedAccount<'a>(&'a mut Account);
+/// A balance change which would take a balance, or the total,
+/// beyond what can be represented, so the account was left as it was.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Overflow;
 
-/// An unlocked account can deposit or withdraw.
-pub struct UnlockedAccount<'a>(&'a mut Account);
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "balance would go beyond what can be represented")
+    }
+}
+
+impl Error for Overflow {}
 
 impl Account {
-    /// Get appropriate mutable access into the account
-    /// based on its state (locked or unlocked).
-    pub fn access<'a>(&'a mut self) -> AccountAccess<'a> {
-        if self.locked {
-            AccountAccess::Locked(LockedAccount(self))
+    /// Get mutable access into the account,
+    /// to be narrowed down by whether it's locked.
+    pub fn access(&mut self) -> AccountRef<'_> {
+        AccountRef(self)
+    }
+
+    /// Set the available and held funds together, unless their total can't be represented.
+    pub(crate) fn set_balances(
+        &mut self,
+        available: Currency,
+        held: Currency,
+    ) -> Result<(), Overflow> {
+        available.checked_add(held).ok_or(Overflow)?;
+        self.available = available;
+        self.held = held;
+        Ok(())
+    }
+
+    /// Change the available funds, unless they or the total would overflow.
+    fn add_available(&mut self, change: Currency) -> Result<(), Overflow> {
+        let available = self.available.checked_add(change).ok_or(Overflow)?;
+        self.set_balances(available, self.held)
+    }
+}
+
+impl<'a> AccountRef<'a> {
+    pub fn view(&self) -> &Account {
+        self.0
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.0.locked
+    }
+
+    /// Access to deposit or withdraw, if the account is unlocked,
+    /// or else access to unlock it.
+    pub fn try_unlocked(self) -> Result<UnlockedAccount<'a>, LockedAccount<'a>> {
+        if self.is_locked() {
+            Err(LockedAccount(self))
         } else {
-            AccountAccess::Unlocked(UnlockedAccount(self))
+            Ok(UnlockedAccount(self))
         }
     }
-}
-pub enum AccountAccess<'a> {
-    Locked(LockedAccount<'a>),
-    Unlocked(UnlockedAccount<'a>),
-}
 
-mod private {
-    // A bit hacky, but this is a workaround to avoid exposing
-    // WrapsAccount publicly (since we don't want to grant
-    // public access to the underlying account - that would
-    // kind of defeat the point of the wrapper).
-    // Normally, it's a warning (soon-to-be error) to expose
-    // a private trait (WrapsAccount)
-    // in a public interface (BaseAccountFeatures)
-    // See https://github.com/rust-lang/rust/issues/34537
-    use super::Account;
-
-    /// Marker trait for a type that privately holds an Account,
-    /// but does not necessarily expose it publicly.
-    pub trait WrapsAccount {
-        fn get_account(&self) -> &Account;
-        fn get_mut_account(&mut self) -> &mut Account;
+    /// Access to unlock the account, if it's locked,
+    /// or else access to deposit or withdraw.
+    pub fn try_locked(self) -> Result<LockedAccount<'a>, UnlockedAccount<'a>> {
+        self.try_unlocked().map_or_else(Ok, Err)
     }
-}
 
-impl<'a> private::WrapsAccount for LockedAccount<'a> {
-    #[inline]
-    fn get_account(&self) -> &Account {
-        &self.0
+    pub fn modify_balances_for_dispute<D: Disputable>(
+        &mut self,
+        disputed_tx: &D,
+        amount: Currency,
+    ) -> Result<(), Overflow> {
+        disputed_tx.modify_balances_for_dispute(self.0, amount)
     }
 
-    #[inline]
-    fn get_mut_account(&mut self) -> &mut Account {
-        &mut self.0
+    pub fn modify_balances_for_resolve<D: Disputable>(
+        &mut self,
+        resolved_tx: &D,
+        amount: Currency,
+    ) -> Result<(), Overflow> {
+        resolved_tx.modify_balances_for_resolve(self.0, amount)
     }
-}
 
-impl<'a> private::WrapsAccount for UnlockedAccount<'a> {
-    #[inline]
-    fn get_account(&self) -> &Account {
-        &self.0
+    /// Charge back a disputed transaction, writing off whatever the account can't cover.
+    pub fn modify_balances_for_chargeback<D: Disputable>(
+        &mut self,
+        chargebacked_tx: &D,
+        amount: Currency,
+        written_off: Currency,
+    ) -> Result<(), Overflow> {
+        let (available, held) = (self.0.available, self.0.held);
+        chargebacked_tx.modify_balances_for_chargeback(self.0, amount)?;
+        if written_off.is_positive() {
+            if let Err(err) = self.write_off(written_off) {
+                // Both or neither
+                self.0.available = available;
+                self.0.held = held;
+                return Err(err);
+            }
+        }
+        Ok(())
     }
 
-    #[inline]
-    fn get_mut_account(&mut self) -> &mut Account {
-        &mut self.0
+    pub fn modify_balances_for_representment<D: Disputable>(
+        &mut self,
+        represented_tx: &D,
+        amount: Currency,
+    ) -> Result<(), Overflow> {
+        represented_tx.modify_balances_for_representment(self.0, amount)
     }
-}
 
-/// This trait implements functionality common to all accounts,
-/// namely viewing, disputing, resolving, and charging back.
-pub trait BaseAccountFeatures: private::WrapsAccount {
-    // Since we're using this trait as an object somewhere,
-    // these functions can only use dynamic dispatch.
-    // They can't be generic over traits.
-    // See https://doc.rust-lang.org/reference/items/traits.html#object-safety
-    // TODO: Remove this & undo dyns?
-    fn modify_balances_for_dispute(&mut self, disputed_tx: &dyn Disputable) {
-        let account = self.get_mut_account();
-        disputed_tx.modify_balances_for_dispute(account);
+    /// Undo a deposit or withdrawal, changing the available funds by its amount,
+    /// which is negative for a deposit.
+    pub fn modify_balances_for_reversal(&mut self, change: Currency) -> Result<(), Overflow> {
+        self.0.add_available(change)
     }
-    fn modify_balances_for_resolve(&mut self, resolved_tx: &dyn Disputable) {
-        let account = self.get_mut_account();
-        resolved_tx.modify_balances_for_resolve(account);
+
+    /// Credit interest to the available funds.
+    pub fn credit_interest(&mut self, interest: Currency) -> Result<(), Overflow> {
+        self.0.add_available(interest)
+    }
+
+    fn account_mut(&mut self) -> &mut Account {
+        self.0
     }
-    fn modify_balances_for_chargeback(&mut self, chargebackd_tx: &dyn Disputable) {
-        let account = self.get_mut_account();
-        chargebackd_tx.modify_balances_for_chargeback(account);
+
+    /// Absorb a loss the account can't cover, and flag the account.
+    pub fn write_off(&mut self, amount: Currency) -> Result<(), Overflow> {
+        self.0.add_available(amount)?;
+        self.flag();
+        Ok(())
     }
 
-    fn view(&self) -> &Account {
-        self.get_account()
+    /// Flag the account for review.
+    pub fn flag(&mut self) {
+        self.0.flagged = true;
     }
 }
 
-/// Only unlocked accounts may deposit, withdraw, or lock.
-pub trait UnlockedAccountFeatures: private::WrapsAccount {
-    fn modify_balances_for_deposit(&mut self, deposit: &Deposit) {
-        self.get_mut_account().available += deposit.amount;
+impl<'a> UnlockedAccount<'a> {
+    pub fn modify_balances_for_deposit(
+        &mut self,
+        deposit: &Deposit,
+        fee: Currency,
+    ) -> Result<(), Overflow> {
+        let account = self.account_mut();
+        let credited = deposit.amount.checked_sub(fee).ok_or(Overflow)?;
+        let fees = account.fees.checked_add(fee).ok_or(Overflow)?;
+        account.add_available(credited)?;
+        account.fees = fees;
+        Ok(())
     }
-    fn modify_balances_for_withdrawal(&mut self, withdrawal: &Withdrawal) {
-        self.get_mut_account().available -= withdrawal.amount;
+
+    pub fn modify_balances_for_withdrawal(
+        &mut self,
+        withdrawal: &Withdrawal,
+        fee: Currency,
+    ) -> Result<(), Overflow> {
+        let account = self.account_mut();
+        let debited = withdrawal.amount.checked_add(fee).ok_or(Overflow)?;
+        let available = account.available.checked_sub(debited).ok_or(Overflow)?;
+        let fees = account.fees.checked_add(fee).ok_or(Overflow)?;
+        account.set_balances(available, account.held)?;
+        account.fees = fees;
+        Ok(())
     }
-    fn lock(&mut self) {
-        self.get_mut_account().locked = true;
+
+    /// Lock the account, noting why and when.
+    pub fn lock(self, lock_info: LockInfo) -> LockedAccount<'a> {
+        let AccountRef(account) = self.0;
+        account.locked = true;
+        account.lock_info = Some(lock_info);
+        LockedAccount(AccountRef(account))
     }
 }
 
-impl<'a> BaseAccountFeatures for LockedAccount<'a> {}
-impl<'a> BaseAccountFeatures for UnlockedAccount<'a> {}
-impl<'a> UnlockedAccountFeatures for UnlockedAccount<'a> {}
-
-impl<'a> AccountAccess<'a> {
-    /// Consume the access and return a reference to the contained
-    /// account wrapper, providing only the base account features.
-    pub fn inner(self) -> Box<dyn BaseAccountFeatures + 'a> {
-        match self {
-            AccountAccess::Locked(account) => Box::new(account),
-            AccountAccess::Unlocked(account) => Box::new(account),
-        }
+impl<'a> LockedAccount<'a> {
+    pub fn unlock(self) -> UnlockedAccount<'a> {
+        let AccountRef(account) = self.0;
+        account.locked = false;
+        account.lock_info = None;
+        UnlockedAccount(AccountRef(account))
     }
 }
 
-impl<'a> private::WrapsAccount for AccountAccess<'a> {
-    fn get_account(&self) -> &Account {
-        match self {
-            AccountAccess::Locked(account) => account.get_account(),
-            AccountAccess::Unlocked(account) => account.get_account(),
-        }
+// Locked and unlocked accounts can do anything any account can.
+
+impl<'a> Deref for LockedAccount<'a> {
+    type Target = AccountRef<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
     }
-    fn get_mut_account(&mut self) -> &mut Account {
-        match self {
-            AccountAccess::Locked(account) => account.get_mut_account(),
-            AccountAccess::Unlocked(account) => account.get_mut_account(),
-        }
+}
+
+impl<'a> DerefMut for LockedAccount<'a> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
     }
 }
 
-impl<'a> BaseAccountFeatures for AccountAccess<'a> {}
+impl<'a> Deref for UnlockedAccount<'a> {
+    type Target = AccountRef<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<'a> DerefMut for UnlockedAccount<'a> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
 
 #[cfg(test)]
 mod tests {
-    use crate::account::{AccountAccess, UnlockedAccountFeatures};
-    use crate::types::Account;
+    use crate::currency::Currency;
+    use crate::test_utils::AccountBuilder;
+    use crate::types::{Account, LockInfo, LockReason};
 
     #[test]
     fn test_account_unlocked() {
         let mut account = Account::default();
-        assert!(matches!(account.access(), AccountAccess::Unlocked(_)));
+        assert!(account.access().try_unlocked().is_ok());
+        assert!(account.access().try_locked().is_err());
     }
 
     #[test]
     fn test_account_locked() {
-        let mut account = Account::default();
-        account.locked = true;
-        assert!(matches!(account.access(), AccountAccess::Locked(_)));
+        let mut account = AccountBuilder::new().locked().build();
+        assert!(account.access().try_unlocked().is_err());
+        assert!(account.access().try_locked().is_ok());
     }
 
     #[test]
     fn test_lock_account() {
         let mut account = Account::default();
-        if let AccountAccess::Unlocked(mut access) = account.access() {
-            access.lock();
+        match account.access().try_unlocked() {
+            Ok(access) => {
+                let lock_info = LockInfo {
+                    reason: LockReason::Admin,
+                    tx: 1,
+                    timestamp: Some(100),
+                };
+                let locked = access.lock(lock_info.clone());
+                assert!(locked.is_locked());
+                assert_eq!(locked.view().lock_info(), Some(&lock_info));
+            }
+            Err(_) => panic!("new account should be unlocked"),
+        }
+        assert!(account.locked());
+    }
+
+    #[test]
+    fn test_unlock_account() {
+        let mut account = AccountBuilder::new()
+            .locked_by(LockReason::Chargeback, 1)
+            .build();
+        match account.access().try_locked() {
+            Ok(access) => {
+                access.unlock();
+            }
+            Err(_) => panic!("account should be locked"),
+        }
+        assert!(!account.locked());
+        assert_eq!(account.lock_info(), None);
+    }
+
+    #[test]
+    fn test_write_off_through_either_access() {
+        let mut account = AccountBuilder::new().locked().build();
+        if let Ok(mut access) = account.access().try_locked() {
+            access.write_off(Currency::from(2.0)).unwrap();
         } else {
-            assert!(false);
+            panic!("account should be locked");
         }
-        assert!(matches!(account.access(), AccountAccess::Locked(_)));
-        assert_eq!(account.locked, true);
+        assert!(account.flagged());
+        assert_eq!(account.available(), Currency::from(2.0));
     }
 }
@@ -0,0 +1,393 @@
+//! An actor per client, each with its own mailbox and state,
+//! run by a fixed pool of threads.
+//!
+//! Whereas a shard's thread handles its clients' transactions one after another,
+//! so that a busy client delays every other client in the shard,
+//! any idle thread may pick up any client with mail waiting.
+//! Each client is only ever run by one thread at a time though,
+//! so its transactions are still handled in the order they were sent.
+
+use std::collections::VecDeque;
+use std::sync::mpsc::{sync_channel, Sender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+
+use rustc_hash::FxHashMap;
+
+use crate::events::EventLog;
+use crate::journal::Journal;
+use crate::ledger::Ledger;
+use crate::observer::Observers;
+use crate::pipeline::{HandlerMessage, InFlightTracker, PipelineConfig, Worker};
+use crate::policy::Policies;
+use crate::risk::RiskScorers;
+use crate::settlement::Settlement;
+use crate::state::State;
+use crate::types::{BalanceUpdate, ClientId, Timestamp};
+
+/// Maximum number of messages an actor handles before yielding its thread,
+/// so that a busy client can't starve the others.
+const MAX_MESSAGES_PER_TURN: usize = 100;
+
+/// Messages waiting for an actor.
+#[derive(Default)]
+struct Mailbox {
+    messages: VecDeque<HandlerMessage>,
+    /// Whether the actor is either waiting for a thread or running.
+    /// Only a scheduled actor may have messages waiting.
+    scheduled: bool,
+}
+
+/// A single client's state, and the messages waiting for it.
+struct Actor {
+    mailbox: Mutex<Mailbox>,
+    worker: Mutex<Worker>,
+}
+
+/// Actors waiting for a thread.
+#[derive(Default)]
+struct RunQueue {
+    ready: VecDeque<Arc<Actor>>,
+    /// Number of messages sent but not yet handled, across all actors
+    pending: usize,
+    /// Set once nothing more will be sent, so that idle threads can exit
+    stopping: bool,
+}
+
+/// Scheduling state shared between the router and the pool's threads.
+#[derive(Default)]
+struct Scheduler {
+    queue: Mutex<RunQueue>,
+    /// Notified when an actor becomes ready, or the pool is stopping
+    ready: Condvar,
+    /// Notified when a message has been handled
+    room: Condvar,
+}
+
+/// Locks are always left consistent, so a poisoned lock is still usable.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl Scheduler {
+    /// Wait for an actor with messages waiting,
+    /// or return `None` once there are none left and the pool is stopping.
+    fn next(&self) -> Option<Arc<Actor>> {
+        let mut queue = lock(&self.queue);
+        loop {
+            if let Some(actor) = queue.ready.pop_front() {
+                return Some(actor);
+            }
+            if queue.stopping {
+                return None;
+            }
+            queue = self
+                .ready
+                .wait(queue)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+    }
+
+    /// Add a message to an actor's mailbox, waiting while `capacity` messages are pending.
+    fn deliver(&self, capacity: usize, actor: Arc<Actor>, message: HandlerMessage) {
+        let mut queue = lock(&self.queue);
+        while queue.pending >= capacity {
+            queue = self
+                .room
+                .wait(queue)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        queue.pending += 1;
+        drop(queue);
+
+        let mut mailbox = lock(&actor.mailbox);
+        mailbox.messages.push_back(message);
+        if !mailbox.scheduled {
+            mailbox.scheduled = true;
+            drop(mailbox);
+            self.push_ready(actor);
+        }
+    }
+
+    fn push_ready(&self, actor: Arc<Actor>) {
+        lock(&self.queue).ready.push_back(actor);
+        self.ready.notify_one();
+    }
+
+    /// Run an actor for one turn, then put it back in line if it still has mail.
+    fn run(&self, actor: Arc<Actor>) {
+        let mut worker = lock(&actor.worker);
+        for _ in 0..MAX_MESSAGES_PER_TURN {
+            let message = {
+                let mut mailbox = lock(&actor.mailbox);
+                match mailbox.messages.pop_front() {
+                    Some(message) => message,
+                    None => {
+                        mailbox.scheduled = false;
+                        return;
+                    }
+                }
+            };
+            worker.handle(message);
+            lock(&self.queue).pending -= 1;
+            self.room.notify_one();
+        }
+        drop(worker);
+        // Still scheduled, so whichever thread picks it up next
+        // will unschedule it if its mailbox has emptied in the meantime
+        self.push_ready(actor);
+    }
+}
+
+/// Runs an actor for each client on a fixed number of threads.
+///
+/// Sending blocks while the total number of messages waiting across all actors
+/// is at capacity, as sending to a shard's full queue does.
+pub(crate) struct ActorPool {
+    actors: FxHashMap<ClientId, Arc<Actor>>,
+    scheduler: Arc<Scheduler>,
+    handles: Vec<JoinHandle<()>>,
+    capacity: usize,
+    /// Used to start each new actor
+    policies: Policies,
+    updates: Option<Sender<BalanceUpdate>>,
+    observers: Observers,
+    risk_scorers: RiskScorers,
+    events: Option<EventLog>,
+    tracker: Option<Arc<InFlightTracker>>,
+    config: PipelineConfig,
+}
+
+impl ActorPool {
+    pub fn spawn(
+        num_threads: usize,
+        config: &PipelineConfig,
+        policies: Policies,
+        tracker: Option<Arc<InFlightTracker>>,
+    ) -> Self {
+        let scheduler = Arc::new(Scheduler::default());
+        let handles = (0..num_threads)
+            .map(|thread| {
+                let scheduler = scheduler.clone();
+                let span = tracing::info_span!("actor_pool", thread);
+                thread::spawn(move || {
+                    span.in_scope(|| {
+                        while let Some(actor) = scheduler.next() {
+                            scheduler.run(actor);
+                        }
+                    })
+                })
+            })
+            .collect();
+
+        Self {
+            actors: FxHashMap::default(),
+            scheduler,
+            handles,
+            capacity: (config.handler_queue_depth * num_threads).max(1),
+            policies,
+            updates: None,
+            observers: Observers::default(),
+            risk_scorers: RiskScorers::default(),
+            events: None,
+            tracker,
+            config: *config,
+        }
+    }
+
+    /// Actor for a client, starting one if it's the client's first message.
+    fn actor(&mut self, client_id: ClientId) -> Arc<Actor> {
+        let Self {
+            actors,
+            policies,
+            updates,
+            observers,
+            risk_scorers,
+            events,
+            tracker,
+            config,
+            ..
+        } = self;
+        actors
+            .entry(client_id)
+            .or_insert_with(|| {
+                let mut state = State::with_policies(policies.clone());
+                if config.ledger {
+                    state.ledger = Some(Ledger::default());
+                }
+                if config.journal {
+                    state.journal = Some(Journal::default());
+                }
+                if config.settlement {
+                    state.settlement = Some(Settlement::default());
+                }
+                state.observers = observers.clone();
+                state.risk_scorers = risk_scorers.clone();
+                state.events = events.clone();
+                let mut worker = Worker::new(state, tracker.clone(), *config);
+                if let Some(updates) = updates {
+                    worker.handle(HandlerMessage::Subscribe(updates.clone()));
+                }
+                Arc::new(Actor {
+                    mailbox: Mutex::default(),
+                    worker: Mutex::new(worker),
+                })
+            })
+            .clone()
+    }
+
+    /// Add a message to a client's mailbox, waiting while the pool is at capacity.
+    pub fn send(&mut self, client_id: ClientId, message: HandlerMessage) {
+        let actor = self.actor(client_id);
+        self.scheduler.deliver(self.capacity, actor, message);
+    }
+
+    /// Send a message to every actor started so far.
+    fn broadcast(&self, mut make_message: impl FnMut() -> HandlerMessage) {
+        for actor in self.actors.values() {
+            self.scheduler
+                .deliver(self.capacity, actor.clone(), make_message());
+        }
+    }
+
+    /// Apply `inspect` to every actor's state, once each has handled
+    /// everything sent before, and collect the results.
+    pub fn collect<T: Send + 'static>(&self, inspect: fn(&State) -> T) -> Vec<T> {
+        let (reply_snd, reply_rcv) = sync_channel(self.actors.len());
+        self.broadcast(|| {
+            let reply_snd = reply_snd.clone();
+            HandlerMessage::Inspect(Box::new(move |state| {
+                // Only fails if the router has given up waiting
+                let _ = reply_snd.send(inspect(state));
+            }))
+        });
+        drop(reply_snd);
+        reply_rcv.iter().collect()
+    }
+
+    /// Send updated balances from every actor, including those not yet started.
+    pub fn subscribe(&mut self, updates: Sender<BalanceUpdate>) {
+        self.broadcast(|| HandlerMessage::Subscribe(updates.clone()));
+        self.updates = Some(updates);
+    }
+
+    /// Call observers around every actor's transactions, including those not yet started.
+    pub fn observe(&mut self, observers: Observers) {
+        self.broadcast(|| HandlerMessage::Observe(observers.clone()));
+        self.observers = observers;
+    }
+
+    /// Score deposits and withdrawals with these scorers too in every actor,
+    /// including those not yet started.
+    pub fn score_risk(&mut self, risk_scorers: RiskScorers) {
+        self.broadcast(|| HandlerMessage::ScoreRisk(risk_scorers.clone()));
+        self.risk_scorers = risk_scorers;
+    }
+
+    /// Send events from every actor, including those not yet started.
+    pub fn log_events(&mut self, events: EventLog) {
+        self.broadcast(|| HandlerMessage::LogEvents(events.clone()));
+        self.events = Some(events);
+    }
+
+    /// Switch every actor to new policies, including those not yet started.
+    /// Credit interest to every actor's accounts.
+    pub fn accrue_interest(&self, timestamp: Option<Timestamp>) {
+        self.broadcast(|| HandlerMessage::AccrueInterest(timestamp));
+    }
+
+    pub fn update_policies(&mut self, policies: Policies) {
+        self.broadcast(|| HandlerMessage::UpdatePolicies(Box::new(policies.clone())));
+        self.policies = policies;
+    }
+
+    /// Wait for every actor to handle all of its messages,
+    /// and return each one's state, along with how many states were lost.
+    pub fn finish(self) -> (Vec<State>, usize) {
+        lock(&self.scheduler.queue).stopping = true;
+        self.scheduler.ready.notify_all();
+        for handle in self.handles {
+            if let Err(err) = handle.join() {
+                tracing::error!("Failed to join actor thread: {:?}", err);
+            }
+        }
+
+        let mut states = Vec::with_capacity(self.actors.len());
+        let mut lost = 0;
+        for actor in self.actors.into_values() {
+            match Arc::try_unwrap(actor) {
+                Ok(actor) => states.push(
+                    actor
+                        .worker
+                        .into_inner()
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .finish(),
+                ),
+                // Only if a thread panicked while running the actor
+                Err(_) => {
+                    tracing::error!("Actor still running after its pool stopped");
+                    lost += 1;
+                }
+            }
+        }
+        (states, lost)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ActorPool;
+    use crate::pipeline::{HandlerMessage, PipelineConfig};
+    use crate::policy::Policies;
+    use crate::test_utils::deposit;
+    use crate::types::ClientId;
+
+    #[test]
+    fn test_each_client_in_order() {
+        let config = PipelineConfig {
+            ledger: true,
+            handler_queue_depth: 1,
+            ..Default::default()
+        };
+        let mut pool = ActorPool::spawn(3, &config, Policies::default(), None);
+        for tx_id in 1..=1000 {
+            let client_id = (tx_id % 7) as ClientId;
+            pool.send(
+                client_id,
+                HandlerMessage::Transaction(deposit(client_id, tx_id, 1.0)),
+            );
+        }
+
+        let (states, lost) = pool.finish();
+        assert_eq!((states.len(), lost), (7, 0));
+        for state in states {
+            let tx_ids: Vec<_> = state
+                .ledger
+                .unwrap()
+                .entries()
+                .iter()
+                .map(|entry| entry.tx)
+                .collect();
+            assert!(tx_ids.windows(2).all(|pair| pair[0] < pair[1]));
+            assert_eq!(state.accounts.len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_snapshot_and_subscribe() {
+        let mut pool = ActorPool::spawn(2, &PipelineConfig::default(), Policies::default(), None);
+        pool.send(1, HandlerMessage::Transaction(deposit(1, 1, 10.0)));
+        let (updates_snd, updates_rcv) = std::sync::mpsc::channel();
+        pool.subscribe(updates_snd);
+        // Started after subscribing, but still subscribed
+        pool.send(2, HandlerMessage::Transaction(deposit(2, 2, 5.0)));
+
+        let accounts = pool.collect(|state| state.accounts.len());
+        assert_eq!(accounts.iter().sum::<usize>(), 2);
+        pool.finish();
+        let updated: Vec<_> = updates_rcv.iter().map(|update| update.tx).collect();
+        assert_eq!(updated, vec![2]);
+    }
+}
@@ -0,0 +1,94 @@
+use std::io::{self, IsTerminal};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process;
+
+use structopt::StructOpt;
+use tracing_subscriber::EnvFilter;
+
+use payments_engine_example::config::EngineConfig;
+use payments_engine_example::server::{router, SharedState};
+use payments_engine_example::state::State;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "payments-engine-server",
+    about = "Serve the payments engine over HTTP, e.g. for integration testing"
+)]
+struct Opts {
+    /// Address to listen on.
+    #[structopt(long, default_value = "127.0.0.1:8080")]
+    address: SocketAddr,
+
+    /// TOML config file to read policies from, as for `payments-engine-example`.
+    /// Other settings don't apply to the server, and are ignored.
+    #[structopt(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// Address to serve gRPC on as well, sharing the same state.
+    #[cfg(feature = "grpc")]
+    #[structopt(long)]
+    grpc_address: Option<SocketAddr>,
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal())
+        .init();
+
+    let Opts {
+        address,
+        config,
+        #[cfg(feature = "grpc")]
+        grpc_address,
+    } = Opts::from_args();
+
+    let config = match &config {
+        Some(path) => match EngineConfig::from_file(path) {
+            Ok(config) => config,
+            Err(err) => {
+                tracing::error!("Could not read config from '{}': {}", path.display(), err);
+                process::exit(1);
+            }
+        },
+        None => EngineConfig::default(),
+    };
+    let state = SharedState::new(State::with_policies(config.policies));
+
+    #[cfg(feature = "grpc")]
+    if let Some(grpc_address) = grpc_address {
+        tokio::spawn(serve_grpc(grpc_address, state.clone()));
+    }
+
+    let listener = match tokio::net::TcpListener::bind(address).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            tracing::error!("Could not listen on {}: {}", address, err);
+            process::exit(1);
+        }
+    };
+    tracing::info!("Listening on {}", address);
+    if let Err(err) = axum::serve(listener, router(state)).await {
+        tracing::error!("Server error: {}", err);
+        process::exit(1);
+    }
+}
+
+/// Serve gRPC requests until the server fails.
+#[cfg(feature = "grpc")]
+async fn serve_grpc(address: SocketAddr, state: SharedState) {
+    use payments_engine_example::grpc::PaymentsService;
+
+    tracing::info!("Serving gRPC on {}", address);
+    let result = tonic::transport::Server::builder()
+        .add_service(PaymentsService::new(state).into_server())
+        .serve(address)
+        .await;
+    if let Err(err) = result {
+        tracing::error!("gRPC server error: {}", err);
+        process::exit(1);
+    }
+}
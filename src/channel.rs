@@ -0,0 +1,156 @@
+//! Bounded channels carrying transactions from the router to handler threads,
+//! backed by std's `sync_channel` by default, or with the `crossbeam` or `flume`
+//! features, by those crates' channels, which contend less with many handlers.
+
+use serde::Deserialize;
+use std::str::FromStr;
+use std::sync::mpsc::{self, RecvError, SendError};
+
+/// Implementation of the channels between the router and handler threads.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelBackend {
+    /// `std::sync::mpsc::sync_channel`
+    #[default]
+    Std,
+    /// `crossbeam_channel::bounded`
+    #[cfg(feature = "crossbeam")]
+    Crossbeam,
+    /// `flume::bounded`
+    #[cfg(feature = "flume")]
+    Flume,
+}
+
+impl FromStr for ChannelBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "std" => Ok(Self::Std),
+            #[cfg(feature = "crossbeam")]
+            "crossbeam" => Ok(Self::Crossbeam),
+            #[cfg(feature = "flume")]
+            "flume" => Ok(Self::Flume),
+            other => Err(match other {
+                "crossbeam" | "flume" => format!(
+                    "the {} channel backend requires building with `--features {}`",
+                    other, other
+                ),
+                _ => format!("unknown channel backend '{}'", other),
+            }),
+        }
+    }
+}
+
+/// Sending half of a bounded channel. Sending blocks while the channel is full.
+pub(crate) enum BoundedSender<T> {
+    Std(mpsc::SyncSender<T>),
+    #[cfg(feature = "crossbeam")]
+    Crossbeam(crossbeam_channel::Sender<T>),
+    #[cfg(feature = "flume")]
+    Flume(flume::Sender<T>),
+}
+
+/// Receiving half of a bounded channel.
+pub(crate) enum BoundedReceiver<T> {
+    Std(mpsc::Receiver<T>),
+    #[cfg(feature = "crossbeam")]
+    Crossbeam(crossbeam_channel::Receiver<T>),
+    #[cfg(feature = "flume")]
+    Flume(flume::Receiver<T>),
+}
+
+/// Create a channel holding up to `capacity` messages.
+pub(crate) fn bounded<T>(
+    backend: ChannelBackend,
+    capacity: usize,
+) -> (BoundedSender<T>, BoundedReceiver<T>) {
+    match backend {
+        ChannelBackend::Std => {
+            let (snd, rcv) = mpsc::sync_channel(capacity);
+            (BoundedSender::Std(snd), BoundedReceiver::Std(rcv))
+        }
+        #[cfg(feature = "crossbeam")]
+        ChannelBackend::Crossbeam => {
+            let (snd, rcv) = crossbeam_channel::bounded(capacity);
+            (
+                BoundedSender::Crossbeam(snd),
+                BoundedReceiver::Crossbeam(rcv),
+            )
+        }
+        #[cfg(feature = "flume")]
+        ChannelBackend::Flume => {
+            let (snd, rcv) = flume::bounded(capacity);
+            (BoundedSender::Flume(snd), BoundedReceiver::Flume(rcv))
+        }
+    }
+}
+
+impl<T> BoundedSender<T> {
+    /// Send a message, failing only if the receiver has hung up.
+    pub fn send(&self, message: T) -> Result<(), SendError<T>> {
+        match self {
+            Self::Std(snd) => snd.send(message),
+            #[cfg(feature = "crossbeam")]
+            Self::Crossbeam(snd) => snd.send(message).map_err(|err| SendError(err.0)),
+            #[cfg(feature = "flume")]
+            Self::Flume(snd) => snd.send(message).map_err(|err| SendError(err.0)),
+        }
+    }
+}
+
+impl<T> BoundedReceiver<T> {
+    /// Wait for a message, failing once every sender has hung up.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        match self {
+            Self::Std(rcv) => rcv.recv(),
+            #[cfg(feature = "crossbeam")]
+            Self::Crossbeam(rcv) => rcv.recv().map_err(|_| RecvError),
+            #[cfg(feature = "flume")]
+            Self::Flume(rcv) => rcv.recv().map_err(|_| RecvError),
+        }
+    }
+
+    /// Every message until every sender has hung up.
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        std::iter::from_fn(move || self.recv().ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bounded, ChannelBackend};
+    use std::thread;
+
+    fn backends() -> Vec<ChannelBackend> {
+        vec![
+            ChannelBackend::Std,
+            #[cfg(feature = "crossbeam")]
+            ChannelBackend::Crossbeam,
+            #[cfg(feature = "flume")]
+            ChannelBackend::Flume,
+        ]
+    }
+
+    #[test]
+    fn test_messages_arrive_in_order() {
+        for backend in backends() {
+            let (snd, rcv) = bounded(backend, 2);
+            let sender = thread::spawn(move || {
+                for i in 0..100 {
+                    snd.send(i).unwrap();
+                }
+            });
+            assert_eq!(rcv.iter().collect::<Vec<_>>(), (0..100).collect::<Vec<_>>());
+            sender.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_parse_backend() {
+        assert_eq!("std".parse(), Ok(ChannelBackend::Std));
+        assert!("carrier-pigeon".parse::<ChannelBackend>().is_err());
+        #[cfg(not(feature = "flume"))]
+        assert!("flume".parse::<ChannelBackend>().is_err());
+    }
+}
@@ -0,0 +1,751 @@
+//! Checkpoints of a run's progress through its input, so that an interrupted run
+//! can resume from where it left off rather than from the start of the file.
+//!
+//! A checkpoint is the position of the last record handled, along with
+//! everything the state needs to carry on: accounts, stored transactions,
+//! disputes, activity counted towards velocity limits, and scheduled transactions
+//! still waiting to take effect. Rejections,
+//! the ledger and other reports aren't included, so a resumed run only
+//! reports on the records it read itself.
+//!
+//! A run can also start from an earlier one without resuming its input,
+//! e.g. from a prior day's closing balances, with `read_initial_state`.
+
+use csv::StringRecord;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::mpsc::{Sender, SyncSender};
+
+use indexmap::IndexSet;
+use rustc_hash::FxHashMap;
+
+use crate::construct_csv_reader;
+use crate::currency::{Currency, CurrencyCode};
+use crate::headers::HeaderMapping;
+use crate::input::{report_unreadable, tagged_records, InputOrder, RecordSource, TaggedRecord};
+use crate::limits::ActivityState;
+use crate::policy::{Policies, TxIdScope};
+use crate::schedule::Schedule;
+use crate::source::ParseError;
+use crate::state::{DisputeDetails, State};
+use crate::types::{Account, AccountKey, ClientId, Deposit, Timestamp, TransactionContainer};
+use crate::types::{LockInfo, Reversal};
+use crate::types::{TransactionError, TransactionId, TransactionType, Withdrawal};
+use crate::verify::read_balances;
+
+/// Read the state to start a run from: either closing balances, as written by
+/// an earlier run, or a whole checkpoint, as JSON, whose position is ignored.
+/// Balances alone leave any held funds held, since the disputes holding them
+/// aren't known, whereas a checkpoint's transactions may still be disputed.
+pub fn read_initial_state<P: AsRef<Path>>(
+    path: P,
+    policies: Policies,
+) -> Result<State, Box<dyn Error>> {
+    let path = path.as_ref();
+    if path.extension().is_some_and(|ext| ext == "json") {
+        return Checkpoint::read(path)?.into_state(policies);
+    }
+
+    let mut state = State::with_policies(policies);
+    for record in read_balances(fs::File::open(path)?)? {
+        let key = (record.client, record.currency);
+        if record.available + record.held != record.total {
+            return Err(format!(
+                "balances of client {} don't add up to their total",
+                record.client
+            )
+            .into());
+        }
+        if state.accounts.get(key.0, key.1).is_some() {
+            return Err(format!(
+                "client {} has more than one row for a currency",
+                record.client
+            )
+            .into());
+        }
+        let account = Account {
+            available: record.available,
+            held: record.held,
+            locked: record.locked,
+            ..Default::default()
+        };
+        state.accounts.insert(key, account);
+    }
+    Ok(state)
+}
+
+/// Where a record starts in its input file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct InputPosition {
+    /// Byte offset from the start of the file
+    pub byte: u64,
+    /// Line number, starting from 1 for the header row
+    pub line: u64,
+    /// Record number, starting from 0 for the header row
+    pub record: u64,
+}
+
+impl From<&csv::Position> for InputPosition {
+    fn from(position: &csv::Position) -> Self {
+        Self {
+            byte: position.byte(),
+            line: position.line(),
+            record: position.record(),
+        }
+    }
+}
+
+impl From<InputPosition> for csv::Position {
+    fn from(position: InputPosition) -> Self {
+        let mut csv_position = csv::Position::new();
+        csv_position
+            .set_byte(position.byte)
+            .set_line(position.line)
+            .set_record(position.record);
+        csv_position
+    }
+}
+
+/// An account's balances and status.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+struct AccountEntry {
+    client: ClientId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    currency: Option<CurrencyCode>,
+    available: Currency,
+    held: Currency,
+    locked: bool,
+    fees: Currency,
+    flagged: bool,
+    /// Why and when the account was locked, if it's known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    lock_info: Option<LockInfo>,
+}
+
+/// A stored deposit or withdrawal, whether it succeeded or failed, or reversal.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+struct TransactionEntry {
+    client: ClientId,
+    tx: TransactionId,
+    #[serde(rename = "type")]
+    transaction_type: TransactionType,
+    /// Amount, timestamp and currency are only kept for transactions which succeeded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    amount: Option<Currency>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timestamp: Option<Timestamp>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    currency: Option<CurrencyCode>,
+    /// Transaction undone, for a reversal
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reverses: Option<TransactionId>,
+    /// Why the transaction failed, if it did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<TransactionError>,
+    /// Whether another client was first to use the id, if ids are unique globally
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    duplicate: bool,
+    /// Whether it was charged back, and not represented since
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    charged_back: bool,
+}
+
+/// A dispute, either active or settled.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+struct DisputeEntry {
+    client: ClientId,
+    tx: TransactionId,
+    /// Amount held, while the dispute is active
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    held: Option<Currency>,
+    /// Reason, representment and history
+    #[serde(flatten)]
+    details: DisputeDetails,
+    /// When the disputed transaction occurred, if the settled dispute
+    /// is to be forgotten once the transaction is too old to dispute
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_from: Option<Timestamp>,
+}
+
+/// A run's progress: the position of the last record handled,
+/// and the state after handling it.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Checkpoint {
+    /// Position of the last record handled, which is skipped when resuming
+    pub position: InputPosition,
+    scope: TxIdScope,
+    /// Latest timestamp seen while compacting settled disputes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    latest: Option<Timestamp>,
+    accounts: Vec<AccountEntry>,
+    transactions: Vec<TransactionEntry>,
+    disputes: Vec<DisputeEntry>,
+    /// Each client's activity counted towards velocity limits, if any
+    #[serde(default, skip_serializing_if = "ActivityState::is_empty")]
+    activity: ActivityState,
+    /// Scheduled transactions held until they take effect, if any
+    #[serde(default, skip_serializing_if = "Schedule::is_empty")]
+    pub(crate) schedule: Schedule,
+}
+
+impl Checkpoint {
+    /// Capture a state as of the record at `position`.
+    pub fn from_state(state: &State, position: InputPosition) -> Self {
+        let accounts = state
+            .accounts
+            .iter()
+            .map(|(&(client, currency), account)| AccountEntry {
+                client,
+                currency,
+                available: account.available,
+                held: account.held,
+                locked: account.locked,
+                fees: account.fees,
+                flagged: account.flagged,
+                lock_info: account.lock_info.clone(),
+            })
+            .collect();
+
+        let transactions = &state.transactions;
+        let scope = transactions.scope();
+        let transactions = transactions
+            .iter()
+            .filter_map(|(client, tx)| {
+                let container = transactions.get(client, tx)?;
+                let mut entry = TransactionEntry {
+                    client,
+                    tx,
+                    transaction_type: container.tx_type(),
+                    amount: None,
+                    timestamp: None,
+                    currency: None,
+                    reverses: None,
+                    error: None,
+                    duplicate: scope == TxIdScope::Global
+                        && transactions.client_of(tx) != Some(client),
+                    charged_back: transactions.is_charged_back(client, tx),
+                };
+                match container {
+                    TransactionContainer::Deposit(Ok(Deposit {
+                        amount,
+                        timestamp,
+                        currency,
+                        ..
+                    }))
+                    | TransactionContainer::Withdrawal(Ok(Withdrawal {
+                        amount,
+                        timestamp,
+                        currency,
+                        ..
+                    })) => {
+                        entry.amount = Some(*amount);
+                        entry.timestamp = *timestamp;
+                        entry.currency = *currency;
+                    }
+                    TransactionContainer::Deposit(Err(err))
+                    | TransactionContainer::Withdrawal(Err(err)) => {
+                        entry.error = Some(err.clone());
+                    }
+                    TransactionContainer::Reversal(reversal) => {
+                        entry.timestamp = reversal.timestamp;
+                        entry.currency = reversal.currency;
+                        entry.reverses = Some(reversal.reverses);
+                    }
+                }
+                Some(entry)
+            })
+            .collect();
+
+        let disputes = &state.disputes;
+        let expiries: FxHashMap<_, _> = disputes
+            .expiries()
+            .map(|(occurred_at, client, tx)| ((client, tx), occurred_at))
+            .collect();
+        let entry = |client, tx, held, expires_from| DisputeEntry {
+            client,
+            tx,
+            held,
+            details: disputes.details(client, tx).cloned().unwrap_or_default(),
+            expires_from,
+        };
+        let active = disputes
+            .active()
+            .map(|(client, tx, amount)| entry(client, tx, Some(amount), None));
+        let settled = disputes
+            .settled()
+            .map(|(client, tx)| entry(client, tx, None, expiries.get(&(client, tx)).copied()));
+
+        Self {
+            position,
+            scope,
+            latest: disputes.latest(),
+            accounts,
+            transactions,
+            disputes: active.chain(settled).collect(),
+            activity: state.activity.clone(),
+            schedule: state.schedule.clone(),
+        }
+    }
+
+    /// Add another state's checkpoint with different clients to this one,
+    /// e.g. from another handler thread.
+    pub(crate) fn extend(&mut self, other: Checkpoint) {
+        self.latest = self.latest.max(other.latest);
+        self.accounts.extend(other.accounts);
+        self.transactions.extend(other.transactions);
+        self.disputes.extend(other.disputes);
+        self.activity.absorb(other.activity);
+        self.schedule.extend(other.schedule);
+    }
+
+    /// Put accounts in the order their keys were first seen.
+    /// Accounts which weren't seen go last.
+    pub(crate) fn sort_by_first_seen(&mut self, first_seen: &IndexSet<AccountKey>) {
+        self.accounts.sort_by_cached_key(|entry| {
+            first_seen
+                .get_index_of(&(entry.client, entry.currency))
+                .unwrap_or(usize::MAX)
+        });
+    }
+
+    /// Restore the state, to handle further transactions with the given policies.
+    /// Transaction ids must be unique among the same scope as when it was captured.
+    pub fn into_state(self, policies: Policies) -> Result<State, Box<dyn Error>> {
+        if policies.validation.tx_id_scope != self.scope {
+            return Err(format!(
+                "checkpoint has transaction ids unique per {:?}, not {:?}",
+                self.scope, policies.validation.tx_id_scope
+            )
+            .into());
+        }
+        let mut state = State::with_policies(policies);
+
+        for entry in self.accounts {
+            let account = Account {
+                available: entry.available,
+                held: entry.held,
+                locked: entry.locked,
+                fees: entry.fees,
+                flagged: entry.flagged,
+                lock_info: entry.lock_info,
+            };
+            state
+                .accounts
+                .insert((entry.client, entry.currency), account);
+        }
+
+        for entry in self.transactions {
+            let TransactionEntry {
+                client: client_id,
+                tx: tx_id,
+                timestamp,
+                currency,
+                ..
+            } = entry;
+            if let (TransactionType::Reversal, Some(reverses)) =
+                (&entry.transaction_type, entry.reverses)
+            {
+                let reversal = Reversal {
+                    client_id,
+                    tx_id,
+                    reverses,
+                    timestamp,
+                    currency,
+                };
+                let container = TransactionContainer::Reversal(reversal);
+                state
+                    .transactions
+                    .restore(client_id, tx_id, container, !entry.duplicate);
+                continue;
+            }
+            let result = match (entry.error, entry.amount) {
+                (Some(err), _) => Err(err),
+                (None, Some(amount)) => Ok(amount),
+                (None, None) => {
+                    return Err(
+                        format!("transaction {} has neither amount nor error", tx_id).into(),
+                    )
+                }
+            };
+            let container = match entry.transaction_type {
+                TransactionType::Deposit => {
+                    TransactionContainer::Deposit(result.map(|amount| Deposit {
+                        client_id,
+                        tx_id,
+                        amount,
+                        timestamp,
+                        currency,
+                    }))
+                }
+                TransactionType::Withdrawal => {
+                    TransactionContainer::Withdrawal(result.map(|amount| Withdrawal {
+                        client_id,
+                        tx_id,
+                        amount,
+                        timestamp,
+                        currency,
+                    }))
+                }
+                other => {
+                    return Err(format!("transaction {} can't be a {}", tx_id, other.name()).into())
+                }
+            };
+            state
+                .transactions
+                .restore(client_id, tx_id, container, !entry.duplicate);
+            if entry.charged_back {
+                state.transactions.record_chargeback(client_id, tx_id);
+            }
+        }
+
+        for entry in self.disputes {
+            match entry.held {
+                Some(amount) => {
+                    state
+                        .disputes
+                        .dispute_tx(entry.client, entry.tx, amount, None, None)?
+                }
+                None => state.disputes.restore_settled(entry.client, entry.tx),
+            }
+            state
+                .disputes
+                .restore_details(entry.client, entry.tx, entry.details);
+            if let Some(occurred_at) = entry.expires_from {
+                state
+                    .disputes
+                    .schedule_expiry(entry.client, entry.tx, occurred_at);
+            }
+        }
+        state.disputes.restore_latest(self.latest);
+        state.activity = self.activity;
+        state.schedule = self.schedule;
+
+        Ok(state)
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    /// Write the checkpoint to a temporary file next to `path`,
+    /// then move it into place, so that a crash while writing
+    /// never leaves a partial checkpoint behind.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let tmp_path = path.with_extension("tmp");
+        let mut file = io::BufWriter::new(fs::File::create(&tmp_path)?);
+        serde_json::to_writer(&mut file, self)?;
+        io::Write::flush(&mut file)?;
+        fs::rename(&tmp_path, path)
+    }
+
+    /// Open the input file the checkpoint was taken from,
+    /// to read the records after the last one handled.
+    pub fn open_input<P: AsRef<Path>>(&self, path: P) -> io::Result<ResumedInput> {
+        Ok(ResumedInput {
+            file: fs::File::open(path)?,
+            after: self.position,
+            headers: HeaderMapping::default(),
+        })
+    }
+}
+
+/// A single uncompressed CSV file, read from just after a checkpoint.
+///
+/// Records keep their positions in the file, so further checkpoints
+/// can be taken while reading it.
+pub struct ResumedInput {
+    file: fs::File,
+    /// Position of the last record already handled
+    after: InputPosition,
+    headers: HeaderMapping,
+}
+
+impl ResumedInput {
+    /// Read columns under other names, as given by `headers`.
+    pub fn with_header_mapping(mut self, headers: HeaderMapping) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    fn read_records_inner(
+        self,
+        headers_snd: SyncSender<Vec<StringRecord>>,
+        records_snd: SyncSender<Vec<TaggedRecord>>,
+        errors_snd: Sender<ParseError>,
+        batch_size: usize,
+        notrim: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut reader = construct_csv_reader(self.file, notrim, self.headers.has_header_row());
+        let headers = self.headers.read_headers(&mut reader)?;
+        reader.seek(self.after.into())?;
+        // Already handled before the checkpoint
+        reader.read_record(&mut StringRecord::new())?;
+
+        let headers = vec![headers];
+        let mut records_iter =
+            tagged_records(vec![reader], &headers, InputOrder::Sequential, errors_snd);
+        headers_snd.send(headers)?;
+
+        loop {
+            let batch: Vec<_> = (&mut records_iter).take(batch_size).collect();
+            if batch.is_empty() {
+                return Ok(());
+            }
+            records_snd.send(batch)?;
+        }
+    }
+}
+
+impl RecordSource for ResumedInput {
+    fn num_inputs(&self) -> usize {
+        1
+    }
+
+    fn read_records(
+        self,
+        headers_snd: SyncSender<Vec<StringRecord>>,
+        records_snd: SyncSender<Vec<TaggedRecord>>,
+        errors_snd: Sender<ParseError>,
+        batch_size: usize,
+        notrim: bool,
+    ) {
+        let result = self.read_records_inner(
+            headers_snd,
+            records_snd,
+            errors_snd.clone(),
+            batch_size,
+            notrim,
+        );
+        if let Err(err) = result {
+            report_unreadable(ParseError::new(None, err), &errors_snd);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_initial_state, Checkpoint, InputPosition};
+    use crate::control::Control;
+    use crate::handlers::handle_transaction;
+    use crate::pipeline::{PipelineConfig, ShardedHandler};
+    use crate::policy::Policies;
+    use crate::state::AccountOrder;
+    use crate::state::State;
+    use crate::test_utils::{
+        chargeback, deposit, dispute, representment, resolve, reversal, withdrawal,
+    };
+    use crate::types::{ClientId, Currency, DisputeReason};
+    use crate::{resume_inputs, run_inputs, write_balances};
+    use std::env;
+    use std::fs;
+    use std::io::{self, Write};
+    use std::time::Duration;
+
+    /// A checkpoint's contents in a fixed order, since states iterate in no particular order.
+    fn sorted(mut checkpoint: Checkpoint) -> Checkpoint {
+        checkpoint.transactions.sort_by_key(|entry| entry.tx);
+        checkpoint.disputes.sort_by_key(|entry| entry.tx);
+        checkpoint
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut policies = Policies::default();
+        policies.dispute.max_age = Some(Duration::from_secs(100));
+        policies.dispute.compact_settled = true;
+        policies.allow_admin = true;
+        let mut state = State::with_policies(policies.clone());
+        let records = vec![
+            deposit(1, 1, 10.0).with_timestamp(0),
+            deposit(2, 2, 5.0).with_timestamp(10),
+            // Fails, but keeps its id and error
+            withdrawal(2, 3, 50.0).with_timestamp(20),
+            dispute(1, 1).with_timestamp(30),
+            resolve(1, 1).with_timestamp(40),
+            dispute(2, 2).with_timestamp(50),
+            deposit(1, 4, 3.0).with_timestamp(55),
+            dispute(1, 4)
+                .with_reason(DisputeReason::Fraud)
+                .with_timestamp(60),
+            chargeback(1, 4).with_timestamp(70),
+            deposit(2, 5, 1.0),
+            reversal(2, 6, 5),
+        ];
+        for record in records {
+            let _ = state.handle(record);
+        }
+
+        let position = InputPosition {
+            byte: 123,
+            line: 7,
+            record: 6,
+        };
+        let checkpoint = Checkpoint::from_state(&state, position);
+        let json = serde_json::to_string(&checkpoint).unwrap();
+        let restored = serde_json::from_str::<Checkpoint>(&json)
+            .unwrap()
+            .into_state(policies)
+            .unwrap();
+
+        assert_eq!(
+            sorted(Checkpoint::from_state(&restored, position)),
+            sorted(checkpoint)
+        );
+        assert_eq!(restored.digest(), state.digest());
+        for client_id in 1..=2 {
+            let history = |state: &State| {
+                state
+                    .history(client_id)
+                    .map(|entry| {
+                        (
+                            entry.tx,
+                            entry.transaction.outcome().is_ok(),
+                            entry.dispute_status,
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(history(&restored), history(&state));
+        }
+        assert_eq!(restored.disputes.latest(), Some(70));
+        assert_eq!(restored.disputes.reason(1, 4), Some(DisputeReason::Fraud));
+        assert_eq!(restored.transactions.reversal_of(2, 5), Some(6));
+        assert!(restored.transactions.is_charged_back(1, 4));
+
+        // Carries on as the original would
+        let mut state = restored;
+        assert!(state.handle(deposit(3, 3, 1.0)).is_err());
+        assert!(state.handle(chargeback(2, 2)).is_ok());
+        assert!(state.handle(representment(1, 4)).is_ok());
+        assert!(!state.transactions.is_charged_back(1, 4));
+    }
+
+    #[test]
+    fn test_scope_must_match() {
+        let checkpoint = Checkpoint::from_state(&State::new(), InputPosition::default());
+        let mut policies = Policies::default();
+        policies.validation.tx_id_scope = crate::policy::TxIdScope::Client;
+        assert!(checkpoint.into_state(policies).is_err());
+    }
+
+    #[test]
+    fn test_checkpoint_while_running() {
+        let config = PipelineConfig {
+            handler_threads: 3,
+            ..Default::default()
+        };
+        let mut handler = ShardedHandler::spawn(&config, Policies::default(), None);
+        for tx_id in 1..=100 {
+            let client_id = (tx_id % 7) as ClientId;
+            handler.dispatch(deposit(client_id, tx_id, 1.0)).unwrap();
+        }
+        let checkpoint = handler.checkpoint(InputPosition::default());
+        let state = handler.finish();
+
+        let restored = checkpoint.into_state(Policies::default()).unwrap();
+        assert_eq!(restored.digest(), state.digest());
+        // In the order accounts first appeared
+        let clients: Vec<_> = restored.accounts.iter().map(|(key, _)| key.0).collect();
+        assert_eq!(clients, vec![1, 2, 3, 4, 5, 6, 0]);
+    }
+
+    #[test]
+    fn test_resume_matches_full_run() {
+        let dir = env::temp_dir();
+        let input_path = dir.join(format!("checkpoint-input-{}.csv", std::process::id()));
+        let checkpoint_path = dir.join(format!("checkpoint-{}.json", std::process::id()));
+        let header = "type, client, tx, amount\n";
+        let first = "deposit, 1, 1, 10.0\ndeposit, 2, 2, 5.0\ndispute, 1, 1,\n";
+        let rest =
+            "resolve, 1, 1,\nwithdrawal, 1, 3, 4.0\ndeposit, 2, 2, 1.0\nwithdrawal, 2, 4, 3.0\n";
+        let config = PipelineConfig {
+            batch_size: 1,
+            ..Default::default()
+        };
+
+        let mut input = fs::File::create(&input_path).unwrap();
+        input
+            .write_all(format!("{}{}", header, first).as_bytes())
+            .unwrap();
+        let control = Control::new(None, None, None)
+            .with_checkpoints(checkpoint_path.clone(), Duration::ZERO);
+        let inputs = crate::input::Inputs::single(fs::File::open(&input_path).unwrap());
+        run_inputs(inputs, config, Policies::default(), None, Some(control));
+
+        input.write_all(rest.as_bytes()).unwrap();
+        let checkpoint = Checkpoint::read(&checkpoint_path).unwrap();
+        assert_eq!(checkpoint.position.line, 4);
+        let inputs = checkpoint.open_input(&input_path).unwrap();
+        let initial = checkpoint.into_state(Policies::default()).unwrap();
+        let resumed = resume_inputs(inputs, initial, None, config, None, None);
+
+        let whole = format!("{}{}{}", header, first, rest);
+        let full = run_inputs(
+            crate::input::Inputs::single(io::Cursor::new(whole)),
+            config,
+            Policies::default(),
+            None,
+            None,
+        );
+        assert_eq!(resumed.digest(), full.digest());
+        // Only the duplicate deposit after the checkpoint
+        assert_eq!(resumed.rejections.len(), 1);
+        assert_eq!(resumed.rejections, full.rejections);
+
+        fs::remove_file(&input_path).unwrap();
+        fs::remove_file(&checkpoint_path).unwrap();
+    }
+
+    #[test]
+    fn test_initial_state_from_balances() {
+        let mut state = State::new();
+        for tx in [deposit(1, 1, 10.0), deposit(2, 2, 5.0), dispute(2, 2)] {
+            handle_transaction(tx, &mut state).unwrap();
+        }
+        let path = env::temp_dir().join(format!("closing-{}.csv", std::process::id()));
+        write_balances(
+            &state,
+            AccountOrder::Client,
+            fs::File::create(&path).unwrap(),
+        );
+
+        // Balances carry over, but not the transactions behind them
+        let mut initial = read_initial_state(&path, Policies::default()).unwrap();
+        assert_eq!(initial.accounts, state.accounts);
+        let resolve = resolve(2, 2);
+        assert!(handle_transaction(resolve, &mut initial).is_err());
+
+        fs::write(
+            &path,
+            "client,available,held,total,locked\n1,1.0,0.0,2.0,false\n",
+        )
+        .unwrap();
+        assert!(read_initial_state(&path, Policies::default()).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_initial_state_from_checkpoint() {
+        let mut state = State::new();
+        for tx in [deposit(1, 1, 10.0), dispute(1, 1)] {
+            handle_transaction(tx, &mut state).unwrap();
+        }
+        let path = env::temp_dir().join(format!("initial-{}.json", std::process::id()));
+        Checkpoint::from_state(&state, InputPosition::default())
+            .write(&path)
+            .unwrap();
+
+        // The dispute can still be resolved
+        let mut initial = read_initial_state(&path, Policies::default()).unwrap();
+        let resolve = resolve(1, 1);
+        handle_transaction(resolve, &mut initial).unwrap();
+        let account = initial.accounts.get(1, None).unwrap();
+        assert_eq!(account.available, Currency::from(10.0));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,169 @@
+use serde::Deserialize;
+use std::error::Error;
+use std::fs;
+use std::num::NonZeroUsize;
+use std::path::Path;
+
+use crate::channel::ChannelBackend;
+use crate::filter::ClientRange;
+use crate::headers::HeaderMapping;
+use crate::pipeline::ExecutionMode;
+use crate::policy::Policies;
+use crate::state::AccountOrder;
+
+/// Engine settings, which can be read from a TOML file
+/// rather than passed one flag at a time. Settings left out
+/// take their usual defaults, and command line flags take precedence.
+///
+/// ```toml
+/// batch_size = 5000
+/// deserialize_workers = 4
+/// handler_threads = 8
+/// batch_buffer = 4
+/// handler_queue_depth = 1000
+/// notrim = true
+/// merge_by_timestamp = true
+/// max_in_flight = 100
+/// clients = "100-200"
+/// output_order = "insertion"
+/// strict = true
+///
+/// [headers]
+/// txn_type = "type"
+/// customer = "client"
+/// transaction_id = "tx"
+///
+/// [policies]
+/// allow_admin = true
+///
+/// [policies.dispute]
+/// max_age_secs = 7776000
+///
+/// [policies.rounding]
+/// mode = "half_even"
+/// precision = 2
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct EngineConfig {
+    /// Batch size for parallel CSV deserialization
+    pub batch_size: Option<usize>,
+    /// Number of threads to dedicate to deserialization
+    pub deserialize_workers: Option<usize>,
+    /// Number of threads handling transactions
+    pub handler_threads: Option<usize>,
+    /// Maximum number of batches read ahead of deserialization
+    pub batch_buffer: Option<usize>,
+    /// Maximum number of transactions waiting in each handler's queue
+    pub handler_queue_depth: Option<usize>,
+    /// Implementation of the handlers' queues
+    pub channel: Option<ChannelBackend>,
+    /// How handler threads divide up clients
+    pub execution: Option<ExecutionMode>,
+    /// Maximum number of transactions to dispatch per second
+    pub rate: Option<f64>,
+    /// Only handle transactions from this range of clients, e.g. `"100-200"`
+    pub clients: Option<ClientRange>,
+    /// Disable trimming whitespace from CSV records
+    pub notrim: bool,
+    /// Inputs have no header row, only `type,client,tx,amount` columns
+    pub no_headers: bool,
+    /// Read amounts written in any locale, e.g. `1.234,56`
+    pub localized_amounts: bool,
+    /// Interleave multiple inputs by their `timestamp` column
+    pub merge_by_timestamp: bool,
+    /// Memory-map input files and parse them in parallel
+    pub mmap: bool,
+    /// Maximum number of transactions per client waiting to be handled
+    pub max_in_flight: Option<NonZeroUsize>,
+    /// Reject transactions beyond `max_in_flight` instead of waiting
+    pub reject_overflow: bool,
+    /// Order of accounts in the output
+    pub output_order: Option<AccountOrder>,
+    /// Fail the run if any transaction is rejected or any row can't be read
+    pub strict: bool,
+    /// Other names for columns, e.g. `customer = "client"`
+    pub headers: HeaderMapping,
+    /// Initial policies, in the same format as a policy file
+    pub policies: Policies,
+}
+
+impl EngineConfig {
+    /// Read settings from a TOML file.
+    /// Any missing values take their defaults.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read_to_string(path)?;
+        Ok(toml::from_str(&contents)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EngineConfig;
+    use crate::currency::RoundingMode;
+    use crate::filter::ClientRange;
+    use crate::headers::{HeaderAlias, HeaderMapping};
+    use crate::policy::{Policies, RoundingPolicy};
+    use crate::state::AccountOrder;
+
+    #[test]
+    fn test_parse_config() {
+        let config: EngineConfig = toml::from_str(
+            r#"
+            batch_size = 5000
+            notrim = true
+            clients = "100-200"
+            output_order = "insertion"
+
+            [headers]
+            customer = "client"
+
+            [policies]
+            allow_admin = true
+
+            [policies.rounding]
+            mode = "half_even"
+            precision = 2
+            "#,
+        )
+        .unwrap();
+
+        let expected = EngineConfig {
+            batch_size: Some(5000),
+            notrim: true,
+            clients: Some(ClientRange::new(100, 200).unwrap()),
+            output_order: Some(AccountOrder::Insertion),
+            headers: {
+                let mut headers = HeaderMapping::default();
+                headers.insert(HeaderAlias::new("customer", "client").unwrap());
+                headers
+            },
+            policies: Policies {
+                allow_admin: true,
+                rounding: RoundingPolicy {
+                    mode: RoundingMode::HalfEven,
+                    precision: 2,
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn test_parse_unknown_header_column() {
+        assert!(toml::from_str::<EngineConfig>("[headers]\ncustomer = \"customer\"").is_err());
+    }
+
+    #[test]
+    fn test_parse_zero_max_in_flight() {
+        assert!(toml::from_str::<EngineConfig>("max_in_flight = 0").is_err());
+    }
+
+    #[test]
+    fn test_parse_empty_config() {
+        let config: EngineConfig = toml::from_str("").unwrap();
+        assert_eq!(config, EngineConfig::default());
+    }
+}
@@ -0,0 +1,337 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::checkpoint::{Checkpoint, InputPosition};
+use crate::pipeline::ShardedHandler;
+use crate::policy::Policies;
+use crate::state::{AccountOrder, State};
+use crate::write_accounts;
+
+/// How often to check whether a paused engine should resume.
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Commands which may be written to the control file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ControlCommand {
+    Run,
+    Pause,
+}
+
+/// Lets an operator adjust a running engine without killing the process.
+///
+/// The control file is checked between batches. If it contains `pause`,
+/// ingestion stops, a snapshot of the current balances is written
+/// (if a snapshot path is configured), and the engine waits until the
+/// file no longer says `pause`. A missing or empty file means `run`.
+///
+/// The policy file is also checked between batches, and whenever it
+/// changes, the new policies apply to all subsequent transactions.
+/// If the new file can't be parsed, the current policies stay in effect.
+///
+/// With `with_periodic_output`, the current balances are also rewritten
+/// every so often, e.g. while following a file which may never end.
+/// With `with_checkpoints`, a checkpoint is written every so often,
+/// and once more when the run finishes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Control {
+    pub control_file: Option<PathBuf>,
+    pub snapshot_path: Option<PathBuf>,
+    pub policy_file: Option<PathBuf>,
+    /// When the policy file was last modified, as of the last check
+    policy_modified: Option<SystemTime>,
+    periodic_output: Option<PeriodicOutput>,
+    checkpoints: Option<PeriodicCheckpoint>,
+    /// Whether balances written are given a `lock_reason` column
+    lock_reasons: bool,
+}
+
+/// Balances rewritten every `interval` while running.
+#[derive(Clone, Debug, PartialEq)]
+struct PeriodicOutput {
+    path: PathBuf,
+    order: AccountOrder,
+    interval: Duration,
+    /// When the balances were last written, and how many transactions they included
+    last_written: Option<(Instant, usize)>,
+}
+
+/// Checkpoint rewritten every `interval` while running.
+#[derive(Clone, Debug, PartialEq)]
+struct PeriodicCheckpoint {
+    path: PathBuf,
+    interval: Duration,
+    /// When the checkpoint was last written, and how many transactions it included
+    last_written: Option<(Instant, usize)>,
+}
+
+/// Whether something last written at `last_written` should be rewritten,
+/// because `interval` has passed and there have been new transactions since.
+fn is_due(last_written: Option<(Instant, usize)>, interval: Duration, dispatched: usize) -> bool {
+    match last_written {
+        Some((written_at, written_dispatched)) => {
+            written_at.elapsed() >= interval && written_dispatched != dispatched
+        }
+        None => true,
+    }
+}
+
+impl Control {
+    pub fn new(
+        control_file: Option<PathBuf>,
+        snapshot_path: Option<PathBuf>,
+        policy_file: Option<PathBuf>,
+    ) -> Self {
+        // Policies are loaded from the file at startup,
+        // so there's no need to reload them until it changes.
+        let policy_modified = policy_file.as_deref().and_then(modified_time);
+        Self {
+            control_file,
+            snapshot_path,
+            policy_file,
+            policy_modified,
+            periodic_output: None,
+            checkpoints: None,
+            lock_reasons: false,
+        }
+    }
+
+    /// Give snapshots and periodically rewritten balances a `lock_reason` column.
+    pub fn with_lock_reasons(mut self) -> Self {
+        self.lock_reasons = true;
+        self
+    }
+
+    /// Also write a checkpoint to `path` every `interval`, as long as there have
+    /// been new transactions since the last, and once more when finished.
+    /// Only for a single uncompressed input file, read with `Inputs` or `ResumedInput`,
+    /// since a checkpoint records the position of the last record in that file.
+    pub fn with_checkpoints(mut self, path: PathBuf, interval: Duration) -> Self {
+        self.checkpoints = Some(PeriodicCheckpoint {
+            path,
+            interval,
+            last_written: None,
+        });
+        self
+    }
+
+    /// Also rewrite the current balances to `path` every `interval`,
+    /// as long as there have been new transactions since they were last written.
+    pub fn with_periodic_output(
+        mut self,
+        path: PathBuf,
+        order: AccountOrder,
+        interval: Duration,
+    ) -> Self {
+        self.periodic_output = Some(PeriodicOutput {
+            path,
+            order,
+            interval,
+            last_written: None,
+        });
+        self
+    }
+
+    /// Read the current command from the control file.
+    pub fn read_command(&self) -> ControlCommand {
+        let control_file = match &self.control_file {
+            Some(control_file) => control_file,
+            None => return ControlCommand::Run,
+        };
+
+        match fs::read_to_string(control_file) {
+            Ok(contents) => match contents.trim() {
+                "pause" => ControlCommand::Pause,
+                "run" | "resume" | "" => ControlCommand::Run,
+                other => {
+                    tracing::warn!("Ignoring unknown control command '{}'", other);
+                    ControlCommand::Run
+                }
+            },
+            Err(_) => ControlCommand::Run,
+        }
+    }
+
+    /// Write the current balances to the snapshot path, if any.
+    /// The snapshot is written to a temporary file first,
+    /// so readers never see a partial snapshot.
+    fn write_snapshot(&self, handler: &ShardedHandler) -> io::Result<()> {
+        if let Some(path) = &self.snapshot_path {
+            write_balances_atomically(handler, path, AccountOrder::Client, self.lock_reasons)?;
+            tracing::info!("Wrote snapshot to '{}'", path.display());
+        }
+        Ok(())
+    }
+
+    /// Check for operator input, to be called between batches,
+    /// with the position of the last record dispatched, if any.
+    pub(crate) fn poll(&mut self, handler: &mut ShardedHandler, position: Option<InputPosition>) {
+        self.pause_if_requested(handler);
+        self.reload_policies_if_changed(handler);
+        self.rewrite_output_if_due(handler);
+        if let Some(position) = position {
+            self.write_checkpoint_if_due(handler, position);
+        }
+    }
+
+    /// Write a final checkpoint, if checkpointing, once every record up to
+    /// and including the one at `position` has been handled.
+    pub(crate) fn finish(&self, state: &State, position: Option<InputPosition>) {
+        if let (Some(checkpoints), Some(position)) = (&self.checkpoints, position) {
+            write_checkpoint(&Checkpoint::from_state(state, position), &checkpoints.path);
+        }
+    }
+
+    /// Write a checkpoint if one is due, and there's anything new to write.
+    fn write_checkpoint_if_due(&mut self, handler: &ShardedHandler, position: InputPosition) {
+        let checkpoints = match &mut self.checkpoints {
+            Some(checkpoints) => checkpoints,
+            None => return,
+        };
+        let dispatched = handler.dispatched();
+        if !is_due(checkpoints.last_written, checkpoints.interval, dispatched) {
+            return;
+        }
+
+        write_checkpoint(&handler.checkpoint(position), &checkpoints.path);
+        checkpoints.last_written = Some((Instant::now(), dispatched));
+    }
+
+    /// Rewrite the periodic output if it's due, and there's anything new to write.
+    fn rewrite_output_if_due(&mut self, handler: &ShardedHandler) {
+        let output = match &mut self.periodic_output {
+            Some(output) => output,
+            None => return,
+        };
+        let dispatched = handler.dispatched();
+        if !is_due(output.last_written, output.interval, dispatched) {
+            return;
+        }
+
+        let lock_reasons = self.lock_reasons;
+        match write_balances_atomically(handler, &output.path, output.order, lock_reasons) {
+            Ok(()) => tracing::debug!(
+                "Rewrote balances to '{}' after {} transactions",
+                output.path.display(),
+                dispatched
+            ),
+            Err(err) => tracing::error!(
+                "Failed to rewrite balances to '{}': {}",
+                output.path.display(),
+                err
+            ),
+        }
+        output.last_written = Some((Instant::now(), dispatched));
+    }
+
+    /// Block for as long as the control file requests a pause.
+    fn pause_if_requested(&self, handler: &ShardedHandler) {
+        if self.read_command() != ControlCommand::Pause {
+            return;
+        }
+
+        tracing::info!("Pausing ingestion");
+        if let Err(err) = self.write_snapshot(handler) {
+            tracing::error!("Failed to write snapshot: {}", err);
+        }
+
+        while self.read_command() == ControlCommand::Pause {
+            thread::sleep(PAUSE_POLL_INTERVAL);
+        }
+        tracing::info!("Resuming ingestion");
+    }
+
+    /// Switch the handler to new policies if the policy file has changed.
+    fn reload_policies_if_changed(&mut self, handler: &mut ShardedHandler) {
+        let policy_file = match &self.policy_file {
+            Some(policy_file) => policy_file,
+            None => return,
+        };
+
+        let modified = modified_time(policy_file);
+        if modified == self.policy_modified {
+            return;
+        }
+        // Only try each version of the file once,
+        // so that an invalid file isn't reported for every batch.
+        self.policy_modified = modified;
+
+        match Policies::from_file(policy_file) {
+            Ok(policies) => handler.update_policies(policies),
+            Err(err) => tracing::error!(
+                "Failed to reload policies from '{}', keeping current policies: {}",
+                policy_file.display(),
+                err
+            ),
+        }
+    }
+}
+
+/// Write the current balances to a temporary file next to `path`,
+/// then move it into place, so readers never see a partial file.
+fn write_balances_atomically(
+    handler: &ShardedHandler,
+    path: &Path,
+    order: AccountOrder,
+    lock_reasons: bool,
+) -> io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    let accounts = handler.snapshot();
+    write_accounts(
+        &accounts,
+        &handler.policies().rounding,
+        order,
+        lock_reasons,
+        fs::File::create(&tmp_path)?,
+    );
+    fs::rename(&tmp_path, path)
+}
+
+/// Write a checkpoint, logging rather than failing if it can't be written,
+/// since the run can carry on without it.
+fn write_checkpoint(checkpoint: &Checkpoint, path: &Path) {
+    match checkpoint.write(path) {
+        Ok(()) => tracing::debug!(
+            "Wrote checkpoint to '{}' at line {}",
+            path.display(),
+            checkpoint.position.line
+        ),
+        Err(err) => tracing::error!(
+            "Failed to write checkpoint to '{}': {}",
+            path.display(),
+            err
+        ),
+    }
+}
+
+/// Last modification time of a file, if it exists.
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Control, ControlCommand};
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_read_command() {
+        let control_file = env::temp_dir().join(format!("control-{}", std::process::id()));
+        let control = Control::new(Some(control_file.clone()), None, None);
+
+        assert_eq!(control.read_command(), ControlCommand::Run);
+
+        fs::write(&control_file, "pause\n").unwrap();
+        assert_eq!(control.read_command(), ControlCommand::Pause);
+
+        fs::write(&control_file, "resume").unwrap();
+        assert_eq!(control.read_command(), ControlCommand::Run);
+
+        fs::remove_file(&control_file).unwrap();
+    }
+}
@@ -1,4 +1,6 @@
-use crate::types::{Chargeback, Deposit, Dispute, Resolve, Withdrawal};
+use crate::types::{
+    Chargeback, Deposit, Dispute, Lock, Representment, Resolve, Reversal, Unlock, Withdrawal,
+};
 use crate::types::{TransactionRecord, TransactionType};
 
 // Convert from individual transaction types
@@ -12,6 +14,11 @@ impl From<Deposit> for TransactionRecord {
             client_id: t.client_id,
             tx_id: t.tx_id,
             amount: Some(t.amount),
+            timestamp: t.timestamp,
+            currency: t.currency,
+            reason: None,
+            reverses: None,
+            effective: None,
         }
     }
 }
@@ -23,6 +30,11 @@ impl From<Withdrawal> for TransactionRecord {
             client_id: t.client_id,
             tx_id: t.tx_id,
             amount: Some(t.amount),
+            timestamp: t.timestamp,
+            currency: t.currency,
+            reason: None,
+            reverses: None,
+            effective: None,
         }
     }
 }
@@ -33,7 +45,12 @@ impl From<Dispute> for TransactionRecord {
             transaction_type: TransactionType::Dispute,
             client_id: t.client_id,
             tx_id: t.tx_id,
-            amount: None,
+            amount: t.amount,
+            timestamp: t.timestamp,
+            currency: t.currency,
+            reason: t.reason,
+            reverses: None,
+            effective: None,
         }
     }
 }
@@ -45,6 +62,11 @@ impl From<Resolve> for TransactionRecord {
             client_id: t.client_id,
             tx_id: t.tx_id,
             amount: None,
+            timestamp: t.timestamp,
+            currency: t.currency,
+            reason: None,
+            reverses: None,
+            effective: None,
         }
     }
 }
@@ -56,6 +78,75 @@ impl From<Chargeback> for TransactionRecord {
             client_id: t.client_id,
             tx_id: t.tx_id,
             amount: None,
+            timestamp: t.timestamp,
+            currency: t.currency,
+            reason: None,
+            reverses: None,
+            effective: None,
+        }
+    }
+}
+
+impl From<Lock> for TransactionRecord {
+    fn from(t: Lock) -> Self {
+        Self {
+            transaction_type: TransactionType::Lock,
+            client_id: t.client_id,
+            tx_id: t.tx_id,
+            amount: None,
+            timestamp: t.timestamp,
+            currency: t.currency,
+            reason: None,
+            reverses: None,
+            effective: None,
+        }
+    }
+}
+
+impl From<Unlock> for TransactionRecord {
+    fn from(t: Unlock) -> Self {
+        Self {
+            transaction_type: TransactionType::Unlock,
+            client_id: t.client_id,
+            tx_id: t.tx_id,
+            amount: None,
+            timestamp: t.timestamp,
+            currency: t.currency,
+            reason: None,
+            reverses: None,
+            effective: None,
+        }
+    }
+}
+
+impl From<Representment> for TransactionRecord {
+    fn from(t: Representment) -> Self {
+        Self {
+            transaction_type: TransactionType::Representment,
+            client_id: t.client_id,
+            tx_id: t.tx_id,
+            amount: None,
+            timestamp: t.timestamp,
+            currency: t.currency,
+            reason: None,
+            reverses: None,
+            effective: None,
+        }
+    }
+}
+
+impl From<Reversal> for TransactionRecord {
+    fn from(t: Reversal) -> Self {
+        Self {
+            transaction_type: TransactionType::Reversal,
+            client_id: t.client_id,
+            tx_id: t.tx_id,
+            amount: None,
+            timestamp: t.timestamp,
+            currency: t.currency,
+            reason: None,
+            reverses: Some(t.reverses),
+            effective: None,
         }
     }
 }
@@ -63,21 +154,28 @@ impl From<Chargeback> for TransactionRecord {
 #[cfg(test)]
 mod tests {
     use crate::types::{Chargeback, Deposit, Dispute, Resolve, Withdrawal};
-    use crate::types::{TransactionRecord, TransactionType};
+    use crate::types::{Currency, DisputeReason, TransactionRecord, TransactionType};
 
     #[test]
     fn test_deposit_to_record() {
         let deposit = Deposit {
-            amount: 3.6,
+            amount: Currency::from(3.6),
             client_id: 17,
             tx_id: 199,
+            timestamp: None,
+            currency: None,
         };
 
         let record = TransactionRecord {
             transaction_type: TransactionType::Deposit,
-            amount: Some(3.6),
+            amount: Some(Currency::from(3.6)),
             client_id: 17,
             tx_id: 199,
+            timestamp: None,
+            currency: None,
+            reason: None,
+            reverses: None,
+            effective: None,
         };
 
         assert_eq!(record, deposit.into());
@@ -86,16 +184,23 @@ mod tests {
     #[test]
     fn test_withdrawal_to_record() {
         let withdrawal = Withdrawal {
-            amount: 3.6,
+            amount: Currency::from(3.6),
             client_id: 17,
             tx_id: 199,
+            timestamp: None,
+            currency: None,
         };
 
         let record = TransactionRecord {
             transaction_type: TransactionType::Withdrawal,
-            amount: Some(3.6),
+            amount: Some(Currency::from(3.6)),
             client_id: 17,
             tx_id: 199,
+            timestamp: None,
+            currency: None,
+            reason: None,
+            reverses: None,
+            effective: None,
         };
 
         assert_eq!(record, withdrawal.into());
@@ -106,6 +211,10 @@ mod tests {
         let dispute = Dispute {
             client_id: 17,
             tx_id: 199,
+            amount: None,
+            timestamp: None,
+            currency: None,
+            reason: Some(DisputeReason::Fraud),
         };
 
         let record = TransactionRecord {
@@ -113,6 +222,11 @@ mod tests {
             amount: None,
             client_id: 17,
             tx_id: 199,
+            timestamp: None,
+            currency: None,
+            reason: Some(DisputeReason::Fraud),
+            reverses: None,
+            effective: None,
         };
 
         assert_eq!(record, dispute.into());
@@ -123,6 +237,8 @@ mod tests {
         let resolve = Resolve {
             client_id: 17,
             tx_id: 199,
+            timestamp: None,
+            currency: None,
         };
 
         let record = TransactionRecord {
@@ -130,6 +246,11 @@ mod tests {
             amount: None,
             client_id: 17,
             tx_id: 199,
+            timestamp: None,
+            currency: None,
+            reason: None,
+            reverses: None,
+            effective: None,
         };
 
         assert_eq!(record, resolve.into());
@@ -140,6 +261,8 @@ mod tests {
         let chargeback = Chargeback {
             client_id: 17,
             tx_id: 199,
+            timestamp: None,
+            currency: None,
         };
 
         let record = TransactionRecord {
@@ -147,6 +270,11 @@ mod tests {
             amount: None,
             client_id: 17,
             tx_id: 199,
+            timestamp: None,
+            currency: None,
+            reason: None,
+            reverses: None,
+            effective: None,
         };
 
         assert_eq!(record, chargeback.into());
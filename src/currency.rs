@@ -1,33 +1,682 @@
-// Only need 4 decimals precision - f64 would be overkill
-pub type CurrencyFloat = f32;
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::convert::TryFrom;
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+use std::str::FromStr;
 
-/// Round to four decimal places.
-pub fn round_currency(amount: CurrencyFloat) -> CurrencyFloat {
-    const NUM_DIGITS: u8 = 4;
-    // Round to NUM_DIGITS decimal places
-    let multiplier: CurrencyFloat = 10.0f32.powf(NUM_DIGITS.into());
-    (amount * multiplier).round() / multiplier
+/// Number of decimal places tracked.
+pub const DECIMALS: u32 = 4;
+
+/// Number of minor units in one whole unit of currency.
+const SCALE: i64 = 10_i64.pow(DECIMALS);
+
+/// Fixed-point representation: a whole number of minor units.
+#[cfg(not(feature = "decimal"))]
+mod repr {
+    use super::{AmountParts, RoundingMode, DECIMALS, SCALE};
+    use std::fmt;
+
+    pub type Repr = i64;
+
+    pub const ZERO: Repr = 0;
+
+    pub const fn from_minor_units(units: i64) -> Repr {
+        units
+    }
+
+    pub fn to_minor_units(amount: Repr) -> i64 {
+        amount
+    }
+
+    pub fn from_whole(whole: i64) -> Option<Repr> {
+        whole.checked_mul(SCALE)
+    }
+
+    pub fn from_f64(amount: f64) -> Repr {
+        (amount * SCALE as f64).round() as i64
+    }
+
+    pub fn to_f64(amount: Repr) -> f64 {
+        amount as f64 / SCALE as f64
+    }
+
+    /// Size of the smallest step at the given precision, in minor units.
+    fn step(precision: u32) -> i64 {
+        10_i64.pow(DECIMALS.saturating_sub(precision))
+    }
+
+    pub fn round_with(amount: Repr, precision: u32, mode: RoundingMode) -> Repr {
+        let step = step(precision);
+        let steps = amount / step;
+        // Same sign as the amount, so it always rounds toward zero
+        let remainder = amount % step;
+        if remainder == 0 {
+            return amount;
+        }
+
+        let away_from_zero = match mode {
+            RoundingMode::HalfUp => 2 * remainder.abs() >= step,
+            RoundingMode::HalfEven => {
+                let twice = 2 * remainder.abs();
+                twice > step || (twice == step && steps % 2 != 0)
+            }
+            RoundingMode::Floor => remainder < 0,
+            RoundingMode::Ceiling => remainder > 0,
+        };
+
+        // Near the limits, the step away from zero may not be representable,
+        // so stay on the last one that is
+        let toward_zero = steps * step;
+        if away_from_zero {
+            steps
+                .checked_add(remainder.signum())
+                .and_then(|steps| steps.checked_mul(step))
+                .unwrap_or(toward_zero)
+        } else {
+            toward_zero
+        }
+    }
+
+    pub fn percent(amount: Repr, percent: f64, precision: u32, mode: RoundingMode) -> Repr {
+        let step = step(precision);
+        let steps = amount as f64 * percent / 100.0 / step as f64;
+        let steps = match mode {
+            RoundingMode::HalfUp => steps.round(),
+            RoundingMode::HalfEven => steps.round_ties_even(),
+            RoundingMode::Floor => steps.floor(),
+            RoundingMode::Ceiling => steps.ceil(),
+        };
+        // Casting saturates, so only whole steps within range are kept
+        (steps as i64).clamp(i64::MIN / step, i64::MAX / step) * step
+    }
+
+    pub fn parse(parts: AmountParts) -> Option<Repr> {
+        let whole: i64 = if parts.whole.is_empty() {
+            0
+        } else {
+            parts.whole.parse().ok()?
+        };
+
+        // Keep the first DECIMALS digits, rounding on the next one
+        let mut minor: i64 = 0;
+        let mut fraction_digits = parts.fraction.bytes().map(|b| i64::from(b - b'0'));
+        for _ in 0..DECIMALS {
+            minor = minor * 10 + fraction_digits.next().unwrap_or(0);
+        }
+        if fraction_digits.next().unwrap_or(0) >= 5 {
+            minor += 1;
+        }
+
+        let units = whole.checked_mul(SCALE)?.checked_add(minor)?;
+        Some(if parts.negative { -units } else { units })
+    }
+
+    pub fn fmt(amount: Repr, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if amount < 0 { "-" } else { "" };
+        let units = amount.unsigned_abs();
+        let whole = units / SCALE as u64;
+        let fraction = units % SCALE as u64;
+
+        let fraction = format!("{:0width$}", fraction, width = DECIMALS as usize);
+        let fraction = fraction.trim_end_matches('0');
+        let fraction = if fraction.is_empty() { "0" } else { fraction };
+
+        write!(f, "{}{}.{}", sign, whole, fraction)
+    }
+}
+
+/// Decimal representation, trading speed for range and exact percentages.
+#[cfg(feature = "decimal")]
+mod repr {
+    use super::{AmountParts, RoundingMode, DECIMALS, SCALE};
+    use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+    use rust_decimal::{Decimal, RoundingStrategy};
+    use std::fmt;
+
+    pub type Repr = Decimal;
+
+    pub const ZERO: Repr = Decimal::ZERO;
+
+    pub const fn from_minor_units(units: i64) -> Repr {
+        let magnitude = units.unsigned_abs();
+        Decimal::from_parts(
+            magnitude as u32,
+            (magnitude >> 32) as u32,
+            0,
+            units < 0,
+            DECIMALS,
+        )
+    }
+
+    pub fn to_minor_units(amount: Repr) -> i64 {
+        (amount * Decimal::from(SCALE))
+            .round()
+            .to_i64()
+            .unwrap_or(if amount.is_sign_negative() {
+                i64::MIN
+            } else {
+                i64::MAX
+            })
+    }
+
+    pub fn from_whole(whole: i64) -> Option<Repr> {
+        Some(Decimal::from(whole))
+    }
+
+    fn round(amount: Decimal) -> Repr {
+        amount.round_dp_with_strategy(DECIMALS, RoundingStrategy::MidpointAwayFromZero)
+    }
+
+    pub fn from_f64(amount: f64) -> Repr {
+        Decimal::from_f64(amount).map(round).unwrap_or_default()
+    }
+
+    pub fn to_f64(amount: Repr) -> f64 {
+        amount.to_f64().unwrap_or_default()
+    }
+
+    fn strategy(mode: RoundingMode) -> RoundingStrategy {
+        match mode {
+            RoundingMode::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            RoundingMode::HalfEven => RoundingStrategy::MidpointNearestEven,
+            RoundingMode::Floor => RoundingStrategy::ToNegativeInfinity,
+            RoundingMode::Ceiling => RoundingStrategy::ToPositiveInfinity,
+        }
+    }
+
+    pub fn round_with(amount: Repr, precision: u32, mode: RoundingMode) -> Repr {
+        amount.round_dp_with_strategy(precision.min(DECIMALS), strategy(mode))
+    }
+
+    pub fn percent(amount: Repr, percent: f64, precision: u32, mode: RoundingMode) -> Repr {
+        let percent = Decimal::from_f64(percent).unwrap_or_default() / Decimal::ONE_HUNDRED;
+        let share = amount.checked_mul(percent).unwrap_or_else(|| {
+            if amount.is_sign_negative() != percent.is_sign_negative() {
+                Decimal::MIN
+            } else {
+                Decimal::MAX
+            }
+        });
+        round_with(share, precision, mode)
+    }
+
+    pub fn parse(parts: AmountParts) -> Option<Repr> {
+        let whole = if parts.whole.is_empty() {
+            "0"
+        } else {
+            parts.whole
+        };
+        let fraction = if parts.fraction.is_empty() {
+            "0"
+        } else {
+            parts.fraction
+        };
+        let amount = Decimal::from_str_exact(&format!("{}.{}", whole, fraction)).ok()?;
+        let amount = round(amount);
+        Some(if parts.negative { -amount } else { amount })
+    }
+
+    pub fn fmt(amount: Repr, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if amount.is_zero() {
+            return write!(f, "0.0");
+        }
+        let amount = amount.normalize();
+        if amount.scale() == 0 {
+            write!(f, "{}.0", amount)
+        } else {
+            write!(f, "{}", amount)
+        }
+    }
+}
+
+/// How to round an amount which has more decimal places than allowed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoundingMode {
+    /// Round to the nearest value, with halves away from zero,
+    /// e.g. `0.125` to `0.13` and `-0.125` to `-0.13`.
+    #[default]
+    HalfUp,
+    /// Round to the nearest value, with halves to the even neighbor
+    /// (banker's rounding), e.g. `0.125` to `0.12` and `0.135` to `0.14`.
+    HalfEven,
+    /// Round toward negative infinity.
+    Floor,
+    /// Round toward positive infinity.
+    Ceiling,
+}
+
+/// The pieces of a decimal amount string like `-12.345`,
+/// already checked to contain only digits.
+struct AmountParts<'a> {
+    negative: bool,
+    whole: &'a str,
+    fraction: &'a str,
+}
+
+impl<'a> AmountParts<'a> {
+    fn split(s: &'a str) -> Option<Self> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (whole, fraction) = match digits.split_once('.') {
+            Some((whole, fraction)) => (whole, fraction),
+            None => (digits, ""),
+        };
+
+        let is_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+        if (whole.is_empty() && fraction.is_empty()) || !is_digits(whole) || !is_digits(fraction) {
+            return None;
+        }
+
+        Some(Self {
+            negative,
+            whole,
+            fraction,
+        })
+    }
+}
+
+/// An exact amount of currency, stored as a whole number of minor units
+/// (ten-thousandths), so that arithmetic never loses precision.
+/// With the `decimal` feature, amounts are stored as `rust_decimal::Decimal`
+/// instead, which is slower, but has a much larger range and computes
+/// percentage fees exactly before rounding.
+///
+/// Amounts are read and written as decimal strings, e.g. `1.5` or `-0.0001`.
+/// Digits beyond the fourth decimal place are rounded (half away from zero).
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Currency(repr::Repr);
+
+impl Currency {
+    pub const ZERO: Currency = Currency(repr::ZERO);
+
+    pub const fn from_minor_units(units: i64) -> Self {
+        Currency(repr::from_minor_units(units))
+    }
+
+    pub fn minor_units(self) -> i64 {
+        repr::to_minor_units(self.0)
+    }
+
+    /// Nearest amount to a float, rounding half away from zero.
+    /// Useful for literals and rates, but not for parsing input.
+    pub fn from_f64(amount: f64) -> Self {
+        Currency(repr::from_f64(amount))
+    }
+
+    pub fn to_f64(self) -> f64 {
+        repr::to_f64(self.0)
+    }
+
+    /// Sum of two amounts, or `None` if it can't be represented.
+    pub fn checked_add(self, other: Currency) -> Option<Self> {
+        self.0.checked_add(other.0).map(Currency)
+    }
+
+    /// Difference of two amounts, or `None` if it can't be represented.
+    pub fn checked_sub(self, other: Currency) -> Option<Self> {
+        self.0.checked_sub(other.0).map(Currency)
+    }
+
+    pub fn is_positive(self) -> bool {
+        self.0 > repr::ZERO
+    }
+
+    pub fn is_negative(self) -> bool {
+        self.0 < repr::ZERO
+    }
+
+    /// Round to the given number of decimal places.
+    /// Amounts never have more than `DECIMALS` places,
+    /// so larger precisions leave the amount unchanged.
+    pub fn round(self, precision: u32, mode: RoundingMode) -> Self {
+        Currency(repr::round_with(self.0, precision, mode))
+    }
+
+    /// The given percentage of this amount,
+    /// rounded to the given number of decimal places.
+    pub fn percent(self, percent: f64, precision: u32, mode: RoundingMode) -> Self {
+        Currency(repr::percent(self.0, percent, precision, mode))
+    }
+}
+
+impl From<f64> for Currency {
+    fn from(amount: f64) -> Self {
+        Self::from_f64(amount)
+    }
+}
+
+impl Add for Currency {
+    type Output = Currency;
+
+    fn add(self, other: Currency) -> Currency {
+        Currency(self.0 + other.0)
+    }
+}
+
+impl Sub for Currency {
+    type Output = Currency;
+
+    fn sub(self, other: Currency) -> Currency {
+        Currency(self.0 - other.0)
+    }
+}
+
+impl AddAssign for Currency {
+    fn add_assign(&mut self, other: Currency) {
+        self.0 += other.0;
+    }
 }
 
+impl SubAssign for Currency {
+    fn sub_assign(&mut self, other: Currency) {
+        self.0 -= other.0;
+    }
+}
+
+impl Neg for Currency {
+    type Output = Currency;
+
+    fn neg(self) -> Currency {
+        Currency(-self.0)
+    }
+}
+
+impl Sum for Currency {
+    fn sum<I: Iterator<Item = Currency>>(iter: I) -> Currency {
+        iter.fold(Currency::ZERO, Add::add)
+    }
+}
+
+/// Formats with as few decimal places as possible, but at least one,
+/// e.g. `1.0`, `1.5`, `-0.0001`.
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        repr::fmt(self.0, f)
+    }
+}
+
+// Errors are reported with their Debug representation,
+// so show amounts the same way they're written.
+impl fmt::Debug for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseCurrencyError(String);
+
+impl fmt::Display for ParseCurrencyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid currency amount '{}'", self.0)
+    }
+}
+
+impl std::error::Error for ParseCurrencyError {}
+
+impl FromStr for Currency {
+    type Err = ParseCurrencyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        AmountParts::split(s)
+            .and_then(repr::parse)
+            .map(Currency)
+            .ok_or_else(|| ParseCurrencyError(s.to_string()))
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+struct CurrencyVisitor;
+
+impl<'de> Visitor<'de> for CurrencyVisitor {
+    type Value = Currency;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a decimal amount with up to {} decimal places", DECIMALS)
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Currency, E> {
+        value.parse().map_err(E::custom)
+    }
+
+    // Self-describing formats like TOML may provide numbers directly
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Currency, E> {
+        Ok(Currency::from_f64(value))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Currency, E> {
+        repr::from_whole(value)
+            .map(Currency)
+            .ok_or_else(|| E::custom(format!("currency amount {} is too large", value)))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Currency, E> {
+        i64::try_from(value)
+            .map_err(|_| E::custom(format!("currency amount {} is too large", value)))
+            .and_then(|value| self.visit_i64(value))
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Ask for a string so that CSV fields are parsed exactly,
+        // rather than being inferred as floats.
+        deserializer.deserialize_str(CurrencyVisitor)
+    }
+}
+
+/// ISO 4217 code naming which currency an amount is in, e.g. `USD`.
+/// Codes are three ASCII letters, stored in upper case.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CurrencyCode([u8; 3]);
+
+impl CurrencyCode {
+    pub fn as_str(&self) -> &str {
+        // Only ASCII letters are ever stored
+        std::str::from_utf8(&self.0).unwrap_or_default()
+    }
+}
+
+impl fmt::Display for CurrencyCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for CurrencyCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseCurrencyCodeError(String);
+
+impl fmt::Display for ParseCurrencyCodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid ISO 4217 currency code '{}'", self.0)
+    }
+}
+
+impl std::error::Error for ParseCurrencyCodeError {}
+
+impl FromStr for CurrencyCode {
+    type Err = ParseCurrencyCodeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.as_bytes() {
+            &[a, b, c] if s.bytes().all(|b| b.is_ascii_alphabetic()) => Ok(CurrencyCode([
+                a.to_ascii_uppercase(),
+                b.to_ascii_uppercase(),
+                c.to_ascii_uppercase(),
+            ])),
+            _ => Err(ParseCurrencyCodeError(s.to_string())),
+        }
+    }
+}
+
+impl Serialize for CurrencyCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+struct CurrencyCodeVisitor;
 
-/// Round _down_ (floor) to four decimal places.
-pub fn floor_currency(amount: CurrencyFloat) -> CurrencyFloat {
-    const NUM_DIGITS: u8 = 4;
-    // Round down to NUM_DIGITS decimal places
-    let multiplier: CurrencyFloat = 10.0f32.powf(NUM_DIGITS.into());
-    (amount * multiplier).floor() / multiplier
+impl<'de> Visitor<'de> for CurrencyCodeVisitor {
+    type Value = CurrencyCode;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a three-letter ISO 4217 currency code")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<CurrencyCode, E> {
+        value.parse().map_err(E::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for CurrencyCode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(CurrencyCodeVisitor)
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::{Currency, CurrencyCode, RoundingMode, DECIMALS};
+
+    #[test]
+    fn test_parse_currency() {
+        let parse = |s: &str| s.parse::<Currency>().map(Currency::minor_units);
+
+        assert_eq!(parse("1"), Ok(10_000));
+        assert_eq!(parse("1.5"), Ok(15_000));
+        assert_eq!(parse("-0.0001"), Ok(-1));
+        assert_eq!(parse(".25"), Ok(2_500));
+        assert_eq!(parse("123456789.1234"), Ok(1_234_567_891_234));
+        // Rounded to four decimal places
+        assert_eq!(parse("1.00005"), Ok(10_001));
+        assert_eq!(parse("1.00004"), Ok(10_000));
+
+        assert!(parse("").is_err());
+        assert!(parse(".").is_err());
+        assert!(parse("1.2.3").is_err());
+        assert!(parse("abc").is_err());
+        #[cfg(not(feature = "decimal"))]
+        assert!(parse("99999999999999999999").is_err());
+    }
+
+    #[test]
+    fn test_display_currency() {
+        assert_eq!(Currency::from_minor_units(10_000).to_string(), "1.0");
+        assert_eq!(Currency::from_minor_units(15_000).to_string(), "1.5");
+        assert_eq!(Currency::from_minor_units(-1).to_string(), "-0.0001");
+        assert_eq!(Currency::ZERO.to_string(), "0.0");
+        assert_eq!(
+            Currency::from_minor_units(1_234_567_891_234).to_string(),
+            "123456789.1234"
+        );
+    }
+
+    #[test]
+    fn test_exact_arithmetic() {
+        // 0.1 + 0.2 is exactly 0.3, unlike with floats
+        let sum = Currency::from(0.1) + Currency::from(0.2);
+        assert_eq!(sum, Currency::from(0.3));
+
+        // Large balances keep all four decimal places
+        let large: Currency = "1000000.0001".parse().unwrap();
+        assert_eq!((large + large).to_string(), "2000000.0002");
+    }
+
+    #[test]
+    #[cfg(not(feature = "decimal"))]
+    fn test_checked_add() {
+        let max = Currency::from_minor_units(i64::MAX);
+        assert_eq!(max.checked_add(Currency::from_minor_units(1)), None);
+        assert_eq!(
+            max.checked_add(Currency::from_minor_units(-1)),
+            Some(Currency::from_minor_units(i64::MAX - 1))
+        );
+    }
+
+    #[test]
+    fn test_percent() {
+        let percent = |amount: f64, percent| {
+            Currency::from(amount).percent(percent, DECIMALS, RoundingMode::HalfUp)
+        };
+        assert_eq!(percent(10.0, 2.5), Currency::from(0.25));
+        assert_eq!(percent(0.0003, 50.0), Currency::from(0.0002));
+
+        let cents = |amount: f64, mode| Currency::from(amount).percent(1.0, 2, mode);
+        assert_eq!(cents(12.5, RoundingMode::HalfUp), Currency::from(0.13));
+        assert_eq!(cents(12.5, RoundingMode::HalfEven), Currency::from(0.12));
+        assert_eq!(cents(12.5, RoundingMode::Floor), Currency::from(0.12));
+        assert_eq!(cents(12.1, RoundingMode::Ceiling), Currency::from(0.13));
+    }
+
+    #[test]
+    fn test_round() {
+        let round = |amount: f64, precision, mode| {
+            Currency::from(amount).round(precision, mode).to_string()
+        };
+
+        assert_eq!(round(0.125, 2, RoundingMode::HalfUp), "0.13");
+        assert_eq!(round(-0.125, 2, RoundingMode::HalfUp), "-0.13");
+        assert_eq!(round(0.125, 2, RoundingMode::HalfEven), "0.12");
+        assert_eq!(round(0.135, 2, RoundingMode::HalfEven), "0.14");
+        assert_eq!(round(0.1251, 2, RoundingMode::HalfEven), "0.13");
+        assert_eq!(round(0.129, 2, RoundingMode::Floor), "0.12");
+        assert_eq!(round(-0.121, 2, RoundingMode::Floor), "-0.13");
+        assert_eq!(round(0.121, 2, RoundingMode::Ceiling), "0.13");
+        assert_eq!(round(-0.129, 2, RoundingMode::Ceiling), "-0.12");
+        assert_eq!(round(7.5, 0, RoundingMode::HalfEven), "8.0");
+        // Amounts already have at most four places
+        assert_eq!(round(1.2345, 6, RoundingMode::Floor), "1.2345");
+    }
+
+    #[test]
+    #[cfg(not(feature = "decimal"))]
+    fn test_round_near_limits() {
+        let max = Currency::from_minor_units(i64::MAX);
+        let min = Currency::from_minor_units(i64::MIN);
+
+        // The next step away from zero can't be represented, so it stops short
+        let last_cent = Currency::from_minor_units(i64::MAX / 100 * 100);
+        assert_eq!(max.round(2, RoundingMode::Ceiling), last_cent);
+        assert_eq!(max.round(2, RoundingMode::Floor), last_cent);
+        assert_eq!(min.round(2, RoundingMode::Floor), -last_cent);
+        assert_eq!(
+            min.round(0, RoundingMode::HalfUp),
+            min.round(0, RoundingMode::Ceiling)
+        );
+        // It still rounds away from zero when there's room
+        let below_max = Currency::from_minor_units(i64::MAX - 100);
+        assert_eq!(below_max.round(2, RoundingMode::Ceiling), last_cent);
+
+        assert_eq!(max.percent(200.0, 2, RoundingMode::HalfUp), last_cent);
+        assert_eq!(min.percent(200.0, 2, RoundingMode::HalfUp), -last_cent);
+    }
+
     #[test]
-    fn test_round_currency() {
-        use super::round_currency;
+    fn test_parse_currency_code() {
+        let usd: CurrencyCode = "USD".parse().unwrap();
+        assert_eq!(usd.to_string(), "USD");
+        assert_eq!("usd".parse(), Ok(usd));
 
-        assert_eq!(round_currency(1.00003), 1.0);
-        assert_eq!(round_currency(0.0001), 0.0001);
-        assert_eq!(round_currency(0.002), 0.002);
-        assert_eq!(round_currency(0.00005), 0.0001);
-        assert_eq!(round_currency(0.00004), 0.0);
+        assert!("".parse::<CurrencyCode>().is_err());
+        assert!("US".parse::<CurrencyCode>().is_err());
+        assert!("USDT".parse::<CurrencyCode>().is_err());
+        assert!("U$D".parse::<CurrencyCode>().is_err());
     }
 }
@@ -0,0 +1,86 @@
+//! Digest of final balances, so that two runs (or two implementations)
+//! can be compared without diffing whole output files.
+//!
+//! The digest is the SHA-1 hash of one line per account, sorted by client
+//! and then currency (the default first), in the same format as the output CSV without headers:
+//! `client,currency,available,held,total,locked\n`, with an empty currency
+//! for the default. Amounts are rounded as in the output.
+//! SHA-1 is fine for telling whether balances match,
+//! but isn't meant to protect against deliberate tampering.
+
+use sha1::{Digest, Sha1};
+
+use crate::policy::RoundingPolicy;
+use crate::state::{AccountOrder, AccountsState};
+use crate::types::{InvalidAccount, OutputRecord};
+
+/// Hex digest of the balances of every account,
+/// or why one of them can't be written.
+pub fn accounts_digest(
+    accounts: &AccountsState,
+    rounding: &RoundingPolicy,
+) -> Result<String, InvalidAccount> {
+    let mut hasher = Sha1::new();
+    for (&key, account) in accounts.ordered(AccountOrder::Client) {
+        let balance = OutputRecord::new(key, account, rounding)?;
+        let line = format!(
+            "{},{},{},{},{},{}\n",
+            balance.client,
+            balance
+                .currency
+                .map(|code| code.to_string())
+                .unwrap_or_default(),
+            balance.available,
+            balance.held,
+            balance.total,
+            balance.locked
+        );
+        hasher.update(line.as_bytes());
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::accounts_digest;
+    use crate::currency::Currency;
+    use crate::policy::RoundingPolicy;
+    use crate::state::AccountsState;
+    use crate::types::{Account, ClientId};
+    use std::collections::HashMap;
+
+    fn accounts(clients: &[(ClientId, f64)]) -> AccountsState {
+        clients
+            .iter()
+            .map(|&(client, available)| {
+                let account = Account {
+                    available: Currency::from(available),
+                    ..Default::default()
+                };
+                (client, account)
+            })
+            .collect::<HashMap<_, _>>()
+            .into()
+    }
+
+    #[test]
+    fn test_digest() {
+        let rounding = RoundingPolicy::default();
+        let digest = accounts_digest(&accounts(&[(1, 1.5), (2, 3.0)]), &rounding).unwrap();
+        // sha1 of "1,,1.5,0.0,1.5,false\n2,,3.0,0.0,3.0,false\n"
+        assert_eq!(digest, "d8d4a7caa5e9d627d58972f4053dfe6d1e3082bf");
+        // Order of insertion doesn't matter
+        assert_eq!(
+            accounts_digest(&accounts(&[(2, 3.0), (1, 1.5)]), &rounding),
+            Ok(digest.clone())
+        );
+        assert_ne!(
+            accounts_digest(&accounts(&[(1, 1.5), (2, 3.1)]), &rounding),
+            Ok(digest)
+        );
+    }
+}
@@ -0,0 +1,696 @@
+//! Domain events for every change the handlers make to the state,
+//! e.g. for an audit trail, building projections, or publishing downstream.
+//!
+//! Events are sent on a channel once each transaction has been handled,
+//! all of a transaction's events together, so a consumer never sees
+//! half of a transaction. Each client's events arrive in the order
+//! their transactions were handled, but in the pipeline, different
+//! clients' events may be interleaved in any order.
+//!
+//! Policies changed while running are sent as `PoliciesChanged` by the router,
+//! not a handler, so they may come before the events of transactions
+//! dispatched earlier which were still being handled.
+//!
+//! `replay_events` rebuilds the state from the events alone: accounts,
+//! stored transactions and disputes, as a checkpoint would. Rejections
+//! and the ledger aren't rebuilt, since rejected transactions change nothing.
+
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use crate::account::{AccountRef, UnlockedAccount};
+use crate::currency::{Currency, CurrencyCode};
+use crate::policy::Policies;
+use crate::state::{AccountsState, State, TransactionsState};
+use crate::traits::{Disputable, Transaction};
+use crate::types::{Account, AccountKey, ClientId, Deposit, Timestamp, TransactionContainer};
+use crate::types::{DisputeReason, LockInfo, LockReason, TransactionError, TransactionId};
+use crate::types::{Reversal, TransactionType, Withdrawal};
+
+/// A change to the state. Accounts are identified by client and currency,
+/// which is left out when it's the default, and the transaction which caused
+/// each change by its id.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "event")]
+pub enum Event {
+    /// An account was created, by its first transaction, whether or not it succeeded.
+    AccountOpened {
+        client: ClientId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<CurrencyCode>,
+    },
+    /// Available funds went up by `amount`, less `fee`.
+    FundsDeposited {
+        client: ClientId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<CurrencyCode>,
+        tx: TransactionId,
+        amount: Currency,
+        fee: Currency,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<Timestamp>,
+    },
+    /// Available funds went down by `amount`, plus `fee`.
+    FundsWithdrawn {
+        client: ClientId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<CurrencyCode>,
+        tx: TransactionId,
+        amount: Currency,
+        fee: Currency,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<Timestamp>,
+    },
+    /// A deposit or withdrawal failed, but was stored, so that its id is taken
+    /// and disputing it fails.
+    TransactionFailed {
+        client: ClientId,
+        tx: TransactionId,
+        #[serde(rename = "type")]
+        transaction_type: TransactionType,
+        error: TransactionError,
+    },
+    /// `amount` of the client's transaction was moved from available to held,
+    /// for the `reason` given, if any.
+    DisputeOpened {
+        client: ClientId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<CurrencyCode>,
+        tx: TransactionId,
+        amount: Currency,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<DisputeReason>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<Timestamp>,
+    },
+    /// A dispute was resolved, moving `amount` back from held to available.
+    DisputeResolved {
+        client: ClientId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<CurrencyCode>,
+        tx: TransactionId,
+        amount: Currency,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<Timestamp>,
+    },
+    /// A dispute was charged back, removing `amount` from held funds.
+    /// Any shortfall the account couldn't cover was `written_off`,
+    /// flagging the account.
+    ChargedBack {
+        client: ClientId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<CurrencyCode>,
+        tx: TransactionId,
+        amount: Currency,
+        written_off: Currency,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<Timestamp>,
+    },
+    /// The merchant contested a chargeback, reopening the dispute,
+    /// so the `amount` charged back is held again.
+    ChargebackRepresented {
+        client: ClientId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<CurrencyCode>,
+        tx: TransactionId,
+        amount: Currency,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<Timestamp>,
+    },
+    /// An administrator reversed a deposit or withdrawal, which `change`d
+    /// the available funds: negative for a deposit, positive for a withdrawal.
+    TransactionReversed {
+        client: ClientId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<CurrencyCode>,
+        tx: TransactionId,
+        reverses: TransactionId,
+        change: Currency,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<Timestamp>,
+    },
+    /// Interest was credited to the account's available funds.
+    InterestCredited {
+        client: ClientId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<CurrencyCode>,
+        amount: Currency,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<Timestamp>,
+    },
+    /// The account was locked, by a chargeback or an administrator,
+    /// as the `reason` says.
+    AccountLocked {
+        client: ClientId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<CurrencyCode>,
+        tx: TransactionId,
+        reason: LockReason,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<Timestamp>,
+    },
+    AccountUnlocked {
+        client: ClientId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<CurrencyCode>,
+        tx: TransactionId,
+    },
+    /// A risk check flagged the account for review, for the `reason` given,
+    /// though the transaction went ahead.
+    AccountFlagged {
+        client: ClientId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        currency: Option<CurrencyCode>,
+        tx: TransactionId,
+        reason: String,
+    },
+    /// The policies were changed while running, e.g. by editing the policy file,
+    /// after `dispatched` transactions, and apply to every transaction after those.
+    PoliciesChanged {
+        dispatched: usize,
+        policies: Box<Policies>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<Timestamp>,
+    },
+}
+
+impl Event {
+    /// The client whose account changed, e.g. to key events by when publishing them,
+    /// or `None` for changes to every account, like `PoliciesChanged`.
+    pub fn client(&self) -> Option<ClientId> {
+        let client = match *self {
+            Event::AccountOpened { client, .. }
+            | Event::FundsDeposited { client, .. }
+            | Event::FundsWithdrawn { client, .. }
+            | Event::TransactionFailed { client, .. }
+            | Event::DisputeOpened { client, .. }
+            | Event::DisputeResolved { client, .. }
+            | Event::ChargedBack { client, .. }
+            | Event::ChargebackRepresented { client, .. }
+            | Event::TransactionReversed { client, .. }
+            | Event::InterestCredited { client, .. }
+            | Event::AccountLocked { client, .. }
+            | Event::AccountUnlocked { client, .. }
+            | Event::AccountFlagged { client, .. } => client,
+            Event::PoliciesChanged { .. } => return None,
+        };
+        Some(client)
+    }
+}
+
+/// Where the handlers send events, once each transaction has been handled.
+/// Clones send to the same channel, e.g. one for each handler thread.
+#[derive(Debug)]
+pub struct EventLog {
+    sender: Sender<Event>,
+    /// Events of the transaction being handled
+    pending: Vec<Event>,
+}
+
+impl Clone for EventLog {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl EventLog {
+    /// A log, and the receiving end of its channel.
+    pub fn channel() -> (Self, Receiver<Event>) {
+        let (sender, receiver) = channel();
+        let log = Self {
+            sender,
+            pending: Vec::new(),
+        };
+        (log, receiver)
+    }
+
+    /// Record an event of the transaction being handled.
+    pub(crate) fn record(&mut self, event: Event) {
+        self.pending.push(event);
+    }
+
+    /// Drop the events of the transaction being handled, e.g. since it was undone.
+    pub(crate) fn discard(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Send the events of the transaction just handled, preceded by opening
+    /// its account, if it was new. Returns false if nobody is listening anymore.
+    pub(crate) fn flush(&mut self, opened: Option<AccountKey>) -> bool {
+        let opened = opened.map(|(client, currency)| Event::AccountOpened { client, currency });
+        let sender = &self.sender;
+        opened
+            .into_iter()
+            .chain(self.pending.drain(..))
+            .all(|event| sender.send(event).is_ok())
+    }
+}
+
+/// Rebuild a state from the events of every transaction it handled,
+/// to handle further transactions with the given policies,
+/// which should be those the events were recorded with.
+/// Each client's events must be in the order they were sent.
+pub fn replay_events(
+    events: impl IntoIterator<Item = Event>,
+    policies: Policies,
+) -> Result<State, Box<dyn Error>> {
+    let mut state = State::with_policies(policies);
+    for event in events {
+        apply_event(event, &mut state)?;
+    }
+    Ok(state)
+}
+
+/// The disputed deposit, or other disputable transaction, of a dispute event.
+fn disputed(
+    transactions: &TransactionsState,
+    client: ClientId,
+    tx: TransactionId,
+) -> Result<&impl Disputable, Box<dyn Error>> {
+    match transactions
+        .get(client, tx)
+        .map(TransactionContainer::try_get_disputable)
+    {
+        Some(Ok(Ok(disputed))) => Ok(disputed),
+        _ => Err(format!("client {} has no disputable transaction {}", client, tx).into()),
+    }
+}
+
+/// Access to an account, which must already be open, to change.
+fn access(
+    accounts: &mut AccountsState,
+    client: ClientId,
+    currency: Option<CurrencyCode>,
+) -> Result<AccountRef<'_>, Box<dyn Error>> {
+    accounts
+        .get_mut(client, currency)
+        .ok_or_else(|| format!("client {} has no account in {:?}", client, currency).into())
+}
+
+/// Access to an unlocked account, to deposit or withdraw.
+fn unlocked(
+    accounts: &mut AccountsState,
+    client: ClientId,
+    currency: Option<CurrencyCode>,
+) -> Result<UnlockedAccount<'_>, Box<dyn Error>> {
+    access(accounts, client, currency)?
+        .try_unlocked()
+        .map_err(|_| format!("client {}'s account is locked", client).into())
+}
+
+/// Arrange for a dispute which was just settled to be forgotten as the handlers would,
+/// and return access to the disputed transaction's account.
+fn settled<'a>(
+    state: &'a mut State,
+    client: ClientId,
+    currency: Option<CurrencyCode>,
+    tx: TransactionId,
+) -> Result<(&'a TransactionsState, AccountRef<'a>), Box<dyn Error>> {
+    let occurred_at = disputed(&state.transactions, client, tx)?.get_timestamp();
+    let policy = &state.policies.dispute;
+    if let (true, Some(_), Some(occurred_at)) =
+        (policy.compact_settled, policy.max_age, occurred_at)
+    {
+        state.disputes.schedule_expiry(client, tx, occurred_at);
+    }
+    Ok((
+        &state.transactions,
+        access(&mut state.accounts, client, currency)?,
+    ))
+}
+
+fn apply_event(event: Event, state: &mut State) -> Result<(), Box<dyn Error>> {
+    match event {
+        Event::AccountOpened { client, currency } => {
+            state
+                .accounts
+                .insert((client, currency), Account::default());
+        }
+        Event::FundsDeposited {
+            client,
+            currency,
+            tx,
+            amount,
+            fee,
+            timestamp,
+        } => {
+            let deposit = Deposit {
+                client_id: client,
+                tx_id: tx,
+                amount,
+                timestamp,
+                currency,
+            };
+            unlocked(&mut state.accounts, client, currency)?
+                .modify_balances_for_deposit(&deposit, fee)?;
+            let container = TransactionContainer::Deposit(Ok(deposit));
+            state.transactions.insert(client, tx, container);
+        }
+        Event::FundsWithdrawn {
+            client,
+            currency,
+            tx,
+            amount,
+            fee,
+            timestamp,
+        } => {
+            let withdrawal = Withdrawal {
+                client_id: client,
+                tx_id: tx,
+                amount,
+                timestamp,
+                currency,
+            };
+            unlocked(&mut state.accounts, client, currency)?
+                .modify_balances_for_withdrawal(&withdrawal, fee)?;
+            let container = TransactionContainer::Withdrawal(Ok(withdrawal));
+            state.transactions.insert(client, tx, container);
+        }
+        Event::TransactionFailed {
+            client,
+            tx,
+            transaction_type,
+            error,
+        } => {
+            let container = match transaction_type {
+                TransactionType::Deposit => TransactionContainer::Deposit(Err(error)),
+                TransactionType::Withdrawal => TransactionContainer::Withdrawal(Err(error)),
+                other => return Err(format!("{} transactions aren't stored", other.name()).into()),
+            };
+            state.transactions.insert(client, tx, container);
+        }
+        Event::DisputeOpened {
+            client,
+            currency,
+            tx,
+            amount,
+            reason,
+            timestamp,
+        } => {
+            let disputed = disputed(&state.transactions, client, tx)?;
+            state
+                .disputes
+                .dispute_tx(client, tx, amount, reason, timestamp)?;
+            access(&mut state.accounts, client, currency)?
+                .modify_balances_for_dispute(disputed, amount)?;
+        }
+        Event::DisputeResolved {
+            client,
+            currency,
+            tx,
+            amount,
+            timestamp,
+        } => {
+            state.disputes.resolve(client, tx, timestamp)?;
+            let (transactions, mut access) = settled(state, client, currency, tx)?;
+            access.modify_balances_for_resolve(disputed(transactions, client, tx)?, amount)?;
+        }
+        Event::ChargedBack {
+            client,
+            currency,
+            tx,
+            amount,
+            written_off,
+            timestamp,
+        } => {
+            state.disputes.charge_back(client, tx, timestamp)?;
+            state.transactions.record_chargeback(client, tx);
+            let (transactions, mut access) = settled(state, client, currency, tx)?;
+            let disputed = disputed(transactions, client, tx)?;
+            access.modify_balances_for_chargeback(disputed, amount, written_off)?;
+        }
+        Event::ChargebackRepresented {
+            client,
+            currency,
+            tx,
+            amount,
+            timestamp,
+        } => {
+            let disputed = disputed(&state.transactions, client, tx)?;
+            state.disputes.represent(client, tx, timestamp)?;
+            access(&mut state.accounts, client, currency)?
+                .modify_balances_for_representment(disputed, amount)?;
+            state.transactions.clear_chargeback(client, tx);
+        }
+        Event::TransactionReversed {
+            client,
+            currency,
+            tx,
+            reverses,
+            change,
+            timestamp,
+        } => {
+            access(&mut state.accounts, client, currency)?.modify_balances_for_reversal(change)?;
+            let reversal = Reversal {
+                client_id: client,
+                tx_id: tx,
+                reverses,
+                timestamp,
+                currency,
+            };
+            let container = TransactionContainer::Reversal(reversal);
+            state.transactions.insert(client, tx, container);
+        }
+        Event::InterestCredited {
+            client,
+            currency,
+            amount,
+            ..
+        } => {
+            access(&mut state.accounts, client, currency)?.credit_interest(amount)?;
+        }
+        Event::AccountLocked {
+            client,
+            currency,
+            tx,
+            reason,
+            timestamp,
+        } => {
+            let access = access(&mut state.accounts, client, currency)?;
+            if let Ok(account) = access.try_unlocked() {
+                account.lock(LockInfo {
+                    reason,
+                    tx,
+                    timestamp,
+                });
+            }
+        }
+        Event::AccountUnlocked {
+            client, currency, ..
+        } => {
+            let access = access(&mut state.accounts, client, currency)?;
+            if let Ok(account) = access.try_locked() {
+                account.unlock();
+            }
+        }
+        Event::AccountFlagged {
+            client, currency, ..
+        } => {
+            access(&mut state.accounts, client, currency)?.flag();
+        }
+        Event::PoliciesChanged { policies, .. } => {
+            state.policies = *policies;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{replay_events, Event, EventLog};
+    use crate::checkpoint::{Checkpoint, InputPosition};
+    use crate::policy::Policies;
+    use crate::rand::{TransactionGenerator, TransactionWeights};
+    use crate::state::State;
+    use crate::test_utils::{chargeback, deposit, dispute, representment, reversal, withdrawal};
+    use crate::types::{Currency, DisputeReason};
+
+    /// Handle the records with an event log, returning the state and its events.
+    fn handled(records: Vec<crate::types::TransactionRecord>) -> (State, Vec<Event>) {
+        let (log, events) = EventLog::channel();
+        let mut state = State::new();
+        state.events = Some(log);
+        for record in records {
+            let _ = state.handle(record);
+        }
+        state.events = None;
+        (state, events.iter().collect())
+    }
+
+    #[test]
+    fn test_events_of_each_transaction() {
+        let (_, events) = handled(vec![
+            deposit(1, 1, 5.0),
+            withdrawal(1, 2, 9.0),
+            dispute(1, 1),
+            chargeback(1, 1),
+        ]);
+        assert_eq!(
+            events,
+            vec![
+                Event::AccountOpened {
+                    client: 1,
+                    currency: None
+                },
+                Event::FundsDeposited {
+                    client: 1,
+                    currency: None,
+                    tx: 1,
+                    amount: Currency::from(5.0),
+                    fee: Currency::ZERO,
+                    timestamp: None,
+                },
+                Event::TransactionFailed {
+                    client: 1,
+                    tx: 2,
+                    transaction_type: crate::types::TransactionType::Withdrawal,
+                    error: crate::types::TransactionError::InsufficientFunds {
+                        client: 1,
+                        tx: 2,
+                        requested: Currency::from(9.0),
+                        available: Currency::from(5.0),
+                    },
+                },
+                Event::DisputeOpened {
+                    client: 1,
+                    currency: None,
+                    tx: 1,
+                    amount: Currency::from(5.0),
+                    reason: None,
+                    timestamp: None,
+                },
+                Event::ChargedBack {
+                    client: 1,
+                    currency: None,
+                    tx: 1,
+                    amount: Currency::from(5.0),
+                    written_off: Currency::ZERO,
+                    timestamp: None,
+                },
+                Event::AccountLocked {
+                    client: 1,
+                    currency: None,
+                    tx: 1,
+                    reason: crate::types::LockReason::Chargeback,
+                    timestamp: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_replay_policy_change() {
+        let policies = Policies {
+            allow_admin: true,
+            ..Policies::default()
+        };
+        let event = Event::PoliciesChanged {
+            dispatched: 3,
+            policies: Box::new(policies.clone()),
+            timestamp: Some(100),
+        };
+        assert_eq!(event.client(), None);
+
+        let json = serde_json::to_string(&event).unwrap();
+        let event: Event = serde_json::from_str(&json).unwrap();
+        let replayed = replay_events(vec![event], Policies::default()).unwrap();
+        assert_eq!(replayed.policies, policies);
+    }
+
+    #[test]
+    fn test_replay_rebuilds_state() {
+        let mix = TransactionWeights::default().distribution().unwrap();
+        let records =
+            TransactionGenerator::new(Some(500), 5, Currency::from(100.0), 1000, mix, 0.2)
+                .with_seed(7)
+                .map(|generated| generated.record)
+                .collect();
+        let (state, events) = handled(records);
+
+        // Events survive being written and read back
+        let events: Vec<Event> = events
+            .iter()
+            .map(|event| serde_json::from_str(&serde_json::to_string(event).unwrap()).unwrap())
+            .collect();
+        let replayed = replay_events(events, Policies::default()).unwrap();
+
+        let position = InputPosition::default();
+        assert_eq!(
+            Checkpoint::from_state(&replayed, position),
+            Checkpoint::from_state(&state, position)
+        );
+    }
+
+    #[test]
+    fn test_replay_representment() {
+        let (state, events) = handled(vec![
+            deposit(1, 1, 5.0),
+            dispute(1, 1).with_reason(DisputeReason::Fraud),
+            chargeback(1, 1),
+            representment(1, 1),
+        ]);
+        assert!(matches!(
+            &events[2],
+            Event::DisputeOpened {
+                reason: Some(DisputeReason::Fraud),
+                ..
+            }
+        ));
+        // The chargeback's lock is lifted
+        assert!(matches!(
+            &events[5..],
+            [
+                Event::ChargebackRepresented { .. },
+                Event::AccountUnlocked { .. }
+            ]
+        ));
+
+        let replayed = replay_events(events, Policies::default()).unwrap();
+        let position = InputPosition::default();
+        assert_eq!(
+            Checkpoint::from_state(&replayed, position),
+            Checkpoint::from_state(&state, position)
+        );
+        assert!(replayed.disputes.is_represented(1, 1));
+        assert_eq!(
+            replayed.disputes.disputed_amount(1, 1),
+            Some(Currency::from(5.0))
+        );
+    }
+
+    #[test]
+    fn test_replay_reversal() {
+        let (log, events) = EventLog::channel();
+        let policies = Policies {
+            allow_admin: true,
+            ..Default::default()
+        };
+        let mut state = State::with_policies(policies.clone());
+        state.events = Some(log);
+        for record in [deposit(1, 1, 5.0), withdrawal(1, 2, 2.0), reversal(1, 3, 2)] {
+            state.handle(record).unwrap();
+        }
+        state.events = None;
+        let events: Vec<_> = events.iter().collect();
+        assert!(matches!(
+            events.last(),
+            Some(Event::TransactionReversed {
+                reverses: 2,
+                change,
+                ..
+            }) if *change == Currency::from(2.0)
+        ));
+
+        let replayed = replay_events(events, policies).unwrap();
+        let position = InputPosition::default();
+        assert_eq!(
+            Checkpoint::from_state(&replayed, position),
+            Checkpoint::from_state(&state, position)
+        );
+        assert_eq!(replayed.transactions.reversal_of(1, 2), Some(3));
+    }
+}
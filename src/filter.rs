@@ -0,0 +1,235 @@
+//! Restricting a run to some of the records read, e.g. to one shard of clients,
+//! so that a giant file can be split across machines by hand, or to some types
+//! of transaction or a period of time, to see what balances would be without the rest.
+//! Records which don't match are ignored, as if they weren't in the input.
+
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::types::{ClientId, Timestamp, TransactionRecord, TransactionType};
+
+/// An inclusive range of client ids, e.g. `100-200`, or a single client, e.g. `150`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct ClientRange {
+    pub first: ClientId,
+    pub last: ClientId,
+}
+
+impl ClientRange {
+    /// Clients from `first` to `last`, which mustn't come before `first`.
+    pub fn new(first: ClientId, last: ClientId) -> Result<Self, String> {
+        if first > last {
+            return Err(format!("client range {}-{} is empty", first, last));
+        }
+        Ok(Self { first, last })
+    }
+
+    pub fn contains(&self, client_id: ClientId) -> bool {
+        (self.first..=self.last).contains(&client_id)
+    }
+}
+
+impl FromStr for ClientRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |id: &str| {
+            id.trim()
+                .parse::<ClientId>()
+                .map_err(|err| format!("invalid client id '{}': {}", id, err))
+        };
+        match s.split_once('-') {
+            Some((first, last)) => Self::new(parse(first)?, parse(last)?),
+            None => {
+                let client_id = parse(s)?;
+                Self::new(client_id, client_id)
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for ClientRange {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for ClientRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.first, self.last)
+    }
+}
+
+/// Every type which may be read, in the order they're numbered in a `TransactionTypes`.
+const TRANSACTION_TYPES: [TransactionType; 10] = [
+    TransactionType::Deposit,
+    TransactionType::Withdrawal,
+    TransactionType::Dispute,
+    TransactionType::Resolve,
+    TransactionType::Chargeback,
+    TransactionType::Lock,
+    TransactionType::Unlock,
+    TransactionType::Representment,
+    TransactionType::Reversal,
+    TransactionType::Interest,
+];
+
+/// A set of transaction types, parsed from their names separated by commas,
+/// e.g. `deposit,withdrawal`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TransactionTypes(u16);
+
+impl TransactionTypes {
+    /// Every type of transaction.
+    pub const ALL: Self = Self((1 << TRANSACTION_TYPES.len()) - 1);
+    pub const NONE: Self = Self(0);
+
+    fn bit(transaction_type: &TransactionType) -> u16 {
+        let index = TRANSACTION_TYPES
+            .iter()
+            .position(|t| t == transaction_type)
+            // Every type is listed
+            .unwrap();
+        1 << index
+    }
+
+    pub fn contains(&self, transaction_type: &TransactionType) -> bool {
+        self.0 & Self::bit(transaction_type) != 0
+    }
+
+    pub fn insert(&mut self, transaction_type: &TransactionType) {
+        self.0 |= Self::bit(transaction_type);
+    }
+
+    /// Types in this set, but not in `other`.
+    pub fn without(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+}
+
+impl Default for TransactionTypes {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+impl FromStr for TransactionTypes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut types = Self::NONE;
+        for name in s.split(',') {
+            let transaction_type = TRANSACTION_TYPES
+                .iter()
+                .find(|t| t.name() == name.trim())
+                .ok_or_else(|| format!("unknown transaction type '{}'", name))?;
+            types.insert(transaction_type);
+        }
+        Ok(types)
+    }
+}
+
+/// Which records a run handles. By default, every one.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RecordFilter {
+    /// Only handle transactions from these clients
+    pub clients: Option<ClientRange>,
+    /// Only handle transactions of these types
+    pub types: TransactionTypes,
+    /// Only handle transactions with a timestamp at or after this one
+    pub since: Option<Timestamp>,
+    /// Only handle transactions with a timestamp before this one
+    pub until: Option<Timestamp>,
+}
+
+impl RecordFilter {
+    /// Whether a record should be handled, rather than ignored.
+    /// With a time range, records without a timestamp can't be in it,
+    /// so they're ignored too.
+    pub fn matches(&self, record: &TransactionRecord) -> bool {
+        self.clients
+            .is_none_or(|clients| clients.contains(record.client_id))
+            && self.types.contains(&record.transaction_type)
+            && self.in_time_range(record.timestamp)
+    }
+
+    fn in_time_range(&self, timestamp: Option<Timestamp>) -> bool {
+        if self.since.is_none() && self.until.is_none() {
+            return true;
+        }
+        timestamp.is_some_and(|timestamp| {
+            self.since.is_none_or(|since| timestamp >= since)
+                && self.until.is_none_or(|until| timestamp < until)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClientRange, RecordFilter, TransactionTypes};
+    use crate::test_utils::{chargeback, deposit, withdrawal};
+    use crate::types::{ClientId, TransactionType};
+
+    #[test]
+    fn test_parse_client_range() {
+        assert_eq!("100-200".parse(), ClientRange::new(100, 200));
+        assert_eq!("150".parse(), ClientRange::new(150, 150));
+        assert_eq!(" 1 - 2 ".trim().parse(), ClientRange::new(1, 2));
+        assert!("200-100".parse::<ClientRange>().is_err());
+        let too_large = format!("1-{}", ClientId::MAX as u64 + 1);
+        assert!(too_large.parse::<ClientRange>().is_err());
+        assert!("a-b".parse::<ClientRange>().is_err());
+    }
+
+    #[test]
+    fn test_filter_clients() {
+        let filter = RecordFilter {
+            clients: Some(ClientRange::new(100, 200).unwrap()),
+            ..Default::default()
+        };
+        assert!(filter.matches(&deposit(100, 1, 1.0)));
+        assert!(filter.matches(&deposit(200, 2, 1.0)));
+        assert!(!filter.matches(&deposit(99, 3, 1.0)));
+        assert!(!filter.matches(&deposit(201, 4, 1.0)));
+        assert!(RecordFilter::default().matches(&deposit(1, 5, 1.0)));
+    }
+
+    #[test]
+    fn test_filter_types() {
+        let types: TransactionTypes = "deposit, withdrawal".parse().unwrap();
+        assert!(types.contains(&TransactionType::Withdrawal));
+        assert!(!types.contains(&TransactionType::Dispute));
+        assert!("deposit,refund".parse::<TransactionTypes>().is_err());
+
+        let filter = RecordFilter {
+            types: TransactionTypes::ALL.without("chargeback".parse().unwrap()),
+            ..Default::default()
+        };
+        assert!(filter.matches(&withdrawal(1, 1, 1.0)));
+        assert!(!filter.matches(&chargeback(1, 1)));
+    }
+
+    #[test]
+    fn test_filter_time_range() {
+        let filter = RecordFilter {
+            since: Some(100),
+            until: Some(200),
+            ..Default::default()
+        };
+        let at = |timestamp| {
+            let mut record = deposit(1, 1, 1.0);
+            record.timestamp = timestamp;
+            filter.matches(&record)
+        };
+        assert!(at(Some(100)));
+        assert!(at(Some(199)));
+        assert!(!at(Some(99)));
+        assert!(!at(Some(200)));
+        assert!(!at(None));
+    }
+}
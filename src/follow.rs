@@ -0,0 +1,247 @@
+//! Following a file as it grows, like `tail -f`, handling each row
+//! as it's appended rather than stopping at the end of the file.
+
+use csv::StringRecord;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Sender, SyncSender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::construct_csv_reader;
+use crate::headers::HeaderMapping;
+use crate::input::{report_unreadable, RecordSource, TaggedRecord};
+use crate::mmap::{count_lines, parse_chunk};
+use crate::source::ParseError;
+
+/// How long to wait for more rows after reaching the end of the file.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A CSV file which is read as rows are appended to it,
+/// until stopped with the flag from `stop_handle`.
+///
+/// Only complete lines are read, so a row being written is left until it ends,
+/// and as with `MappedInputs`, quoted fields mustn't contain line breaks.
+/// While waiting for more rows, an empty batch is sent every `POLL_INTERVAL`,
+/// so that the pipeline keeps checking for operator input, e.g. to rewrite its output.
+pub struct FollowedInput {
+    file: fs::File,
+    stop: Arc<AtomicBool>,
+    headers: HeaderMapping,
+}
+
+impl FollowedInput {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self {
+            file: fs::File::open(path)?,
+            stop: Default::default(),
+            headers: HeaderMapping::default(),
+        })
+    }
+
+    /// Read columns under other names, as given by `headers`.
+    pub fn with_header_mapping(mut self, headers: HeaderMapping) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    /// Flag which, once set, stops following after reading whatever has been written so far.
+    pub fn stop_handle(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    fn read_records_inner(
+        mut self,
+        headers_snd: SyncSender<Vec<StringRecord>>,
+        records_snd: SyncSender<Vec<TaggedRecord>>,
+        errors_snd: &Sender<ParseError>,
+        batch_size: usize,
+        notrim: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        // Bytes read, but not yet a complete line
+        let mut pending = Vec::new();
+        // Lines already read, to number those which can't be
+        let mut lines_read = 0;
+        let mut num_fields = None;
+        loop {
+            // Checked before reading, so that everything written before stopping is read
+            let stopping = self.stop.load(Ordering::Relaxed);
+            let num_read = self.file.read_to_end(&mut pending)?;
+            let end = if stopping {
+                // The last line is complete if nothing more is coming
+                pending.len()
+            } else {
+                match pending.iter().rposition(|&byte| byte == b'\n') {
+                    Some(newline) => newline + 1,
+                    None => 0,
+                }
+            };
+            let lines: Vec<u8> = pending.drain(..end).collect();
+
+            let mut body = &lines[..];
+            if num_fields.is_none() && !body.is_empty() {
+                let (header_row, rest) = self.headers.split_header_row(body);
+                let headers = self
+                    .headers
+                    .read_headers(&mut construct_csv_reader(header_row, notrim, true))?;
+                num_fields = Some(headers.len());
+                headers_snd.send(vec![headers])?;
+                body = rest;
+            }
+            if let Some(num_fields) = num_fields {
+                let lines_before = lines_read + count_lines(&lines[..lines.len() - body.len()]);
+                let records = parse_chunk(body, num_fields, notrim, || lines_before, errors_snd);
+                if records.is_empty() {
+                    records_snd.send(Vec::new())?;
+                }
+                let mut records = records.into_iter().map(|record| (0, record)).peekable();
+                while records.peek().is_some() {
+                    records_snd.send((&mut records).take(batch_size).collect())?;
+                }
+            }
+
+            lines_read += count_lines(&lines);
+
+            if stopping {
+                return Ok(());
+            }
+            if num_read == 0 {
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+    }
+}
+
+impl RecordSource for FollowedInput {
+    fn num_inputs(&self) -> usize {
+        1
+    }
+
+    fn read_records(
+        self,
+        headers_snd: SyncSender<Vec<StringRecord>>,
+        records_snd: SyncSender<Vec<TaggedRecord>>,
+        errors_snd: Sender<ParseError>,
+        batch_size: usize,
+        notrim: bool,
+    ) {
+        let result =
+            self.read_records_inner(headers_snd, records_snd, &errors_snd, batch_size, notrim);
+        if let Err(err) = result {
+            report_unreadable(ParseError::new(None, err), &errors_snd);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FollowedInput;
+    use crate::control::Control;
+    use crate::input::RecordSource;
+    use crate::pipeline::PipelineConfig;
+    use crate::policy::Policies;
+    use crate::run_inputs;
+    use crate::state::AccountOrder;
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+    use std::sync::atomic::Ordering;
+    use std::sync::mpsc::{channel, sync_channel};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// Wait up to a few seconds for a file to have the given contents.
+    fn wait_for_contents(path: &std::path::Path, contents: &str) {
+        let start = Instant::now();
+        while fs::read_to_string(path).ok().as_deref() != Some(contents) {
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "'{}' never became {:?}",
+                path.display(),
+                contents
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_follow_appended_rows() {
+        let path = env::temp_dir().join(format!("follow-{}.csv", std::process::id()));
+        let mut file = fs::File::create(&path).unwrap();
+        file.write_all(b"type,client,tx\ndeposit,1,1\ndepo")
+            .unwrap();
+
+        let input = FollowedInput::open(&path).unwrap();
+        let stop = input.stop_handle();
+        let (headers_snd, headers_rcv) = sync_channel(1);
+        let (records_snd, records_rcv) = sync_channel(100);
+        let (errors_snd, errors_rcv) = channel();
+        let reader = thread::spawn(move || {
+            input.read_records(headers_snd, records_snd, errors_snd, 10, false)
+        });
+
+        assert_eq!(headers_rcv.recv().unwrap().len(), 1);
+        let tx_ids = || {
+            records_rcv
+                .iter()
+                .find(|batch| !batch.is_empty())
+                .unwrap()
+                .into_iter()
+                .map(|(_, record)| record[2].to_string())
+                .collect::<Vec<_>>()
+        };
+        // The partial row waits until it's finished
+        assert_eq!(tx_ids(), vec!["1"]);
+        file.write_all(b"sit,1,2\ndeposit,1,3\n").unwrap();
+        assert_eq!(tx_ids(), vec!["2", "3"]);
+
+        // Once stopped, even an unfinished last row is read
+        file.write_all(b"deposit,1,4").unwrap();
+        stop.store(true, Ordering::Relaxed);
+        reader.join().unwrap();
+        assert_eq!(errors_rcv.try_iter().count(), 0);
+        let remaining: Vec<_> = records_rcv.iter().flatten().collect();
+        assert_eq!(remaining.last().unwrap().1[2].to_string(), "4");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_periodic_output_while_following() {
+        let dir = env::temp_dir();
+        let input_path = dir.join(format!("follow-input-{}.csv", std::process::id()));
+        let output_path = dir.join(format!("follow-output-{}.csv", std::process::id()));
+        let mut file = fs::File::create(&input_path).unwrap();
+        file.write_all(b"type,client,tx,amount\ndeposit,1,1,5.0\n")
+            .unwrap();
+
+        let input = FollowedInput::open(&input_path).unwrap();
+        let stop = input.stop_handle();
+        let control = Control::new(None, None, None).with_periodic_output(
+            output_path.clone(),
+            AccountOrder::Client,
+            Duration::ZERO,
+        );
+        let config = PipelineConfig::default();
+        let runner = thread::spawn(move || {
+            run_inputs(input, config, Policies::default(), None, Some(control))
+        });
+
+        let header = "client,available,held,total,locked\n";
+        wait_for_contents(&output_path, &format!("{}1,5.0,0.0,5.0,false\n", header));
+        file.write_all(b"deposit,2,2,1.0\n").unwrap();
+        wait_for_contents(
+            &output_path,
+            &format!("{}1,5.0,0.0,5.0,false\n2,1.0,0.0,1.0,false\n", header),
+        );
+
+        stop.store(true, Ordering::Relaxed);
+        let state = runner.join().unwrap();
+        assert_eq!(state.accounts.len(), 2);
+        fs::remove_file(&input_path).unwrap();
+        fs::remove_file(&output_path).unwrap();
+    }
+}
@@ -0,0 +1,274 @@
+//! gRPC service on top of a shared `State`, as defined in `proto/payments.proto`.
+//! Messages mirror the CSV schema, with amounts as decimal strings.
+//! Transactions with malformed fields, e.g. an amount which can't be parsed,
+//! fail with `INVALID_ARGUMENT`, while transactions the engine rejects
+//! succeed with a `Rejection` carrying the error code.
+
+use std::convert::{TryFrom, TryInto};
+use std::pin::Pin;
+
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::service::SharedState;
+use crate::types::{OutputRecord, TransactionRecord, TransactionType};
+
+/// Code generated from `proto/payments.proto`.
+pub mod proto {
+    tonic::include_proto!("payments");
+}
+
+use proto::payments_server::{Payments, PaymentsServer};
+use proto::submit_response;
+
+/// Handles gRPC requests against a shared `State`.
+pub struct PaymentsService {
+    state: SharedState,
+}
+
+impl PaymentsService {
+    pub fn new(state: SharedState) -> Self {
+        Self { state }
+    }
+
+    /// Wrap the service to be added to a `tonic` server.
+    pub fn into_server(self) -> PaymentsServer<Self> {
+        PaymentsServer::new(self)
+    }
+}
+
+impl TryFrom<proto::Transaction> for TransactionRecord {
+    type Error = Status;
+
+    fn try_from(tx: proto::Transaction) -> Result<Self, Status> {
+        let transaction_type = match proto::TransactionType::try_from(tx.r#type) {
+            Ok(proto::TransactionType::Deposit) => TransactionType::Deposit,
+            Ok(proto::TransactionType::Withdrawal) => TransactionType::Withdrawal,
+            Ok(proto::TransactionType::Dispute) => TransactionType::Dispute,
+            Ok(proto::TransactionType::Resolve) => TransactionType::Resolve,
+            Ok(proto::TransactionType::Chargeback) => TransactionType::Chargeback,
+            Ok(proto::TransactionType::Lock) => TransactionType::Lock,
+            Ok(proto::TransactionType::Unlock) => TransactionType::Unlock,
+            Ok(proto::TransactionType::Representment) => TransactionType::Representment,
+            Ok(proto::TransactionType::Reversal) => TransactionType::Reversal,
+            Ok(proto::TransactionType::Unspecified) | Err(_) => {
+                return Err(Status::invalid_argument(format!(
+                    "unknown transaction type {}",
+                    tx.r#type
+                )))
+            }
+        };
+        let client_id = tx
+            .client
+            .try_into()
+            .map_err(|_| Status::invalid_argument(format!("client {} is too large", tx.client)))?;
+        let tx_id = tx
+            .tx
+            .try_into()
+            .map_err(|_| Status::invalid_argument(format!("tx {} is too large", tx.tx)))?;
+        let reverses = tx
+            .reverses
+            .map(|reverses| {
+                reverses.try_into().map_err(|_| {
+                    Status::invalid_argument(format!("reverses {} is too large", reverses))
+                })
+            })
+            .transpose()?;
+        let amount = tx
+            .amount
+            .map(|amount| amount.parse())
+            .transpose()
+            .map_err(|err| Status::invalid_argument(format!("{}", err)))?;
+        let currency = tx
+            .currency
+            .map(|currency| currency.parse())
+            .transpose()
+            .map_err(|err| Status::invalid_argument(format!("{}", err)))?;
+        let reason = tx
+            .reason
+            .map(|reason| reason.parse())
+            .transpose()
+            .map_err(Status::invalid_argument)?;
+
+        Ok(TransactionRecord {
+            transaction_type,
+            client_id,
+            tx_id,
+            amount,
+            timestamp: tx.timestamp,
+            currency,
+            reason,
+            reverses,
+            effective: tx.effective,
+        })
+    }
+}
+
+impl From<OutputRecord> for proto::Balance {
+    fn from(record: OutputRecord) -> Self {
+        Self {
+            client: record.client.into(),
+            currency: record
+                .currency
+                .map(|currency| currency.as_str().to_string()),
+            available: record.available.to_string(),
+            held: record.held.to_string(),
+            total: record.total.to_string(),
+            locked: record.locked,
+        }
+    }
+}
+
+/// Handle a transaction, responding with either its account's balances or why it was rejected.
+fn submit(state: &SharedState, tx: proto::Transaction) -> Result<proto::SubmitResponse, Status> {
+    let tx_id = tx.tx;
+    let record = TransactionRecord::try_from(tx)?;
+    let result = match state.submit(record) {
+        Ok(balance) => submit_response::Result::Balance(balance.into()),
+        Err(err) => submit_response::Result::Rejection(proto::Rejection {
+            code: err.code().to_string(),
+            message: err.to_string(),
+        }),
+    };
+    Ok(proto::SubmitResponse {
+        tx: tx_id,
+        result: Some(result),
+    })
+}
+
+#[tonic::async_trait]
+impl Payments for PaymentsService {
+    async fn submit_transaction(
+        &self,
+        request: Request<proto::Transaction>,
+    ) -> Result<Response<proto::SubmitResponse>, Status> {
+        submit(&self.state, request.into_inner()).map(Response::new)
+    }
+
+    type StreamTransactionsStream =
+        Pin<Box<dyn Stream<Item = Result<proto::SubmitResponse, Status>> + Send>>;
+
+    async fn stream_transactions(
+        &self,
+        request: Request<Streaming<proto::Transaction>>,
+    ) -> Result<Response<Self::StreamTransactionsStream>, Status> {
+        let state = self.state.clone();
+        let responses = request
+            .into_inner()
+            .map(move |tx| tx.and_then(|tx| submit(&state, tx)));
+        Ok(Response::new(Box::pin(responses)))
+    }
+
+    async fn get_balances(
+        &self,
+        request: Request<proto::GetBalancesRequest>,
+    ) -> Result<Response<proto::Balances>, Status> {
+        let client = match request.into_inner().client {
+            Some(client) => Some(client.try_into().map_err(|_| {
+                Status::invalid_argument(format!("client {} is too large", client))
+            })?),
+            None => None,
+        };
+        let balances = self
+            .state
+            .balances(client)
+            .map_err(|err| Status::internal(err.to_string()))?
+            .into_iter()
+            .map(proto::Balance::from)
+            .collect();
+        Ok(Response::new(proto::Balances { balances }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::proto::payments_server::Payments;
+    use super::proto::{self, submit_response};
+    use super::PaymentsService;
+    use crate::service::SharedState;
+    use crate::state::State;
+    use tonic::{Code, Request};
+
+    fn deposit(client: u32, tx: u64, amount: &str) -> proto::Transaction {
+        proto::Transaction {
+            r#type: proto::TransactionType::Deposit.into(),
+            client,
+            tx,
+            amount: Some(amount.to_string()),
+            timestamp: None,
+            currency: None,
+            reason: None,
+            reverses: None,
+            effective: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_submit_transaction() {
+        let service = PaymentsService::new(SharedState::new(State::new()));
+
+        let response = service
+            .submit_transaction(Request::new(deposit(1, 1, "2.5")))
+            .await
+            .unwrap()
+            .into_inner();
+        let balance = proto::Balance {
+            client: 1,
+            currency: None,
+            available: "2.5".to_string(),
+            held: "0.0".to_string(),
+            total: "2.5".to_string(),
+            locked: false,
+        };
+        assert_eq!(
+            response.result,
+            Some(submit_response::Result::Balance(balance))
+        );
+
+        // Rejected by the engine
+        let response = service
+            .submit_transaction(Request::new(deposit(1, 1, "1.0")))
+            .await
+            .unwrap()
+            .into_inner();
+        match response.result {
+            Some(submit_response::Result::Rejection(rejection)) => {
+                assert_eq!(rejection.code, "DUPLICATE_TX")
+            }
+            other => panic!("Expected a rejection, got {:?}", other),
+        }
+
+        // Malformed
+        let status = service
+            .submit_transaction(Request::new(deposit(1, 2, "lots")))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_get_balances() {
+        let service = PaymentsService::new(SharedState::new(State::new()));
+        for (client, tx) in [(2, 1), (1, 2)] {
+            service
+                .submit_transaction(Request::new(deposit(client, tx, "1.0")))
+                .await
+                .unwrap();
+        }
+
+        let service = &service;
+        let clients = |client| async move {
+            service
+                .get_balances(Request::new(proto::GetBalancesRequest { client }))
+                .await
+                .unwrap()
+                .into_inner()
+                .balances
+                .into_iter()
+                .map(|balance| balance.client)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(clients(None).await, vec![1, 2]);
+        assert_eq!(clients(Some(2)).await, vec![2]);
+    }
+}
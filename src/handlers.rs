@@ -1,17 +1,175 @@
-use crate::account::{AccountAccess, BaseAccountFeatures, UnlockedAccountFeatures};
-use crate::currency::round_currency;
-use crate::state::State;
-use crate::types::{Chargeback, Deposit, Dispute, Resolve, Withdrawal};
+use crate::currency::Currency;
+use crate::events::{Event, EventLog};
+use crate::ledger::LedgerAccount::{Available, External, Fees, Held, WriteOff};
+use crate::ledger::{Ledger, LedgerAccount};
+use crate::limits::ActivityState;
+use crate::policy::{DisputePolicy, Limits, Policies};
+use crate::risk::RiskScorers;
+use crate::settlement::{Settlement, SettlementTotals};
+use crate::state::{DisputesState, State};
+use crate::telemetry;
+use crate::traits::{Disputable, Transaction};
+use crate::types::{Account, AccountKey, ClientId, LockInfo, LockReason, Timestamp, TransactionId};
+use crate::types::{
+    Chargeback, Deposit, Dispute, Lock, Representment, Resolve, Reversal, Unlock, Withdrawal,
+};
 use crate::types::{TransactionContainer, TransactionError, TransactionRecord, TransactionType};
 use crate::validate;
 
-fn handle_deposit(deposit: Deposit, state: &mut State) -> Result<(), TransactionError> {
-    log::trace!("Handling {:?}", deposit);
+/// Record a balance change in the ledger, if it's enabled.
+fn post(
+    ledger: &mut Option<Ledger>,
+    tx_id: TransactionId,
+    key: AccountKey,
+    debit: LedgerAccount,
+    credit: LedgerAccount,
+    amount: Currency,
+) {
+    if let Some(ledger) = ledger {
+        ledger.post(tx_id, key, debit, credit, amount);
+    }
+}
+
+/// Add a transaction to the settlement totals in its currency, if they're being kept.
+fn settle(
+    settlement: &mut Option<Settlement>,
+    (_, currency): AccountKey,
+    add: impl FnOnce(&mut SettlementTotals),
+) {
+    if let Some(settlement) = settlement {
+        add(settlement.totals_mut(currency));
+    }
+}
+
+/// Record an event of the transaction being handled, if events are being logged.
+fn emit(events: &mut Option<EventLog>, event: Event) {
+    if let Some(events) = events {
+        events.record(event);
+    }
+}
+
+/// What a client's deposits and withdrawals are checked against.
+fn checks<'a>(
+    policies: &'a Policies,
+    limits: &'a Limits,
+    activity: &'a ActivityState,
+    risk_scorers: &'a RiskScorers,
+) -> validate::Checks<'a> {
+    validate::Checks {
+        validation: &policies.validation,
+        limits,
+        activity,
+        risk: &policies.risk,
+        risk_scorers,
+    }
+}
+
+/// Why a transaction was rejected, when it would take one of the account's balances
+/// beyond what can be represented. The account is left as it was.
+fn overflow(
+    client: ClientId,
+    tx: TransactionId,
+    account: &Account,
+    amount: Currency,
+) -> TransactionError {
+    validate::balance_overflow(client, tx, Some(account), amount)
+}
+
+/// The amount held by a transaction's dispute.
+fn disputed_amount(
+    disputes: &DisputesState,
+    client_id: ClientId,
+    tx_id: TransactionId,
+) -> Result<Currency, TransactionError> {
+    disputes.disputed_amount(client_id, tx_id).ok_or_else(|| {
+        TransactionError::UnexpectedError(format!(
+            "Cannot retrieve disputed amount for transaction {}",
+            tx_id
+        ))
+    })
+}
+
+/// Note that a risk check flagged the account for review.
+fn flag_account(events: &mut Option<EventLog>, key: AccountKey, tx: TransactionId, reason: String) {
+    tracing::info!(client = key.0, tx, "Flagged account for review: {}", reason);
+    let event = Event::AccountFlagged {
+        client: key.0,
+        currency: key.1,
+        tx,
+        reason,
+    };
+    emit(events, event);
+}
+
+fn handle_deposit(mut deposit: Deposit, state: &mut State) -> Result<(), TransactionError> {
+    tracing::trace!("Handling {:?}", deposit);
     let client_id = deposit.client_id;
     let tx_id = deposit.tx_id;
-    match validate::validate_deposit(deposit, &mut state.accounts, &state.transactions) {
-        Ok((valid_deposit, mut account)) => {
-            account.modify_balances_for_deposit(&valid_deposit);
+    let key = (client_id, deposit.currency);
+    let rounding = &state.policies.rounding;
+    deposit.amount = rounding.round(deposit.amount);
+    let fee = state.policies.fees.deposit.fee(deposit.amount, rounding);
+    if state.policies.validation.idempotent
+        && validate::is_resubmission(
+            TransactionType::Deposit,
+            key,
+            tx_id,
+            deposit.amount,
+            &state.transactions,
+        )
+    {
+        tracing::debug!("Ignoring resubmitted deposit {}", tx_id);
+        return Ok(());
+    }
+    let limits = &state.policies.limits.limits(client_id);
+    match validate::validate_deposit(
+        deposit,
+        fee,
+        state.policies.max_balance,
+        &mut state.accounts,
+        &state.transactions,
+        checks(
+            &state.policies,
+            limits,
+            &state.activity,
+            &state.risk_scorers,
+        ),
+    )
+    .and_then(|(deposit, mut account, flag)| {
+        account
+            .modify_balances_for_deposit(&deposit, fee)
+            .map_err(|_| overflow(client_id, tx_id, account.view(), deposit.amount))?;
+        Ok((deposit, account, flag))
+    }) {
+        Ok((valid_deposit, mut account, flag)) => {
+            if let Some(reason) = flag {
+                account.flag();
+                flag_account(&mut state.events, key, tx_id, reason);
+            }
+            state.activity.record_deposit(&valid_deposit, limits);
+            let ledger = &mut state.ledger;
+            post(
+                ledger,
+                tx_id,
+                key,
+                External,
+                Available,
+                valid_deposit.amount,
+            );
+            post(ledger, tx_id, key, Available, Fees, fee);
+            let amount = valid_deposit.amount;
+            settle(&mut state.settlement, key, |totals| {
+                totals.deposit(amount, fee)
+            });
+            let event = Event::FundsDeposited {
+                client: client_id,
+                currency: key.1,
+                tx: tx_id,
+                amount: valid_deposit.amount,
+                fee,
+                timestamp: valid_deposit.timestamp,
+            };
+            emit(&mut state.events, event);
             state.transactions.insert(
                 client_id,
                 tx_id,
@@ -20,23 +178,103 @@ fn handle_deposit(deposit: Deposit, state: &mut State) -> Result<(), Transaction
             Ok(())
         }
         Err(err) => {
-            state.transactions.insert(
-                client_id,
-                tx_id,
-                TransactionContainer::Deposit(Err(err.clone())),
-            );
+            // A duplicate id still refers to the transaction which first took it
+            if !matches!(err, TransactionError::DuplicateTxId { .. }) {
+                let event = Event::TransactionFailed {
+                    client: client_id,
+                    tx: tx_id,
+                    transaction_type: TransactionType::Deposit,
+                    error: err.clone(),
+                };
+                emit(&mut state.events, event);
+                state.transactions.insert(
+                    client_id,
+                    tx_id,
+                    TransactionContainer::Deposit(Err(err.clone())),
+                );
+            }
             Err(err)
         }
     }
 }
 
-fn handle_withdrawal(withdrawal: Withdrawal, state: &mut State) -> Result<(), TransactionError> {
-    log::trace!("Handling {:?}", withdrawal);
+fn handle_withdrawal(
+    mut withdrawal: Withdrawal,
+    state: &mut State,
+) -> Result<(), TransactionError> {
+    tracing::trace!("Handling {:?}", withdrawal);
     let client_id = withdrawal.client_id;
     let tx_id = withdrawal.tx_id;
-    match validate::validate_withdrawal(withdrawal, &mut state.accounts, &state.transactions) {
-        Ok((valid_withdrawal, mut account)) => {
-            account.modify_balances_for_withdrawal(&valid_withdrawal);
+    let key = (client_id, withdrawal.currency);
+    let rounding = &state.policies.rounding;
+    withdrawal.amount = rounding.round(withdrawal.amount);
+    let fee = state
+        .policies
+        .fees
+        .withdrawal
+        .fee(withdrawal.amount, rounding);
+    let credit_limit = state.policies.credit.limit(client_id);
+    if state.policies.validation.idempotent
+        && validate::is_resubmission(
+            TransactionType::Withdrawal,
+            key,
+            tx_id,
+            withdrawal.amount,
+            &state.transactions,
+        )
+    {
+        tracing::debug!("Ignoring resubmitted withdrawal {}", tx_id);
+        return Ok(());
+    }
+    let limits = &state.policies.limits.limits(client_id);
+    match validate::validate_withdrawal(
+        withdrawal,
+        fee,
+        credit_limit,
+        &mut state.accounts,
+        &state.transactions,
+        checks(
+            &state.policies,
+            limits,
+            &state.activity,
+            &state.risk_scorers,
+        ),
+    )
+    .and_then(|(withdrawal, mut account, flag)| {
+        account
+            .modify_balances_for_withdrawal(&withdrawal, fee)
+            .map_err(|_| overflow(client_id, tx_id, account.view(), withdrawal.amount))?;
+        Ok((withdrawal, account, flag))
+    }) {
+        Ok((valid_withdrawal, mut account, flag)) => {
+            if let Some(reason) = flag {
+                account.flag();
+                flag_account(&mut state.events, key, tx_id, reason);
+            }
+            state.activity.record_withdrawal(&valid_withdrawal, limits);
+            let ledger = &mut state.ledger;
+            post(
+                ledger,
+                tx_id,
+                key,
+                Available,
+                External,
+                valid_withdrawal.amount,
+            );
+            post(ledger, tx_id, key, Available, Fees, fee);
+            let amount = valid_withdrawal.amount;
+            settle(&mut state.settlement, key, |totals| {
+                totals.withdraw(amount, fee)
+            });
+            let event = Event::FundsWithdrawn {
+                client: client_id,
+                currency: key.1,
+                tx: tx_id,
+                amount: valid_withdrawal.amount,
+                fee,
+                timestamp: valid_withdrawal.timestamp,
+            };
+            emit(&mut state.events, event);
             state.transactions.insert(
                 client_id,
                 tx_id,
@@ -45,48 +283,137 @@ fn handle_withdrawal(withdrawal: Withdrawal, state: &mut State) -> Result<(), Tr
             Ok(())
         }
         Err(err) => {
-            state.transactions.insert(
-                client_id,
-                tx_id,
-                TransactionContainer::Withdrawal(Err(err.clone())),
-            );
+            // A duplicate id still refers to the transaction which first took it
+            if !matches!(err, TransactionError::DuplicateTxId { .. }) {
+                let event = Event::TransactionFailed {
+                    client: client_id,
+                    tx: tx_id,
+                    transaction_type: TransactionType::Withdrawal,
+                    error: err.clone(),
+                };
+                emit(&mut state.events, event);
+                state.transactions.insert(
+                    client_id,
+                    tx_id,
+                    TransactionContainer::Withdrawal(Err(err.clone())),
+                );
+            }
             Err(err)
         }
     }
 }
 
-fn handle_dispute(dispute: Dispute, state: &mut State) -> Result<(), TransactionError> {
-    log::trace!("Handling {:?}", dispute);
-    let client_id = dispute.client_id;
+fn handle_dispute(mut dispute: Dispute, state: &mut State) -> Result<(), TransactionError> {
+    tracing::trace!("Handling {:?}", dispute);
+    let rounding = &state.policies.rounding;
+    dispute.amount = dispute.amount.map(|amount| rounding.round(amount));
     let tx_id = dispute.tx_id;
+    let requested_amount = dispute.amount;
+    let reason = dispute.reason;
+    let timestamp = dispute.timestamp;
     match validate::validate_dispute(
         dispute,
         &mut state.accounts,
         &state.transactions,
         &state.disputes,
+        (&state.policies.dispute, &state.policies.validation),
     ) {
         Ok((disputed_tx, mut account)) => {
-            account.modify_balances_for_dispute(disputed_tx);
-            state.disputes.dispute_tx(client_id, tx_id)?;
+            let client_id = disputed_tx.get_client_id();
+            let key = (client_id, disputed_tx.get_currency());
+            // Dispute the whole transaction unless otherwise specified
+            let amount = requested_amount.unwrap_or_else(|| disputed_tx.disputable_amount());
+            let reopened = state.disputes.is_settled(client_id, tx_id);
+            account
+                .modify_balances_for_dispute(disputed_tx, amount)
+                .map_err(|_| overflow(client_id, tx_id, account.view(), amount))?;
+            state
+                .disputes
+                .dispute_tx(client_id, tx_id, amount, reason, timestamp)?;
+            if reopened {
+                telemetry::record_dispute_reopened();
+            }
+            post(&mut state.ledger, tx_id, key, Available, Held, amount);
+            settle(&mut state.settlement, key, |totals| totals.dispute(amount));
+            let event = Event::DisputeOpened {
+                client: client_id,
+                currency: key.1,
+                tx: tx_id,
+                amount,
+                reason,
+                timestamp,
+            };
+            emit(&mut state.events, event);
             Ok(())
         }
         Err(err) => Err(err),
     }
 }
 
+/// Arrange for a settled dispute to be forgotten once the transaction
+/// can no longer be disputed, if the policy says so.
+fn schedule_expiry(
+    disputes: &mut DisputesState,
+    policy: &DisputePolicy,
+    client_id: ClientId,
+    tx_id: TransactionId,
+    occurred_at: Option<Timestamp>,
+) {
+    if let (true, Some(_), Some(occurred_at)) =
+        (policy.compact_settled, policy.max_age, occurred_at)
+    {
+        disputes.schedule_expiry(client_id, tx_id, occurred_at);
+    }
+}
+
+/// Forget settled disputes which have aged out as of the given time, if the policy says so.
+fn expire_settled(state: &mut State, now: Option<Timestamp>) {
+    let policy = &state.policies.dispute;
+    if let (true, Some(max_age), Some(now)) = (policy.compact_settled, policy.max_age, now) {
+        let expired = state.disputes.expire_settled(now, max_age);
+        if expired > 0 {
+            tracing::debug!("Forgot {} settled disputes", expired);
+            telemetry::record_disputes_expired(expired);
+        }
+    }
+}
+
 fn handle_resolve(resolve: Resolve, state: &mut State) -> Result<(), TransactionError> {
-    log::trace!("Handling {:?}", resolve);
-    let client_id = resolve.client_id;
+    tracing::trace!("Handling {:?}", resolve);
     let tx_id = resolve.tx_id;
+    let timestamp = resolve.timestamp;
     match validate::validate_post_dispute(
         resolve,
         &mut state.accounts,
         &state.transactions,
         &state.disputes,
+        &state.policies.validation,
     ) {
         Ok((disputed_tx, mut access)) => {
-            access.modify_balances_for_resolve(disputed_tx);
-            state.disputes.settle_dispute(client_id, tx_id)?;
+            let client_id = disputed_tx.get_client_id();
+            let key = (client_id, disputed_tx.get_currency());
+            let amount = disputed_amount(&state.disputes, client_id, tx_id)?;
+            access
+                .modify_balances_for_resolve(disputed_tx, amount)
+                .map_err(|_| overflow(client_id, tx_id, access.view(), amount))?;
+            state.disputes.resolve(client_id, tx_id, timestamp)?;
+            schedule_expiry(
+                &mut state.disputes,
+                &state.policies.dispute,
+                client_id,
+                tx_id,
+                disputed_tx.get_timestamp(),
+            );
+            post(&mut state.ledger, tx_id, key, Held, Available, amount);
+            settle(&mut state.settlement, key, |totals| totals.resolve(amount));
+            let event = Event::DisputeResolved {
+                client: client_id,
+                currency: key.1,
+                tx: tx_id,
+                amount,
+                timestamp,
+            };
+            emit(&mut state.events, event);
             Ok(())
         }
         Err(err) => Err(err),
@@ -94,42 +421,273 @@ fn handle_resolve(resolve: Resolve, state: &mut State) -> Result<(), Transaction
 }
 
 fn handle_chargeback(chargeback: Chargeback, state: &mut State) -> Result<(), TransactionError> {
-    log::trace!("Handling {:?}", chargeback);
-    let client_id = chargeback.client_id;
+    tracing::trace!("Handling {:?}", chargeback);
     let tx_id = chargeback.tx_id;
+    let timestamp = chargeback.timestamp;
     match validate::validate_post_dispute(
         chargeback,
         &mut state.accounts,
         &state.transactions,
         &state.disputes,
+        &state.policies.validation,
     ) {
         Ok((disputed_tx, mut access)) => {
-            access.modify_balances_for_chargeback(disputed_tx);
-            if let AccountAccess::Unlocked(mut account) = access {
-                account.lock();
+            let client_id = disputed_tx.get_client_id();
+            let key = (client_id, disputed_tx.get_currency());
+            let amount = disputed_amount(&state.disputes, client_id, tx_id)?;
+            let shortfall = validate::check_chargeback_funds(
+                client_id,
+                tx_id,
+                access.view(),
+                amount,
+                state.policies.chargeback,
+            )?;
+            access
+                .modify_balances_for_chargeback(disputed_tx, amount, shortfall)
+                .map_err(|_| overflow(client_id, tx_id, access.view(), amount))?;
+
+            state.disputes.charge_back(client_id, tx_id, timestamp)?;
+            schedule_expiry(
+                &mut state.disputes,
+                &state.policies.dispute,
+                client_id,
+                tx_id,
+                disputed_tx.get_timestamp(),
+            );
+            state.transactions.record_chargeback(client_id, tx_id);
+            post(&mut state.ledger, tx_id, key, Held, External, amount);
+            if shortfall.is_positive() {
+                tracing::warn!(
+                    "Wrote off {} from chargeback {} for client {}",
+                    shortfall,
+                    tx_id,
+                    client_id
+                );
+                post(
+                    &mut state.ledger,
+                    tx_id,
+                    key,
+                    WriteOff,
+                    Available,
+                    shortfall,
+                );
+            }
+            settle(&mut state.settlement, key, |totals| {
+                totals.charge_back(amount, shortfall)
+            });
+            let event = Event::ChargedBack {
+                client: client_id,
+                currency: key.1,
+                tx: tx_id,
+                amount,
+                written_off: shortfall,
+                timestamp,
+            };
+            emit(&mut state.events, event);
+            if let Ok(account) = access.try_unlocked() {
+                account.lock(LockInfo {
+                    reason: LockReason::Chargeback,
+                    tx: tx_id,
+                    timestamp,
+                });
+                let event = Event::AccountLocked {
+                    client: client_id,
+                    currency: key.1,
+                    tx: tx_id,
+                    reason: LockReason::Chargeback,
+                    timestamp,
+                };
+                emit(&mut state.events, event);
             }
-            state.disputes.settle_dispute(client_id, tx_id)?;
             Ok(())
         }
         Err(err) => Err(err),
     }
 }
 
+fn handle_representment(
+    representment: Representment,
+    state: &mut State,
+) -> Result<(), TransactionError> {
+    tracing::trace!("Handling {:?}", representment);
+    let tx_id = representment.tx_id;
+    let timestamp = representment.timestamp;
+    let (disputed_tx, mut access, amount) = validate::validate_representment(
+        representment,
+        &mut state.accounts,
+        &state.transactions,
+        &state.disputes,
+        &state.policies.validation,
+    )?;
+    let client_id = disputed_tx.get_client_id();
+    let key = (client_id, disputed_tx.get_currency());
+    access
+        .modify_balances_for_representment(disputed_tx, amount)
+        .map_err(|_| overflow(client_id, tx_id, access.view(), amount))?;
+    state.disputes.represent(client_id, tx_id, timestamp)?;
+    state.transactions.clear_chargeback(client_id, tx_id);
+    post(&mut state.ledger, tx_id, key, External, Held, amount);
+    settle(&mut state.settlement, key, |totals| {
+        totals.represent(amount)
+    });
+    let event = Event::ChargebackRepresented {
+        client: client_id,
+        currency: key.1,
+        tx: tx_id,
+        amount,
+        timestamp,
+    };
+    emit(&mut state.events, event);
+    // The chargeback is contested, so the lock it put on the account is lifted,
+    // though any other lock stays
+    let locked_by_chargeback = access
+        .view()
+        .lock_info()
+        .is_some_and(|lock| lock.reason == LockReason::Chargeback && lock.tx == tx_id);
+    if let (true, Ok(account)) = (locked_by_chargeback, access.try_locked()) {
+        account.unlock();
+        let event = Event::AccountUnlocked {
+            client: client_id,
+            currency: key.1,
+            tx: tx_id,
+        };
+        emit(&mut state.events, event);
+    }
+    Ok(())
+}
+
+fn handle_lock(lock: Lock, state: &mut State) -> Result<(), TransactionError> {
+    tracing::trace!("Handling {:?}", lock);
+    let account = validate::validate_lock(&lock, &mut state.accounts, state.policies.allow_admin)?;
+    account.lock(LockInfo {
+        reason: LockReason::Admin,
+        tx: lock.tx_id,
+        timestamp: lock.timestamp,
+    });
+    let event = Event::AccountLocked {
+        client: lock.client_id,
+        currency: lock.currency,
+        tx: lock.tx_id,
+        reason: LockReason::Admin,
+        timestamp: lock.timestamp,
+    };
+    emit(&mut state.events, event);
+    Ok(())
+}
+
+fn handle_unlock(unlock: Unlock, state: &mut State) -> Result<(), TransactionError> {
+    tracing::trace!("Handling {:?}", unlock);
+    let account =
+        validate::validate_unlock(&unlock, &mut state.accounts, state.policies.allow_admin)?;
+    account.unlock();
+    let event = Event::AccountUnlocked {
+        client: unlock.client_id,
+        currency: unlock.currency,
+        tx: unlock.tx_id,
+    };
+    emit(&mut state.events, event);
+    Ok(())
+}
+
+fn handle_reversal(reversal: Reversal, state: &mut State) -> Result<(), TransactionError> {
+    tracing::trace!("Handling {:?}", reversal);
+    let client_id = reversal.client_id;
+    let tx_id = reversal.tx_id;
+    let key = (client_id, reversal.currency);
+    let credit_limit = state.policies.credit.limit(client_id);
+    let (mut access, change) = validate::validate_reversal(
+        &reversal,
+        &mut state.accounts,
+        &state.transactions,
+        &state.disputes,
+        &state.policies,
+        credit_limit,
+    )?;
+    access
+        .modify_balances_for_reversal(change)
+        .map_err(|_| overflow(client_id, tx_id, access.view(), change))?;
+    if change.is_negative() {
+        post(&mut state.ledger, tx_id, key, Available, External, -change);
+    } else {
+        post(&mut state.ledger, tx_id, key, External, Available, change);
+    }
+    settle(&mut state.settlement, key, |totals| totals.reverse(change));
+    let event = Event::TransactionReversed {
+        client: client_id,
+        currency: key.1,
+        tx: tx_id,
+        reverses: reversal.reverses,
+        change,
+        timestamp: reversal.timestamp,
+    };
+    emit(&mut state.events, event);
+    state
+        .transactions
+        .insert(client_id, tx_id, TransactionContainer::Reversal(reversal));
+    Ok(())
+}
+
+/// Handle a transaction, calling the state's observers before and after,
+/// sending its events, if they're being logged, and journaling its balance changes,
+/// if the journal is enabled.
 pub fn handle_transaction(
     record: TransactionRecord,
     state: &mut State,
 ) -> Result<(), TransactionError> {
+    if state.observers.is_empty() && state.events.is_none() && state.journal.is_none() {
+        return apply_transaction(record, state);
+    }
+    state.observers.before(&record);
+    let (client_id, currency) = (record.client_id, record.currency);
+    let is_new = state.accounts.get(client_id, currency).is_none();
+    // Balances before the transaction, to journal how it changed them
+    let affected = state.affected_account(&record);
+    let before = state.journal.as_ref().map(|_| {
+        let (client_id, currency) = affected;
+        state
+            .accounts
+            .get(client_id, currency)
+            .cloned()
+            .unwrap_or_default()
+    });
+    let result = apply_transaction(record.clone(), state);
+    if let (Some(journal), Some(before)) = (&mut state.journal, before) {
+        if let Some(after) = state.accounts.get(affected.0, affected.1) {
+            let transaction_type = record.transaction_type.clone();
+            journal.record(record.tx_id, transaction_type, affected, &before, after);
+        }
+    }
+    if let Some(events) = &mut state.events {
+        let opened = is_new && state.accounts.get(client_id, currency).is_some();
+        if !events.flush(opened.then_some((client_id, currency))) {
+            tracing::warn!("Nobody is receiving events anymore, so no longer sending them");
+            state.events = None;
+        }
+    }
+    let (client_id, currency) = state.affected_account(&record);
+    let account = state.account_in(client_id, currency);
+    state.observers.after(&record, &result, account.as_ref());
+    result
+}
+
+fn apply_transaction(record: TransactionRecord, state: &mut State) -> Result<(), TransactionError> {
+    expire_settled(state, record.timestamp);
     match record {
         TransactionRecord {
             transaction_type: TransactionType::Deposit,
             client_id,
             tx_id,
             amount: Some(amount),
+            timestamp,
+            currency,
+            ..
         } => {
             let deposit = Deposit {
                 client_id,
                 tx_id,
-                amount: round_currency(amount),
+                amount,
+                timestamp,
+                currency,
             };
             handle_deposit(deposit, state)
         }
@@ -138,11 +696,16 @@ pub fn handle_transaction(
             client_id,
             tx_id,
             amount: Some(amount),
+            timestamp,
+            currency,
+            ..
         } => {
             let withdrawal = Withdrawal {
                 client_id,
                 tx_id,
-                amount: round_currency(amount),
+                amount,
+                timestamp,
+                currency,
             };
             handle_withdrawal(withdrawal, state)
         }
@@ -150,9 +713,20 @@ pub fn handle_transaction(
             transaction_type: TransactionType::Dispute,
             client_id,
             tx_id,
-            amount: None,
+            amount,
+            timestamp,
+            currency,
+            reason,
+            ..
         } => {
-            let dispute = Dispute { client_id, tx_id };
+            let dispute = Dispute {
+                client_id,
+                tx_id,
+                amount,
+                timestamp,
+                currency,
+                reason,
+            };
             handle_dispute(dispute, state)
         }
         TransactionRecord {
@@ -160,8 +734,16 @@ pub fn handle_transaction(
             client_id,
             tx_id,
             amount: None,
+            timestamp,
+            currency,
+            ..
         } => {
-            let resolve = Resolve { client_id, tx_id };
+            let resolve = Resolve {
+                client_id,
+                tx_id,
+                timestamp,
+                currency,
+            };
             handle_resolve(resolve, state)
         }
         TransactionRecord {
@@ -169,10 +751,88 @@ pub fn handle_transaction(
             client_id,
             tx_id,
             amount: None,
+            timestamp,
+            currency,
+            ..
         } => {
-            let chargeback = Chargeback { client_id, tx_id };
+            let chargeback = Chargeback {
+                client_id,
+                tx_id,
+                timestamp,
+                currency,
+            };
             handle_chargeback(chargeback, state)
         }
+        TransactionRecord {
+            transaction_type: TransactionType::Representment,
+            client_id,
+            tx_id,
+            amount: None,
+            timestamp,
+            currency,
+            ..
+        } => {
+            let representment = Representment {
+                client_id,
+                tx_id,
+                timestamp,
+                currency,
+            };
+            handle_representment(representment, state)
+        }
+        TransactionRecord {
+            transaction_type: TransactionType::Lock,
+            client_id,
+            tx_id,
+            amount: None,
+            timestamp,
+            currency,
+            ..
+        } => {
+            let lock = Lock {
+                client_id,
+                tx_id,
+                timestamp,
+                currency,
+            };
+            handle_lock(lock, state)
+        }
+        TransactionRecord {
+            transaction_type: TransactionType::Unlock,
+            client_id,
+            tx_id,
+            amount: None,
+            timestamp,
+            currency,
+            ..
+        } => {
+            let unlock = Unlock {
+                client_id,
+                tx_id,
+                timestamp,
+                currency,
+            };
+            handle_unlock(unlock, state)
+        }
+        TransactionRecord {
+            transaction_type: TransactionType::Reversal,
+            client_id,
+            tx_id,
+            amount: None,
+            timestamp,
+            currency,
+            reverses: Some(reverses),
+            ..
+        } => {
+            let reversal = Reversal {
+                client_id,
+                tx_id,
+                reverses,
+                timestamp,
+                currency,
+            };
+            handle_reversal(reversal, state)
+        }
         _ => Err(TransactionError::ImproperTransaction(record)),
     }
 }
@@ -0,0 +1,251 @@
+//! Other names for the columns of transaction CSV files, so that files from
+//! different processors can be read without renaming their columns first,
+//! e.g. one calling the `client` column `customer`, or leaving out its header row.
+//! Columns which aren't transaction fields, by any name, are ignored.
+
+use csv::StringRecord;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::io;
+use std::str::FromStr;
+
+use crate::mmap::split_header;
+
+/// Names of the columns a transaction may have.
+pub const COLUMNS: [&str; 9] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "timestamp",
+    "currency",
+    "reason",
+    "reverses",
+    "effective",
+];
+
+/// Number of columns, in the order of `COLUMNS`, assumed by inputs without a header row.
+const HEADERLESS_COLUMNS: usize = 4;
+
+/// Another name for a column, e.g. `customer=client`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeaderAlias {
+    pub alias: String,
+    pub column: String,
+}
+
+impl HeaderAlias {
+    /// `alias` for `column`, which must be one of `COLUMNS`.
+    pub fn new(alias: &str, column: &str) -> Result<Self, String> {
+        let column = column.trim();
+        if !COLUMNS.contains(&column) {
+            return Err(format!(
+                "unknown column '{}', expected one of {}",
+                column,
+                COLUMNS.join(", ")
+            ));
+        }
+        Ok(Self {
+            alias: alias.trim().to_owned(),
+            column: column.to_owned(),
+        })
+    }
+}
+
+impl FromStr for HeaderAlias {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((alias, column)) => Self::new(alias, column),
+            None => Err(format!("expected ALIAS=COLUMN, found '{}'", s)),
+        }
+    }
+}
+
+/// Header names to read as other columns, e.g. `txn_type` as `type`,
+/// or the columns of inputs without a header row.
+///
+/// An input may have a column under its own name and under an alias, or under
+/// two aliases, but only the first of them is read, and the others are ignored.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(try_from = "HashMap<String, String>")]
+pub struct HeaderMapping {
+    /// Column of each alias
+    aliases: HashMap<String, String>,
+    /// Whether inputs start with their first record, rather than a header row
+    no_header_row: bool,
+}
+
+impl HeaderMapping {
+    pub fn insert(&mut self, alias: HeaderAlias) {
+        self.aliases.insert(alias.alias, alias.column);
+    }
+
+    /// Read inputs without a header row, as if each had the header row
+    /// `type,client,tx,amount`, so that their first record isn't taken for one.
+    pub fn without_header_row(mut self) -> Self {
+        self.no_header_row = true;
+        self
+    }
+
+    pub fn has_header_row(&self) -> bool {
+        !self.no_header_row
+    }
+
+    /// Headers with any aliases replaced by the names of their columns.
+    pub fn apply(&self, headers: &StringRecord) -> StringRecord {
+        if self.aliases.is_empty() {
+            return headers.clone();
+        }
+        let mut taken: HashSet<&str> = headers
+            .iter()
+            .map(str::trim)
+            .filter(|header| COLUMNS.contains(header))
+            .collect();
+        headers
+            .iter()
+            .map(|header| match self.aliases.get(header.trim()) {
+                Some(column) if taken.insert(column) => column,
+                Some(column) => {
+                    tracing::warn!(
+                        "Ignoring column '{}', since there's already a '{}' column",
+                        header,
+                        column
+                    );
+                    header
+                }
+                None => header,
+            })
+            .collect()
+    }
+
+    /// Read a CSV reader's headers, with any aliases replaced,
+    /// or the usual columns if inputs have no header row.
+    pub(crate) fn read_headers<R: io::Read>(
+        &self,
+        reader: &mut csv::Reader<R>,
+    ) -> csv::Result<StringRecord> {
+        if self.no_header_row {
+            return Ok(COLUMNS[..HEADERLESS_COLUMNS].iter().collect());
+        }
+        reader.headers().map(|headers| self.apply(headers))
+    }
+
+    /// Split an input into its header row, which is empty if it has none, and the rest.
+    pub(crate) fn split_header_row<'a>(&self, data: &'a [u8]) -> (&'a [u8], &'a [u8]) {
+        if self.no_header_row {
+            return (&[], data);
+        }
+        split_header(data)
+    }
+}
+
+impl Extend<HeaderAlias> for HeaderMapping {
+    fn extend<I: IntoIterator<Item = HeaderAlias>>(&mut self, aliases: I) {
+        for alias in aliases {
+            self.insert(alias);
+        }
+    }
+}
+
+impl TryFrom<HashMap<String, String>> for HeaderMapping {
+    type Error = String;
+
+    fn try_from(aliases: HashMap<String, String>) -> Result<Self, Self::Error> {
+        let mut mapping = Self::default();
+        for (alias, column) in aliases {
+            mapping.insert(HeaderAlias::new(&alias, &column)?);
+        }
+        Ok(mapping)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HeaderAlias, HeaderMapping};
+    use crate::input::Inputs;
+    use crate::read_transactions;
+    use crate::test_utils::{deposit, withdrawal};
+    use csv::StringRecord;
+
+    fn mapping(aliases: &[&str]) -> HeaderMapping {
+        let mut mapping = HeaderMapping::default();
+        mapping.extend(aliases.iter().map(|alias| alias.parse().unwrap()));
+        mapping
+    }
+
+    #[test]
+    fn test_parse_alias() {
+        assert_eq!(
+            "customer = client".parse(),
+            HeaderAlias::new("customer", "client")
+        );
+        assert!("customer".parse::<HeaderAlias>().is_err());
+        assert!("customer=clients".parse::<HeaderAlias>().is_err());
+    }
+
+    #[test]
+    fn test_apply_aliases() {
+        let mapping = mapping(&["txn_type=type", "customer=client", "id=client"]);
+        let headers = StringRecord::from(vec!["txn_type", "customer", "id", "tx", "client"]);
+        assert_eq!(
+            mapping.apply(&headers),
+            StringRecord::from(vec!["type", "customer", "id", "tx", "client"])
+        );
+
+        let headers = StringRecord::from(vec!["customer", "id"]);
+        assert_eq!(
+            mapping.apply(&headers),
+            StringRecord::from(vec!["client", "id"])
+        );
+    }
+
+    #[test]
+    fn test_read_aliased_columns() {
+        let input = "\
+txn_type,customer,transaction_id,amount,note
+deposit,1,1,2.5,first deposit
+";
+        let inputs = Inputs::single(input.as_bytes()).with_header_mapping(mapping(&[
+            "txn_type=type",
+            "customer=client",
+            "transaction_id=tx",
+        ]));
+        let records: Result<Vec<_>, _> = read_transactions(inputs, false).unwrap().collect();
+        assert_eq!(records, Ok(vec![deposit(1, 1, 2.5)]));
+    }
+
+    #[test]
+    fn test_read_without_header_row() {
+        let input = "deposit,1,1,2.5\nwithdrawal,1,2,1.0\n";
+        let inputs = Inputs::single(input.as_bytes())
+            .with_header_mapping(HeaderMapping::default().without_header_row());
+        let records: Result<Vec<_>, _> = read_transactions(inputs, false).unwrap().collect();
+        assert_eq!(records, Ok(vec![deposit(1, 1, 2.5), withdrawal(1, 2, 1.0)]));
+    }
+
+    #[test]
+    fn test_read_unreadable_rows_in_order() {
+        let input = "type,client,tx,amount
+deposit,1,1,2.5
+deposit,1
+deposit,1,2,x
+withdrawal,1,3,1.0
+";
+        let lines: Vec<_> = read_transactions(Inputs::single(input.as_bytes()), false)
+            .unwrap()
+            .map(|record| record.map_err(|err| err.line))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                Ok(deposit(1, 1, 2.5)),
+                Err(Some(3)),
+                Err(Some(4)),
+                Ok(withdrawal(1, 3, 1.0))
+            ]
+        );
+    }
+}
@@ -0,0 +1,439 @@
+use csv::StringRecord;
+use std::borrow::Borrow;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::mpsc::{Sender, SyncSender};
+
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+
+use crate::headers::HeaderMapping;
+use crate::source::ParseError;
+use crate::types::Timestamp;
+
+/// How to combine the records of several inputs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputOrder {
+    /// Read each input in full, one after another.
+    Sequential,
+    /// Interleave inputs by their `timestamp` column, taking the earliest
+    /// next record each time. Ties go to whichever input was given first,
+    /// and records without a timestamp stay right after the record before them,
+    /// so the result is the same on every run.
+    Timestamp,
+}
+
+/// One or more transaction streams to be processed in a single run,
+/// e.g. a day's worth of transactions per file.
+/// Each input has its own CSV header row.
+pub struct Inputs<R> {
+    pub streams: Vec<R>,
+    pub order: InputOrder,
+    /// Other names for columns in the header rows
+    pub headers: HeaderMapping,
+}
+
+impl<R> Inputs<R> {
+    pub fn new(streams: Vec<R>, order: InputOrder) -> Self {
+        Self {
+            streams,
+            order,
+            headers: HeaderMapping::default(),
+        }
+    }
+
+    pub fn single(stream: R) -> Self {
+        Self::new(vec![stream], InputOrder::Sequential)
+    }
+
+    /// Read columns under other names, as given by `headers`.
+    pub fn with_header_mapping(mut self, headers: HeaderMapping) -> Self {
+        self.headers = headers;
+        self
+    }
+}
+
+/// Compression formats which can be read transparently.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gzip" | "gz" => Ok(Self::Gzip),
+            "zstd" | "zst" => Ok(Self::Zstd),
+            other => Err(format!("unknown compression format '{}'", other)),
+        }
+    }
+}
+
+impl Compression {
+    /// Guess the compression format from a file's extension,
+    /// e.g. `transactions.csv.gz`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "gz" => Some(Self::Gzip),
+            "zst" => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+}
+
+/// Wrap an input stream so that it's decompressed as it's read.
+/// Since the CSV reader thread is the one reading, that's
+/// also where decompression happens, and nothing is expanded on disk.
+pub fn decompress<R: io::Read + Send + 'static>(
+    input: R,
+    compression: Option<Compression>,
+) -> io::Result<Box<dyn io::Read + Send>> {
+    Ok(match compression {
+        None => Box::new(input),
+        // Concatenated gzip files are common for logs, so read every member
+        Some(Compression::Gzip) => Box::new(MultiGzDecoder::new(input)),
+        #[cfg(not(target_arch = "wasm32"))]
+        Some(Compression::Zstd) => Box::new(zstd::Decoder::new(input)?),
+        #[cfg(target_arch = "wasm32")]
+        Some(Compression::Zstd) => return Err(zstd_unsupported()),
+    })
+}
+
+/// Wrap an output stream so that it's compressed as it's written,
+/// e.g. to generate inputs for `decompress`.
+/// The compressed stream is finished when the writer is dropped.
+pub fn compress<W: io::Write + Send + 'static>(
+    output: W,
+    compression: Option<Compression>,
+) -> io::Result<Box<dyn io::Write + Send>> {
+    Ok(match compression {
+        None => Box::new(output),
+        Some(Compression::Gzip) => Box::new(GzEncoder::new(output, Default::default())),
+        #[cfg(not(target_arch = "wasm32"))]
+        Some(Compression::Zstd) => Box::new(zstd::Encoder::new(output, 0)?.auto_finish()),
+        #[cfg(target_arch = "wasm32")]
+        Some(Compression::Zstd) => return Err(zstd_unsupported()),
+    })
+}
+
+#[cfg(target_arch = "wasm32")]
+fn zstd_unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "zstd isn't supported in WebAssembly",
+    )
+}
+
+/// A CSV record, along with the index of the input it came from,
+/// so that it can be deserialized using that input's headers.
+pub(crate) type TaggedRecord = (usize, StringRecord);
+
+/// Somewhere the pipeline can read CSV records from, e.g. `Inputs`,
+/// read one record at a time, `MappedInputs`, parsed in parallel,
+/// or `FollowedInput`, read as it grows.
+/// Records are read on a thread of their own, then deserialized in parallel.
+pub trait RecordSource: Send + 'static {
+    /// Number of inputs, each with its own header row.
+    fn num_inputs(&self) -> usize;
+
+    /// Send the headers of every input, then every record in batches.
+    /// A batch may be empty, to let the pipeline check for operator input
+    /// while waiting for more records.
+    /// Records which can't be read are sent to `errors_snd` instead,
+    /// as is the reason for failing to read any further.
+    fn read_records(
+        self,
+        headers_snd: SyncSender<Vec<StringRecord>>,
+        records_snd: SyncSender<Vec<TaggedRecord>>,
+        errors_snd: Sender<ParseError>,
+        batch_size: usize,
+        notrim: bool,
+    );
+}
+
+impl<R: io::Read + Send + 'static> RecordSource for Inputs<R> {
+    fn num_inputs(&self) -> usize {
+        self.streams.len()
+    }
+
+    fn read_records(
+        self,
+        headers_snd: SyncSender<Vec<StringRecord>>,
+        records_snd: SyncSender<Vec<TaggedRecord>>,
+        errors_snd: Sender<ParseError>,
+        batch_size: usize,
+        notrim: bool,
+    ) {
+        crate::read_string_records(
+            self,
+            headers_snd,
+            records_snd,
+            errors_snd,
+            batch_size,
+            notrim,
+        )
+    }
+}
+
+/// Combine the records of several CSV readers in the given order.
+/// Records which can't be read are sent to `errors_snd` instead.
+pub(crate) fn tagged_records<R: io::Read + 'static>(
+    readers: Vec<csv::Reader<R>>,
+    headers: &[StringRecord],
+    order: InputOrder,
+    errors_snd: Sender<ParseError>,
+) -> Box<dyn Iterator<Item = TaggedRecord>> {
+    match order {
+        InputOrder::Sequential => {
+            let num_fields: Vec<_> = headers.iter().map(StringRecord::len).collect();
+            Box::new(
+                readers
+                    .into_iter()
+                    .enumerate()
+                    .flat_map(move |(input, reader)| {
+                        let num_fields = num_fields[input];
+                        let errors_snd = errors_snd.clone();
+                        reader
+                            .into_records()
+                            .filter_map(move |result| {
+                                read_or_report(result, num_fields, &errors_snd)
+                            })
+                            .map(move |record| (input, record))
+                    }),
+            )
+        }
+        InputOrder::Timestamp => Box::new(TimestampMerge::new(readers, headers, errors_snd)),
+    }
+}
+
+/// The record, if it could be read and has as many fields as the headers, or why not.
+pub(crate) fn readable<T: Borrow<StringRecord>>(
+    result: csv::Result<T>,
+    num_fields: usize,
+) -> Result<T, ParseError> {
+    let record = result.map_err(|err| {
+        let line = err.position().map(csv::Position::line);
+        ParseError::new(line, err)
+    })?;
+    let len = record.borrow().len();
+    if len != num_fields {
+        let err = format!(
+            "found record with {} fields, but the headers have {}",
+            len, num_fields
+        );
+        return Err(ParseError::for_record(record.borrow(), err));
+    }
+    Ok(record)
+}
+
+/// The record, if it could be read, or else log why not and send it to `errors_snd`.
+fn read_or_report(
+    result: csv::Result<StringRecord>,
+    num_fields: usize,
+    errors_snd: &Sender<ParseError>,
+) -> Option<StringRecord> {
+    readable(result, num_fields)
+        .map_err(|err| report_unreadable(err, errors_snd))
+        .ok()
+}
+
+/// Log why a record couldn't be read, and send it on to be reported.
+pub(crate) fn report_unreadable(err: ParseError, errors_snd: &Sender<ParseError>) {
+    tracing::error!("Error while reading: {}", err);
+    // Nobody may be listening, e.g. if only the transactions are wanted
+    let _ = errors_snd.send(err);
+}
+
+/// The records of a single input taking part in a `TimestampMerge`.
+struct MergeSource<R> {
+    records: csv::StringRecordsIntoIter<R>,
+    timestamp_column: Option<usize>,
+    /// Timestamp of the most recent record, used for records without one
+    last_timestamp: Timestamp,
+    next: Option<StringRecord>,
+    num_fields: usize,
+    errors_snd: Sender<ParseError>,
+}
+
+impl<R: io::Read> MergeSource<R> {
+    /// Read the next record, returning the timestamp to order it by.
+    fn advance(&mut self) -> Option<Timestamp> {
+        let (num_fields, errors_snd) = (self.num_fields, &self.errors_snd);
+        let record = self
+            .records
+            .by_ref()
+            .find_map(|result| read_or_report(result, num_fields, errors_snd))?;
+        if let Some(timestamp) = self
+            .timestamp_column
+            .and_then(|column| record.get(column))
+            .and_then(|field| field.trim().parse().ok())
+        {
+            self.last_timestamp = timestamp;
+        }
+        self.next = Some(record);
+        Some(self.last_timestamp)
+    }
+}
+
+/// K-way merge of several inputs by timestamp.
+struct TimestampMerge<R> {
+    sources: Vec<MergeSource<R>>,
+    /// Next (timestamp, input) of each input which has records left
+    heads: BinaryHeap<Reverse<(Timestamp, usize)>>,
+}
+
+impl<R: io::Read> TimestampMerge<R> {
+    fn new(
+        readers: Vec<csv::Reader<R>>,
+        headers: &[StringRecord],
+        errors_snd: Sender<ParseError>,
+    ) -> Self {
+        let mut sources: Vec<_> = readers
+            .into_iter()
+            .zip(headers)
+            .map(|(reader, headers)| MergeSource {
+                records: reader.into_records(),
+                timestamp_column: headers.iter().position(|h| h.trim() == "timestamp"),
+                last_timestamp: 0,
+                next: None,
+                num_fields: headers.len(),
+                errors_snd: errors_snd.clone(),
+            })
+            .collect();
+
+        let heads = sources
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(input, source)| Some(Reverse((source.advance()?, input))))
+            .collect();
+
+        Self { sources, heads }
+    }
+}
+
+impl<R: io::Read> Iterator for TimestampMerge<R> {
+    type Item = TaggedRecord;
+
+    fn next(&mut self) -> Option<TaggedRecord> {
+        let Reverse((_, input)) = self.heads.pop()?;
+        let source = &mut self.sources[input];
+        let record = source.next.take()?;
+        if let Some(timestamp) = source.advance() {
+            self.heads.push(Reverse((timestamp, input)));
+        }
+        Some((input, record))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compress, decompress, tagged_records, Compression, InputOrder};
+    use flate2::write::GzEncoder;
+    use std::io::{Read, Write};
+    use std::path::Path;
+    use std::sync::mpsc::channel;
+
+    const CSV: &str = "type,client,tx,amount\ndeposit,1,1,1.0\n";
+
+    fn read_all(compressed: Vec<u8>, compression: Compression) -> String {
+        let mut output = String::new();
+        decompress(std::io::Cursor::new(compressed), Some(compression))
+            .unwrap()
+            .read_to_string(&mut output)
+            .unwrap();
+        output
+    }
+
+    #[test]
+    fn test_compression_from_path() {
+        let from_path = |path: &str| Compression::from_path(Path::new(path));
+        assert_eq!(from_path("tx.csv.gz"), Some(Compression::Gzip));
+        assert_eq!(from_path("tx.csv.zst"), Some(Compression::Zstd));
+        assert_eq!(from_path("tx.csv"), None);
+        assert_eq!(from_path("tx"), None);
+    }
+
+    #[test]
+    fn test_decompress_gzip() {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(CSV.as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        // Concatenated members are read one after another
+        let twice = [compressed.clone(), compressed].concat();
+        assert_eq!(read_all(twice, Compression::Gzip), CSV.repeat(2));
+    }
+
+    #[test]
+    fn test_decompress_zstd() {
+        let compressed = zstd::encode_all(CSV.as_bytes(), 0).unwrap();
+        assert_eq!(read_all(compressed, Compression::Zstd), CSV);
+    }
+
+    #[test]
+    fn test_compress_round_trip() {
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let path = std::env::temp_dir().join(format!(
+                "compress-{:?}-{}",
+                compression,
+                std::process::id()
+            ));
+            let file = std::fs::File::create(&path).unwrap();
+            let mut writer = compress(file, Some(compression)).unwrap();
+            writer.write_all(CSV.as_bytes()).unwrap();
+            // Finished once dropped
+            drop(writer);
+
+            let compressed = std::fs::read(&path).unwrap();
+            assert_eq!(read_all(compressed, compression), CSV);
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    fn merged_tx_ids(inputs: &[&'static str], order: InputOrder) -> Vec<(usize, String)> {
+        let mut readers: Vec<_> = inputs
+            .iter()
+            .map(|input| csv::Reader::from_reader(input.as_bytes()))
+            .collect();
+        let headers: Vec<_> = readers
+            .iter_mut()
+            .map(|reader| reader.headers().unwrap().clone())
+            .collect();
+        tagged_records(readers, &headers, order, channel().0)
+            .map(|(input, record)| (input, record[1].to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_sequential_inputs() {
+        let first = "type,tx,timestamp\ndeposit,1,20\ndeposit,2,30\n";
+        let second = "type,tx,timestamp\ndeposit,3,10\n";
+        assert_eq!(
+            merged_tx_ids(&[first, second], InputOrder::Sequential),
+            vec![(0, "1".into()), (0, "2".into()), (1, "3".into())]
+        );
+    }
+
+    #[test]
+    fn test_merge_inputs_by_timestamp() {
+        let first = "type,tx,timestamp\ndeposit,1,10\ndeposit,2,30\ndispute,2,\n";
+        // Column order may differ between inputs
+        let second = "timestamp,tx,type\n20,3,deposit\n30,4,deposit\n";
+        let third = "type,tx\ndeposit,5\n";
+        assert_eq!(
+            merged_tx_ids(&[first, second, third], InputOrder::Timestamp)
+                .into_iter()
+                .map(|(input, _)| input)
+                .collect::<Vec<_>>(),
+            // Input without timestamps goes first, ties go to the earlier input,
+            // and a record without a timestamp stays with the one before it
+            vec![2, 0, 1, 0, 0, 1]
+        );
+    }
+}
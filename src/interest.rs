@@ -0,0 +1,168 @@
+//! Interest on available balances, as set by the `InterestPolicy`.
+//!
+//! Interest is credited once, at the end of the run, or if the policy has a period,
+//! whenever the timestamps read pass the end of a period, counted from the epoch.
+//! The pipeline's router decides when, since it reads every record in order,
+//! and each handler credits the accounts it owns.
+//!
+//! Each credit is a synthetic `interest` transaction with the id `INTEREST_TX_ID`,
+//! posted to the ledger from `interest`, journaled, and sent as an `InterestCredited` event.
+
+use crate::events::Event;
+use crate::ledger::LedgerAccount;
+use crate::policy::InterestPolicy;
+use crate::state::State;
+use crate::types::{Timestamp, TransactionId, TransactionType};
+
+/// Id of every synthetic interest transaction, which real ones are unlikely to use.
+pub const INTEREST_TX_ID: TransactionId = TransactionId::MAX;
+
+impl InterestPolicy {
+    /// Whether any interest is paid.
+    pub fn is_enabled(&self) -> bool {
+        self.percent > 0.0
+    }
+
+    /// Ends of the policy's periods after `from`, up to and including `to`,
+    /// when interest is credited. None if the policy has no period,
+    /// or either time isn't known.
+    pub fn period_ends(
+        &self,
+        from: Option<Timestamp>,
+        to: Option<Timestamp>,
+    ) -> impl Iterator<Item = Timestamp> {
+        let period = self
+            .period
+            .map(|period| period.as_secs())
+            .filter(|&secs| secs > 0 && self.is_enabled());
+        let periods = match (period, from, to) {
+            (Some(period), Some(from), Some(to)) => {
+                Some(((from / period + 1)..=(to / period), period))
+            }
+            _ => None,
+        };
+        periods
+            .into_iter()
+            .flat_map(|(periods, period)| periods.map(move |n| n * period))
+    }
+}
+
+/// Credit interest to every account with positive available funds,
+/// locked or not, as of `timestamp`. Returns how many accounts were credited.
+pub fn accrue(state: &mut State, timestamp: Option<Timestamp>) -> usize {
+    let percent = state.policies.interest.percent;
+    if !state.policies.interest.is_enabled() {
+        return 0;
+    }
+    let mut credited = 0;
+    for (&key, account) in state.accounts.iter_mut() {
+        let interest = state
+            .policies
+            .rounding
+            .percent(account.available(), percent);
+        if !interest.is_positive() {
+            continue;
+        }
+        let before = state.journal.as_ref().map(|_| account.clone());
+        // Accounts which can't hold any more are left as they are
+        if account.access().credit_interest(interest).is_err() {
+            continue;
+        }
+        if let Some(ledger) = &mut state.ledger {
+            let (debit, credit) = (LedgerAccount::Interest, LedgerAccount::Available);
+            ledger.post(INTEREST_TX_ID, key, debit, credit, interest);
+        }
+        if let Some(settlement) = &mut state.settlement {
+            settlement.totals_mut(key.1).credit_interest(interest);
+        }
+        if let (Some(journal), Some(before)) = (&mut state.journal, before) {
+            let transaction_type = TransactionType::Interest;
+            journal.record(INTEREST_TX_ID, transaction_type, key, &before, account);
+        }
+        if let Some(events) = &mut state.events {
+            events.record(Event::InterestCredited {
+                client: key.0,
+                currency: key.1,
+                amount: interest,
+                timestamp,
+            });
+        }
+        credited += 1;
+    }
+    if let Some(events) = &mut state.events {
+        if !events.flush(None) {
+            tracing::warn!("Nobody is receiving events anymore, so no longer sending them");
+            state.events = None;
+        }
+    }
+    tracing::debug!("Credited interest to {} accounts", credited);
+    credited
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{accrue, INTEREST_TX_ID};
+    use crate::currency::Currency;
+    use crate::journal::Journal;
+    use crate::ledger::{Ledger, LedgerAccount};
+    use crate::policy::{InterestPolicy, Policies};
+    use crate::state::State;
+    use crate::test_utils::{deposit, withdrawal};
+    use crate::types::Timestamp;
+    use std::time::Duration;
+
+    #[test]
+    fn test_period_ends() {
+        let policy = InterestPolicy {
+            percent: 1.0,
+            period: Some(Duration::from_secs(100)),
+        };
+        let ends =
+            |from: Option<Timestamp>, to| policy.period_ends(from, Some(to)).collect::<Vec<_>>();
+        assert_eq!(ends(Some(50), 99), Vec::<Timestamp>::new());
+        assert_eq!(ends(Some(50), 100), vec![100]);
+        assert_eq!(ends(Some(100), 350), vec![200, 300]);
+        // The clock hasn't started
+        assert_eq!(ends(None, 350), Vec::<Timestamp>::new());
+
+        let end_of_run = InterestPolicy {
+            period: None,
+            ..policy
+        };
+        assert_eq!(end_of_run.period_ends(Some(0), Some(350)).count(), 0);
+    }
+
+    #[test]
+    fn test_accrue() {
+        let mut policies = Policies::default();
+        policies.interest.percent = 10.0;
+        policies.credit.default_limit = Currency::from(10.0);
+        let mut state = State::with_policies(policies);
+        state.ledger = Some(Ledger::default());
+        state.journal = Some(Journal::default());
+        for record in [
+            deposit(1, 1, 5.0),
+            deposit(2, 2, 1.0),
+            withdrawal(2, 3, 3.0),
+        ] {
+            state.handle(record).unwrap();
+        }
+
+        assert_eq!(accrue(&mut state, Some(100)), 1);
+        assert_eq!(state.account(1).unwrap().available, Currency::from(5.5));
+        // No interest on overdrawn accounts
+        assert_eq!(state.account(2).unwrap().available, Currency::from(-2.0));
+
+        let entry = state.ledger.as_ref().unwrap().entries().last().unwrap();
+        assert_eq!(
+            (entry.tx, entry.debit, entry.credit, entry.amount),
+            (
+                INTEREST_TX_ID,
+                LedgerAccount::Interest,
+                LedgerAccount::Available,
+                Currency::from(0.5)
+            )
+        );
+        assert_eq!(state.journal(1).count(), 2);
+    }
+}
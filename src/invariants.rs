@@ -0,0 +1,306 @@
+//! Consistency checks on the engine's state, which should hold
+//! after every transaction no matter what the input was.
+//! A violation means a bug in the engine, not a bad transaction.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::currency::{Currency, CurrencyCode};
+use crate::policy::TxIdScope;
+use crate::state::{AccountOrder, State};
+use crate::types::{ClientId, OutputRecord, TransactionId};
+
+/// A broken invariant, with enough detail to find what broke it.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Violation {
+    /// An account's total, as written, isn't the sum of its available and held funds.
+    TotalMismatch {
+        client: ClientId,
+        currency: Option<CurrencyCode>,
+        available: Currency,
+        held: Currency,
+        total: Currency,
+    },
+    /// An account's available and held funds add up to more than can be represented,
+    /// so it has no total to write.
+    TotalOverflow {
+        client: ClientId,
+        currency: Option<CurrencyCode>,
+        available: Currency,
+        held: Currency,
+    },
+    /// An account has negative held funds. No policy allows this,
+    /// since funds are only held by disputes.
+    NegativeHeld {
+        client: ClientId,
+        currency: Option<CurrencyCode>,
+        held: Currency,
+    },
+    /// A client's held funds differ from the total of its active disputes.
+    HeldMismatch {
+        client: ClientId,
+        held: Currency,
+        disputed: Currency,
+    },
+    /// A transaction is both actively disputed and settled.
+    DisputeActiveAndSettled { client: ClientId, tx: TransactionId },
+    /// A dispute refers to a transaction which isn't stored.
+    DisputeWithoutTransaction { client: ClientId, tx: TransactionId },
+    /// A transaction id is stored for more than one client,
+    /// though the validation policy requires ids to be unique globally.
+    DuplicateTxId {
+        tx: TransactionId,
+        clients: (ClientId, ClientId),
+    },
+}
+
+impl Violation {
+    /// Client whose state is inconsistent, or the first of them.
+    pub fn client(&self) -> ClientId {
+        match self {
+            Self::TotalMismatch { client, .. }
+            | Self::TotalOverflow { client, .. }
+            | Self::NegativeHeld { client, .. }
+            | Self::HeldMismatch { client, .. }
+            | Self::DisputeActiveAndSettled { client, .. }
+            | Self::DisputeWithoutTransaction { client, .. } => *client,
+            Self::DuplicateTxId { clients, .. } => clients.0,
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TotalMismatch {
+                client,
+                available,
+                held,
+                total,
+                ..
+            } => write!(
+                f,
+                "client {} has total {}, but available {} and held {}",
+                client, total, available, held
+            ),
+            Self::TotalOverflow {
+                client,
+                available,
+                held,
+                ..
+            } => write!(
+                f,
+                "client {} has available {} and held {}, whose total is too large",
+                client, available, held
+            ),
+            Self::NegativeHeld { client, held, .. } => {
+                write!(f, "client {} has negative held funds {}", client, held)
+            }
+            Self::HeldMismatch {
+                client,
+                held,
+                disputed,
+            } => write!(
+                f,
+                "client {} holds {}, but has {} in active disputes",
+                client, held, disputed
+            ),
+            Self::DisputeActiveAndSettled { client, tx } => write!(
+                f,
+                "transaction {} of client {} is both disputed and settled",
+                tx, client
+            ),
+            Self::DisputeWithoutTransaction { client, tx } => write!(
+                f,
+                "client {} disputes transaction {}, which doesn't exist",
+                client, tx
+            ),
+            Self::DuplicateTxId { tx, clients } => write!(
+                f,
+                "transaction {} is stored for both client {} and client {}",
+                tx, clients.0, clients.1
+            ),
+        }
+    }
+}
+
+impl Error for Violation {}
+
+/// Check every invariant, returning all violations, grouped by client.
+pub fn check(state: &State) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    check_accounts(state, &mut violations);
+    check_disputes(state, &mut violations);
+    let globally_unique = state.transactions.scope() == TxIdScope::Global;
+    if globally_unique && !state.policies.validation.allow_duplicate_tx_ids {
+        check_tx_ids(state, &mut violations);
+    }
+    violations.sort_by_key(Violation::client);
+    violations
+}
+
+fn check_accounts(state: &State, violations: &mut Vec<Violation>) {
+    for (&key, account) in state.accounts.ordered(AccountOrder::Client) {
+        let (client, currency) = key;
+        match OutputRecord::new(key, account, &state.policies.rounding) {
+            Ok(row) if row.available.checked_add(row.held) != Some(row.total) => {
+                violations.push(Violation::TotalMismatch {
+                    client,
+                    currency,
+                    available: row.available,
+                    held: row.held,
+                    total: row.total,
+                });
+            }
+            Ok(_) => {}
+            Err(_) => violations.push(Violation::TotalOverflow {
+                client,
+                currency,
+                available: account.available,
+                held: account.held,
+            }),
+        }
+        if account.held.is_negative() {
+            violations.push(Violation::NegativeHeld {
+                client,
+                currency,
+                held: account.held,
+            });
+        }
+    }
+}
+
+fn check_disputes(state: &State, violations: &mut Vec<Violation>) {
+    let mut active: Vec<_> = state.disputes.active().collect();
+    active.sort_by_key(|&(client, tx, _)| (client, tx));
+
+    let mut disputed: HashMap<ClientId, Currency> = HashMap::new();
+    for &(client, tx, amount) in &active {
+        *disputed.entry(client).or_default() += amount;
+        if state.disputes.is_settled(client, tx) {
+            violations.push(Violation::DisputeActiveAndSettled { client, tx });
+        }
+        if state.transactions.get(client, tx).is_none() {
+            violations.push(Violation::DisputeWithoutTransaction { client, tx });
+        }
+    }
+
+    // Disputes aren't tracked by currency, so compare across all of a client's accounts
+    let mut held: HashMap<ClientId, Currency> = HashMap::new();
+    for (&(client, _), account) in state.accounts.iter() {
+        *held.entry(client).or_default() += account.held;
+    }
+    let mut clients: Vec<_> = held.keys().chain(disputed.keys()).copied().collect();
+    clients.sort_unstable();
+    clients.dedup();
+    for client in clients {
+        let held = held.get(&client).copied().unwrap_or_default();
+        let disputed = disputed.get(&client).copied().unwrap_or_default();
+        if held != disputed {
+            violations.push(Violation::HeldMismatch {
+                client,
+                held,
+                disputed,
+            });
+        }
+    }
+}
+
+fn check_tx_ids(state: &State, violations: &mut Vec<Violation>) {
+    let mut stored: Vec<_> = state.transactions.iter().collect();
+    stored.sort_unstable();
+
+    let mut clients_by_tx: HashMap<TransactionId, ClientId> = HashMap::new();
+    for (client, tx) in stored {
+        if let Some(&first) = clients_by_tx.get(&tx) {
+            violations.push(Violation::DuplicateTxId {
+                tx,
+                clients: (first, client),
+            });
+        } else {
+            clients_by_tx.insert(tx, client);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, Violation};
+    use crate::currency::Currency;
+    use crate::state::State;
+    use crate::test_utils::{chargeback, deposit, dispute};
+    use crate::types::{Account, Deposit, TransactionContainer};
+
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_consistent_state() {
+        let mut state = State::new();
+        for record in [
+            deposit(1, 1, 5.0),
+            deposit(2, 2, 3.0),
+            dispute(1, 1),
+            dispute(2, 2),
+            chargeback(2, 2),
+        ] {
+            state.handle(record).unwrap();
+        }
+        assert_eq!(check(&state), vec![]);
+    }
+
+    #[test]
+    fn test_violations() {
+        let mut state = State::new();
+        state.handle(deposit(1, 1, 5.0)).unwrap();
+
+        // Tamper with the state in ways the engine never should
+        let account = Account {
+            held: Currency::from(-1.0),
+            ..Default::default()
+        };
+        let accounts: HashMap<_, _> = vec![(2, account)].into_iter().collect();
+        state.accounts.extend(accounts.into());
+        let deposit = Deposit {
+            client_id: 2,
+            tx_id: 1,
+            amount: Currency::from(1.0),
+            timestamp: None,
+            currency: None,
+        };
+        state
+            .transactions
+            .insert(2, 1, TransactionContainer::Deposit(Ok(deposit)));
+        state
+            .disputes
+            .dispute_tx(1, 9, Currency::from(2.0), None, None)
+            .unwrap();
+
+        assert_eq!(
+            check(&state),
+            vec![
+                Violation::DisputeWithoutTransaction { client: 1, tx: 9 },
+                Violation::HeldMismatch {
+                    client: 1,
+                    held: Currency::ZERO,
+                    disputed: Currency::from(2.0),
+                },
+                Violation::DuplicateTxId {
+                    tx: 1,
+                    clients: (1, 2),
+                },
+                Violation::NegativeHeld {
+                    client: 2,
+                    currency: None,
+                    held: Currency::from(-1.0),
+                },
+                Violation::HeldMismatch {
+                    client: 2,
+                    held: Currency::from(-1.0),
+                    disputed: Currency::ZERO,
+                },
+            ]
+        );
+    }
+}
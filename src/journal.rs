@@ -0,0 +1,219 @@
+//! Running journal of each account's balance changes, so that support staff
+//! can explain any final balance line by line.
+//!
+//! Where the ledger posts every change as a double entry, for reconciling
+//! with a general ledger, the journal has one line per transaction which
+//! changed an account: how much its available and held funds went up or down,
+//! net of any fee, and the balances that left it with.
+
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::io;
+
+use crate::currency::{Currency, CurrencyCode};
+use crate::ledger::LedgerFormat;
+use crate::types::{Account, AccountKey, ClientId, TransactionId, TransactionType};
+
+/// How one transaction changed one account's balances.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct JournalEntry {
+    /// Transaction which caused the change
+    pub tx: TransactionId,
+    #[serde(rename = "type")]
+    pub transaction_type: TransactionType,
+    pub client: ClientId,
+    pub currency: Option<CurrencyCode>,
+    /// Change in available funds
+    pub available_change: Currency,
+    /// Change in held funds
+    pub held_change: Currency,
+    /// Balances after the change
+    pub available: Currency,
+    pub held: Currency,
+    pub total: Currency,
+}
+
+/// Entries for every change to an account's balances, in the order they were made.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Journal {
+    entries: Vec<JournalEntry>,
+}
+
+impl Journal {
+    /// Record how a transaction changed an account, from `before` to `after`.
+    /// Transactions which left the balances as they were, e.g. rejected ones, are left out,
+    /// as are any which left a total that can't be represented, for the invariant checks to report.
+    pub(crate) fn record(
+        &mut self,
+        tx: TransactionId,
+        transaction_type: TransactionType,
+        (client, currency): AccountKey,
+        before: &Account,
+        after: &Account,
+    ) {
+        let available_change = after.available() - before.available();
+        let held_change = after.held() - before.held();
+        if available_change == Currency::ZERO && held_change == Currency::ZERO {
+            return;
+        }
+        match after.total() {
+            Ok(total) => self.entries.push(JournalEntry {
+                tx,
+                transaction_type,
+                client,
+                currency,
+                available_change,
+                held_change,
+                available: after.available(),
+                held: after.held(),
+                total,
+            }),
+            Err(err) => tracing::error!(
+                "Could not journal transaction {} of client {}: {}",
+                tx,
+                client,
+                err
+            ),
+        }
+    }
+
+    pub fn entries(&self) -> &[JournalEntry] {
+        &self.entries
+    }
+
+    /// One client's entries, in every currency, in order.
+    pub fn client(&self, client: ClientId) -> impl Iterator<Item = &JournalEntry> {
+        self.entries
+            .iter()
+            .filter(move |entry| entry.client == client)
+    }
+
+    /// Append another journal's entries, e.g. from another shard.
+    pub fn extend(&mut self, other: Journal) {
+        self.entries.extend(other.entries);
+    }
+
+    /// Group entries by client, keeping each client's entries in order.
+    pub(crate) fn sort_by_client(&mut self) {
+        self.entries.sort_by_key(|entry| entry.client);
+    }
+
+    /// Keep only the first `len` entries, e.g. to undo a transaction's changes.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.entries.truncate(len);
+    }
+
+    /// Write every entry in the given format, as for the ledger.
+    pub fn write<W: io::Write>(
+        &self,
+        format: LedgerFormat,
+        mut output_stream: W,
+    ) -> Result<(), Box<dyn Error>> {
+        match format {
+            LedgerFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(output_stream);
+                for entry in &self.entries {
+                    writer.serialize(entry)?;
+                }
+                writer.flush()?;
+            }
+            LedgerFormat::Jsonl => {
+                for entry in &self.entries {
+                    serde_json::to_writer(&mut output_stream, entry)?;
+                    writeln!(output_stream)?;
+                }
+                output_stream.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Journal;
+    use crate::currency::Currency;
+    use crate::ledger::LedgerFormat;
+    use crate::state::State;
+    use crate::test_utils::{chargeback, deposit, dispute, withdrawal};
+
+    fn journal_state() -> State {
+        let mut state = State::new();
+        state.journal = Some(Journal::default());
+        for record in [
+            deposit(1, 1, 10.0),
+            deposit(2, 2, 4.0),
+            withdrawal(1, 3, 3.0),
+            withdrawal(1, 4, 100.0),
+            dispute(1, 1),
+            chargeback(1, 1),
+        ] {
+            let _ = state.handle(record);
+        }
+        state
+    }
+
+    #[test]
+    fn test_journal_explains_balances() {
+        let state = journal_state();
+        let changes: Vec<_> = state
+            .journal(1)
+            .map(|entry| {
+                (
+                    entry.tx,
+                    entry.available_change,
+                    entry.held_change,
+                    entry.total,
+                )
+            })
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                (
+                    1,
+                    Currency::from(10.0),
+                    Currency::ZERO,
+                    Currency::from(10.0)
+                ),
+                (3, Currency::from(-3.0), Currency::ZERO, Currency::from(7.0)),
+                // The rejected withdrawal changes nothing
+                (
+                    1,
+                    Currency::from(-10.0),
+                    Currency::from(10.0),
+                    Currency::from(7.0)
+                ),
+                (
+                    1,
+                    Currency::ZERO,
+                    Currency::from(-10.0),
+                    Currency::from(-3.0)
+                ),
+            ]
+        );
+
+        // Adding up the changes gives the final balances
+        let account = state.accounts.get(1, None).unwrap();
+        let available = state.journal(1).map(|entry| entry.available_change);
+        assert_eq!(available.sum::<Currency>(), account.available());
+        assert_eq!(state.journal(2).count(), 1);
+        assert_eq!(state.journal(3).count(), 0);
+    }
+
+    #[test]
+    fn test_write_journal() {
+        let state = journal_state();
+        let mut csv = Vec::new();
+        let journal = state.journal.as_ref().unwrap();
+        journal.write(LedgerFormat::Csv, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(
+            csv.lines().take(2).collect::<Vec<_>>(),
+            vec![
+                "tx,type,client,currency,available_change,held_change,available,held,total",
+                "1,deposit,1,,10.0,0.0,10.0,0.0,10.0",
+            ]
+        );
+    }
+}
@@ -0,0 +1,274 @@
+//! Double-entry ledger of balance changes, for reconciling
+//! the engine's balances with an external general ledger.
+//!
+//! Client balances are what the engine owes its clients, so a client's
+//! `available` or `held` balance goes up when credited and down when debited.
+//! Money entering or leaving the engine goes through `external`,
+//! fees are credited to `fees`, losses absorbed on a client's behalf
+//! are debited to `write_off`, and interest paid to clients to `interest`.
+//! Every entry debits one account and credits another by the same amount,
+//! so the ledger always balances.
+
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::io;
+use std::path::Path;
+
+use crate::currency::{Currency, CurrencyCode};
+use crate::types::{AccountKey, ClientId, TransactionId};
+
+/// An account in the ledger.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LedgerAccount {
+    /// The client's available funds
+    Available,
+    /// The client's funds held by disputes
+    Held,
+    /// Fees collected from all clients
+    Fees,
+    /// Losses absorbed when a chargeback exceeds the client's funds
+    WriteOff,
+    /// Money outside the engine, e.g. the client's bank account
+    External,
+    /// Interest paid to clients
+    Interest,
+}
+
+/// A single balance change, moving `amount` from the `debit` account
+/// to the `credit` account. `Available` and `Held` refer to `client`'s account.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct LedgerEntry {
+    /// Transaction which caused the change
+    pub tx: TransactionId,
+    pub client: ClientId,
+    pub currency: Option<CurrencyCode>,
+    pub debit: LedgerAccount,
+    pub credit: LedgerAccount,
+    pub amount: Currency,
+}
+
+/// Format to write ledger entries in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LedgerFormat {
+    Csv,
+    /// One JSON object per line
+    Jsonl,
+}
+
+impl LedgerFormat {
+    /// Guess the format from a file's extension:
+    /// JSON lines for `.jsonl` or `.ndjson`, and CSV otherwise.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("jsonl") | Some("ndjson") => Self::Jsonl,
+            _ => Self::Csv,
+        }
+    }
+}
+
+/// Entries for every balance change, in the order they were made.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Ledger {
+    entries: Vec<LedgerEntry>,
+}
+
+impl Ledger {
+    /// Record a balance change. Changes of zero, e.g. a zero fee, are left out.
+    pub fn post(
+        &mut self,
+        tx: TransactionId,
+        (client, currency): AccountKey,
+        debit: LedgerAccount,
+        credit: LedgerAccount,
+        amount: Currency,
+    ) {
+        if amount != Currency::ZERO {
+            self.entries.push(LedgerEntry {
+                tx,
+                client,
+                currency,
+                debit,
+                credit,
+                amount,
+            });
+        }
+    }
+
+    pub fn entries(&self) -> &[LedgerEntry] {
+        &self.entries
+    }
+
+    /// Append another ledger's entries, e.g. from another shard.
+    pub fn extend(&mut self, other: Ledger) {
+        self.entries.extend(other.entries);
+    }
+
+    /// Group entries by client, keeping each client's entries in order.
+    pub(crate) fn sort_by_client(&mut self) {
+        self.entries.sort_by_key(|entry| entry.client);
+    }
+
+    /// Keep only the first `len` entries, e.g. to undo a transaction's postings.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.entries.truncate(len);
+    }
+
+    /// Write every entry in the given format.
+    pub fn write<W: io::Write>(
+        &self,
+        format: LedgerFormat,
+        mut output_stream: W,
+    ) -> Result<(), Box<dyn Error>> {
+        match format {
+            LedgerFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(output_stream);
+                for entry in &self.entries {
+                    writer.serialize(entry)?;
+                }
+                writer.flush()?;
+            }
+            LedgerFormat::Jsonl => {
+                for entry in &self.entries {
+                    serde_json::to_writer(&mut output_stream, entry)?;
+                    writeln!(output_stream)?;
+                }
+                output_stream.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Ledger, LedgerAccount, LedgerFormat};
+    use crate::currency::Currency;
+    use crate::policy::{FeePolicy, FeeSchedule, Policies};
+    use crate::state::State;
+    use crate::types::{TransactionId, TransactionRecord, TransactionType};
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    fn record(
+        transaction_type: TransactionType,
+        tx_id: TransactionId,
+        amount: Option<f64>,
+    ) -> TransactionRecord {
+        TransactionRecord {
+            transaction_type,
+            client_id: 1,
+            tx_id,
+            amount: amount.map(Currency::from),
+            timestamp: None,
+            currency: None,
+            reason: None,
+            reverses: None,
+            effective: None,
+        }
+    }
+
+    fn ledger_state() -> State {
+        let policies = Policies {
+            fees: FeePolicy {
+                withdrawal: FeeSchedule {
+                    flat: Currency::from(0.5),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut state = State::with_policies(policies);
+        state.ledger = Some(Ledger::default());
+        for (tx_type, tx_id, amount) in [
+            (TransactionType::Deposit, 1, Some(10.0)),
+            (TransactionType::Withdrawal, 2, Some(3.0)),
+            (TransactionType::Withdrawal, 3, Some(100.0)),
+            (TransactionType::Dispute, 1, None),
+            (TransactionType::Chargeback, 1, None),
+        ] {
+            let _ = state.handle(record(tx_type, tx_id, amount));
+        }
+        state
+    }
+
+    #[test]
+    fn test_ledger_entries() {
+        use LedgerAccount::*;
+
+        let state = ledger_state();
+        let entries: Vec<_> = state
+            .ledger
+            .as_ref()
+            .unwrap()
+            .entries()
+            .iter()
+            .map(|entry| (entry.tx, entry.debit, entry.credit, entry.amount))
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                (1, External, Available, Currency::from(10.0)),
+                (2, Available, External, Currency::from(3.0)),
+                (2, Available, Fees, Currency::from(0.5)),
+                // The rejected withdrawal changes nothing
+                (1, Available, Held, Currency::from(10.0)),
+                (1, Held, External, Currency::from(10.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ledger_balances() {
+        let state = ledger_state();
+        // Replaying the entries gives the same balances as the engine
+        let mut balances: HashMap<&str, Currency> = HashMap::new();
+        for entry in state.ledger.as_ref().unwrap().entries() {
+            let name = |account| match account {
+                LedgerAccount::Available => "available",
+                LedgerAccount::Held => "held",
+                _ => "other",
+            };
+            *balances.entry(name(entry.credit)).or_default() += entry.amount;
+            *balances.entry(name(entry.debit)).or_default() -= entry.amount;
+        }
+        let account = state.accounts.get(1, None).unwrap();
+        assert_eq!(balances["available"], account.available);
+        assert_eq!(balances["held"], account.held);
+    }
+
+    #[test]
+    fn test_write_ledger() {
+        let state = ledger_state();
+        let ledger = state.ledger.as_ref().unwrap();
+
+        let mut csv = Vec::new();
+        ledger.write(LedgerFormat::Csv, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(
+            csv.lines().take(2).collect::<Vec<_>>(),
+            vec![
+                "tx,client,currency,debit,credit,amount",
+                "1,1,,external,available,10.0",
+            ]
+        );
+
+        let mut jsonl = Vec::new();
+        ledger.write(LedgerFormat::Jsonl, &mut jsonl).unwrap();
+        let first: serde_json::Value =
+            serde_json::from_str(String::from_utf8(jsonl).unwrap().lines().next().unwrap())
+                .unwrap();
+        assert_eq!(first["amount"], "10.0");
+        assert_eq!(first["debit"], "external");
+
+        assert_eq!(
+            LedgerFormat::from_path(Path::new("ledger.jsonl")),
+            LedgerFormat::Jsonl
+        );
+        assert_eq!(
+            LedgerFormat::from_path(Path::new("ledger.csv")),
+            LedgerFormat::Csv
+        );
+    }
+}
@@ -1,29 +1,97 @@
+// With wider ids, conversions to and from fixed-width integers may be identities
+#![cfg_attr(
+    any(feature = "wide-client-ids", feature = "wide-tx-ids"),
+    allow(clippy::useless_conversion, clippy::unnecessary_cast)
+)]
+
 mod account;
+mod actors;
+pub mod channel;
+pub mod checkpoint;
+pub mod config;
+pub mod control;
 mod conversions;
 mod currency;
+pub mod digest;
+pub mod events;
+pub mod filter;
+pub mod follow;
+#[cfg(feature = "grpc")]
+pub mod grpc;
 mod handlers;
+pub mod headers;
+pub mod input;
+pub mod interest;
+pub mod invariants;
+pub mod journal;
+pub mod ledger;
+pub mod limits;
+pub mod locale;
+pub mod merge;
+pub mod mmap;
+pub mod observer;
+pub mod pipeline;
+pub mod policy;
+pub mod publish;
 pub mod rand;
+pub mod report;
+pub mod risk;
+pub mod schedule;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod service;
+pub mod settlement;
+pub mod simulate;
+pub mod sink;
+pub mod source;
 pub mod state;
+pub mod statement;
+pub mod stats;
+pub mod store;
+pub mod telemetry;
 pub mod test_utils;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod throttle;
 mod traits;
 pub mod types;
 mod validate;
+pub mod verify;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
 use csv::StringRecord;
 use rayon::prelude::*;
 use std::error::Error;
 use std::io;
-use std::sync::mpsc::{sync_channel, SyncSender};
+use std::iter;
+use std::sync::mpsc::{channel, sync_channel, Sender, SyncSender};
 use std::thread;
+use std::time::Instant;
 
-use state::State;
-use types::{OutputRecord, TransactionRecord};
+use checkpoint::InputPosition;
+use control::Control;
+use input::{tagged_records, Inputs, RecordSource, TaggedRecord};
+use pipeline::{ClientQueueLimit, ExecutionMode, PipelineConfig, ShardedHandler};
+use policy::{Policies, RoundingPolicy};
+use sink::{sink_accounts, CsvSink};
+use source::{ParseError, TransactionSource};
+use state::{AccountOrder, AccountsState, State};
+use throttle::Throttle;
+use types::{BalanceUpdate, FeesRecord, Rejection, TransactionRecord};
 
 /// Construct csv reader with options.
 /// In particular, disabling trim can
 /// speed up deserialization.
-fn construct_csv_reader<R: io::Read + Send>(input: R, notrim: bool) -> csv::Reader<R> {
+/// Records may have any number of fields, so that those which don't match
+/// their headers can be reported along with their content, see `input::readable`.
+pub(crate) fn construct_csv_reader<R: io::Read>(
+    input: R,
+    notrim: bool,
+    has_headers: bool,
+) -> csv::Reader<R> {
     let mut builder = csv::ReaderBuilder::new();
+    builder.has_headers(has_headers).flexible(true);
 
     // Optionally disable whitespace trimming
     if !notrim {
@@ -33,27 +101,34 @@ fn construct_csv_reader<R: io::Read + Send>(input: R, notrim: bool) -> csv::Read
     builder.from_reader(input)
 }
 
-/// Read CSV string records from a stream and send them
+/// Read CSV string records from each input and send them
 /// across a channel to be deserialized elsewhere.
-fn read_string_records_inner<R: io::Read + Send>(
-    input: R,
-    headers_snd: SyncSender<StringRecord>,
-    records_snd: SyncSender<Vec<StringRecord>>,
+/// The headers of every input are sent first.
+fn read_string_records_inner<R: io::Read + Send + 'static>(
+    inputs: Inputs<R>,
+    headers_snd: SyncSender<Vec<StringRecord>>,
+    records_snd: SyncSender<Vec<TaggedRecord>>,
+    errors_snd: Sender<ParseError>,
     batch_size: usize,
     notrim: bool,
 ) -> Result<(), Box<dyn Error>> {
-    let mut reader = construct_csv_reader(input, notrim);
-    let headers = reader.headers()?;
-    headers_snd.send(headers.clone())?;
-
-    let mut records_iter = reader.records();
+    let has_headers = inputs.headers.has_header_row();
+    let mut readers: Vec<_> = inputs
+        .streams
+        .into_iter()
+        .map(|input| construct_csv_reader(input, notrim, has_headers))
+        .collect();
+    let mapping = &inputs.headers;
+    let headers = readers
+        .iter_mut()
+        .map(|reader| mapping.read_headers(reader))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut records_iter = tagged_records(readers, &headers, inputs.order, errors_snd);
+    headers_snd.send(headers)?;
 
     loop {
-        let batch: Vec<_> = (&mut records_iter)
-            .take(batch_size)
-            .filter_map(Result::ok)
-            .collect();
-        if batch.len() > 0 {
+        let batch: Vec<_> = (&mut records_iter).take(batch_size).collect();
+        if !batch.is_empty() {
             records_snd.send(batch)?;
         } else {
             break;
@@ -63,29 +138,122 @@ fn read_string_records_inner<R: io::Read + Send>(
     Ok(())
 }
 
-/// Thin error-handling wrapper around `read_string_records_inner`
-fn read_string_records<R: io::Read + Send>(
-    input: R,
-    headers_snd: SyncSender<StringRecord>,
-    records_snd: SyncSender<Vec<StringRecord>>,
+/// Thin error-handling wrapper around `read_string_records_inner`,
+/// sending the reason for failing to read any further to `errors_snd`.
+fn read_string_records<R: io::Read + Send + 'static>(
+    inputs: Inputs<R>,
+    headers_snd: SyncSender<Vec<StringRecord>>,
+    records_snd: SyncSender<Vec<TaggedRecord>>,
+    errors_snd: Sender<ParseError>,
     batch_size: usize,
     notrim: bool,
 ) {
-    if let Err(err) = read_string_records_inner(input, headers_snd, records_snd, batch_size, notrim)
-    {
-        log::error!("Error while reading: {}", err);
+    let result = read_string_records_inner(
+        inputs,
+        headers_snd,
+        records_snd,
+        errors_snd.clone(),
+        batch_size,
+        notrim,
+    );
+    if let Err(err) = result {
+        input::report_unreadable(ParseError::new(None, err), &errors_snd);
     }
 }
 
-/// Deserialize a single CSV string record.
-fn deserialize_record(record: StringRecord, headers: &StringRecord) -> Option<TransactionRecord> {
-    match record.deserialize(Some(headers)) {
-        Ok(ab) => Some(ab),
-        Err(err) => {
-            log::error!("Error while deserializing: {}", err);
-            None
+/// Rewrite a record's amount, written in any locale, as a plain decimal,
+/// or say why it can't be read.
+fn localize_amount(
+    record: StringRecord,
+    amount_column: Option<usize>,
+) -> Result<StringRecord, ParseError> {
+    let column = match amount_column {
+        Some(column) => column,
+        None => return Ok(record),
+    };
+    locale::localize_record(&record, column).map_err(|err| {
+        tracing::error!("Error while deserializing: {}", err);
+        ParseError::for_record(&record, err)
+    })
+}
+
+/// Deserialize a single CSV string record, logging why it can't be.
+fn deserialize_record(
+    record: StringRecord,
+    headers: &StringRecord,
+) -> Result<TransactionRecord, ParseError> {
+    record.deserialize(Some(headers)).map_err(|err| {
+        tracing::error!("Error while deserializing: {}", err);
+        ParseError::for_record(&record, err)
+    })
+}
+
+/// Read and deserialize every transaction from the inputs in order,
+/// on the current thread, along with why each row which can't be read couldn't be,
+/// in the order they appear.
+/// Useful when each transaction's effect needs following one at a time,
+/// rather than processing everything as fast as possible.
+pub fn read_transactions<R: io::Read + Send + 'static>(
+    inputs: Inputs<R>,
+    notrim: bool,
+) -> csv::Result<impl Iterator<Item = Result<TransactionRecord, ParseError>>> {
+    let has_headers = inputs.headers.has_header_row();
+    let mut readers: Vec<_> = inputs
+        .streams
+        .into_iter()
+        .map(|input| construct_csv_reader(input, notrim, has_headers))
+        .collect();
+    let mapping = &inputs.headers;
+    let headers = readers
+        .iter_mut()
+        .map(|reader| mapping.read_headers(reader))
+        .collect::<Result<Vec<_>, _>>()?;
+    let (errors_snd, errors_rcv) = channel();
+    let mut records = tagged_records(readers, &headers, inputs.order, errors_snd);
+    let mut next = None;
+    Ok(iter::from_fn(move || {
+        // Reading the next record reports any unreadable rows before it,
+        // which come out first
+        if next.is_none() {
+            next = records
+                .next()
+                .map(|(input, record)| deserialize_record(record, &headers[input]));
+        }
+        errors_rcv.try_recv().map(Err).ok().or_else(|| next.take())
+    }))
+}
+
+/// Read, deserialize, validate and handle transactions from arbitrary bytes,
+/// on the current thread, returning the final state after writing its balances nowhere.
+/// As in any other run, whatever can't be read is kept in `unreadable_rows`, and invalid
+/// transactions are rejected, so no input should make this panic: it's the entry point for fuzzing
+/// (see `fuzz/`), where a panic means a bug in the parser or the handlers.
+pub fn process_bytes(input: &[u8]) -> State {
+    process_bytes_with_policies(input, Policies::default())
+}
+
+/// Like `process_bytes`, but under the given policies, crediting any interest
+/// they pay at the end, so that fuzzing can reach admin transactions, credit and interest.
+pub fn process_bytes_with_policies(input: &[u8], policies: Policies) -> State {
+    let mut state = State::with_policies(policies);
+    let inputs = Inputs::single(io::Cursor::new(input.to_vec()));
+    if let Ok(records) = read_transactions(inputs, false) {
+        for record in records {
+            let record = match record {
+                Ok(record) => record,
+                Err(err) => {
+                    state.unreadable_rows.push(err);
+                    continue;
+                }
+            };
+            if let Err(err) = handlers::handle_transaction(record, &mut state) {
+                tracing::debug!("Rejected transaction: {}", err);
+            }
         }
     }
+    interest::accrue(&mut state, None);
+    write_balances(&state, AccountOrder::Client, io::sink());
+    state
 }
 
 /// Set the number of workers in rayon's global
@@ -99,68 +267,527 @@ pub fn configure_deserialize_workers(num_workers: Option<usize>) {
         .build_global();
 
     if let Err(err) = config_result {
-        log::error!("Error configuring rayon thread pool: {}", err);
+        tracing::error!("Error configuring rayon thread pool: {}", err);
     }
 }
 
 /// Read CSV records from an input stream and write them to an output stream.
-/// Transactions are deserialized in parallel, but currently handled serially.
+/// Transactions are deserialized in parallel, then handled in parallel
+/// by `DEFAULT_HANDLER_THREADS` threads, each responsible for a shard of clients.
+/// The final state is returned for any further reporting.
 pub fn process_transactions<R: io::Read + Send + 'static, W: io::Write>(
     input_stream: R,
     output_stream: &mut W,
     batch_size: usize,
     notrim: bool,
-) {
-    // TODO: Async / multithreaded?
-    let mut state = State::new();
+    policies: Policies,
+    client_queue_limit: Option<ClientQueueLimit>,
+    control: Option<Control>,
+) -> State {
+    let config = PipelineConfig {
+        batch_size,
+        notrim,
+        ..Default::default()
+    };
+    process_inputs(
+        Inputs::single(input_stream),
+        output_stream,
+        config,
+        policies,
+        client_queue_limit,
+        control,
+    )
+}
 
-    // Maximum number of batches to keep in the channel at once.
-    // Once this limit is reached, IO will pause until one is processed.
-    let max_batches = 1;
+/// Like `process_transactions`, but reading from several inputs,
+/// e.g. `Inputs`, combined in the order given by `inputs.order`,
+/// or `MappedInputs`, and with the number of handler threads etc. given by `config`.
+pub fn process_inputs<S: RecordSource, W: io::Write>(
+    inputs: S,
+    output_stream: &mut W,
+    config: PipelineConfig,
+    policies: Policies,
+    client_queue_limit: Option<ClientQueueLimit>,
+    control: Option<Control>,
+) -> State {
+    let state = run_inputs(inputs, config, policies, client_queue_limit, control);
+    write_balances(&state, AccountOrder::Client, output_stream);
+    state
+}
 
-    let (records_snd, records_rcv) = sync_channel::<Vec<StringRecord>>(max_batches);
-    let (headers_snd, headers_rcv) = sync_channel::<StringRecord>(1);
+/// Like `process_inputs`, but only return the final state,
+/// e.g. to write balances in a different order with `write_balances`.
+pub fn run_inputs<S: RecordSource>(
+    inputs: S,
+    config: PipelineConfig,
+    policies: Policies,
+    client_queue_limit: Option<ClientQueueLimit>,
+    control: Option<Control>,
+) -> State {
+    run_pipeline(
+        inputs,
+        config,
+        State::with_policies(policies),
+        client_queue_limit,
+        control,
+        None,
+    )
+}
 
-    let reader_handle = thread::spawn(move || {
-        read_string_records(input_stream, headers_snd, records_snd, batch_size, notrim)
-    });
+/// Like `process_inputs`, but rather than writing final balances,
+/// write an account's updated balances to `updates_stream`
+/// after every successful transaction, as it happens.
+/// Updates for each client are in order, but different clients may be interleaved.
+/// The final state is returned, e.g. to write final balances with `write_balances`.
+pub fn stream_inputs<S: RecordSource, U: io::Write>(
+    inputs: S,
+    updates_stream: &mut U,
+    config: PipelineConfig,
+    policies: Policies,
+    client_queue_limit: Option<ClientQueueLimit>,
+    control: Option<Control>,
+) -> State {
+    run_pipeline(
+        inputs,
+        config,
+        State::with_policies(policies),
+        client_queue_limit,
+        control,
+        Some(updates_stream),
+    )
+}
 
-    if let Ok(headers) = headers_rcv.recv() {
-        for batch in records_rcv {
-            let tx_batch: Vec<_> = batch
-                .into_par_iter()
-                .filter_map(|record| deserialize_record(record, &headers))
-                .collect();
+/// Like `run_inputs`, or with `updates_stream`, `stream_inputs`,
+/// but carrying on from a previous run's state, e.g. restored from a checkpoint
+/// with `Checkpoint::into_state`, and read from where it left off with `Checkpoint::open_input`.
+/// Transactions are handled with the state's policies.
+pub fn resume_inputs<S: RecordSource>(
+    inputs: S,
+    initial: State,
+    updates_stream: Option<&mut dyn io::Write>,
+    config: PipelineConfig,
+    client_queue_limit: Option<ClientQueueLimit>,
+    control: Option<Control>,
+) -> State {
+    run_pipeline(
+        inputs,
+        config,
+        initial,
+        client_queue_limit,
+        control,
+        updates_stream,
+    )
+}
 
-            for tx in tx_batch {
-                if let Err(err) = handlers::handle_transaction(tx, &mut state) {
-                    log::error!("Error while handling transaction: {}", err);
-                }
+/// Like `process_inputs`, but reading transactions already parsed by a `TransactionSource`,
+/// e.g. `JsonLinesSource`, on the current thread, rather than CSV records parsed in parallel.
+/// Records which can't be parsed are kept in `State::unreadable_rows`, rather than handled.
+pub fn process_source<T: TransactionSource, W: io::Write>(
+    source: T,
+    output_stream: &mut W,
+    config: PipelineConfig,
+    policies: Policies,
+    client_queue_limit: Option<ClientQueueLimit>,
+    control: Option<Control>,
+) -> State {
+    let state = resume_source(
+        source,
+        State::with_policies(policies),
+        None,
+        config,
+        client_queue_limit,
+        control,
+    );
+    write_balances(&state, AccountOrder::Client, output_stream);
+    state
+}
+
+/// Like `resume_inputs`, but for a `TransactionSource`, as in `process_source`.
+pub fn resume_source<T: TransactionSource>(
+    mut source: T,
+    initial: State,
+    updates_stream: Option<&mut dyn io::Write>,
+    config: PipelineConfig,
+    client_queue_limit: Option<ClientQueueLimit>,
+    control: Option<Control>,
+) -> State {
+    let batches = std::iter::from_fn(|| read_source_batch(&mut source, config.batch_size));
+    dispatch_batches(
+        batches,
+        config,
+        initial,
+        client_queue_limit,
+        control,
+        updates_stream,
+    )
+}
+
+/// Transactions ready to be handled, as read and parsed together.
+struct ParsedBatch {
+    records: Vec<TransactionRecord>,
+    /// Position of the last record read, if known, for checkpoints
+    position: Option<InputPosition>,
+    /// Records which couldn't be parsed
+    unreadable: Vec<ParseError>,
+    /// When the batch started being parsed
+    started: Instant,
+}
+
+/// Read up to `batch_size` records from the source, or nothing once it's exhausted.
+fn read_source_batch<T: TransactionSource>(
+    source: &mut T,
+    batch_size: usize,
+) -> Option<ParsedBatch> {
+    let mut batch = ParsedBatch {
+        records: Vec::new(),
+        position: None,
+        unreadable: Vec::new(),
+        started: Instant::now(),
+    };
+    while batch.records.len() + batch.unreadable.len() < batch_size {
+        match source.next_record() {
+            Some(Ok(record)) => batch.records.push(record),
+            Some(Err(err)) => {
+                tracing::error!("Error while parsing: {}", err);
+                batch.unreadable.push(err);
             }
+            None if batch.records.is_empty() && batch.unreadable.is_empty() => return None,
+            None => break,
         }
-    } else {
-        log::error!("Failed to get CSV headers from reader thread");
     }
+    Some(batch)
+}
 
-    write_balances(state, output_stream);
+/// Read, deserialize, and handle every transaction, returning the final state.
+#[tracing::instrument(name = "pipeline", skip_all)]
+fn run_pipeline<S: RecordSource>(
+    inputs: S,
+    config: PipelineConfig,
+    initial: State,
+    client_queue_limit: Option<ClientQueueLimit>,
+    control: Option<Control>,
+    updates_stream: Option<&mut dyn io::Write>,
+) -> State {
+    // Once `batch_buffer` batches are waiting, IO will pause until one is processed.
+    let (records_snd, records_rcv) = sync_channel::<Vec<TaggedRecord>>(config.batch_buffer);
+    let (headers_snd, headers_rcv) = sync_channel::<Vec<StringRecord>>(1);
+    // Unbounded, since nothing receives until the reader has finished
+    let (errors_snd, errors_rcv) = channel::<ParseError>();
+
+    let read_span = tracing::info_span!("read", inputs = inputs.num_inputs());
+    let reader_handle = thread::spawn(move || {
+        read_span.in_scope(|| {
+            inputs.read_records(
+                headers_snd,
+                records_snd,
+                errors_snd,
+                config.batch_size,
+                config.notrim,
+            )
+        })
+    });
+
+    // Without headers, the reader has failed, and won't send any records either
+    let headers = headers_rcv.recv().unwrap_or_else(|_| {
+        tracing::error!("Failed to get CSV headers from reader thread");
+        Vec::new()
+    });
+    // Only looked up if amounts need localizing
+    let amount_columns: Vec<_> = headers
+        .iter()
+        .map(|headers| {
+            let column = headers.iter().position(|header| header.trim() == "amount");
+            column.filter(|_| config.localized_amounts)
+        })
+        .collect();
+    let batches = records_rcv.into_iter().map(|batch| {
+        let started = Instant::now();
+        let batch_len = batch.len();
+        let position = batch
+            .last()
+            .and_then(|(_, record)| record.position().map(InputPosition::from));
+        let deserialize = |(input, record): TaggedRecord| {
+            let record = localize_amount(record, amount_columns[input])?;
+            deserialize_record(record, &headers[input])
+        };
+        let results: Vec<_> =
+            tracing::debug_span!("deserialize", records = batch_len).in_scope(|| {
+                if config.execution == ExecutionMode::Sequential {
+                    batch.into_iter().map(deserialize).collect()
+                } else {
+                    batch.into_par_iter().map(deserialize).collect()
+                }
+            });
+        let mut records = Vec::with_capacity(results.len());
+        let mut unreadable = Vec::new();
+        for result in results {
+            match result {
+                Ok(record) => records.push(record),
+                Err(err) => unreadable.push(err),
+            }
+        }
+        ParsedBatch {
+            records,
+            position,
+            unreadable,
+            started,
+        }
+    });
+    let mut state = dispatch_batches(
+        batches,
+        config,
+        initial,
+        client_queue_limit,
+        control,
+        updates_stream,
+    );
 
     // Should already have finished, but wait just in case
     if let Err(err) = reader_handle.join() {
-        log::error!("Failed to join reader thread: {:?}", err);
+        tracing::error!("Failed to join reader thread: {:?}", err);
+        let err = ParseError::new(None, "reader thread panicked");
+        state.unreadable_rows.push(err);
     }
+    // The reader has hung up, so this gets every record it couldn't read
+    state.unreadable_rows.extend(errors_rcv.try_iter());
+
+    state
 }
 
-/// Write final account balances to an output stream, consuming the state.
-pub fn write_balances<W: io::Write>(state: State, output_stream: W) {
-    let mut writer = csv::Writer::from_writer(output_stream);
-    for (&client_id, account) in state.accounts.iter() {
-        let record = OutputRecord::new(client_id, account);
+/// Handle every batch of transactions, starting from `initial`,
+/// and returning the final state, whatever format they were read from.
+fn dispatch_batches(
+    batches: impl Iterator<Item = ParsedBatch>,
+    config: PipelineConfig,
+    initial: State,
+    client_queue_limit: Option<ClientQueueLimit>,
+    mut control: Option<Control>,
+    updates_stream: Option<&mut dyn io::Write>,
+) -> State {
+    let mut handler = ShardedHandler::spawn(&config, initial.policies.clone(), client_queue_limit);
+    if let Err(err) = handler.restore(initial) {
+        tracing::error!("Failed to restore initial state: {}", err);
+    }
+    let mut updates = updates_stream.map(|updates_stream| {
+        let writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(updates_stream);
+        (handler.subscribe(), writer)
+    });
+    if let Some((_, writer)) = &mut updates {
+        if let Err(err) = writer.write_record(UPDATE_HEADERS) {
+            tracing::error!("error writing CSV headers: {}", err);
+        }
+    }
 
-        if let Err(err) = writer.serialize(&record) {
-            log::error!("error writing serialized account balances: {}", err);
+    let mut throttle = config.rate.map(Throttle::new);
+    let mut unreadable = Vec::new();
+    // Position of the last record dispatched, for checkpoints
+    let mut position = None;
+    for batch in batches {
+        if let Some(control) = &mut control {
+            control.poll(&mut handler, position);
+        }
+        position = batch.position.or(position);
+        unreadable.extend(batch.unreadable);
+
+        for tx in batch.records {
+            if let Some(throttle) = &mut throttle {
+                throttle.wait();
+            }
+            if let Err(err) = handler.dispatch(tx) {
+                tracing::error!("Error while handling transaction: {}", err);
+            }
         }
+        telemetry::record_batch(batch.started.elapsed());
+
+        if let Some((updates_rcv, writer)) = &mut updates {
+            write_updates(updates_rcv.try_iter(), writer);
+        }
+    }
+
+    let mut state = handler.finish();
+    if let Some(control) = &control {
+        control.finish(&state, position);
+    }
+    // Handlers have hung up, so this gets every remaining update
+    if let Some((updates_rcv, writer)) = &mut updates {
+        write_updates(updates_rcv.iter(), writer);
     }
+    state.unreadable_rows.extend(unreadable);
+
+    state
+}
+
+/// Write balance updates which have arrived so far.
+fn write_updates<W: io::Write>(
+    updates: impl Iterator<Item = BalanceUpdate>,
+    writer: &mut csv::Writer<W>,
+) {
+    for BalanceUpdate { tx, balance } in updates {
+        let result = writer.serialize((
+            tx,
+            balance.client,
+            balance.currency,
+            balance.available,
+            balance.held,
+            balance.total,
+            balance.locked,
+        ));
+        if let Err(err) = result {
+            tracing::error!("error writing serialized balance update: {}", err);
+        }
+    }
+    // Flush every batch, so that updates can be followed as they happen
     if let Err(err) = writer.flush() {
-        log::error!("error flusing serialized account balances: {}", err);
+        tracing::error!("error flushing serialized balance updates: {}", err);
+    }
+}
+
+/// Write final account balances to an output stream, as CSV.
+/// Other formats can be written with `sink::sink_balances`.
+pub fn write_balances<W: io::Write>(state: &State, order: AccountOrder, output_stream: W) {
+    write_accounts(
+        &state.accounts,
+        &state.policies.rounding,
+        order,
+        false,
+        output_stream,
+    );
+}
+
+/// Write account balances to an output stream, as CSV,
+/// with a `lock_reason` column if `lock_reasons`.
+pub(crate) fn write_accounts<W: io::Write>(
+    accounts: &AccountsState,
+    rounding: &RoundingPolicy,
+    order: AccountOrder,
+    lock_reasons: bool,
+    output_stream: W,
+) {
+    let sink = CsvSink::new(output_stream).with_lock_reason(lock_reasons);
+    if let Err(err) = sink_accounts(accounts, rounding, order, sink) {
+        tracing::error!("error writing serialized account balances: {}", err);
+    }
+}
+
+/// Write the total fees charged to each account to an output stream.
+pub fn write_fees<W: io::Write>(
+    accounts: &AccountsState,
+    rounding: &RoundingPolicy,
+    order: AccountOrder,
+    output_stream: W,
+) {
+    let with_currency = accounts.has_currency_codes();
+    let mut writer = account_rows_writer(output_stream, with_currency, &FEES_HEADERS);
+    for (&key, account) in accounts.ordered(order) {
+        let record = FeesRecord::new(key, account, rounding);
+
+        let result = if with_currency {
+            writer.serialize((record.client, record.currency, record.fees))
+        } else {
+            writer.serialize(&record)
+        };
+        if let Err(err) = result {
+            tracing::error!("error writing serialized fees: {}", err);
+        }
+    }
+    if let Err(err) = writer.flush() {
+        tracing::error!("error flushing serialized fees: {}", err);
+    }
+}
+
+/// Write rejected transactions to an output stream, each with
+/// the code of the error which rejected it, and a message with details,
+/// followed by input rows which couldn't be read, with the code `UNREADABLE`,
+/// and their line and content in place of a transaction.
+pub fn write_rejections<W: io::Write>(
+    rejections: &[Rejection],
+    unreadable: &[ParseError],
+    output_stream: W,
+) {
+    // Optional columns are always included, so that every row lines up
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(output_stream);
+    if let Err(err) = writer.write_record(REJECTION_HEADERS) {
+        tracing::error!("error writing CSV headers: {}", err);
+    }
+    for Rejection { record, error } in rejections {
+        let result = writer.serialize((
+            &record.transaction_type,
+            record.client_id,
+            record.tx_id,
+            record.amount,
+            record.timestamp,
+            record.currency,
+            error.code(),
+            error.to_string(),
+            None::<u64>,
+            "",
+        ));
+        if let Err(err) = result {
+            tracing::error!("error writing serialized rejection: {}", err);
+        }
+    }
+    for err in unreadable {
+        let mut row = vec![""; 6];
+        let line = err.line.map(|line| line.to_string()).unwrap_or_default();
+        row.extend([UNREADABLE_CODE, &err.message, &line, &err.content]);
+        if let Err(err) = writer.write_record(row) {
+            tracing::error!("error writing unreadable row: {}", err);
+        }
+    }
+    if let Err(err) = writer.flush() {
+        tracing::error!("error flushing serialized rejections: {}", err);
+    }
+}
+
+const OUTPUT_HEADERS: [&str; 6] = ["client", "currency", "available", "held", "total", "locked"];
+const FEES_HEADERS: [&str; 3] = ["client", "currency", "fees"];
+const UPDATE_HEADERS: [&str; 7] = [
+    "tx",
+    "client",
+    "currency",
+    "available",
+    "held",
+    "total",
+    "locked",
+];
+const REJECTION_HEADERS: [&str; 10] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "timestamp",
+    "currency",
+    "error_code",
+    "error_message",
+    "line",
+    "content",
+];
+/// Error code of input rows which couldn't be read, among rejections.
+const UNREADABLE_CODE: &str = "UNREADABLE";
+
+/// Construct a CSV writer for one row per account.
+/// Records skip their currency when it's the default, which would
+/// leave rows with different numbers of fields, so when any account
+/// has a currency code, the header is written up front and rows are
+/// written as tuples with an empty field for the default currency.
+fn account_rows_writer<W: io::Write>(
+    output_stream: W,
+    with_currency: bool,
+    headers: &[&str],
+) -> csv::Writer<W> {
+    if with_currency {
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(output_stream);
+        if let Err(err) = writer.write_record(headers) {
+            tracing::error!("error writing CSV headers: {}", err);
+        }
+        writer
+    } else {
+        csv::Writer::from_writer(output_stream)
     }
 }
@@ -0,0 +1,309 @@
+//! Velocity limits on each client's deposits and withdrawals, e.g. to slow down
+//! fraud on a compromised account, as set by the `LimitPolicy`.
+//!
+//! Single deposits and withdrawals are checked against their own limits.
+//! Daily withdrawals and transactions per minute are counted in windows of
+//! the transactions' timestamps: calendar days (UTC), and minutes on the clock.
+//! A transaction from earlier than a client's latest window counts towards it,
+//! so that replaying a late transaction can't get around the limits.
+
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::currency::{Currency, CurrencyCode};
+use crate::policy::Limits;
+use crate::traits::Transaction;
+use crate::types::{ClientId, Deposit, TransactionError, Withdrawal};
+
+const SECONDS_PER_MINUTE: u64 = 60;
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// A client's successful deposits and withdrawals in their latest windows.
+/// Only what the client's limits need is counted.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Activity {
+    /// Latest minute with a deposit or withdrawal, since the epoch
+    minute: u64,
+    /// Deposits and withdrawals in that minute
+    count: u32,
+    /// Latest day with a withdrawal, since the epoch
+    day: u64,
+    /// Amount withdrawn that day, in each currency
+    withdrawn: Vec<(Option<CurrencyCode>, Currency)>,
+}
+
+impl Activity {
+    fn count_in(&self, minute: u64) -> u32 {
+        if minute > self.minute {
+            0
+        } else {
+            self.count
+        }
+    }
+
+    fn withdrawn_on(&self, day: u64, currency: Option<CurrencyCode>) -> Currency {
+        if day > self.day {
+            return Currency::ZERO;
+        }
+        self.withdrawn
+            .iter()
+            .find(|(withdrawn_currency, _)| *withdrawn_currency == currency)
+            .map_or(Currency::ZERO, |&(_, amount)| amount)
+    }
+}
+
+/// Each client's recent activity, for checking their velocity limits.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct ActivityState(FxHashMap<ClientId, Activity>);
+
+impl ActivityState {
+    /// Check that a deposit is within the client's limits.
+    pub fn check_deposit(
+        &self,
+        deposit: &Deposit,
+        limits: &Limits,
+    ) -> Result<(), TransactionError> {
+        check_single(deposit, deposit.amount, limits.max_deposit)?;
+        self.check_per_minute(deposit, limits)
+    }
+
+    /// Check that a withdrawal is within the client's limits.
+    pub fn check_withdrawal(
+        &self,
+        withdrawal: &Withdrawal,
+        limits: &Limits,
+    ) -> Result<(), TransactionError> {
+        check_single(withdrawal, withdrawal.amount, limits.max_withdrawal)?;
+        if let (Some(max), Some(timestamp)) = (limits.max_daily_withdrawal, withdrawal.timestamp) {
+            let withdrawn = self
+                .0
+                .get(&withdrawal.client_id)
+                .map_or(Currency::ZERO, |activity| {
+                    activity.withdrawn_on(timestamp / SECONDS_PER_DAY, withdrawal.currency)
+                });
+            let total = withdrawn.checked_add(withdrawal.amount);
+            if total.is_none_or(|total| total > max) {
+                return Err(TransactionError::DailyLimitExceeded {
+                    client: withdrawal.client_id,
+                    tx: withdrawal.tx_id,
+                    amount: withdrawal.amount,
+                    withdrawn,
+                    max,
+                });
+            }
+        }
+        self.check_per_minute(withdrawal, limits)
+    }
+
+    fn check_per_minute<T: Transaction>(
+        &self,
+        tx: &T,
+        limits: &Limits,
+    ) -> Result<(), TransactionError> {
+        if let (Some(max), Some(timestamp)) = (limits.max_per_minute, tx.get_timestamp()) {
+            let count = self.0.get(&tx.get_client_id()).map_or(0, |activity| {
+                activity.count_in(timestamp / SECONDS_PER_MINUTE)
+            });
+            if count >= max {
+                return Err(TransactionError::RateLimitExceeded {
+                    client: tx.get_client_id(),
+                    tx: tx.get_tx_id(),
+                    max_per_minute: max,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Count a successful deposit towards the client's limits.
+    pub fn record_deposit(&mut self, deposit: &Deposit, limits: &Limits) {
+        self.record(deposit, None, limits);
+    }
+
+    /// Count a successful withdrawal towards the client's limits.
+    pub fn record_withdrawal(&mut self, withdrawal: &Withdrawal, limits: &Limits) {
+        self.record(withdrawal, Some(withdrawal.amount), limits);
+    }
+
+    fn record<T: Transaction>(&mut self, tx: &T, withdrawn: Option<Currency>, limits: &Limits) {
+        let timestamp = match tx.get_timestamp() {
+            Some(timestamp) => timestamp,
+            None => return,
+        };
+        let withdrawn = withdrawn.filter(|_| limits.max_daily_withdrawal.is_some());
+        if limits.max_per_minute.is_none() && withdrawn.is_none() {
+            return;
+        }
+        let activity = self.0.entry(tx.get_client_id()).or_default();
+
+        let minute = timestamp / SECONDS_PER_MINUTE;
+        if minute > activity.minute {
+            activity.minute = minute;
+            activity.count = 0;
+        }
+        activity.count = activity.count.saturating_add(1);
+
+        if let Some(amount) = withdrawn {
+            let day = timestamp / SECONDS_PER_DAY;
+            if day > activity.day {
+                activity.day = day;
+                activity.withdrawn.clear();
+            }
+            let currency = tx.get_currency();
+            match activity
+                .withdrawn
+                .iter_mut()
+                .find(|(withdrawn_currency, _)| *withdrawn_currency == currency)
+            {
+                // Checked against the daily limit, so can't overflow
+                Some((_, total)) => *total += amount,
+                None => activity.withdrawn.push((currency, amount)),
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Each client's activity, e.g. to split it between states.
+    pub(crate) fn into_clients(self) -> impl Iterator<Item = (ClientId, Activity)> {
+        self.0.into_iter()
+    }
+
+    pub(crate) fn insert(&mut self, client_id: ClientId, activity: Activity) {
+        self.0.insert(client_id, activity);
+    }
+
+    /// A client's activity, if any has been counted.
+    pub(crate) fn get(&self, client_id: ClientId) -> Option<&Activity> {
+        self.0.get(&client_id)
+    }
+
+    /// Forget a client's activity, e.g. to undo counting it.
+    pub(crate) fn remove(&mut self, client_id: ClientId) {
+        self.0.remove(&client_id);
+    }
+
+    /// Move all activity from another state with different clients into this one.
+    pub(crate) fn absorb(&mut self, other: ActivityState) {
+        self.0.extend(other.0);
+    }
+}
+
+fn check_single<T: Transaction>(
+    tx: &T,
+    amount: Currency,
+    max: Option<Currency>,
+) -> Result<(), TransactionError> {
+    match max {
+        Some(max) if amount > max => Err(TransactionError::LimitExceeded {
+            client: tx.get_client_id(),
+            tx: tx.get_tx_id(),
+            amount,
+            max,
+        }),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ActivityState;
+    use crate::currency::Currency;
+    use crate::policy::Limits;
+    use crate::types::{Deposit, TransactionError, TransactionId, Withdrawal};
+
+    fn withdrawal(tx_id: TransactionId, amount: f64, timestamp: u64) -> Withdrawal {
+        Withdrawal {
+            client_id: 1,
+            tx_id,
+            amount: Currency::from(amount),
+            timestamp: Some(timestamp),
+            currency: None,
+        }
+    }
+
+    #[test]
+    fn test_single_limits() {
+        let limits = Limits {
+            max_deposit: Some(Currency::from(10.0)),
+            max_withdrawal: Some(Currency::from(5.0)),
+            ..Default::default()
+        };
+        let activity = ActivityState::default();
+        let deposit = Deposit {
+            client_id: 1,
+            tx_id: 1,
+            amount: Currency::from(10.0),
+            timestamp: None,
+            currency: None,
+        };
+        assert_eq!(activity.check_deposit(&deposit, &limits), Ok(()));
+        assert_eq!(
+            activity.check_withdrawal(&withdrawal(2, 5.5, 0), &limits),
+            Err(TransactionError::LimitExceeded {
+                client: 1,
+                tx: 2,
+                amount: Currency::from(5.5),
+                max: Currency::from(5.0),
+            })
+        );
+    }
+
+    #[test]
+    fn test_daily_withdrawal_limit() {
+        let limits = Limits {
+            max_daily_withdrawal: Some(Currency::from(10.0)),
+            ..Default::default()
+        };
+        let mut activity = ActivityState::default();
+        for (tx_id, amount, timestamp) in [(1, 4.0, 100), (2, 6.0, 200)] {
+            let withdrawal = withdrawal(tx_id, amount, timestamp);
+            assert_eq!(activity.check_withdrawal(&withdrawal, &limits), Ok(()));
+            activity.record_withdrawal(&withdrawal, &limits);
+        }
+        assert_eq!(
+            activity.check_withdrawal(&withdrawal(3, 0.5, 300), &limits),
+            Err(TransactionError::DailyLimitExceeded {
+                client: 1,
+                tx: 3,
+                amount: Currency::from(0.5),
+                withdrawn: Currency::from(10.0),
+                max: Currency::from(10.0),
+            })
+        );
+        // The next day starts afresh
+        let next_day = withdrawal(3, 0.5, 86_400);
+        assert_eq!(activity.check_withdrawal(&next_day, &limits), Ok(()));
+    }
+
+    #[test]
+    fn test_per_minute_limit() {
+        let limits = Limits {
+            max_per_minute: Some(2),
+            ..Default::default()
+        };
+        let mut activity = ActivityState::default();
+        for (tx_id, timestamp) in [(1, 60), (2, 119)] {
+            let withdrawal = withdrawal(tx_id, 1.0, timestamp);
+            assert_eq!(activity.check_withdrawal(&withdrawal, &limits), Ok(()));
+            activity.record_withdrawal(&withdrawal, &limits);
+        }
+        assert!(activity
+            .check_withdrawal(&withdrawal(3, 1.0, 100), &limits)
+            .is_err());
+        // Late transactions count towards the latest minute
+        assert!(activity
+            .check_withdrawal(&withdrawal(3, 1.0, 30), &limits)
+            .is_err());
+        assert_eq!(
+            activity.check_withdrawal(&withdrawal(3, 1.0, 120), &limits),
+            Ok(())
+        );
+        // Nothing is counted without a timestamp
+        let mut untimed = withdrawal(4, 1.0, 0);
+        untimed.timestamp = None;
+        assert_eq!(activity.check_withdrawal(&untimed, &limits), Ok(()));
+    }
+}
@@ -0,0 +1,259 @@
+//! Amounts written the way people write them, rather than as plain decimals,
+//! e.g. `1.234,56`, `1,234.56` or `€ 12,50`, for inputs exported from
+//! spreadsheets or from processors in other locales.
+//!
+//! A separator may be for thousands or for decimals: it's a decimal separator
+//! if it comes after the other kind, or is the only separator and isn't followed
+//! by exactly three digits. `1,234` could be either, so it's rejected as ambiguous
+//! rather than guessed at.
+
+use csv::StringRecord;
+use std::error::Error;
+use std::fmt;
+
+use crate::types::Currency;
+
+/// Symbols which may come before or after an amount.
+const CURRENCY_SYMBOLS: [char; 12] = ['$', '€', '£', '¥', '₹', '₩', '₽', '₺', '₪', '₫', '₱', '₦'];
+
+/// Longest currency code or abbreviation which may come before or after an amount,
+/// e.g. `USD` or `kr`.
+const MAX_CODE_LEN: usize = 3;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AmountError {
+    /// Could be read more than one way, e.g. `1,234` as 1234 or 1.234
+    Ambiguous(String),
+    /// Not an amount in any locale
+    Invalid {
+        amount: String,
+        reason: &'static str,
+    },
+}
+
+impl fmt::Display for AmountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ambiguous(amount) => write!(
+                f,
+                "ambiguous amount '{}': the separator could be for thousands or decimals",
+                amount
+            ),
+            Self::Invalid { amount, reason } => {
+                write!(f, "invalid amount '{}': {}", amount, reason)
+            }
+        }
+    }
+}
+
+impl Error for AmountError {}
+
+/// Remove a currency symbol or code, e.g. `€` or `USD`, from the start of `s`, if it has one.
+fn strip_prefix_symbol(s: &str) -> &str {
+    if let Some(rest) = s.strip_prefix(&CURRENCY_SYMBOLS[..]) {
+        return rest.trim_start();
+    }
+    let rest = s.trim_start_matches(char::is_alphabetic);
+    if s[..s.len() - rest.len()].chars().count() <= MAX_CODE_LEN {
+        rest.trim_start()
+    } else {
+        s
+    }
+}
+
+/// Remove a currency symbol or code from the end of `s`, if it has one.
+fn strip_suffix_symbol(s: &str) -> &str {
+    if let Some(rest) = s.strip_suffix(&CURRENCY_SYMBOLS[..]) {
+        return rest.trim_end();
+    }
+    let rest = s.trim_end_matches(char::is_alphabetic);
+    if s[rest.len()..].chars().count() <= MAX_CODE_LEN {
+        rest.trim_end()
+    } else {
+        s
+    }
+}
+
+/// Remove a sign from the start of `s`, returning whether it was negative.
+fn strip_sign(s: &str) -> (bool, Option<&str>) {
+    if let Some(rest) = s.strip_prefix('-') {
+        (true, Some(rest.trim_start()))
+    } else {
+        (false, s.strip_prefix('+').map(str::trim_start))
+    }
+}
+
+fn is_thousands_separator(c: char) -> bool {
+    c == '.' || c == ',' || c == '\'' || c.is_whitespace()
+}
+
+fn is_digits(s: &str) -> bool {
+    s.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Read an amount in any of the usual locale formats, e.g. `-$1,234.56`,
+/// `1.234,56 €` or `1 234,56`.
+pub fn parse_localized(s: &str) -> Result<Currency, AmountError> {
+    let invalid = |reason| AmountError::Invalid {
+        amount: s.to_owned(),
+        reason,
+    };
+
+    // A sign may come before or after a leading symbol, e.g. `-$1` or `$-1`
+    let mut number = s.trim();
+    let (mut negative, rest) = strip_sign(number);
+    number = strip_prefix_symbol(rest.unwrap_or(number));
+    if rest.is_none() {
+        let (sign, rest) = strip_sign(number);
+        negative = sign;
+        number = rest.unwrap_or(number);
+    }
+    number = strip_suffix_symbol(number);
+
+    let last_dot = number.rfind('.');
+    let last_comma = number.rfind(',');
+    let decimal = match (last_dot, last_comma) {
+        (Some(dot), Some(comma)) => Some(dot.max(comma)),
+        (Some(only), None) | (None, Some(only)) => {
+            let (whole, fraction) = (&number[..only], &number[only + 1..]);
+            if number.matches(&number[only..=only]).count() > 1 {
+                // Only thousands are separated more than once
+                None
+            } else if is_digits(whole)
+                && (1..=3).contains(&whole.len())
+                && !whole.starts_with('0')
+                && is_digits(fraction)
+                && fraction.len() == 3
+            {
+                return Err(AmountError::Ambiguous(s.to_owned()));
+            } else {
+                Some(only)
+            }
+        }
+        (None, None) => None,
+    };
+
+    let (whole, fraction) = match decimal {
+        Some(decimal) => {
+            let whole = &number[..decimal];
+            if whole.contains(&number[decimal..=decimal]) {
+                return Err(invalid("more than one decimal separator"));
+            }
+            (whole, &number[decimal + 1..])
+        }
+        None => (number, ""),
+    };
+    if !is_digits(fraction) {
+        return Err(invalid("decimal places must only be digits"));
+    }
+    if decimal.is_some() && fraction.is_empty() {
+        return Err(invalid("no digits after the decimal separator"));
+    }
+
+    let groups: Vec<_> = whole.split(is_thousands_separator).collect();
+    if !groups.iter().all(|group| is_digits(group)) {
+        return Err(invalid("expected only digits and separators"));
+    }
+    if groups.len() > 1
+        && (!(1..=3).contains(&groups[0].len()) || groups[1..].iter().any(|group| group.len() != 3))
+    {
+        return Err(invalid("thousands must be grouped in threes"));
+    }
+    let digits = groups.concat();
+    if digits.is_empty() && fraction.is_empty() {
+        return Err(invalid("no digits"));
+    }
+
+    let sign = if negative { "-" } else { "" };
+    format!("{}{}.{}", sign, digits, fraction)
+        .trim_end_matches('.')
+        .parse()
+        .map_err(|_| invalid("out of range"))
+}
+
+/// The record with the amount in `column`, if any, rewritten as a plain decimal,
+/// so that it can be deserialized as usual.
+pub(crate) fn localize_record(
+    record: &StringRecord,
+    column: usize,
+) -> Result<StringRecord, AmountError> {
+    let amount = match record.get(column) {
+        Some(amount) if !amount.trim().is_empty() => parse_localized(amount)?.to_string(),
+        _ => return Ok(record.clone()),
+    };
+    let mut localized: StringRecord = record
+        .iter()
+        .enumerate()
+        .map(|(index, field)| if index == column { &amount } else { field })
+        .collect();
+    localized.set_position(record.position().cloned());
+    Ok(localized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{localize_record, parse_localized, AmountError};
+    use crate::types::Currency;
+    use csv::StringRecord;
+
+    #[test]
+    fn test_parse_localized() {
+        let parse = |s: &str| parse_localized(s).map(Currency::to_f64);
+        assert_eq!(parse("1234.56"), Ok(1234.56));
+        assert_eq!(parse("1,234.56"), Ok(1234.56));
+        assert_eq!(parse("1.234,56"), Ok(1234.56));
+        assert_eq!(parse("1 234,56"), Ok(1234.56));
+        assert_eq!(parse("1'234'567.5"), Ok(1234567.5));
+        assert_eq!(parse("1.234.567"), Ok(1234567.0));
+        assert_eq!(parse("12,5"), Ok(12.5));
+        assert_eq!(parse("0,125"), Ok(0.125));
+        assert_eq!(parse("1,2345"), Ok(1.2345));
+        assert_eq!(parse(",5"), Ok(0.5));
+        assert_eq!(parse("  € 12,50 "), Ok(12.5));
+        assert_eq!(parse("12,50€"), Ok(12.5));
+        assert_eq!(parse("-$1,234.00"), Ok(-1234.0));
+        assert_eq!(parse("$-3"), Ok(-3.0));
+        assert_eq!(parse("USD 7.25"), Ok(7.25));
+        assert_eq!(parse("100 kr"), Ok(100.0));
+    }
+
+    #[test]
+    fn test_parse_localized_errors() {
+        assert_eq!(
+            parse_localized("1,234"),
+            Err(AmountError::Ambiguous("1,234".to_string()))
+        );
+        assert!(matches!(
+            parse_localized("999.000"),
+            Err(AmountError::Ambiguous(_))
+        ));
+        for invalid in [
+            "",
+            "$",
+            "1,23,456",
+            "12,34.5,6",
+            "1.",
+            "1.5x5",
+            "12 34",
+            "1,234.5.6",
+            "abcd 5",
+        ] {
+            assert!(
+                matches!(parse_localized(invalid), Err(AmountError::Invalid { .. })),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_localize_record() {
+        let record = StringRecord::from(vec!["deposit", "1", "1", "1.234,5"]);
+        assert_eq!(
+            localize_record(&record, 3).unwrap(),
+            StringRecord::from(vec!["deposit", "1", "1", "1234.5"])
+        );
+        let record = StringRecord::from(vec!["dispute", "1", "1", ""]);
+        assert_eq!(localize_record(&record, 3).unwrap(), record);
+    }
+}
@@ -0,0 +1,118 @@
+//! Combine the balances written by sharded runs, each over a disjoint set
+//! of clients, into one set of balances, as if written by a single run.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::sink::{BalanceSink, CsvSink};
+use crate::types::{ClientId, OutputRecord};
+
+/// A client whose balances were found in more than one input,
+/// so the inputs weren't from disjoint shards.
+#[derive(Debug, PartialEq)]
+pub struct DuplicateClient {
+    pub client: ClientId,
+    /// Index of the first input with the client
+    pub first: usize,
+    /// Index of the next input with the client
+    pub second: usize,
+}
+
+impl fmt::Display for DuplicateClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "client {} is in both input {} and input {}",
+            self.client, self.first, self.second
+        )
+    }
+}
+
+impl Error for DuplicateClient {}
+
+/// Balances from every input, ordered by client and currency.
+/// A client may have several accounts in one input, one per currency,
+/// but fails the merge if it's in more than one.
+pub fn merge_balances(
+    inputs: Vec<Vec<OutputRecord>>,
+) -> Result<Vec<OutputRecord>, DuplicateClient> {
+    let mut merged: Vec<_> = inputs
+        .into_iter()
+        .enumerate()
+        .flat_map(|(index, balances)| balances.into_iter().map(move |record| (index, record)))
+        .collect();
+    merged.sort_by_key(|(index, record)| (record.client, *index, record.currency));
+
+    for pair in merged.windows(2) {
+        let ((first, a), (second, b)) = (&pair[0], &pair[1]);
+        if a.client == b.client && first != second {
+            return Err(DuplicateClient {
+                client: a.client,
+                first: *first,
+                second: *second,
+            });
+        }
+    }
+    Ok(merged.into_iter().map(|(_, record)| record).collect())
+}
+
+/// Write merged balances as CSV, in the format written by `process`.
+/// The `currency` and `lock_reason` columns are included if any account has one.
+pub fn write_merged<W: io::Write>(balances: &[OutputRecord], output_stream: W) -> io::Result<()> {
+    let with_currency = balances.iter().any(|record| record.currency.is_some());
+    let with_lock_reason = balances.iter().any(|record| record.lock_reason.is_some());
+    let mut sink = CsvSink::new(output_stream).with_lock_reason(with_lock_reason);
+    sink.start(with_currency)?;
+    for record in balances {
+        sink.write_balance(record)?;
+    }
+    sink.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{merge_balances, write_merged, DuplicateClient};
+    use crate::verify::read_balances;
+
+    #[test]
+    fn test_merge_shards() {
+        let shards = vec![
+            read_balances("client,available,held,total,locked\n3,1.0,0.0,1.0,false\n".as_bytes())
+                .unwrap(),
+            read_balances(
+                "client,available,held,total,locked\n4,2.0,1.0,3.0,false\n1,0.5,0.0,0.5,false\n"
+                    .as_bytes(),
+            )
+            .unwrap(),
+        ];
+        let merged = merge_balances(shards).unwrap();
+        let mut output = Vec::new();
+        write_merged(&merged, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "\
+client,available,held,total,locked
+1,0.5,0.0,0.5,false
+3,1.0,0.0,1.0,false
+4,2.0,1.0,3.0,false
+"
+        );
+    }
+
+    #[test]
+    fn test_merge_duplicate_client() {
+        let shard = || {
+            read_balances("client,available,held,total,locked\n2,1.0,0.0,1.0,false\n".as_bytes())
+                .unwrap()
+        };
+        assert_eq!(
+            merge_balances(vec![vec![], shard(), shard()]),
+            Err(DuplicateClient {
+                client: 2,
+                first: 1,
+                second: 2
+            })
+        );
+    }
+}
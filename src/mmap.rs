@@ -0,0 +1,272 @@
+//! Memory-mapped input files, split into chunks at record boundaries
+//! so that they're parsed in parallel, rather than one record at a time
+//! by a single reader thread, which is the bottleneck for very large files.
+
+use csv::StringRecord;
+use memmap2::Mmap;
+use rayon::prelude::*;
+use std::error::Error;
+use std::fs;
+use std::mem;
+use std::path::Path;
+use std::sync::mpsc::{Sender, SyncSender};
+
+use crate::construct_csv_reader;
+use crate::headers::HeaderMapping;
+use crate::input::{readable, report_unreadable, RecordSource, TaggedRecord};
+use crate::source::ParseError;
+
+/// Approximate size of each chunk parsed as a single task.
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// Uncompressed CSV files mapped into memory, read one after another.
+///
+/// Chunks are split at line breaks, so quoted fields mustn't contain any.
+/// Records aren't checked against each other for length, only against their headers.
+pub struct MappedInputs {
+    files: Vec<Mmap>,
+    headers: HeaderMapping,
+}
+
+impl MappedInputs {
+    /// Map each file into memory.
+    pub fn open<P: AsRef<Path>>(paths: &[P]) -> std::io::Result<Self> {
+        let files = paths
+            .iter()
+            .map(|path| {
+                let file = fs::File::open(path)?;
+                // SAFETY: The mapping is only valid while the file is unchanged.
+                // As with any other input, it mustn't be modified during the run.
+                unsafe { Mmap::map(&file) }
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            files,
+            headers: HeaderMapping::default(),
+        })
+    }
+
+    /// Read columns under other names, as given by `headers`.
+    pub fn with_header_mapping(mut self, headers: HeaderMapping) -> Self {
+        self.headers = headers;
+        self
+    }
+}
+
+/// Split a file into its header row and the rest.
+pub(crate) fn split_header(data: &[u8]) -> (&[u8], &[u8]) {
+    match data.iter().position(|&byte| byte == b'\n') {
+        Some(end) => data.split_at(end + 1),
+        None => (data, &[]),
+    }
+}
+
+/// Split data into chunks of about `chunk_size` bytes, each ending with a line break
+/// (except perhaps the last), so that no record is split between chunks.
+fn split_chunks(mut data: &[u8], chunk_size: usize) -> Vec<&[u8]> {
+    let mut chunks = Vec::new();
+    while data.len() > chunk_size {
+        let end = match data[chunk_size..].iter().position(|&byte| byte == b'\n') {
+            Some(offset) => chunk_size + offset + 1,
+            None => data.len(),
+        };
+        let (chunk, rest) = data.split_at(end);
+        chunks.push(chunk);
+        data = rest;
+    }
+    if !data.is_empty() {
+        chunks.push(data);
+    }
+    chunks
+}
+
+/// Number of line breaks in some data.
+pub(crate) fn count_lines(data: &[u8]) -> u64 {
+    data.iter().filter(|&&byte| byte == b'\n').count() as u64
+}
+
+/// Read every record in a chunk which has the same number of fields as the headers,
+/// sending the rest to `errors_snd`, numbered as lines of the whole input,
+/// where `lines_before` counts the lines before the chunk, only if it has any errors.
+pub(crate) fn parse_chunk(
+    chunk: &[u8],
+    num_fields: usize,
+    notrim: bool,
+    lines_before: impl Fn() -> u64,
+    errors_snd: &Sender<ParseError>,
+) -> Vec<StringRecord> {
+    let mut builder = csv::ReaderBuilder::new();
+    builder.has_headers(false).flexible(true);
+    if !notrim {
+        builder.trim(csv::Trim::All);
+    }
+    builder
+        .from_reader(chunk)
+        .into_records()
+        .filter_map(|result| match readable(result, num_fields) {
+            Ok(record) => Some(record),
+            Err(mut err) => {
+                err.line = err.line.map(|line| line + lines_before());
+                report_unreadable(err, errors_snd);
+                None
+            }
+        })
+        .collect()
+}
+
+impl MappedInputs {
+    fn read_records_inner(
+        &self,
+        headers_snd: SyncSender<Vec<StringRecord>>,
+        records_snd: SyncSender<Vec<TaggedRecord>>,
+        errors_snd: &Sender<ParseError>,
+        batch_size: usize,
+        notrim: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let (header_rows, bodies): (Vec<_>, Vec<_>) = self
+            .files
+            .iter()
+            .map(|file| self.headers.split_header_row(file))
+            .unzip();
+        let headers = header_rows
+            .into_iter()
+            .map(|row| {
+                self.headers
+                    .read_headers(&mut construct_csv_reader(row, notrim, true))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let num_fields: Vec<_> = headers.iter().map(StringRecord::len).collect();
+        headers_snd.send(headers)?;
+
+        // Parse as many chunks at a time as there are threads,
+        // then send their records on in order
+        let group_size = rayon::current_num_threads();
+        let mut batch = Vec::with_capacity(batch_size);
+        for (input, body) in bodies.into_iter().enumerate() {
+            let file = &self.files[input];
+            for group in split_chunks(body, CHUNK_SIZE).chunks(group_size) {
+                let records: Vec<_> = group
+                    .par_iter()
+                    .map(|chunk| {
+                        let start = chunk.as_ptr() as usize - file.as_ptr() as usize;
+                        let lines_before = || count_lines(&file[..start]);
+                        parse_chunk(chunk, num_fields[input], notrim, lines_before, errors_snd)
+                    })
+                    .collect();
+                for record in records.into_iter().flatten() {
+                    batch.push((input, record));
+                    if batch.len() >= batch_size {
+                        let full = mem::replace(&mut batch, Vec::with_capacity(batch_size));
+                        records_snd.send(full)?;
+                    }
+                }
+            }
+        }
+        if !batch.is_empty() {
+            records_snd.send(batch)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl RecordSource for MappedInputs {
+    fn num_inputs(&self) -> usize {
+        self.files.len()
+    }
+
+    fn read_records(
+        self,
+        headers_snd: SyncSender<Vec<StringRecord>>,
+        records_snd: SyncSender<Vec<TaggedRecord>>,
+        errors_snd: Sender<ParseError>,
+        batch_size: usize,
+        notrim: bool,
+    ) {
+        let result =
+            self.read_records_inner(headers_snd, records_snd, &errors_snd, batch_size, notrim);
+        if let Err(err) = result {
+            report_unreadable(ParseError::new(None, err), &errors_snd);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_chunk, split_chunks, split_header, MappedInputs};
+    use crate::input::RecordSource;
+    use std::env;
+    use std::fs;
+    use std::sync::mpsc::{channel, sync_channel};
+
+    #[test]
+    fn test_split_chunks() {
+        let data = b"aaa\nbb\nc\ndddd\ne";
+        assert_eq!(
+            split_chunks(data, 3),
+            vec![&b"aaa\n"[..], b"bb\nc\n", b"dddd\n", b"e"]
+        );
+        assert_eq!(split_chunks(data, 100), vec![&data[..]]);
+        assert!(split_chunks(b"", 3).is_empty());
+        assert_eq!(
+            split_header(b"type,tx\n1,2\n"),
+            (&b"type,tx\n"[..], &b"1,2\n"[..])
+        );
+        assert_eq!(split_header(b"type,tx"), (&b"type,tx"[..], &b""[..]));
+    }
+
+    #[test]
+    fn test_parse_chunk() {
+        let (errors_snd, errors_rcv) = channel();
+        let chunk = b"deposit, 1\nbad\n\nwithdrawal,2\n";
+        let records = parse_chunk(chunk, 2, false, || 10, &errors_snd);
+        let fields: Vec<_> = records.iter().map(|record| record[1].to_string()).collect();
+        assert_eq!(fields, vec!["1", "2"]);
+        let errors: Vec<_> = errors_rcv.try_iter().collect();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            (errors[0].line, errors[0].content.as_str()),
+            (Some(12), "bad")
+        );
+    }
+
+    #[test]
+    fn test_read_mapped_inputs() {
+        let paths: Vec<_> = ["first", "second"]
+            .iter()
+            .map(|name| env::temp_dir().join(format!("mmap-{}-{}.csv", name, std::process::id())))
+            .collect();
+        fs::write(&paths[0], "type,client,tx\ndeposit,1,1\ndeposit,1,2\n").unwrap();
+        fs::write(&paths[1], "client,type,tx\n2,deposit,3\n").unwrap();
+
+        let inputs = MappedInputs::open(&paths).unwrap();
+        assert_eq!(inputs.num_inputs(), 2);
+        let (headers_snd, headers_rcv) = sync_channel(1);
+        let (records_snd, records_rcv) = sync_channel(10);
+        let (errors_snd, errors_rcv) = channel();
+        inputs.read_records(headers_snd, records_snd, errors_snd, 2, false);
+        assert_eq!(errors_rcv.try_iter().count(), 0);
+        for path in &paths {
+            fs::remove_file(path).unwrap();
+        }
+
+        let headers = headers_rcv.recv().unwrap();
+        assert_eq!(headers[1].get(0), Some("client"));
+        let batches: Vec<Vec<_>> = records_rcv
+            .iter()
+            .map(|batch| {
+                batch
+                    .into_iter()
+                    .map(|(input, record)| (input, record[2].to_string()))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            batches,
+            vec![
+                vec![(0, "1".to_string()), (0, "2".to_string())],
+                vec![(1, "3".to_string())],
+            ]
+        );
+    }
+}
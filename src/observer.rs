@@ -0,0 +1,157 @@
+//! Hooks for applications embedding the engine, called around every transaction,
+//! e.g. to send notifications, keep a cache up to date, or record their own metrics,
+//! without changing the handlers.
+//!
+//! Observers are added to a `State` before it's handed to the pipeline,
+//! which calls them from whichever handler thread owns each client,
+//! so each client's transactions are observed in order, but different
+//! clients' may be observed concurrently.
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::state::AccountView;
+use crate::types::{TransactionError, TransactionRecord};
+
+/// Called before and after each transaction is handled.
+/// Both methods do nothing unless overridden.
+pub trait TransactionObserver: Send + Sync {
+    /// Called with each transaction, before it's validated.
+    fn before_transaction(&self, _record: &TransactionRecord) {}
+
+    /// Called once a transaction has been applied or rejected,
+    /// with the account it affected as it is afterwards, if there is one.
+    fn after_transaction(
+        &self,
+        _record: &TransactionRecord,
+        _result: &Result<(), TransactionError>,
+        _account: Option<&AccountView>,
+    ) {
+    }
+}
+
+/// Observers of a state's transactions, called in the order they were added.
+#[derive(Clone, Default)]
+pub struct Observers(Vec<Arc<dyn TransactionObserver>>);
+
+impl Observers {
+    pub fn add(&mut self, observer: Arc<dyn TransactionObserver>) {
+        self.0.push(observer);
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn before(&self, record: &TransactionRecord) {
+        for observer in &self.0 {
+            observer.before_transaction(record);
+        }
+    }
+
+    pub(crate) fn after(
+        &self,
+        record: &TransactionRecord,
+        result: &Result<(), TransactionError>,
+        account: Option<&AccountView>,
+    ) {
+        for observer in &self.0 {
+            observer.after_transaction(record, result, account);
+        }
+    }
+}
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Observers({})", self.0.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TransactionObserver;
+    use crate::input::{InputOrder, Inputs};
+    use crate::pipeline::{ExecutionMode, PipelineConfig};
+    use crate::resume_inputs;
+    use crate::state::{AccountView, State};
+    use crate::types::{TransactionError, TransactionRecord};
+    use std::sync::{Arc, Mutex};
+
+    /// Every call, as `before <tx>` or `after <tx> <error code> <available>`.
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl TransactionObserver for Recorder {
+        fn before_transaction(&self, record: &TransactionRecord) {
+            let call = format!("before {}", record.tx_id);
+            self.0.lock().unwrap().push(call);
+        }
+
+        fn after_transaction(
+            &self,
+            record: &TransactionRecord,
+            result: &Result<(), TransactionError>,
+            account: Option<&AccountView>,
+        ) {
+            let code = result.as_ref().err().map_or("OK", TransactionError::code);
+            let available = account.map(|account| account.available.to_string());
+            let call = format!("after {} {} {:?}", record.tx_id, code, available);
+            self.0.lock().unwrap().push(call);
+        }
+    }
+
+    const INPUT: &str = "type, client, tx, amount
+deposit, 1, 1, 5.0
+withdrawal, 1, 2, 9.0
+withdrawal, 2, 3, 1.0
+deposit, 2, 1, 1.0
+";
+
+    #[test]
+    fn test_observers_see_every_transaction() {
+        for execution in [
+            ExecutionMode::Sharded,
+            ExecutionMode::Actors,
+            ExecutionMode::Sequential,
+        ] {
+            let recorder = Arc::new(Recorder::default());
+            let mut initial = State::new();
+            initial.observers.add(recorder.clone());
+            let config = PipelineConfig {
+                execution,
+                ..Default::default()
+            };
+            let state = resume_inputs(
+                Inputs::new(vec![INPUT.as_bytes()], InputOrder::Sequential),
+                initial,
+                None,
+                config,
+                None,
+                None,
+            );
+            assert_eq!(state.observers.len(), 1);
+
+            let mut calls = recorder.0.lock().unwrap().clone();
+            calls.sort();
+            assert_eq!(
+                calls,
+                vec![
+                    "after 1 DUPLICATE_TX None",
+                    "after 1 OK Some(\"5.0\")",
+                    "after 2 INSUFFICIENT_FUNDS Some(\"5.0\")",
+                    "after 3 INSUFFICIENT_FUNDS None",
+                    "before 1",
+                    "before 1",
+                    "before 2",
+                    "before 3",
+                ],
+                "{:?}",
+                execution
+            );
+        }
+    }
+}
@@ -0,0 +1,1746 @@
+use std::any::Any;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::panic::{self, AssertUnwindSafe};
+use std::str::FromStr;
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+
+use indexmap::IndexSet;
+use rustc_hash::FxHashMap;
+use serde::Deserialize;
+
+use crate::actors::ActorPool;
+use crate::channel::{self, BoundedReceiver, BoundedSender, ChannelBackend};
+use crate::checkpoint::{Checkpoint, InputPosition};
+use crate::events::{Event, EventLog};
+use crate::filter::RecordFilter;
+use crate::handlers;
+use crate::interest;
+use crate::invariants::Violation;
+use crate::journal::Journal;
+use crate::ledger::Ledger;
+use crate::observer::Observers;
+use crate::policy::{Policies, TxIdScope, ValidationPolicy};
+use crate::risk::RiskScorers;
+use crate::schedule::Schedule;
+use crate::settlement::Settlement;
+use crate::state::{AccountsState, MergeError, State};
+use crate::telemetry;
+use crate::types::{AccountKey, BalanceUpdate, ClientId, OutputRecord, Rejection, Timestamp};
+use crate::types::{TransactionError, TransactionId, TransactionRecord, TransactionType};
+
+/// Default number of threads handling transactions, each owning a shard of clients.
+pub const DEFAULT_HANDLER_THREADS: usize = 4;
+
+/// Clients are sharded by id, so any threads beyond one per possible client
+/// would never receive a transaction.
+pub const MAX_HANDLER_THREADS: usize = (ClientId::MAX as usize).saturating_add(1);
+
+/// How records are read, and how work is divided among threads.
+///
+/// The defaults keep memory use small. On a big machine, deeper buffers
+/// let the reader and handlers get further ahead of each other,
+/// which smooths over uneven batches at the cost of more memory.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PipelineConfig {
+    /// Number of CSV records to deserialize in parallel at once
+    pub batch_size: usize,
+    /// Disable trimming whitespace from CSV records
+    pub notrim: bool,
+    /// Read amounts written in any locale, e.g. `1.234,56`, rather than only plain decimals
+    pub localized_amounts: bool,
+    /// Number of threads handling transactions, each owning a shard of clients
+    pub handler_threads: usize,
+    /// Maximum number of batches read ahead of deserialization.
+    /// Once this many are waiting, reading pauses until one is taken.
+    pub batch_buffer: usize,
+    /// Maximum number of transactions waiting in each handler's queue.
+    /// Once a queue is full, dispatching to it blocks until there's room.
+    pub handler_queue_depth: usize,
+    /// Implementation of the handlers' queues
+    pub channel: ChannelBackend,
+    /// How handler threads divide up clients
+    pub execution: ExecutionMode,
+    /// Record every balance change in a double-entry ledger
+    pub ledger: bool,
+    /// Record how each transaction changed each account in a journal
+    pub journal: bool,
+    /// Keep totals of the money moved in each currency, for a settlement report
+    pub settlement: bool,
+    /// Check each handler's state for invariant violations once it's finished
+    pub check_invariants: bool,
+    /// Also check every this many transactions per handler, if checking at all
+    pub invariant_interval: Option<usize>,
+    /// Dispatch at most this many transactions per second, e.g. for soak testing
+    pub rate: Option<f64>,
+    /// Only handle the records which match, ignoring the rest
+    pub filter: RecordFilter,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: 1000,
+            notrim: false,
+            localized_amounts: false,
+            handler_threads: DEFAULT_HANDLER_THREADS,
+            batch_buffer: 1,
+            handler_queue_depth: 10,
+            channel: ChannelBackend::Std,
+            execution: ExecutionMode::Sharded,
+            ledger: false,
+            journal: false,
+            settlement: false,
+            check_invariants: false,
+            invariant_interval: None,
+            rate: None,
+            filter: RecordFilter::default(),
+        }
+    }
+}
+
+/// How handler threads divide up the work of handling clients' transactions.
+///
+/// In every mode, each client's transactions are handled in the order they were read,
+/// so final balances, rejections and ledger entries are the same whichever is used.
+/// Only the interleaving of different clients' balance updates and log lines may differ.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecutionMode {
+    /// Each thread owns a fixed shard of clients, with its own queue.
+    #[default]
+    Sharded,
+    /// Each client is an actor with its own mailbox and state,
+    /// and any idle thread runs whichever client has mail waiting,
+    /// so that a busy client doesn't hold up the rest of its shard.
+    Actors,
+    /// No handler threads at all: every transaction is handled by the router as it's read,
+    /// so that each run handles transactions in exactly the same order, for debugging.
+    Sequential,
+}
+
+impl FromStr for ExecutionMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sharded" => Ok(Self::Sharded),
+            "actors" => Ok(Self::Actors),
+            "sequential" => Ok(Self::Sequential),
+            other => Err(format!("unknown execution mode '{}'", other)),
+        }
+    }
+}
+
+/// Check that a number of handler threads is between 1 and `MAX_HANDLER_THREADS`.
+pub fn validate_handler_threads(handler_threads: usize) -> Result<usize, String> {
+    match handler_threads {
+        0 => Err("at least one handler thread is required".to_string()),
+        n if n > MAX_HANDLER_THREADS => Err(format!(
+            "{} handler threads is more than the {} possible client shards",
+            n, MAX_HANDLER_THREADS
+        )),
+        n => Ok(n),
+    }
+}
+
+/// What to do with a transaction whose client
+/// already has the maximum number of transactions in flight.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OverflowStrategy {
+    /// Reject the transaction with `TransactionError::ClientQueueFull`.
+    Reject,
+    /// Pause ingestion until the client's queue has room.
+    Backpressure,
+}
+
+/// Cap on the number of transactions per client which have been
+/// read but not yet handled, so that a single busy client
+/// can't monopolize its handler's queue.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClientQueueLimit {
+    /// At least one, or no transaction could ever be handled
+    pub max_in_flight: NonZeroUsize,
+    pub overflow: OverflowStrategy,
+}
+
+/// Counts in-flight transactions per client.
+pub(crate) struct InFlightTracker {
+    limit: ClientQueueLimit,
+    counts: Mutex<HashMap<ClientId, usize>>,
+    drained: Condvar,
+}
+
+impl InFlightTracker {
+    fn new(limit: ClientQueueLimit) -> Self {
+        Self {
+            limit,
+            counts: Default::default(),
+            drained: Condvar::new(),
+        }
+    }
+
+    fn lock_counts(&self) -> MutexGuard<'_, HashMap<ClientId, usize>> {
+        // Counts are always left consistent, so a poisoned lock is still usable
+        self.counts
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Reserve a slot for one of the client's transactions,
+    /// either waiting for room or failing if the client is at its limit.
+    fn acquire(&self, client_id: ClientId, tx_id: TransactionId) -> Result<(), TransactionError> {
+        let mut counts = self.lock_counts();
+        loop {
+            let count = counts.entry(client_id).or_default();
+            if *count < self.limit.max_in_flight.get() {
+                *count += 1;
+                return Ok(());
+            }
+
+            match self.limit.overflow {
+                OverflowStrategy::Reject => {
+                    return Err(TransactionError::ClientQueueFull {
+                        client: client_id,
+                        tx: tx_id,
+                    })
+                }
+                OverflowStrategy::Backpressure => {
+                    counts = self
+                        .drained
+                        .wait(counts)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner());
+                }
+            }
+        }
+    }
+
+    /// Release a slot once one of the client's transactions has been handled.
+    fn release(&self, client_id: ClientId) {
+        let mut counts = self.lock_counts();
+        if let Some(count) = counts.get_mut(&client_id) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&client_id);
+            }
+        }
+        self.drained.notify_all();
+    }
+}
+
+/// Messages sent from the router to a handler thread.
+pub(crate) enum HandlerMessage {
+    Transaction(TransactionRecord),
+    /// Run a function on the shard's state, e.g. to send back a copy of its balances.
+    Inspect(Box<dyn FnOnce(&State) + Send>),
+    /// Take on a client's state from a previous run, e.g. restored from a checkpoint.
+    Restore(Box<State>),
+    /// Handle all subsequent transactions with new policies.
+    UpdatePolicies(Box<Policies>),
+    /// Send updated balances after each subsequent successful transaction.
+    Subscribe(Sender<BalanceUpdate>),
+    /// Call these observers around each subsequent transaction.
+    Observe(Observers),
+    /// Score each subsequent deposit and withdrawal with these scorers too.
+    ScoreRisk(RiskScorers),
+    /// Send events for every subsequent change to this log.
+    LogEvents(EventLog),
+    /// Credit interest to every account, as of the given time.
+    AccrueInterest(Option<Timestamp>),
+}
+
+/// Send the balances of the account affected by a successful transaction.
+/// Returns false if nobody is listening anymore.
+fn send_update(record: &TransactionRecord, state: &State, updates: &Sender<BalanceUpdate>) -> bool {
+    let key = state.affected_account(record);
+    let account = match state.accounts.get(key.0, key.1) {
+        Some(account) => account,
+        None => return true,
+    };
+    match OutputRecord::new(key, account, &state.policies.rounding) {
+        Ok(balance) => updates
+            .send(BalanceUpdate {
+                tx: record.tx_id,
+                balance,
+            })
+            .is_ok(),
+        // Leaves a gap in the updates, which the invariant checks also report
+        Err(err) => {
+            tracing::error!(
+                "Could not send balances of client {} after transaction {}: {}",
+                key.0,
+                record.tx_id,
+                err
+            );
+            true
+        }
+    }
+}
+
+/// Span for one phase of handling a transaction, identifying the transaction.
+/// Span names must be known at compile time, hence the macro.
+macro_rules! transaction_span {
+    ($phase:literal, $record:expr) => {
+        tracing::debug_span!(
+            $phase,
+            client = $record.client_id,
+            tx = $record.tx_id,
+            r#type = $record.transaction_type.name()
+        )
+    };
+}
+
+/// Check a handler's state, recording and logging any new violations.
+fn check_invariants(state: &mut State) {
+    for violation in state.check_invariants() {
+        if !state.violations.contains(&violation) {
+            tracing::error!("Invariant violated: {}", violation);
+            state.violations.push(violation);
+        }
+    }
+}
+
+/// The message a panic was raised with, if it was a string.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    match (
+        payload.downcast_ref::<&str>(),
+        payload.downcast_ref::<String>(),
+    ) {
+        (Some(message), _) => message,
+        (_, Some(message)) => message,
+        _ => "unknown cause",
+    }
+}
+
+/// A shard's state, and everything else needed to handle its messages.
+pub(crate) struct Worker {
+    state: State,
+    updates: Option<Sender<BalanceUpdate>>,
+    tracker: Option<Arc<InFlightTracker>>,
+    config: PipelineConfig,
+    /// Number of transactions handled so far
+    handled: usize,
+}
+
+impl Worker {
+    pub fn new(
+        state: State,
+        tracker: Option<Arc<InFlightTracker>>,
+        config: PipelineConfig,
+    ) -> Self {
+        Self {
+            state,
+            updates: None,
+            tracker,
+            config,
+            handled: 0,
+        }
+    }
+
+    pub fn handle(&mut self, message: HandlerMessage) {
+        let state = &mut self.state;
+        match message {
+            HandlerMessage::Transaction(record) => {
+                let client_id = record.client_id;
+                let num_accounts = state.accounts.len();
+                let result = transaction_span!("apply", record).in_scope(|| {
+                    // A bug in one transaction's handling shouldn't lose the whole shard,
+                    // nor leave it part way through the transaction
+                    let undo = state.undo_point(&record);
+                    panic::catch_unwind(AssertUnwindSafe(|| {
+                        handlers::handle_transaction(record.clone(), state)
+                    }))
+                    .unwrap_or_else(|payload| {
+                        state.undo(undo);
+                        let message = panic_message(payload.as_ref());
+                        Err(TransactionError::UnexpectedError(format!(
+                            "handler panicked: {}",
+                            message
+                        )))
+                    })
+                });
+                telemetry::record_handled(&record, &result, state.accounts.len() > num_accounts);
+                match result {
+                    Ok(()) => {
+                        if let Some(sender) = &self.updates {
+                            if !send_update(&record, state, sender) {
+                                self.updates = None;
+                            }
+                        }
+                    }
+                    Err(err) => {
+                        tracing::error!("Error while handling transaction: {}", err);
+                        state.rejections.push(Rejection { record, error: err });
+                    }
+                }
+                if let Some(tracker) = &self.tracker {
+                    tracker.release(client_id);
+                }
+                self.handled += 1;
+                let config = &self.config;
+                if let (true, Some(interval)) = (config.check_invariants, config.invariant_interval)
+                {
+                    if self.handled.is_multiple_of(interval.max(1)) {
+                        check_invariants(state);
+                    }
+                }
+            }
+            HandlerMessage::Inspect(inspect) => inspect(state),
+            HandlerMessage::Restore(client) => {
+                if let Err(err) = state.merge(*client) {
+                    tracing::error!("Failed to restore client state: {}", err);
+                }
+            }
+            HandlerMessage::UpdatePolicies(policies) => {
+                state.policies = *policies;
+            }
+            HandlerMessage::Subscribe(sender) => {
+                self.updates = Some(sender);
+            }
+            HandlerMessage::Observe(observers) => {
+                state.observers = observers;
+            }
+            HandlerMessage::ScoreRisk(risk_scorers) => {
+                state.risk_scorers = risk_scorers;
+            }
+            HandlerMessage::LogEvents(events) => {
+                state.events = Some(events);
+            }
+            HandlerMessage::AccrueInterest(timestamp) => {
+                interest::accrue(state, timestamp);
+            }
+        }
+    }
+
+    /// Run any final checks, and give up the state.
+    pub fn finish(mut self) -> State {
+        if self.config.check_invariants {
+            check_invariants(&mut self.state);
+        }
+        self.state
+    }
+}
+
+/// Handle all transactions for a shard of clients, in the order received.
+fn run_handler(messages: BoundedReceiver<HandlerMessage>, mut worker: Worker) -> State {
+    for message in messages.iter() {
+        worker.handle(message);
+    }
+    worker.finish()
+}
+
+/// Deposits, withdrawals and reversals claim their transaction id globally,
+/// whether or not they eventually succeed,
+/// unless the validation policy allows duplicates.
+/// In idempotent mode, the same client may resubmit its own deposits and withdrawals,
+/// leaving its handler to tell a resubmission from a duplicate.
+/// If ids are only unique per client, the handlers check for duplicates,
+/// since each client's transactions all go to the same one.
+fn check_for_duplicate_tx_id(
+    record: &TransactionRecord,
+    clients_by_tx: &mut FxHashMap<TransactionId, ClientId>,
+    policy: &ValidationPolicy,
+) -> Result<(), TransactionError> {
+    if policy.tx_id_scope == TxIdScope::Client {
+        return Ok(());
+    }
+    let (client_id, tx_id) = (record.client_id, record.tx_id);
+    // Only deposits and withdrawals can be resubmitted
+    let resubmittable = match record {
+        TransactionRecord {
+            transaction_type: TransactionType::Deposit | TransactionType::Withdrawal,
+            amount: Some(_),
+            ..
+        } => true,
+        TransactionRecord {
+            transaction_type: TransactionType::Reversal,
+            reverses: Some(_),
+            ..
+        } => false,
+        _ => return Ok(()),
+    };
+    match clients_by_tx.entry(tx_id) {
+        Entry::Occupied(entry) => {
+            let maybe_resubmission =
+                resubmittable && policy.idempotent && *entry.get() == client_id;
+            if !(policy.allow_duplicate_tx_ids || maybe_resubmission) {
+                return Err(TransactionError::DuplicateTxId { tx: tx_id });
+            }
+        }
+        // The first client to use an id keeps it
+        Entry::Vacant(entry) => {
+            entry.insert(client_id);
+        }
+    }
+    Ok(())
+}
+
+/// Threads handling transactions, and how to reach them.
+enum Workers {
+    /// A thread for each shard of clients, each with its own queue
+    Sharded {
+        senders: Vec<BoundedSender<HandlerMessage>>,
+        handles: Vec<JoinHandle<State>>,
+    },
+    /// An actor for each client, run by a pool of threads
+    Actors(Box<ActorPool>),
+    /// A single state, handled on the router's thread
+    Sequential(Box<Worker>),
+}
+
+impl Workers {
+    fn spawn(
+        num_threads: usize,
+        config: &PipelineConfig,
+        policies: Policies,
+        tracker: Option<Arc<InFlightTracker>>,
+    ) -> Self {
+        let new_state = || {
+            let mut state = State::with_policies(policies.clone());
+            if config.ledger {
+                state.ledger = Some(Ledger::default());
+            }
+            if config.journal {
+                state.journal = Some(Journal::default());
+            }
+            if config.settlement {
+                state.settlement = Some(Settlement::default());
+            }
+            state
+        };
+        match config.execution {
+            ExecutionMode::Sharded => {}
+            ExecutionMode::Actors => {
+                return Self::Actors(Box::new(ActorPool::spawn(
+                    num_threads,
+                    config,
+                    policies,
+                    tracker,
+                )))
+            }
+            ExecutionMode::Sequential => {
+                return Self::Sequential(Box::new(Worker::new(new_state(), tracker, *config)))
+            }
+        }
+
+        let mut senders = Vec::with_capacity(num_threads);
+        let mut handles = Vec::with_capacity(num_threads);
+        for shard in 0..num_threads {
+            let (snd, rcv) = channel::bounded(config.channel, config.handler_queue_depth);
+            let worker = Worker::new(new_state(), tracker.clone(), *config);
+            let span = tracing::info_span!("handler", shard);
+            senders.push(snd);
+            handles.push(thread::spawn(move || {
+                span.in_scope(|| run_handler(rcv, worker))
+            }));
+        }
+        Self::Sharded { senders, handles }
+    }
+
+    /// Send a message to whichever thread or actor handles a client.
+    fn send(&mut self, client_id: ClientId, message: HandlerMessage) -> Result<(), String> {
+        match self {
+            Self::Sharded { senders, .. } => {
+                let shard = client_id as usize % senders.len();
+                senders[shard].send(message).map_err(|err| err.to_string())
+            }
+            Self::Actors(pool) => {
+                pool.send(client_id, message);
+                Ok(())
+            }
+            Self::Sequential(worker) => {
+                worker.handle(message);
+                Ok(())
+            }
+        }
+    }
+
+    /// Apply `inspect` to the state of every shard or actor, once each has
+    /// handled everything sent before, and collect the results.
+    fn collect<T: Send + 'static>(&self, inspect: fn(&State) -> T) -> Vec<T> {
+        let senders = match self {
+            Self::Sharded { senders, .. } => senders,
+            Self::Actors(pool) => return pool.collect(inspect),
+            Self::Sequential(worker) => return vec![inspect(&worker.state)],
+        };
+        let (reply_snd, reply_rcv) = sync_channel(senders.len());
+        for (shard, sender) in senders.iter().enumerate() {
+            let reply_snd = reply_snd.clone();
+            let message = HandlerMessage::Inspect(Box::new(move |state| {
+                // Only fails if the router has given up waiting
+                let _ = reply_snd.send(inspect(state));
+            }));
+            if let Err(err) = sender.send(message) {
+                tracing::error!("Failed to inspect handler {}: {}", shard, err);
+            }
+        }
+        // Hang up, so that collecting stops once every handler has replied
+        drop(reply_snd);
+        reply_rcv.iter().collect()
+    }
+
+    fn subscribe(&mut self, updates: Sender<BalanceUpdate>) {
+        let senders = match self {
+            Self::Sharded { senders, .. } => senders,
+            Self::Actors(pool) => return pool.subscribe(updates),
+            Self::Sequential(worker) => return worker.handle(HandlerMessage::Subscribe(updates)),
+        };
+        for (shard, sender) in senders.iter().enumerate() {
+            if let Err(err) = sender.send(HandlerMessage::Subscribe(updates.clone())) {
+                tracing::error!("Failed to subscribe to handler {}: {}", shard, err);
+            }
+        }
+    }
+
+    fn observe(&mut self, observers: &Observers) {
+        let senders = match self {
+            Self::Sharded { senders, .. } => senders,
+            Self::Actors(pool) => return pool.observe(observers.clone()),
+            Self::Sequential(worker) => {
+                return worker.handle(HandlerMessage::Observe(observers.clone()))
+            }
+        };
+        for (shard, sender) in senders.iter().enumerate() {
+            if let Err(err) = sender.send(HandlerMessage::Observe(observers.clone())) {
+                tracing::error!("Failed to add observers to handler {}: {}", shard, err);
+            }
+        }
+    }
+
+    fn score_risk(&mut self, risk_scorers: &RiskScorers) {
+        let senders = match self {
+            Self::Sharded { senders, .. } => senders,
+            Self::Actors(pool) => return pool.score_risk(risk_scorers.clone()),
+            Self::Sequential(worker) => {
+                return worker.handle(HandlerMessage::ScoreRisk(risk_scorers.clone()))
+            }
+        };
+        for (shard, sender) in senders.iter().enumerate() {
+            if let Err(err) = sender.send(HandlerMessage::ScoreRisk(risk_scorers.clone())) {
+                tracing::error!("Failed to add risk scorers to handler {}: {}", shard, err);
+            }
+        }
+    }
+
+    fn log_events(&mut self, events: &EventLog) {
+        let senders = match self {
+            Self::Sharded { senders, .. } => senders,
+            Self::Actors(pool) => return pool.log_events(events.clone()),
+            Self::Sequential(worker) => {
+                return worker.handle(HandlerMessage::LogEvents(events.clone()))
+            }
+        };
+        for (shard, sender) in senders.iter().enumerate() {
+            if let Err(err) = sender.send(HandlerMessage::LogEvents(events.clone())) {
+                tracing::error!("Failed to log events from handler {}: {}", shard, err);
+            }
+        }
+    }
+
+    fn update_policies(&mut self, policies: &Policies) {
+        let senders = match self {
+            Self::Sharded { senders, .. } => senders,
+            Self::Actors(pool) => return pool.update_policies(policies.clone()),
+            Self::Sequential(worker) => {
+                return worker.handle(HandlerMessage::UpdatePolicies(Box::new(policies.clone())))
+            }
+        };
+        for (shard, sender) in senders.iter().enumerate() {
+            if let Err(err) =
+                sender.send(HandlerMessage::UpdatePolicies(Box::new(policies.clone())))
+            {
+                tracing::error!("Failed to update policies for handler {}: {}", shard, err);
+            }
+        }
+    }
+
+    fn accrue_interest(&mut self, timestamp: Option<Timestamp>) {
+        let senders = match self {
+            Self::Sharded { senders, .. } => senders,
+            Self::Actors(pool) => return pool.accrue_interest(timestamp),
+            Self::Sequential(worker) => {
+                return worker.handle(HandlerMessage::AccrueInterest(timestamp))
+            }
+        };
+        for (shard, sender) in senders.iter().enumerate() {
+            if let Err(err) = sender.send(HandlerMessage::AccrueInterest(timestamp)) {
+                tracing::error!("Failed to accrue interest for handler {}: {}", shard, err);
+            }
+        }
+    }
+
+    /// Wait for every shard or actor to finish, and return their states,
+    /// along with how many were lost, since their threads panicked.
+    fn finish(self) -> (Vec<State>, usize) {
+        let (senders, handles) = match self {
+            Self::Sharded { senders, handles } => (senders, handles),
+            Self::Actors(pool) => return (*pool).finish(),
+            Self::Sequential(worker) => return (vec![worker.finish()], 0),
+        };
+        // Hang up so that handlers know there's nothing left to do
+        drop(senders);
+        let mut shards = Vec::with_capacity(handles.len());
+        let mut lost = 0;
+        for handle in handles {
+            match handle.join() {
+                Ok(shard) => shards.push(shard),
+                Err(err) => {
+                    tracing::error!("Failed to join handler thread: {:?}", err);
+                    lost += 1;
+                }
+            }
+        }
+        (shards, lost)
+    }
+}
+
+/// Distributes transactions among handler threads by client.
+///
+/// Since accounts are independent, each handler owns the state
+/// for its own shard of clients, or in actor mode, each client owns its own,
+/// and all transactions for a given client are handled in order.
+/// Transaction ids must be unique across all clients though,
+/// so the router checks for duplicates before dispatching,
+/// and sends disputes, resolves and chargebacks to the handler
+/// of the disputed transaction's client, whichever client they're from.
+pub(crate) struct ShardedHandler {
+    workers: Workers,
+    tracker: Option<Arc<InFlightTracker>>,
+    /// Client of each transaction id, or of the first to use it
+    clients_by_tx: FxHashMap<TransactionId, ClientId>,
+    policies: Policies,
+    /// Number of transactions dispatched so far
+    dispatched: usize,
+    /// Transactions rejected before reaching a handler
+    rejections: Vec<Rejection>,
+    /// Rejections and other reports from a previous run, if restored
+    restored: Option<State>,
+    /// Also called for transactions rejected before reaching a handler
+    observers: Observers,
+    /// Where to send changes of policy, which aren't any one handler's
+    events: Option<EventLog>,
+    /// Accounts in the order they first appeared, since each
+    /// handler only knows the order of its own shard
+    first_seen: IndexSet<AccountKey>,
+    /// Scheduled transactions held until they take effect
+    schedule: Schedule,
+    /// Which transactions to handle, ignoring the rest
+    filter: RecordFilter,
+}
+
+impl ShardedHandler {
+    pub fn spawn(
+        config: &PipelineConfig,
+        policies: Policies,
+        limit: Option<ClientQueueLimit>,
+    ) -> Self {
+        let num_threads = config.handler_threads;
+        let num_threads = validate_handler_threads(num_threads).unwrap_or_else(|err| {
+            let clamped = num_threads.clamp(1, MAX_HANDLER_THREADS);
+            tracing::warn!("{}, using {} instead", err, clamped);
+            clamped
+        });
+        let tracker = limit.map(|limit| Arc::new(InFlightTracker::new(limit)));
+        let workers = Workers::spawn(num_threads, config, policies.clone(), tracker.clone());
+
+        Self {
+            workers,
+            tracker,
+            clients_by_tx: FxHashMap::default(),
+            policies,
+            dispatched: 0,
+            rejections: Vec::new(),
+            restored: None,
+            observers: Observers::default(),
+            events: None,
+            first_seen: IndexSet::new(),
+            schedule: Schedule::default(),
+            filter: config.filter,
+        }
+    }
+
+    /// Continue from a previous run's state, e.g. restored from a checkpoint,
+    /// by handing each client's part to whichever handler is responsible for it,
+    /// calling its observers around every transaction from now on,
+    /// scoring deposits and withdrawals with its risk scorers,
+    /// and sending events to its event log, if it has one.
+    /// Must be called before dispatching any transactions.
+    pub fn restore(&mut self, mut state: State) -> Result<(), MergeError> {
+        if state.transactions.scope() != self.policies.validation.tx_id_scope {
+            return Err(MergeError::ScopeMismatch);
+        }
+        if !state.observers.is_empty() {
+            self.workers.observe(&state.observers);
+            self.observers = state.observers.clone();
+        }
+        if !state.risk_scorers.is_empty() {
+            self.workers.score_risk(&state.risk_scorers);
+        }
+        if let Some(events) = &state.events {
+            self.workers.log_events(events);
+            self.events = Some(events.clone());
+        }
+        for (client_id, tx_id) in state.transactions.iter() {
+            if state.transactions.client_of(tx_id) == Some(client_id) {
+                self.clients_by_tx.insert(tx_id, client_id);
+            }
+        }
+        self.first_seen
+            .extend(state.accounts.iter().map(|(&key, _)| key));
+        self.schedule.extend(std::mem::take(&mut state.schedule));
+
+        let (reports, clients) = state.split_by_client();
+        for (client_id, client) in clients {
+            let message = HandlerMessage::Restore(Box::new(client));
+            if let Err(err) = self.workers.send(client_id, message) {
+                tracing::error!("Failed to restore client {}: {}", client_id, err);
+            }
+        }
+        self.restored = Some(reports);
+        Ok(())
+    }
+
+    /// Send a transaction to the handler responsible for its client,
+    /// after any scheduled transactions which its timestamp brings into effect.
+    /// If it's scheduled for later itself, it's held or rejected, as the policies say.
+    /// If it doesn't match the filter, it's ignored, as if it had never been read.
+    pub fn dispatch(&mut self, record: TransactionRecord) -> Result<(), TransactionError> {
+        let _span = transaction_span!("dispatch", record).entered();
+        if !self.filter.matches(&record) {
+            tracing::trace!("Ignoring transaction {} outside the filter", record.tx_id);
+            return Ok(());
+        }
+        let interest = &self.policies.interest;
+        let period_ends: Vec<_> = interest
+            .period_ends(self.schedule.now(), record.timestamp)
+            .collect();
+        for period_end in period_ends {
+            self.advance_clock(Some(period_end));
+            self.workers.accrue_interest(Some(period_end));
+        }
+        self.advance_clock(record.timestamp);
+        match record.effective {
+            Some(effective) if !self.schedule.is_effective(&record) => {
+                if self.policies.hold_scheduled {
+                    tracing::debug!("Holding transaction {} until {}", record.tx_id, effective);
+                    self.schedule.hold(record);
+                    return Ok(());
+                }
+                let err = TransactionError::NotYetEffective {
+                    client: record.client_id,
+                    tx: record.tx_id,
+                    effective,
+                };
+                self.first_seen.insert((record.client_id, record.currency));
+                Err(self.reject(record, err))
+            }
+            _ => self.route(record),
+        }
+    }
+
+    /// Move the clock forward, and send any scheduled transactions
+    /// which that brings into effect to their handlers.
+    fn advance_clock(&mut self, timestamp: Option<Timestamp>) {
+        self.schedule.advance(timestamp);
+        for due in self.schedule.release() {
+            if let Err(err) = self.route(due) {
+                tracing::error!("Error while handling scheduled transaction: {}", err);
+            }
+        }
+    }
+
+    /// Record a transaction rejected before reaching a handler.
+    fn reject(&mut self, record: TransactionRecord, err: TransactionError) -> TransactionError {
+        telemetry::record_rejected(&record, &err);
+        // No handler has the account, so the observers only see the rejection
+        self.observers.before(&record);
+        self.observers.after(&record, &Err(err.clone()), None);
+        self.rejections.push(Rejection {
+            record,
+            error: err.clone(),
+        });
+        err
+    }
+
+    /// Send a transaction which is in effect to its handler.
+    fn route(&mut self, record: TransactionRecord) -> Result<(), TransactionError> {
+        self.first_seen.insert((record.client_id, record.currency));
+        if let Err(err) = self.admit(&record) {
+            return Err(self.reject(record, err));
+        }
+
+        let client_id = self.shard_client(&record);
+        self.dispatched += 1;
+        self.workers
+            .send(client_id, HandlerMessage::Transaction(record))
+            .map_err(|err| {
+                TransactionError::UnexpectedError(format!(
+                    "Handler for client {} has stopped: {}",
+                    client_id, err
+                ))
+            })
+    }
+
+    /// Client whose handler should receive the transaction. A dispute, resolve,
+    /// chargeback or representment goes to the disputed transaction's client, so that its handler
+    /// can tell a client mismatch from a transaction which doesn't exist.
+    fn shard_client(&self, record: &TransactionRecord) -> ClientId {
+        match record.transaction_type {
+            TransactionType::Dispute
+            | TransactionType::Resolve
+            | TransactionType::Chargeback
+            | TransactionType::Representment => self
+                .clients_by_tx
+                .get(&record.tx_id)
+                .copied()
+                .unwrap_or(record.client_id),
+            _ => record.client_id,
+        }
+    }
+
+    /// Check whether a transaction may be dispatched,
+    /// reserving a slot for its client if there's a queue limit.
+    fn admit(&mut self, record: &TransactionRecord) -> Result<(), TransactionError> {
+        let client_id = record.client_id;
+        let validation = &self.policies.validation;
+        if let Some(tracker) = &self.tracker {
+            tracker.acquire(client_id, record.tx_id)?;
+            // Rejected transactions never reach a handler to release their slot
+            if let Err(err) = check_for_duplicate_tx_id(record, &mut self.clients_by_tx, validation)
+            {
+                tracker.release(client_id);
+                return Err(err);
+            }
+            Ok(())
+        } else {
+            check_for_duplicate_tx_id(record, &mut self.clients_by_tx, validation)
+        }
+    }
+
+    /// Collect the current balances from all handlers.
+    /// Since each handler replies once it has handled everything
+    /// dispatched before the request, this reflects all transactions so far.
+    pub fn snapshot(&self) -> AccountsState {
+        let mut accounts = AccountsState::default();
+        for shard_accounts in self.workers.collect(|state| state.accounts.clone()) {
+            accounts.extend(shard_accounts);
+        }
+        accounts.sort_by_first_seen(&self.first_seen);
+        accounts
+    }
+
+    /// Capture all handlers' states as of the record at `position`,
+    /// which must be the last record dispatched. Like `snapshot`,
+    /// this reflects all transactions so far.
+    pub fn checkpoint(&self, position: InputPosition) -> Checkpoint {
+        let mut checkpoint = Checkpoint::default();
+        for shard in self
+            .workers
+            .collect(|state| Checkpoint::from_state(state, InputPosition::default()))
+        {
+            checkpoint.extend(shard);
+        }
+        checkpoint.schedule = self.schedule.clone();
+        checkpoint.sort_by_first_seen(&self.first_seen);
+        checkpoint.position = position;
+        checkpoint
+    }
+
+    /// Receive updated balances after every successful transaction
+    /// dispatched from now on. Updates for each client arrive in the order
+    /// their transactions were handled, but clients in different shards
+    /// may be interleaved in any order.
+    pub fn subscribe(&mut self) -> Receiver<BalanceUpdate> {
+        let (updates_snd, updates_rcv) = channel();
+        self.workers.subscribe(updates_snd);
+        updates_rcv
+    }
+
+    /// The policies currently in effect.
+    pub fn policies(&self) -> &Policies {
+        &self.policies
+    }
+
+    /// Number of transactions dispatched to handlers so far.
+    pub fn dispatched(&self) -> usize {
+        self.dispatched
+    }
+
+    /// Switch all handlers to new policies.
+    /// Since messages to each handler are ordered, every transaction dispatched
+    /// before this call uses the old policies, and every one after uses the new.
+    pub fn update_policies(&mut self, mut policies: Policies) {
+        // Each handler's state is indexed according to the original scope
+        let scope = self.policies.validation.tx_id_scope;
+        if policies.validation.tx_id_scope != scope {
+            tracing::warn!(
+                "Transaction id scope can't change while running, keeping {:?}",
+                scope
+            );
+            policies.validation.tx_id_scope = scope;
+        }
+        if policies == self.policies {
+            return;
+        }
+
+        self.workers.update_policies(&policies);
+
+        tracing::info!(
+            target: "audit",
+            "Policies changed after {} transactions from {:?} to {:?}",
+            self.dispatched,
+            self.policies,
+            policies
+        );
+        if let Some(events) = &mut self.events {
+            events.record(Event::PoliciesChanged {
+                dispatched: self.dispatched,
+                policies: Box::new(policies.clone()),
+                timestamp: self.schedule.now(),
+            });
+            if !events.flush(None) {
+                tracing::warn!("Nobody is receiving events anymore, so no longer sending them");
+                self.events = None;
+            }
+        }
+        self.policies = policies;
+    }
+
+    /// Wait for all handlers to finish, and combine their accounts into a single state,
+    /// crediting interest first, if it's only credited at the end of the run.
+    /// Rejections, ledger and journal entries and invariant violations are grouped by client.
+    pub fn finish(mut self) -> State {
+        let interest = &self.policies.interest;
+        if interest.is_enabled() && interest.period.is_none() {
+            self.workers.accrue_interest(self.schedule.now());
+        }
+        let policies = self.policies;
+        let mut state = self
+            .restored
+            .unwrap_or_else(|| State::with_policies(policies.clone()));
+        state.policies = policies;
+        state.rejections.extend(self.rejections);
+        if !self.schedule.held().is_empty() {
+            tracing::warn!(
+                "{} scheduled transactions never took effect",
+                self.schedule.held().len()
+            );
+        }
+        state.schedule.extend(self.schedule);
+        let (shards, lost) = self.workers.finish();
+        if lost > 0 {
+            tracing::error!("Lost the accounts of {} handler shard(s)", lost);
+        }
+        state.lost_shards += lost;
+        for shard in shards {
+            // Shards never share clients, so this can't fail
+            if let Err(err) = state.merge(shard) {
+                tracing::error!("Failed to merge handler state: {}", err);
+            }
+        }
+        state.accounts.sort_by_first_seen(&self.first_seen);
+        state
+            .rejections
+            .sort_by_key(|rejection| rejection.record.client_id);
+        if let Some(ledger) = &mut state.ledger {
+            ledger.sort_by_client();
+        }
+        if let Some(journal) = &mut state.journal {
+            journal.sort_by_client();
+        }
+        state.violations.sort_by_key(Violation::client);
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ShardedHandler;
+    use super::{validate_handler_threads, PipelineConfig, MAX_HANDLER_THREADS};
+    use super::{ClientQueueLimit, ExecutionMode, InFlightTracker, OverflowStrategy};
+    use crate::events::{Event, EventLog};
+    use crate::filter::RecordFilter;
+    use crate::observer::{Observers, TransactionObserver};
+    use crate::policy::Policies;
+    use crate::state::{AccountOrder, AccountView, State};
+    use crate::test_utils::{deposit, dispute, lock, reversal, withdrawal};
+    use crate::types::{BalanceUpdate, ClientId, Currency, OutputRecord, Rejection};
+    use crate::types::{TransactionError, TransactionId, TransactionRecord, TransactionType};
+    use std::num::NonZeroUsize;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_reject_over_limit() {
+        let tracker = InFlightTracker::new(ClientQueueLimit {
+            max_in_flight: NonZeroUsize::new(2).unwrap(),
+            overflow: OverflowStrategy::Reject,
+        });
+
+        assert_eq!(tracker.acquire(1, 1), Ok(()));
+        assert_eq!(tracker.acquire(1, 2), Ok(()));
+        assert_eq!(
+            tracker.acquire(1, 3),
+            Err(TransactionError::ClientQueueFull { client: 1, tx: 3 })
+        );
+        // Other clients are unaffected
+        assert_eq!(tracker.acquire(2, 4), Ok(()));
+
+        tracker.release(1);
+        assert_eq!(tracker.acquire(1, 5), Ok(()));
+    }
+
+    #[test]
+    fn test_duplicate_tx_id_across_shards() {
+        let mut handler =
+            ShardedHandler::spawn(&PipelineConfig::default(), Policies::default(), None);
+
+        // Consecutive clients are handled by different shards
+        assert_eq!(handler.dispatch(deposit(1, 1, 10.0)), Ok(()));
+        assert_eq!(
+            handler.dispatch(deposit(2, 1, 5.0)),
+            Err(TransactionError::DuplicateTxId { tx: 1 })
+        );
+
+        let state = handler.finish();
+        assert!(state.accounts.get(1, None).is_some());
+        assert!(state.accounts.get(2, None).is_none());
+    }
+
+    #[test]
+    fn test_duplicate_reversal_tx_id_across_shards() {
+        let policies = Policies {
+            allow_admin: true,
+            ..Policies::default()
+        };
+        let mut handler = ShardedHandler::spawn(&PipelineConfig::default(), policies, None);
+
+        // A reversal's own id is taken on every shard, whichever came first
+        handler.dispatch(deposit(1, 1, 10.0)).unwrap();
+        assert_eq!(handler.dispatch(reversal(1, 2, 1)), Ok(()));
+        assert_eq!(
+            handler.dispatch(deposit(2, 2, 5.0)),
+            Err(TransactionError::DuplicateTxId { tx: 2 })
+        );
+        handler.dispatch(deposit(2, 3, 5.0)).unwrap();
+        assert_eq!(
+            handler.dispatch(reversal(1, 3, 1)),
+            Err(TransactionError::DuplicateTxId { tx: 3 })
+        );
+
+        let state = handler.finish();
+        assert_eq!(state.account(1).unwrap().available, Currency::ZERO);
+        assert_eq!(state.account(2).unwrap().available, Currency::from(5.0));
+    }
+
+    #[test]
+    fn test_client_mismatch_across_shards() {
+        let mut handler =
+            ShardedHandler::spawn(&PipelineConfig::default(), Policies::default(), None);
+
+        // The dispute reaches the shard holding the transaction, not the disputing client's
+        handler.dispatch(deposit(1, 7, 10.0)).unwrap();
+        handler.dispatch(dispute(2, 7)).unwrap();
+
+        let state = handler.finish();
+        assert_eq!(
+            state.rejections[0].error,
+            TransactionError::ClientMismatch {
+                tx: 7,
+                tx_client: 1,
+                dispute_client: 2,
+            }
+        );
+        assert_eq!(state.accounts.get(1, None).unwrap().held, Currency::ZERO);
+    }
+
+    #[test]
+    fn test_duplicate_tx_id_allowed() {
+        let mut policies = Policies::default();
+        policies.validation.allow_duplicate_tx_ids = true;
+        let mut handler = ShardedHandler::spawn(&PipelineConfig::default(), policies, None);
+
+        assert_eq!(handler.dispatch(deposit(1, 1, 10.0)), Ok(()));
+        assert_eq!(handler.dispatch(deposit(2, 1, 5.0)), Ok(()));
+
+        let state = handler.finish();
+        assert!(state.accounts.get(1, None).is_some());
+        assert!(state.accounts.get(2, None).is_some());
+    }
+
+    #[test]
+    fn test_rejections() {
+        let mut handler =
+            ShardedHandler::spawn(&PipelineConfig::default(), Policies::default(), None);
+        let withdrawal = withdrawal(1, 3, 20.0);
+
+        assert_eq!(handler.dispatch(deposit(2, 1, 10.0)), Ok(()));
+        assert!(handler.dispatch(deposit(2, 1, 5.0)).is_err());
+        assert_eq!(handler.dispatch(withdrawal.clone()), Ok(()));
+
+        let rejections = handler.finish().rejections;
+        assert_eq!(
+            rejections,
+            vec![
+                Rejection {
+                    record: withdrawal,
+                    error: TransactionError::InsufficientFunds {
+                        client: 1,
+                        tx: 3,
+                        requested: Currency::from(20.0),
+                        available: Currency::ZERO,
+                    },
+                },
+                Rejection {
+                    record: deposit(2, 1, 5.0),
+                    error: TransactionError::DuplicateTxId { tx: 1 },
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_handler_panic() {
+        struct Panicking;
+        impl TransactionObserver for Panicking {
+            fn before_transaction(&self, record: &TransactionRecord) {
+                assert_ne!(record.tx_id, 2, "observer failed");
+            }
+        }
+
+        let mut handler =
+            ShardedHandler::spawn(&PipelineConfig::default(), Policies::default(), None);
+        let mut observed = State::with_policies(Policies::default());
+        observed.observers.add(Arc::new(Panicking));
+        handler.restore(observed).unwrap();
+        for record in [deposit(1, 1, 10.0), deposit(2, 2, 5.0), deposit(2, 3, 1.0)] {
+            assert_eq!(handler.dispatch(record), Ok(()));
+        }
+
+        let state = handler.finish();
+        assert_eq!(state.lost_shards, 0);
+        assert!(state.accounts.get(1, None).is_some());
+        assert_eq!(state.account(2).unwrap().available, Currency::from(1.0));
+        assert_eq!(state.rejections.len(), 1);
+        assert!(matches!(
+            &state.rejections[0].error,
+            TransactionError::UnexpectedError(message) if message.contains("observer failed")
+        ));
+    }
+
+    #[test]
+    fn test_handler_panic_undoes_changes() {
+        /// Panics once after a dispute, and after depositing tx 2,
+        /// by which time their balance changes have been made.
+        #[derive(Default)]
+        struct PanicsOnce {
+            disputed: AtomicBool,
+            deposited: AtomicBool,
+        }
+        impl TransactionObserver for PanicsOnce {
+            fn after_transaction(
+                &self,
+                record: &TransactionRecord,
+                result: &Result<(), TransactionError>,
+                _account: Option<&AccountView>,
+            ) {
+                let once = match record.transaction_type {
+                    TransactionType::Dispute => &self.disputed,
+                    TransactionType::Deposit if record.tx_id == 2 => &self.deposited,
+                    _ => return,
+                };
+                if result.is_ok() && !once.swap(true, Ordering::SeqCst) {
+                    panic!("observer failed");
+                }
+            }
+        }
+
+        let config = PipelineConfig {
+            ledger: true,
+            journal: true,
+            ..Default::default()
+        };
+        let run = |records: Vec<TransactionRecord>, observers| {
+            let mut handler = ShardedHandler::spawn(&config, Policies::default(), None);
+            let mut observed = State::with_policies(Policies::default());
+            observed.observers = observers;
+            handler.restore(observed).unwrap();
+            for record in records {
+                assert_eq!(handler.dispatch(record), Ok(()));
+            }
+            handler.finish()
+        };
+        let mut observers = Observers::default();
+        observers.add(Arc::new(PanicsOnce::default()));
+        let state = run(
+            vec![
+                deposit(1, 1, 10.0),
+                dispute(1, 1),
+                deposit(1, 2, 5.0),
+                // Neither took effect, so both can be tried again,
+                // though the router still counts the deposit's id as taken
+                dispute(1, 1),
+                deposit(1, 3, 5.0),
+            ],
+            observers,
+        );
+        let expected = run(
+            vec![deposit(1, 1, 10.0), dispute(1, 1), deposit(1, 3, 5.0)],
+            Observers::default(),
+        );
+
+        assert_eq!(state.rejections.len(), 2);
+        assert!(state.rejections.iter().all(|rejection| matches!(
+            &rejection.error,
+            TransactionError::UnexpectedError(message) if message.contains("observer failed")
+        )));
+        assert_eq!(state.account(1), expected.account(1));
+        assert_eq!(state.account(1).unwrap().held, Currency::from(10.0));
+        assert_eq!(
+            state.transactions.iter_client(1).collect::<Vec<_>>(),
+            expected.transactions.iter_client(1).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            state.disputes.history(1, 1),
+            expected.disputes.history(1, 1)
+        );
+        assert_eq!(state.ledger, expected.ledger);
+        assert_eq!(state.journal, expected.journal);
+    }
+
+    #[test]
+    fn test_scheduled_transactions() {
+        let scheduled = || {
+            vec![
+                deposit(1, 1, 10.0).with_timestamp(100),
+                // Applied at 200, once the deposit scheduled for 150 has taken effect
+                withdrawal(1, 2, 12.0)
+                    .with_timestamp(110)
+                    .with_effective(200),
+                deposit(1, 3, 5.0).with_timestamp(120).with_effective(150),
+                deposit(2, 4, 1.0).with_timestamp(200),
+                // Never takes effect
+                deposit(2, 5, 1.0).with_timestamp(210).with_effective(300),
+            ]
+        };
+
+        let mut handler =
+            ShardedHandler::spawn(&PipelineConfig::default(), Policies::default(), None);
+        for record in scheduled() {
+            let _ = handler.dispatch(record);
+        }
+        let state = handler.finish();
+        assert_eq!(
+            state.account(1).map(|account| account.available),
+            Some(Currency::from(10.0))
+        );
+        let codes: Vec<_> = state
+            .rejections
+            .iter()
+            .map(|rejection| (rejection.record.tx_id, rejection.error.code()))
+            .collect();
+        assert_eq!(
+            codes,
+            vec![
+                (2, "NOT_YET_EFFECTIVE"),
+                (3, "NOT_YET_EFFECTIVE"),
+                (5, "NOT_YET_EFFECTIVE")
+            ]
+        );
+
+        let policies = Policies {
+            hold_scheduled: true,
+            ..Default::default()
+        };
+        let config = PipelineConfig {
+            execution: ExecutionMode::Sequential,
+            ..Default::default()
+        };
+        let mut handler = ShardedHandler::spawn(&config, policies, None);
+        for record in scheduled() {
+            assert_eq!(handler.dispatch(record), Ok(()));
+        }
+        let checkpoint = handler.checkpoint(Default::default());
+        assert_eq!(checkpoint.schedule.held().len(), 1);
+        let state = handler.finish();
+        assert_eq!(
+            state.account(1).map(|account| account.available),
+            Some(Currency::from(3.0))
+        );
+        assert!(state.rejections.is_empty());
+        assert_eq!(state.schedule.held()[0].tx_id, 5);
+    }
+
+    #[test]
+    fn test_interest() {
+        let mut policies = Policies::default();
+        policies.interest.percent = 10.0;
+        let records = || {
+            vec![
+                deposit(1, 1, 10.0).with_timestamp(50),
+                deposit(2, 2, 20.0).with_timestamp(60),
+                // Passes the end of the first period
+                deposit(1, 3, 1.0).with_timestamp(120),
+            ]
+        };
+        let balances = |policies: &Policies, execution| {
+            let config = PipelineConfig {
+                execution,
+                ..Default::default()
+            };
+            let mut handler = ShardedHandler::spawn(&config, policies.clone(), None);
+            for record in records() {
+                handler.dispatch(record).unwrap();
+            }
+            let state = handler.finish();
+            let available = |client| state.account(client).map(|account| account.available);
+            (available(1), available(2))
+        };
+
+        // Credited once at the end of the run
+        for execution in [ExecutionMode::Sharded, ExecutionMode::Actors] {
+            assert_eq!(
+                balances(&policies, execution),
+                (Some(Currency::from(12.1)), Some(Currency::from(22.0)))
+            );
+        }
+
+        // Credited at the end of each period
+        policies.interest.period = Some(Duration::from_secs(100));
+        assert_eq!(
+            balances(&policies, ExecutionMode::Sequential),
+            (Some(Currency::from(12.0)), Some(Currency::from(22.0)))
+        );
+    }
+
+    #[test]
+    fn test_filter_clients() {
+        let config = PipelineConfig {
+            filter: RecordFilter {
+                clients: Some("2-3".parse().unwrap()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut handler = ShardedHandler::spawn(&config, Policies::default(), None);
+        for record in [
+            deposit(1, 1, 10.0),
+            deposit(2, 2, 5.0),
+            deposit(3, 3, 1.0),
+            // Reuses an ignored transaction's id, without being a duplicate
+            deposit(3, 1, 1.0),
+            withdrawal(4, 4, 1.0),
+        ] {
+            assert_eq!(handler.dispatch(record), Ok(()));
+        }
+
+        let state = handler.finish();
+        let clients: Vec<_> = state
+            .accounts
+            .iter()
+            .map(|(&(client, _), _)| client)
+            .collect();
+        assert_eq!(clients, vec![2, 3]);
+        assert!(state.rejections.is_empty());
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let mut handler =
+            ShardedHandler::spawn(&PipelineConfig::default(), Policies::default(), None);
+
+        assert_eq!(handler.dispatch(deposit(1, 1, 10.0)), Ok(()));
+        assert_eq!(handler.dispatch(deposit(2, 2, 5.0)), Ok(()));
+        let snapshot = handler.snapshot();
+        assert_eq!(handler.dispatch(deposit(3, 3, 1.0)), Ok(()));
+
+        assert_eq!(snapshot.iter().count(), 2);
+        assert_eq!(handler.finish().accounts.iter().count(), 3);
+    }
+
+    #[test]
+    fn test_subscribe() {
+        let mut handler =
+            ShardedHandler::spawn(&PipelineConfig::default(), Policies::default(), None);
+
+        // Only transactions after subscribing are included
+        assert_eq!(handler.dispatch(deposit(1, 1, 10.0)), Ok(()));
+        let updates = handler.subscribe();
+        assert_eq!(handler.dispatch(deposit(1, 2, 5.0)), Ok(()));
+        // Rejected transactions don't cause an update
+        assert!(handler.dispatch(deposit(1, 2, 5.0)).is_err());
+        assert_eq!(handler.dispatch(deposit(1, 3, -1.0)), Ok(()));
+        handler.finish();
+
+        let updates: Vec<_> = updates.iter().collect();
+        assert_eq!(
+            updates,
+            vec![BalanceUpdate {
+                tx: 2,
+                balance: OutputRecord {
+                    client: 1,
+                    currency: None,
+                    available: Currency::from(15.0),
+                    held: Currency::ZERO,
+                    total: Currency::from(15.0),
+                    locked: false,
+                    lock_reason: None,
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn test_update_policies() {
+        let mut handler =
+            ShardedHandler::spawn(&PipelineConfig::default(), Policies::default(), None);
+        let (log, events) = EventLog::channel();
+        let mut logged = State::with_policies(Policies::default());
+        logged.events = Some(log);
+        handler.restore(logged).unwrap();
+
+        assert_eq!(handler.dispatch(deposit(1, 1, 10.0)), Ok(()));
+        // Rejected, since admin transactions are disabled by default
+        assert_eq!(handler.dispatch(lock(1, 2)), Ok(()));
+        assert!(!handler.snapshot().get(1, None).unwrap().locked);
+
+        handler.update_policies(Policies {
+            allow_admin: true,
+            ..Default::default()
+        });
+        assert_eq!(handler.dispatch(lock(1, 3)), Ok(()));
+
+        let state = handler.finish();
+        assert!(state.policies.allow_admin);
+        assert!(state.accounts.get(1, None).unwrap().locked);
+
+        let changes: Vec<_> = events
+            .try_iter()
+            .filter_map(|event| match event {
+                Event::PoliciesChanged {
+                    dispatched,
+                    policies,
+                    ..
+                } => Some((dispatched, policies.allow_admin)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(changes, vec![(2, true)]);
+    }
+
+    #[test]
+    fn test_accounts_in_input_order() {
+        let mut handler =
+            ShardedHandler::spawn(&PipelineConfig::default(), Policies::default(), None);
+
+        for (tx_id, client_id) in [(1, 9), (2, 3), (3, 6), (4, 1), (5, 3)] {
+            assert_eq!(handler.dispatch(deposit(client_id, tx_id, 1.0)), Ok(()));
+        }
+
+        let state = handler.finish();
+        let clients = |order| {
+            state
+                .accounts
+                .ordered(order)
+                .into_iter()
+                .map(|(&(client_id, _), _)| client_id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(clients(AccountOrder::Insertion), vec![9, 3, 6, 1]);
+        assert_eq!(clients(AccountOrder::Client), vec![1, 3, 6, 9]);
+    }
+
+    #[test]
+    fn test_shards_combined() {
+        let mut handler =
+            ShardedHandler::spawn(&PipelineConfig::default(), Policies::default(), None);
+
+        for client_id in 1..=10 {
+            let tx_id = client_id as TransactionId;
+            assert_eq!(handler.dispatch(deposit(client_id, tx_id, 1.0)), Ok(()));
+        }
+
+        let state = handler.finish();
+        assert_eq!(state.accounts.iter().count(), 10);
+    }
+
+    #[test]
+    fn test_shard_ledgers_combined() {
+        let config = PipelineConfig {
+            ledger: true,
+            ..Default::default()
+        };
+        let mut handler = ShardedHandler::spawn(&config, Policies::default(), None);
+
+        for tx_id in 1..=10 {
+            let client_id = (tx_id % 5) as ClientId;
+            assert_eq!(handler.dispatch(deposit(client_id, tx_id, 1.0)), Ok(()));
+        }
+
+        let state = handler.finish();
+        let entries: Vec<_> = state
+            .ledger
+            .unwrap()
+            .entries()
+            .iter()
+            .map(|entry| (entry.client, entry.tx))
+            .collect();
+        // Grouped by client, each in the order handled
+        assert_eq!(
+            entries,
+            vec![
+                (0, 5),
+                (0, 10),
+                (1, 1),
+                (1, 6),
+                (2, 2),
+                (2, 7),
+                (3, 3),
+                (3, 8),
+                (4, 4),
+                (4, 9)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unbuffered_queues() {
+        // Each dispatch waits for a handler to take the transaction
+        let config = PipelineConfig {
+            handler_threads: 2,
+            handler_queue_depth: 0,
+            ..Default::default()
+        };
+        let mut handler = ShardedHandler::spawn(&config, Policies::default(), None);
+
+        for tx_id in 1..=10 {
+            let client_id = (tx_id % 3) as ClientId;
+            assert_eq!(handler.dispatch(deposit(client_id, tx_id, 1.0)), Ok(()));
+        }
+
+        let state = handler.finish();
+        let balances: Vec<_> = state
+            .accounts
+            .iter()
+            .map(|(&(client_id, _), account)| (client_id, account.available))
+            .collect();
+        assert_eq!(balances.len(), 3);
+        assert!(balances.contains(&(1, Currency::from(4.0))));
+    }
+
+    #[test]
+    fn test_modes_match() {
+        let records: Vec<_> = (1..=200)
+            .flat_map(|tx_id| {
+                let client_id = (tx_id % 7) as ClientId;
+                let mut records = vec![deposit(client_id, tx_id, 1.0)];
+                // Disputes from the wrong client reach the right shard or actor
+                if tx_id % 5 == 0 {
+                    records.push(dispute(client_id + 1, tx_id));
+                } else if tx_id % 3 == 0 {
+                    records.push(dispute(client_id, tx_id));
+                }
+                records
+            })
+            .collect();
+
+        let finish = |execution| {
+            let config = PipelineConfig {
+                execution,
+                ledger: true,
+                journal: true,
+                settlement: true,
+                handler_queue_depth: 1,
+                ..Default::default()
+            };
+            let mut handler = ShardedHandler::spawn(&config, Policies::default(), None);
+            for record in &records {
+                let _ = handler.dispatch(record.clone());
+            }
+            assert_eq!(handler.snapshot().len(), 7);
+            handler.finish()
+        };
+
+        let sequential = finish(ExecutionMode::Sequential);
+        assert!(!sequential.rejections.is_empty());
+        for execution in [ExecutionMode::Sharded, ExecutionMode::Actors] {
+            let state = finish(execution);
+            assert_eq!(state.rejections, sequential.rejections);
+            assert_eq!(
+                state.accounts.iter().collect::<Vec<_>>(),
+                sequential.accounts.iter().collect::<Vec<_>>()
+            );
+            assert_eq!(
+                state.ledger.unwrap().entries(),
+                sequential.ledger.as_ref().unwrap().entries()
+            );
+            assert_eq!(state.journal, sequential.journal);
+            assert_eq!(state.settlement, sequential.settlement);
+        }
+    }
+
+    #[test]
+    fn test_client_order_in_every_mode() {
+        for execution in [
+            ExecutionMode::Sharded,
+            ExecutionMode::Actors,
+            ExecutionMode::Sequential,
+        ] {
+            let config = PipelineConfig {
+                execution,
+                handler_threads: 3,
+                ..Default::default()
+            };
+            let mut handler = ShardedHandler::spawn(&config, Policies::default(), None);
+            let updates = handler.subscribe();
+            for tx_id in 1..=1000 {
+                let client_id = (tx_id * 7 % 11) as ClientId;
+                assert_eq!(handler.dispatch(deposit(client_id, tx_id, 1.0)), Ok(()));
+            }
+            handler.finish();
+
+            let updates: Vec<_> = updates.iter().collect();
+            assert_eq!(updates.len(), 1000);
+            for client_id in 0..11 {
+                let tx_ids: Vec<_> = updates
+                    .iter()
+                    .filter(|update| update.balance.client == client_id)
+                    .map(|update| update.tx)
+                    .collect();
+                assert!(
+                    tx_ids.windows(2).all(|pair| pair[0] < pair[1]),
+                    "{:?} reordered client {}",
+                    execution,
+                    client_id
+                );
+            }
+            if execution == ExecutionMode::Sequential {
+                // Updates arrive in exactly the order dispatched
+                let tx_ids: Vec<_> = updates.iter().map(|update| update.tx).collect();
+                assert_eq!(tx_ids, (1..=1000).collect::<Vec<_>>());
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_execution_mode() {
+        assert_eq!("actors".parse(), Ok(ExecutionMode::Actors));
+        assert_eq!("sequential".parse(), Ok(ExecutionMode::Sequential));
+        assert!("threads".parse::<ExecutionMode>().is_err());
+    }
+
+    #[test]
+    fn test_validate_handler_threads() {
+        assert!(validate_handler_threads(0).is_err());
+        assert_eq!(validate_handler_threads(1), Ok(1));
+        assert_eq!(
+            validate_handler_threads(MAX_HANDLER_THREADS),
+            Ok(MAX_HANDLER_THREADS)
+        );
+        assert!(validate_handler_threads(MAX_HANDLER_THREADS + 1).is_err());
+    }
+}
@@ -0,0 +1,714 @@
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::currency::{Currency, RoundingMode, DECIMALS};
+use crate::types::ClientId;
+
+/// Rules governing which transactions may be disputed.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DisputePolicy {
+    /// Maximum age of a transaction (measured at the time of the dispute)
+    /// which may still be disputed. `None` means no limit.
+    ///
+    /// This is only enforced when both the dispute and the disputed
+    /// transaction carry a timestamp.
+    #[serde(
+        rename = "max_age_secs",
+        with = "optional_secs",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_age: Option<Duration>,
+    /// Forget settled disputes once their transaction is older than `max_age`,
+    /// since it can no longer be disputed anyway, so that memory use doesn't
+    /// grow with every dispute ever settled. Disputes are then aged as of the
+    /// latest timestamp seen, if it's later than their own, so that a forgotten
+    /// transaction can't be disputed again.
+    pub compact_settled: bool,
+    /// How many times a transaction may be disputed again after a dispute of it
+    /// was resolved. Charged back transactions can't be disputed again, only represented.
+    pub max_redisputes: u32,
+}
+
+/// What to do when a chargeback exceeds the account's funds,
+/// e.g. because the disputed deposit was already withdrawn.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChargebackPolicy {
+    /// Charge back the full amount, leaving a negative balance.
+    #[default]
+    AllowNegative,
+    /// Reject the chargeback with `TransactionError::ChargebackExceedsFunds`,
+    /// leaving the transaction disputed.
+    Block,
+    /// Charge back only what the account can cover,
+    /// write off the rest, and flag the account.
+    Clamp,
+}
+
+impl FromStr for ChargebackPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow-negative" => Ok(Self::AllowNegative),
+            "block" => Ok(Self::Block),
+            "clamp" => Ok(Self::Clamp),
+            other => Err(format!("unknown chargeback policy '{}'", other)),
+        }
+    }
+}
+
+/// Fee charged for a single kind of transaction:
+/// a flat amount plus a percentage of the transaction amount.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FeeSchedule {
+    pub flat: Currency,
+    pub percent: f64,
+}
+
+impl FeeSchedule {
+    /// Fee for a transaction of the given amount.
+    pub fn fee(&self, amount: Currency, rounding: &RoundingPolicy) -> Currency {
+        rounding.round(self.flat) + rounding.percent(amount, self.percent)
+    }
+}
+
+/// Fees charged to the client's account for each deposit and withdrawal.
+/// Deposits credit the amount less the fee,
+/// and withdrawals debit the amount plus the fee.
+/// Fees are not refunded if the transaction is later charged back.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FeePolicy {
+    pub deposit: FeeSchedule,
+    pub withdrawal: FeeSchedule,
+}
+
+/// Interest paid on positive available balances. See `interest`.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct InterestPolicy {
+    /// Interest credited each time, as a percentage of the available funds.
+    /// Zero, the default, means no interest is paid.
+    pub percent: f64,
+    /// How often interest is credited, counted from the epoch by the timestamps read.
+    /// `None` means once, at the end of the run.
+    #[serde(
+        rename = "period_secs",
+        with = "optional_secs",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub period: Option<Duration>,
+}
+
+/// How far below zero each account's available funds may go.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CreditPolicy {
+    /// Credit limit for accounts without their own limit.
+    #[serde(deserialize_with = "deserialize_credit_limit")]
+    pub default_limit: Currency,
+    /// Credit limits for individual accounts, by client.
+    #[serde(rename = "accounts", with = "credit_lines")]
+    pub limits: HashMap<ClientId, Currency>,
+}
+
+/// Check that a credit limit isn't negative, since it's how far below zero
+/// an account may go, not an amount it must keep.
+pub fn validate_credit_limit(limit: Currency) -> Result<Currency, String> {
+    if limit.is_negative() {
+        Err(format!("credit limit must not be negative, not {}", limit))
+    } else {
+        Ok(limit)
+    }
+}
+
+fn deserialize_credit_limit<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Currency, D::Error> {
+    validate_credit_limit(Currency::deserialize(deserializer)?).map_err(de::Error::custom)
+}
+
+impl CreditPolicy {
+    /// Credit limit for the given client's account.
+    pub fn limit(&self, client_id: ClientId) -> Currency {
+        self.limits
+            .get(&client_id)
+            .copied()
+            .unwrap_or(self.default_limit)
+    }
+}
+
+/// Velocity limits on a client's deposits and withdrawals. `None` means no limit.
+///
+/// Daily withdrawals and transactions per minute are only counted for
+/// transactions with a timestamp, by calendar day (UTC) and by minute
+/// on the clock. Transactions without one are only checked on their own.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Limits {
+    /// Largest single deposit.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_deposit: Option<Currency>,
+    /// Largest single withdrawal, not including its fee.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_withdrawal: Option<Currency>,
+    /// Most which may be withdrawn from each of a client's accounts in a day.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_daily_withdrawal: Option<Currency>,
+    /// Most deposits and withdrawals a client may make in a minute.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_per_minute: Option<u32>,
+}
+
+/// Velocity limits for each client, rejecting transactions
+/// which exceed them with `TransactionError::LimitExceeded`,
+/// `DailyLimitExceeded` or `RateLimitExceeded`.
+/// The limits here are for clients without their own.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LimitPolicy {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_deposit: Option<Currency>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_withdrawal: Option<Currency>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_daily_withdrawal: Option<Currency>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_per_minute: Option<u32>,
+    /// Limits for individual clients, by client,
+    /// in place of the default limits.
+    #[serde(rename = "accounts", with = "client_limits")]
+    pub clients: HashMap<ClientId, Limits>,
+}
+
+impl LimitPolicy {
+    /// Limits for the given client.
+    pub fn limits(&self, client_id: ClientId) -> Limits {
+        self.clients.get(&client_id).copied().unwrap_or(Limits {
+            max_deposit: self.max_deposit,
+            max_withdrawal: self.max_withdrawal,
+            max_daily_withdrawal: self.max_daily_withdrawal,
+            max_per_minute: self.max_per_minute,
+        })
+    }
+}
+
+/// A simple rules-based risk score for deposits and withdrawals, out of 100,
+/// and the scores at which to flag or reject them. See `risk::RiskScorer`.
+/// Each rule a transaction matches adds its points to the score.
+/// Nothing is scored unless a threshold is set.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RiskPolicy {
+    /// Score at which to flag the account for review,
+    /// though the transaction is still accepted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flag_score: Option<u32>,
+    /// Score at which to reject the transaction with `TransactionError::RiskRejected`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reject_score: Option<u32>,
+    /// Amount above which a transaction counts as large.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub large_amount: Option<Currency>,
+    /// Points for a transaction above `large_amount`.
+    pub large_amount_points: u32,
+    /// Points for a transaction on an account with no funds, e.g. a new one.
+    pub empty_account_points: u32,
+    /// Points for a withdrawal of all of the account's available funds.
+    pub draining_points: u32,
+    /// Points for a transaction on an account already flagged for review.
+    pub flagged_account_points: u32,
+}
+
+impl Default for RiskPolicy {
+    fn default() -> Self {
+        Self {
+            flag_score: None,
+            reject_score: None,
+            large_amount: None,
+            large_amount_points: 50,
+            empty_account_points: 20,
+            draining_points: 20,
+            flagged_account_points: 30,
+        }
+    }
+}
+
+/// Which transactions a transaction id must be unique among.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TxIdScope {
+    /// Unique across all clients. This takes an index of every id,
+    /// roughly 16 bytes per transaction, but lets disputes from the wrong
+    /// client be rejected with `ClientMismatch`.
+    #[default]
+    Global,
+    /// Unique within each client, for upstream processors which only
+    /// guarantee that. No index is kept, and a dispute refers to
+    /// its own client's transaction with the id, if there is one.
+    Client,
+}
+
+impl FromStr for TxIdScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "global" => Ok(Self::Global),
+            "client" => Ok(Self::Client),
+            other => Err(format!("unknown transaction id scope '{}'", other)),
+        }
+    }
+}
+
+/// Rules a transaction must follow to be accepted.
+/// The defaults are the engine's usual rules, so each field
+/// only needs setting to loosen or tighten one of them.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ValidationPolicy {
+    /// Accept deposits, withdrawals, and partial disputes of zero.
+    /// Negative amounts are always rejected.
+    pub allow_zero_amounts: bool,
+    /// Accept deposits and withdrawals reusing a transaction id which is
+    /// already taken. Disputes refer to the first transaction with the id.
+    pub allow_duplicate_tx_ids: bool,
+    /// Ignore a deposit or withdrawal identical to one which already succeeded,
+    /// rather than rejecting it as a duplicate, e.g. for at-least-once delivery.
+    pub idempotent: bool,
+    /// Which transactions a deposit or withdrawal's id must be unique among.
+    /// This is fixed once the engine starts, and ignored when policies are reloaded.
+    pub tx_id_scope: TxIdScope,
+    /// Accept disputes of transactions on locked accounts.
+    /// Resolves and chargebacks of existing disputes are always accepted.
+    pub allow_locked_disputes: bool,
+    /// Reject disputes, resolves, and chargebacks from a client
+    /// other than the disputed transaction's.
+    pub require_matching_client: bool,
+    /// Reject disputes, resolves, and chargebacks in a currency
+    /// other than the disputed transaction's.
+    pub require_matching_currency: bool,
+}
+
+impl Default for ValidationPolicy {
+    fn default() -> Self {
+        Self {
+            allow_zero_amounts: false,
+            allow_duplicate_tx_ids: false,
+            idempotent: false,
+            tx_id_scope: TxIdScope::Global,
+            allow_locked_disputes: true,
+            require_matching_client: true,
+            require_matching_currency: true,
+        }
+    }
+}
+
+/// How amounts are rounded, to match the ledger's conventions.
+/// This applies to incoming amounts, to fees, and to output balances.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RoundingPolicy {
+    pub mode: RoundingMode,
+    /// Number of decimal places to keep.
+    /// Amounts never have more than `currency::DECIMALS` places,
+    /// so larger values have no effect.
+    pub precision: u32,
+}
+
+impl Default for RoundingPolicy {
+    fn default() -> Self {
+        Self {
+            mode: RoundingMode::default(),
+            precision: DECIMALS,
+        }
+    }
+}
+
+impl RoundingPolicy {
+    pub fn round(&self, amount: Currency) -> Currency {
+        amount.round(self.precision, self.mode)
+    }
+
+    /// The given percentage of an amount, rounded once.
+    pub fn percent(&self, amount: Currency, percent: f64) -> Currency {
+        amount.percent(percent, self.precision, self.mode)
+    }
+}
+
+/// All configurable policies which affect transaction handling.
+///
+/// Policies can be read from a TOML file, e.g.
+///
+/// ```toml
+/// allow_admin = true
+/// chargeback = "block"
+/// max_balance = 1000000.0
+///
+/// [dispute]
+/// max_age_secs = 7776000
+///
+/// [fees.withdrawal]
+/// flat = 0.5
+/// percent = 1.0
+///
+/// [[credit.accounts]]
+/// client = 7
+/// limit = 100.0
+///
+/// [limits]
+/// max_withdrawal = 1000.0
+/// max_per_minute = 10
+///
+/// [risk]
+/// flag_score = 50
+/// large_amount = 10000.0
+///
+/// [rounding]
+/// mode = "half_even"
+/// precision = 2
+///
+/// [validation]
+/// allow_duplicate_tx_ids = true
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Policies {
+    // NOTE: Plain values must come before tables to serialize as TOML
+    /// Whether to accept administrative `lock`, `unlock` and `reversal` transactions.
+    /// These are disabled by default so that ordinary
+    /// transaction feeds can't unlock accounts, or undo transactions.
+    pub allow_admin: bool,
+    /// Whether to hold transactions with a later `effective` time than the latest
+    /// timestamp read until a later record reaches it, rather than rejecting them.
+    /// See `schedule`.
+    pub hold_scheduled: bool,
+    pub chargeback: ChargebackPolicy,
+    /// Largest total (`available` + `held`) an account may reach through deposits.
+    /// `None` means no limit, other than what amounts can represent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_balance: Option<Currency>,
+    pub dispute: DisputePolicy,
+    pub fees: FeePolicy,
+    pub credit: CreditPolicy,
+    pub interest: InterestPolicy,
+    pub limits: LimitPolicy,
+    pub risk: RiskPolicy,
+    pub rounding: RoundingPolicy,
+    pub validation: ValidationPolicy,
+}
+
+impl Policies {
+    /// Read policies from a TOML file.
+    /// Any missing values take their defaults.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read_to_string(path)?;
+        Ok(toml::from_str(&contents)?)
+    }
+}
+
+/// (De)serialize an optional duration as a whole number of seconds.
+mod optional_secs {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => serializer.serialize_some(&duration.as_secs()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        let secs: Option<u64> = Option::deserialize(deserializer)?;
+        Ok(secs.map(Duration::from_secs))
+    }
+}
+
+/// (De)serialize credit limits by client as a list of tables,
+/// since TOML keys can't be integers.
+mod credit_lines {
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::HashMap;
+
+    use super::validate_credit_limit;
+    use crate::currency::Currency;
+    use crate::types::ClientId;
+
+    #[derive(Deserialize, Serialize)]
+    struct CreditLine {
+        client: ClientId,
+        limit: Currency,
+    }
+
+    pub fn serialize<S: Serializer>(
+        limits: &HashMap<ClientId, Currency>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut lines: Vec<_> = limits
+            .iter()
+            .map(|(&client, &limit)| CreditLine { client, limit })
+            .collect();
+        lines.sort_by_key(|line| line.client);
+        lines.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<HashMap<ClientId, Currency>, D::Error> {
+        let lines: Vec<CreditLine> = Vec::deserialize(deserializer)?;
+        lines
+            .into_iter()
+            .map(|line| Ok((line.client, validate_credit_limit(line.limit)?)))
+            .collect::<Result<_, String>>()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// (De)serialize limits by client as a list of tables, like credit limits.
+mod client_limits {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::HashMap;
+
+    use super::Limits;
+    use crate::currency::Currency;
+    use crate::types::ClientId;
+
+    // Fields are spelled out rather than flattened,
+    // since flattened amounts can't be read from TOML numbers
+    #[derive(Deserialize, Serialize)]
+    struct ClientLimits {
+        client: ClientId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_deposit: Option<Currency>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_withdrawal: Option<Currency>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_daily_withdrawal: Option<Currency>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_per_minute: Option<u32>,
+    }
+
+    pub fn serialize<S: Serializer>(
+        limits: &HashMap<ClientId, Limits>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut lines: Vec<_> = limits
+            .iter()
+            .map(|(&client, limits)| ClientLimits {
+                client,
+                max_deposit: limits.max_deposit,
+                max_withdrawal: limits.max_withdrawal,
+                max_daily_withdrawal: limits.max_daily_withdrawal,
+                max_per_minute: limits.max_per_minute,
+            })
+            .collect();
+        lines.sort_by_key(|line| line.client);
+        lines.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<HashMap<ClientId, Limits>, D::Error> {
+        let lines: Vec<ClientLimits> = Vec::deserialize(deserializer)?;
+        Ok(lines
+            .into_iter()
+            .map(|line| {
+                let limits = Limits {
+                    max_deposit: line.max_deposit,
+                    max_withdrawal: line.max_withdrawal,
+                    max_daily_withdrawal: line.max_daily_withdrawal,
+                    max_per_minute: line.max_per_minute,
+                };
+                (line.client, limits)
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChargebackPolicy, CreditPolicy, DisputePolicy, FeePolicy, FeeSchedule, Policies};
+    use super::{LimitPolicy, Limits, RoundingPolicy, ValidationPolicy};
+    use crate::currency::{Currency, RoundingMode};
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_policies() {
+        let policies: Policies = toml::from_str(
+            r#"
+            allow_admin = true
+            chargeback = "clamp"
+
+            [dispute]
+            max_age_secs = 60
+            compact_settled = true
+            max_redisputes = 2
+
+            [fees.withdrawal]
+            flat = 0.5
+
+            [validation]
+            allow_zero_amounts = true
+            "#,
+        )
+        .unwrap();
+
+        let expected = Policies {
+            dispute: DisputePolicy {
+                max_age: Some(Duration::from_secs(60)),
+                compact_settled: true,
+                max_redisputes: 2,
+            },
+            fees: FeePolicy {
+                withdrawal: FeeSchedule {
+                    flat: Currency::from(0.5),
+                    percent: 0.0,
+                },
+                ..Default::default()
+            },
+            validation: ValidationPolicy {
+                allow_zero_amounts: true,
+                ..Default::default()
+            },
+            allow_admin: true,
+            chargeback: ChargebackPolicy::Clamp,
+            ..Default::default()
+        };
+        assert_eq!(policies, expected);
+    }
+
+    #[test]
+    fn test_parse_empty_policies() {
+        let policies: Policies = toml::from_str("").unwrap();
+        assert_eq!(policies, Policies::default());
+    }
+
+    #[test]
+    fn test_fee() {
+        let schedule = FeeSchedule {
+            flat: Currency::from(0.25),
+            percent: 2.0,
+        };
+        let rounding = RoundingPolicy::default();
+        assert_eq!(
+            schedule.fee(Currency::from(10.0), &rounding),
+            Currency::from(0.45)
+        );
+        assert_eq!(
+            FeeSchedule::default().fee(Currency::from(10.0), &rounding),
+            Currency::ZERO
+        );
+    }
+
+    #[test]
+    fn test_rounded_fee() {
+        let policies: Policies = toml::from_str(
+            r#"
+            [fees.deposit]
+            percent = 1.5
+
+            [rounding]
+            mode = "half_even"
+            precision = 2
+            "#,
+        )
+        .unwrap();
+        assert_eq!(policies.rounding.mode, RoundingMode::HalfEven);
+
+        // 1.5% of 2.5 is 0.0375, which is 0.04 to the nearest cent
+        let fee = |amount: f64| {
+            policies
+                .fees
+                .deposit
+                .fee(Currency::from(amount), &policies.rounding)
+        };
+        assert_eq!(fee(2.5), Currency::from(0.04));
+        // 1.5% of 1.0 is exactly half way between 0.01 and 0.02
+        assert_eq!(fee(1.0), Currency::from(0.02));
+        // 1.5% of 0.5 is 0.0075, which is 0.01
+        assert_eq!(fee(0.5), Currency::from(0.01));
+    }
+
+    #[test]
+    fn test_parse_credit_limits() {
+        let policies: Policies = toml::from_str(
+            r#"
+            [credit]
+            default_limit = 10.0
+
+            [[credit.accounts]]
+            client = 7
+            limit = 100.0
+            "#,
+        )
+        .unwrap();
+
+        let credit: &CreditPolicy = &policies.credit;
+        assert_eq!(credit.limit(7), Currency::from(100.0));
+        assert_eq!(credit.limit(8), Currency::from(10.0));
+
+        // Round trip
+        let serialized = toml::to_string(&policies).unwrap();
+        assert_eq!(toml::from_str::<Policies>(&serialized).unwrap(), policies);
+    }
+
+    #[test]
+    fn test_parse_negative_credit_limits() {
+        assert!(toml::from_str::<Policies>("[credit]\ndefault_limit = -10.0").is_err());
+        assert!(
+            toml::from_str::<Policies>("[[credit.accounts]]\nclient = 7\nlimit = -1.0").is_err()
+        );
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        let policies: Policies = toml::from_str(
+            r#"
+            [limits]
+            max_withdrawal = 100.0
+            max_per_minute = 5
+
+            [[limits.accounts]]
+            client = 7
+            max_daily_withdrawal = 1000.0
+            "#,
+        )
+        .unwrap();
+
+        let limits: &LimitPolicy = &policies.limits;
+        assert_eq!(
+            limits.limits(8),
+            Limits {
+                max_withdrawal: Some(Currency::from(100.0)),
+                max_per_minute: Some(5),
+                ..Default::default()
+            }
+        );
+        // A client's own limits replace the default ones
+        assert_eq!(
+            limits.limits(7),
+            Limits {
+                max_daily_withdrawal: Some(Currency::from(1000.0)),
+                ..Default::default()
+            }
+        );
+
+        // Round trip
+        let serialized = toml::to_string(&policies).unwrap();
+        assert_eq!(toml::from_str::<Policies>(&serialized).unwrap(), policies);
+    }
+}
@@ -1,83 +1,271 @@
-use rand::distributions::{Distribution, Standard};
-use rand::{thread_rng, Rng};
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::io::{self, Write};
+use std::str::FromStr;
 
-use crate::currency::floor_currency;
 use crate::handlers::handle_transaction;
 use crate::state::State;
 use crate::types::{Chargeback, Deposit, Dispute, Resolve, Withdrawal};
-use crate::types::{ClientId, CurrencyFloat, TransactionId};
+use crate::types::{ClientId, Currency, TransactionError, TransactionId};
 use crate::types::{TransactionRecord, TransactionType};
 
-const MIN_AMOUNT: CurrencyFloat = 0.0001;
-
-// Proportions of randomly generated types
-// to fall in each category
-// NOTE: The real, proper way to do this might
-// be to implement a custom rand::Distribution,
-// but I'm not going to do that.
-const DEPOSIT_PCNT: f32 = 0.5;
-const WITHDRAWAL_PCNT: f32 = 0.4;
-const DISPUTE_PCNT: f32 = 0.05;
-const RESOLVE_PCNT: f32 = 0.04;
-// const CHARGEBACK_PCNT: f32 = 0.01;
-
-const CUM_DEPOSIT: f32 = 0.0 + DEPOSIT_PCNT;
-const CUM_WITHDRAWAL: f32 = CUM_DEPOSIT + WITHDRAWAL_PCNT;
-const CUM_DISPUTE: f32 = CUM_WITHDRAWAL + DISPUTE_PCNT;
-const CUM_RESOLVE: f32 = CUM_DISPUTE + RESOLVE_PCNT;
-// const CUM_CHARGEBACK: f32 = CUM_RESOLVE + CHARGEBACK_PCNT;
-
-impl Distribution<TransactionType> for Standard {
-    fn sample<R: rand::Rng + ?Sized>(&self, rng: &mut R) -> TransactionType {
-        // Inspired by https://stackoverflow.com/a/58434531/4228052
-
-        let x: f32 = rng.gen();
-
-        match x {
-            x if x < CUM_DEPOSIT => TransactionType::Deposit,
-            x if x < CUM_WITHDRAWAL => TransactionType::Withdrawal,
-            x if x < CUM_DISPUTE => TransactionType::Dispute,
-            x if x < CUM_RESOLVE => TransactionType::Resolve,
-            _ => TransactionType::Chargeback,
+const MIN_AMOUNT: Currency = Currency::from_minor_units(1);
+
+/// Id of a transaction which doesn't exist, since generated ids count up from 1.
+const MISSING_TX_ID: TransactionId = TransactionId::MAX;
+
+/// Types of transaction which may be generated, in the order of their weights.
+const GENERATED_TYPES: [TransactionType; 5] = [
+    TransactionType::Deposit,
+    TransactionType::Withdrawal,
+    TransactionType::Dispute,
+    TransactionType::Resolve,
+    TransactionType::Chargeback,
+];
+
+/// Relative weights of each type of transaction to generate,
+/// e.g. with a heavier `dispute` weight for a dispute-heavy workload.
+/// Weights needn't add up to anything in particular; only their ratios matter.
+///
+/// The weights govern which type is attempted next, so the actual mix may differ
+/// when a type often can't be generated, e.g. resolves with no disputes open.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TransactionWeights {
+    pub deposit: f64,
+    pub withdrawal: f64,
+    pub dispute: f64,
+    pub resolve: f64,
+    pub chargeback: f64,
+}
+
+impl Default for TransactionWeights {
+    fn default() -> Self {
+        Self {
+            deposit: 50.0,
+            withdrawal: 40.0,
+            dispute: 5.0,
+            resolve: 4.0,
+            chargeback: 1.0,
         }
     }
 }
 
-struct TransactionGenerator {
+impl TransactionWeights {
+    fn as_array(&self) -> [f64; 5] {
+        [
+            self.deposit,
+            self.withdrawal,
+            self.dispute,
+            self.resolve,
+            self.chargeback,
+        ]
+    }
+
+    /// Distribution to sample transaction types from,
+    /// as long as some weight is positive and none are negative.
+    pub fn distribution(&self) -> Result<TransactionMix, String> {
+        WeightedIndex::new(self.as_array())
+            .map(TransactionMix)
+            .map_err(|err| format!("invalid transaction weights: {}", err))
+    }
+}
+
+/// Weights given as comma-separated `type=weight` pairs, e.g. `dispute=20,resolve=15`.
+/// Types which aren't given keep their default weight.
+impl FromStr for TransactionWeights {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut weights = Self::default();
+        for pair in s.split(',') {
+            let (name, weight) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("expected `type=weight`, not '{}'", pair))?;
+            let weight: f64 = weight
+                .trim()
+                .parse()
+                .map_err(|err| format!("invalid weight '{}': {}", weight, err))?;
+            if !(weight.is_finite() && weight >= 0.0) {
+                return Err(format!("weights can't be negative, not {}", weight));
+            }
+            let slot = match name.trim() {
+                "deposit" => &mut weights.deposit,
+                "withdrawal" => &mut weights.withdrawal,
+                "dispute" => &mut weights.dispute,
+                "resolve" => &mut weights.resolve,
+                "chargeback" => &mut weights.chargeback,
+                other => return Err(format!("can't generate '{}' transactions", other)),
+            };
+            *slot = weight;
+        }
+        weights.distribution()?;
+        Ok(weights)
+    }
+}
+
+/// Transaction types, sampled according to `TransactionWeights`.
+#[derive(Clone, Debug)]
+pub struct TransactionMix(WeightedIndex<f64>);
+
+impl Distribution<TransactionType> for TransactionMix {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> TransactionType {
+        GENERATED_TYPES[self.0.sample(rng)].clone()
+    }
+}
+
+/// Format of transactions, as generated, or read by `process`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TransactionFormat {
+    /// With a header row, as read by `process`
+    #[default]
+    Csv,
+    /// One JSON object per line
+    Jsonl,
+}
+
+impl FromStr for TransactionFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "jsonl" | "ndjson" => Ok(Self::Jsonl),
+            other => Err(format!("unknown transaction format '{}'", other)),
+        }
+    }
+}
+
+/// Writes transactions, one at a time, in a given format.
+pub enum TransactionWriter<W: io::Write> {
+    Csv(Box<csv::Writer<W>>),
+    Jsonl(io::BufWriter<W>),
+}
+
+impl<W: io::Write> TransactionWriter<W> {
+    pub fn new(output: W, format: TransactionFormat) -> Self {
+        match format {
+            TransactionFormat::Csv => Self::Csv(Box::new(csv::Writer::from_writer(output))),
+            TransactionFormat::Jsonl => Self::Jsonl(io::BufWriter::new(output)),
+        }
+    }
+
+    pub fn write(&mut self, record: &TransactionRecord) -> io::Result<()> {
+        match self {
+            Self::Csv(writer) => writer.serialize(record).map_err(into_io_error),
+            Self::Jsonl(writer) => {
+                serde_json::to_writer(&mut *writer, record)?;
+                writer.write_all(b"\n")
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Csv(writer) => writer.flush(),
+            Self::Jsonl(writer) => writer.flush(),
+        }
+    }
+}
+
+/// Unwrap an I/O error from the CSV writer, keeping its kind, e.g. a broken pipe.
+fn into_io_error(err: csv::Error) -> io::Error {
+    if err.is_io_error() {
+        if let csv::ErrorKind::Io(err) = err.into_kind() {
+            return err;
+        }
+        unreachable!()
+    }
+    io::Error::other(err)
+}
+
+/// Check that a proportion of invalid transactions is between 0 and 1.
+pub fn validate_invalid_ratio(ratio: f64) -> Result<f64, String> {
+    if (0.0..=1.0).contains(&ratio) {
+        Ok(ratio)
+    } else {
+        Err(format!(
+            "invalid ratio must be between 0 and 1, not {}",
+            ratio
+        ))
+    }
+}
+
+/// A generated transaction, and the error it's expected to be rejected with, if it's invalid.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GeneratedTransaction {
+    pub record: TransactionRecord,
+    pub expected_error: Option<TransactionError>,
+}
+
+/// Random amount at least `MIN_AMOUNT` and less than `max`.
+fn random_amount<R: Rng>(rng: &mut R, max: Currency) -> Currency {
+    Currency::from_minor_units(rng.gen_range(MIN_AMOUNT.minor_units()..max.minor_units()))
+}
+
+/// Iterator over random transactions, which handles each one as it's generated,
+/// so that the next can be chosen to be valid (or not) given the accounts so far.
+pub struct TransactionGenerator {
     state: State,
+    /// Id for the next new deposit or withdrawal
     tx_id: TransactionId,
+    /// Number of transactions generated so far
+    generated: TransactionId,
     num_tx: Option<TransactionId>,
     max_client: ClientId,
-    max_deposit: CurrencyFloat,
+    max_deposit: Currency,
     max_attempts: usize,
+    mix: TransactionMix,
+    /// Proportion of transactions which deliberately break a rule
+    invalid_ratio: f64,
+    rng: StdRng,
 }
 
 impl TransactionGenerator {
-    fn new(
+    pub(crate) fn new(
         num_tx: Option<TransactionId>,
         max_client: ClientId,
-        max_deposit: CurrencyFloat,
+        max_deposit: Currency,
         max_attempts: usize,
+        mix: TransactionMix,
+        invalid_ratio: f64,
     ) -> Self {
         Self {
             state: State::new(),
             tx_id: 1,
+            generated: 0,
             num_tx,
             max_client,
             max_deposit,
             max_attempts,
+            mix,
+            invalid_ratio,
+            rng: StdRng::from_entropy(),
         }
     }
+
+    /// Generate the same transactions every time, given the same seed and options.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    /// Accounts and transactions after handling everything generated so far,
+    /// e.g. to write the balances which the transactions should result in.
+    pub fn state(&self) -> &State {
+        &self.state
+    }
 }
 
 impl TransactionGenerator {
-    fn get_client_id<R: Rng>(&self, rng: &mut R) -> ClientId {
-        rng.gen_range(1..=self.max_client)
+    fn get_client_id(&mut self) -> ClientId {
+        self.rng.gen_range(1..=self.max_client)
     }
 
     fn get_disputed_tx_id_for_client(&self, client_id: ClientId) -> Option<TransactionId> {
         let disputed_tx_ids = self.state.disputes.get_disputed_tx_ids_by_client(client_id);
-        disputed_tx_ids.iter().next().cloned()
+        // The lowest, rather than whichever the set yields first, so that seeds repeat
+        disputed_tx_ids.iter().min().cloned()
     }
 
     fn get_undisputed_tx_id_for_client(&self, client_id: ClientId) -> Option<TransactionId> {
@@ -86,7 +274,11 @@ impl TransactionGenerator {
         let settled_tx_ids = self.state.disputes.get_settled_tx_ids_by_client(client_id);
         // The set difference yields all elements of the first set but not the second
         let undisputed_tx_ids = &(&all_tx_ids - &disputed_tx_ids) - &settled_tx_ids;
-        undisputed_tx_ids.iter().next().cloned()
+        // Skipping any which failed, e.g. those generated to be invalid
+        undisputed_tx_ids
+            .into_iter()
+            .filter(|&tx_id| self.is_transaction_disputable(client_id, tx_id))
+            .min()
     }
 
     /// Returns true if the (client_id, tx_id) pair is valid and of a disputable type.
@@ -104,10 +296,9 @@ impl TransactionGenerator {
     }
 
     /// Generate a deposit for a random client if possible
-    fn generate_deposit(&self) -> Option<TransactionRecord> {
-        let mut rng = thread_rng();
-        let client_id = self.get_client_id(&mut rng);
-        if let Some(account) = self.state.accounts.get(client_id) {
+    fn generate_deposit(&mut self) -> Option<TransactionRecord> {
+        let client_id = self.get_client_id();
+        if let Some(account) = self.state.accounts.get(client_id, None) {
             if account.locked {
                 return None;
             }
@@ -117,7 +308,9 @@ impl TransactionGenerator {
             let deposit = Deposit {
                 client_id,
                 tx_id: self.tx_id,
-                amount: rng.gen_range(MIN_AMOUNT..self.max_deposit),
+                amount: random_amount(&mut self.rng, self.max_deposit),
+                timestamp: None,
+                currency: None,
             };
             Some(deposit.into())
         } else {
@@ -126,22 +319,18 @@ impl TransactionGenerator {
     }
 
     /// Generate a withdrawal for a random client if possible
-    fn generate_withdrawal(&self) -> Option<TransactionRecord> {
-        let mut rng = thread_rng();
-        let client_id = self.get_client_id(&mut rng);
-        if let Some(account) = self.state.accounts.get(client_id) {
+    fn generate_withdrawal(&mut self) -> Option<TransactionRecord> {
+        let client_id = self.get_client_id();
+        if let Some(account) = self.state.accounts.get(client_id, None) {
             if !account.locked && account.available > MIN_AMOUNT {
-                // Floor here to make sure amount doesn't exceed
-                // the available balance after rounding.
-                let max_amount = floor_currency(account.available);
-                if max_amount > MIN_AMOUNT {
-                    let withdrawal = Withdrawal {
-                        client_id,
-                        tx_id: self.tx_id,
-                        amount: rng.gen_range(MIN_AMOUNT..max_amount),
-                    };
-                    return Some(withdrawal.into());
-                }
+                let withdrawal = Withdrawal {
+                    client_id,
+                    tx_id: self.tx_id,
+                    amount: random_amount(&mut self.rng, account.available),
+                    timestamp: None,
+                    currency: None,
+                };
+                return Some(withdrawal.into());
             }
         }
 
@@ -149,13 +338,19 @@ impl TransactionGenerator {
     }
 
     /// Generate a dispute for a random client if possible
-    fn generate_dispute(&self) -> Option<TransactionRecord> {
-        let mut rng = thread_rng();
-        let client_id = self.get_client_id(&mut rng);
-        if let Some(_) = self.state.accounts.get(client_id) {
+    fn generate_dispute(&mut self) -> Option<TransactionRecord> {
+        let client_id = self.get_client_id();
+        if self.state.accounts.get(client_id, None).is_some() {
             if let Some(tx_id) = self.get_undisputed_tx_id_for_client(client_id) {
                 if self.is_transaction_disputable(client_id, tx_id) {
-                    let dispute = Dispute { client_id, tx_id };
+                    let dispute = Dispute {
+                        client_id,
+                        tx_id,
+                        amount: None,
+                        timestamp: None,
+                        currency: None,
+                        reason: None,
+                    };
                     return Some(dispute.into());
                 }
             }
@@ -164,107 +359,330 @@ impl TransactionGenerator {
     }
 
     /// Generate a resolve for a random client if possible
-    fn generate_resolve(&self) -> Option<TransactionRecord> {
-        let mut rng = thread_rng();
-        let client_id = self.get_client_id(&mut rng);
-        if let Some(_) = self.state.accounts.get(client_id) {
+    fn generate_resolve(&mut self) -> Option<TransactionRecord> {
+        let client_id = self.get_client_id();
+        if self.state.accounts.get(client_id, None).is_some() {
             if let Some(tx_id) = self.get_disputed_tx_id_for_client(client_id) {
-                let resolve = Resolve { client_id, tx_id };
+                let resolve = Resolve {
+                    client_id,
+                    tx_id,
+                    timestamp: None,
+                    currency: None,
+                };
                 return Some(resolve.into());
             }
         }
         None
     }
 
-    fn generate_chargeback(&self) -> Option<TransactionRecord> {
-        let mut rng = thread_rng();
-        let client_id = self.get_client_id(&mut rng);
-        if let Some(_) = self.state.accounts.get(client_id) {
+    fn generate_chargeback(&mut self) -> Option<TransactionRecord> {
+        let client_id = self.get_client_id();
+        if self.state.accounts.get(client_id, None).is_some() {
             if let Some(tx_id) = self.get_disputed_tx_id_for_client(client_id) {
-                let chargeback = Chargeback { client_id, tx_id };
+                let chargeback = Chargeback {
+                    client_id,
+                    tx_id,
+                    timestamp: None,
+                    currency: None,
+                };
                 return Some(chargeback.into());
             }
         }
         None
     }
 
+    /// Generate a transaction for a random client which breaks one of a few rules,
+    /// chosen at random, if possible
+    fn generate_invalid(&mut self) -> Option<TransactionRecord> {
+        let client_id = self.get_client_id();
+        let record = |transaction_type, tx_id, amount| TransactionRecord {
+            transaction_type,
+            client_id,
+            tx_id,
+            amount,
+            timestamp: None,
+            currency: None,
+            reason: None,
+            reverses: None,
+            effective: None,
+        };
+        let max_deposit = self.max_deposit;
+        let some_amount = |rng: &mut _| {
+            if max_deposit > MIN_AMOUNT {
+                random_amount(rng, max_deposit)
+            } else {
+                MIN_AMOUNT
+            }
+        };
+
+        let rng = &mut self.rng;
+        match rng.gen_range(0..5) {
+            // An amount which isn't positive
+            0 => {
+                let amount = -some_amount(rng);
+                Some(record(TransactionType::Deposit, self.tx_id, Some(amount)))
+            }
+            // An id which is already taken
+            1 if self.tx_id > 1 => {
+                let tx_id = rng.gen_range(1..self.tx_id);
+                let amount = some_amount(rng);
+                Some(record(TransactionType::Deposit, tx_id, Some(amount)))
+            }
+            // A dispute of a transaction which doesn't exist
+            2 => Some(record(TransactionType::Dispute, MISSING_TX_ID, None)),
+            // A withdrawal of more than is available
+            3 => {
+                let account = self.state.accounts.get(client_id, None)?;
+                let amount = account.available + some_amount(rng);
+                Some(record(
+                    TransactionType::Withdrawal,
+                    self.tx_id,
+                    Some(amount),
+                ))
+            }
+            // A resolve of a transaction which isn't disputed
+            4 => {
+                let tx_id = self.get_undisputed_tx_id_for_client(client_id)?;
+                Some(record(TransactionType::Resolve, tx_id, None))
+            }
+            _ => None,
+        }
+    }
+
     fn generate_potential_transaction(&mut self) -> Option<TransactionRecord> {
-        let mut rng = thread_rng();
-        let transaction_type: TransactionType = rng.gen();
+        let transaction_type = self.mix.sample(&mut self.rng);
         match transaction_type {
             TransactionType::Deposit => self.generate_deposit(),
             TransactionType::Withdrawal => self.generate_withdrawal(),
             TransactionType::Dispute => self.generate_dispute(),
             TransactionType::Resolve => self.generate_resolve(),
             TransactionType::Chargeback => self.generate_chargeback(),
+            // Administrative transactions and representments aren't part of ordinary traffic,
+            // and interest is only credited by the engine
+            TransactionType::Lock
+            | TransactionType::Unlock
+            | TransactionType::Representment
+            | TransactionType::Reversal
+            | TransactionType::Interest => None,
         }
     }
 }
 
 impl Iterator for TransactionGenerator {
-    type Item = TransactionRecord;
+    type Item = GeneratedTransaction;
 
     fn next(&mut self) -> Option<Self::Item> {
         if let Some(desired) = self.num_tx {
             // Maybe break early
-            if self.tx_id > desired {
+            if self.generated >= desired {
                 return None;
             }
 
-            // Log progress every 10%
-            let tenth = desired / 10;
-            let div = self.tx_id / tenth;
-            let rem = self.tx_id % tenth;
+            // Log progress every 10%, or every transaction if there are fewer than 10
+            let count = self.generated + 1;
+            let tenth = (desired / 10).max(1);
+            let div = count / tenth;
+            let rem = count % tenth;
             if rem == 0 {
-                log::info!("Generating transactions: {}% complete", 10 * div);
+                tracing::info!("Generating transactions: {}% complete", 10 * div);
             }
         }
 
+        let invalid = self.invalid_ratio > 0.0 && self.rng.gen_bool(self.invalid_ratio);
         // NOTE: it's possible that all accounts are locked, all disputes are resolve,
         // and no further transactions can be generated.
         for _ in 0..self.max_attempts {
-            if let Some(tx) = self.generate_potential_transaction() {
-                handle_transaction(tx.clone(), &mut self.state)
-                    .expect("Generated invalid transaction");
-                self.tx_id += 1;
-                return Some(tx);
+            let tx = if invalid {
+                self.generate_invalid()
+            } else {
+                self.generate_potential_transaction()
+            };
+            if let Some(tx) = tx {
+                // The engine's own verdict, so that expected errors always match.
+                // An attempt at an invalid transaction may turn out to be valid,
+                // e.g. a deposit reusing an id which was never taken.
+                let result = handle_transaction(tx.clone(), &mut self.state);
+                if let (false, Err(err)) = (invalid, &result) {
+                    panic!("Generated invalid transaction: {}", err);
+                }
+                if tx.tx_id == self.tx_id {
+                    self.tx_id += 1;
+                }
+                self.generated += 1;
+                return Some(GeneratedTransaction {
+                    record: tx,
+                    expected_error: result.err(),
+                });
             }
         }
 
-        log::error!("Reached max attempts to generate new transaction.");
+        tracing::error!("Reached max attempts to generate new transaction.");
 
         None
     }
 }
 
-/// Generate a random sequence of valid transactions.
+/// Generate a random sequence of valid transactions,
+/// with types in the proportions given by `mix`.
 pub fn generate_random_valid_transaction_sequence(
     num_tx: Option<TransactionId>,
     max_client: ClientId,
-    max_deposit: CurrencyFloat,
+    max_deposit: Currency,
     max_attempts: usize,
+    mix: TransactionMix,
 ) -> impl Iterator<Item = TransactionRecord> {
-    let generator = TransactionGenerator::new(num_tx, max_client, max_deposit, max_attempts);
-    generator.into_iter()
+    generate_random_transaction_sequence(num_tx, max_client, max_deposit, max_attempts, mix, 0.0)
+        .map(|generated| generated.record)
+}
+
+/// Like `generate_random_valid_transaction_sequence`, but with roughly `invalid_ratio`
+/// of transactions deliberately breaking a rule, e.g. amounts which aren't positive,
+/// reused ids, or disputes of transactions which don't exist, each along with
+/// the error the engine is expected to reject it with, e.g. for negative test fixtures.
+pub fn generate_random_transaction_sequence(
+    num_tx: Option<TransactionId>,
+    max_client: ClientId,
+    max_deposit: Currency,
+    max_attempts: usize,
+    mix: TransactionMix,
+    invalid_ratio: f64,
+) -> TransactionGenerator {
+    TransactionGenerator::new(
+        num_tx,
+        max_client,
+        max_deposit,
+        max_attempts,
+        mix,
+        invalid_ratio,
+    )
 }
 
 #[cfg(test)]
 mod tests {
-    use super::TransactionGenerator;
+    use super::{validate_invalid_ratio, GeneratedTransaction};
+    use super::{TransactionFormat, TransactionGenerator, TransactionWeights, TransactionWriter};
+    use crate::currency::Currency;
     use crate::handlers::handle_transaction;
     use crate::state::State;
+    use crate::test_utils::{deposit, dispute};
+    use crate::types::{TransactionRecord, TransactionType};
 
     #[test]
     fn test_transaction_sequence_is_valid() {
         let num_tx = Some(10000);
         let max_client = 300;
-        let max_deposit = 500.0;
+        let max_deposit = Currency::from(500.0);
         let max_attempts = 10_000;
-        let generator = TransactionGenerator::new(num_tx, max_client, max_deposit, max_attempts);
+        let mix = TransactionWeights::default().distribution().unwrap();
+        let generator =
+            TransactionGenerator::new(num_tx, max_client, max_deposit, max_attempts, mix, 0.0);
         let mut state = State::new();
-        for record in generator {
-            let result = handle_transaction(record, &mut state);
-            assert!(matches!(result, Ok(_)))
+        for generated in generator {
+            assert_eq!(generated.expected_error, None);
+            let result = handle_transaction(generated.record, &mut state);
+            assert!(result.is_ok())
         }
     }
+
+    #[test]
+    fn test_dispute_heavy_mix() {
+        let weights: TransactionWeights = "withdrawal=0,dispute=30,resolve=0,chargeback=0"
+            .parse()
+            .unwrap();
+        assert_eq!(weights.deposit, 50.0);
+        let mix = weights.distribution().unwrap();
+        let generator =
+            TransactionGenerator::new(Some(2000), 10, Currency::from(100.0), 1000, mix, 0.0);
+
+        let mut disputes = 0;
+        for GeneratedTransaction { record, .. } in generator {
+            match record.transaction_type {
+                TransactionType::Deposit => {}
+                TransactionType::Dispute => disputes += 1,
+                other => panic!("Generated a {} despite its zero weight", other.name()),
+            }
+        }
+        // Around 3 in 8, since disputes are nearly always possible
+        assert!(disputes > 500, "only {} disputes", disputes);
+    }
+
+    #[test]
+    fn test_parse_weights() {
+        assert_eq!("".parse::<TransactionWeights>().ok(), None);
+        assert!("dispute=-1".parse::<TransactionWeights>().is_err());
+        assert!("lock=1".parse::<TransactionWeights>().is_err());
+        let all_zero = "deposit=0,withdrawal=0,dispute=0,resolve=0,chargeback=0";
+        assert!(all_zero.parse::<TransactionWeights>().is_err());
+    }
+
+    #[test]
+    fn test_invalid_transactions_fail_as_expected() {
+        let mix = TransactionWeights::default().distribution().unwrap();
+        let generator =
+            TransactionGenerator::new(Some(2000), 20, Currency::from(100.0), 1000, mix, 0.2);
+        let mut state = State::new();
+        let mut invalid = 0;
+        let mut codes = std::collections::HashSet::new();
+        for generated in generator {
+            let result = handle_transaction(generated.record, &mut state);
+            assert_eq!(result.err(), generated.expected_error);
+            if let Some(err) = generated.expected_error {
+                invalid += 1;
+                codes.insert(err.code());
+            }
+        }
+        // Roughly a fifth, and of every kind
+        assert!((300..500).contains(&invalid), "{} invalid", invalid);
+        for code in [
+            "AMOUNT_NOT_POSITIVE",
+            "DUPLICATE_TX",
+            "TX_NOT_FOUND",
+            "INSUFFICIENT_FUNDS",
+            "TX_NOT_DISPUTED",
+        ] {
+            assert!(codes.contains(code), "no {} in {:?}", code, codes);
+        }
+    }
+
+    #[test]
+    fn test_seed_repeats_transactions() {
+        let generate = |seed| {
+            let mix = TransactionWeights::default().distribution().unwrap();
+            TransactionGenerator::new(Some(200), 10, Currency::from(100.0), 1000, mix, 0.1)
+                .with_seed(seed)
+                .map(|generated| generated.record)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(generate(7), generate(7));
+        assert_ne!(generate(7), generate(8));
+    }
+
+    #[test]
+    fn test_validate_invalid_ratio() {
+        assert_eq!(validate_invalid_ratio(0.0), Ok(0.0));
+        assert_eq!(validate_invalid_ratio(1.0), Ok(1.0));
+        assert!(validate_invalid_ratio(1.5).is_err());
+        assert!(validate_invalid_ratio(-0.1).is_err());
+        assert!(validate_invalid_ratio(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn test_write_jsonl() {
+        let records = vec![deposit(1, 1, 1.5), dispute(1, 1)];
+        let mut writer = TransactionWriter::new(Vec::new(), TransactionFormat::Jsonl);
+        for record in &records {
+            writer.write(record).unwrap();
+        }
+        let output = match writer {
+            TransactionWriter::Jsonl(writer) => writer.into_inner().unwrap(),
+            TransactionWriter::Csv(_) => unreachable!(),
+        };
+
+        let read: Vec<TransactionRecord> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(read, records);
+    }
 }
@@ -0,0 +1,256 @@
+//! Triage report of the accounts operations should look at after a batch run:
+//! the largest, and those with negative balances, locks, or open disputes.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io;
+use std::str::FromStr;
+
+use crate::currency::{Currency, CurrencyCode};
+use crate::state::{AccountOrder, State};
+use crate::traits::Transaction;
+use crate::types::{Account, AccountKey, ClientId, InvalidAccount, LockReason, OutputRecord};
+
+/// Format to write a report in.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ReportFormat {
+    /// One row per account in each section, with the section's name first.
+    #[default]
+    Csv,
+    /// A single object with a list of accounts for each section.
+    Json,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            other => Err(format!("unknown report format '{}'", other)),
+        }
+    }
+}
+
+/// Section of a report, which an account may appear in several of.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportSection {
+    Largest,
+    Negative,
+    Locked,
+    Disputed,
+}
+
+/// An account on a report, with its balances rounded as for output.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ReportLine {
+    pub client: ClientId,
+    pub currency: Option<CurrencyCode>,
+    pub available: Currency,
+    pub held: Currency,
+    pub total: Currency,
+    pub locked: bool,
+    pub lock_reason: Option<LockReason>,
+    /// Number of the account's disputes still open
+    pub open_disputes: usize,
+}
+
+/// Accounts needing attention, in sections. Accounts are listed by client
+/// within each section, except for the largest.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct AccountsReport {
+    /// Accounts with the largest totals in each currency, largest first
+    pub largest: Vec<ReportLine>,
+    /// Accounts whose available funds are below zero
+    pub negative: Vec<ReportLine>,
+    pub locked: Vec<ReportLine>,
+    /// Accounts with at least one open dispute
+    pub disputed: Vec<ReportLine>,
+}
+
+impl AccountsReport {
+    /// Report on a state's accounts, listing the `top` largest in each currency.
+    /// Fails if one of their balances can't be written.
+    pub fn new(state: &State, top: usize) -> Result<Self, InvalidAccount> {
+        // Disputes are kept by client, so each is counted against
+        // the account in its transaction's currency
+        let mut open_disputes: HashMap<AccountKey, usize> = HashMap::new();
+        for (client, tx, _) in state.disputes.active() {
+            if let Some(Ok(Ok(disputed_tx))) = state
+                .transactions
+                .get(client, tx)
+                .map(|transaction| transaction.try_get_disputable())
+            {
+                *open_disputes
+                    .entry((client, disputed_tx.get_currency()))
+                    .or_default() += 1;
+            }
+        }
+
+        let lines: Vec<_> = state
+            .accounts
+            .ordered(AccountOrder::Client)
+            .into_iter()
+            .map(|(&key, account)| {
+                let balance = OutputRecord::new(key, account, &state.policies.rounding)?;
+                let line = ReportLine {
+                    client: balance.client,
+                    currency: balance.currency,
+                    available: balance.available,
+                    held: balance.held,
+                    total: balance.total,
+                    locked: balance.locked,
+                    lock_reason: balance.lock_reason,
+                    open_disputes: open_disputes.get(&key).copied().unwrap_or(0),
+                };
+                Ok((account, line))
+            })
+            .collect::<Result<_, InvalidAccount>>()?;
+        let section = |include: fn(&Account, &ReportLine) -> bool| -> Vec<_> {
+            lines
+                .iter()
+                .filter(|(account, line)| include(account, line))
+                .map(|(_, line)| line.clone())
+                .collect()
+        };
+
+        let mut largest = section(|_, _| true);
+        largest
+            .sort_by(|a, b| (a.currency, b.total, a.client).cmp(&(b.currency, a.total, b.client)));
+        let mut ranks: HashMap<Option<CurrencyCode>, usize> = HashMap::new();
+        largest.retain(|line| {
+            let rank = ranks.entry(line.currency).or_default();
+            *rank += 1;
+            *rank <= top
+        });
+
+        Ok(Self {
+            largest,
+            negative: section(|account, _| account.available().is_negative()),
+            locked: section(|account, _| account.locked()),
+            disputed: section(|_, line| line.open_disputes > 0),
+        })
+    }
+
+    /// Each section's name, with its accounts, in order.
+    pub fn sections(&self) -> [(ReportSection, &[ReportLine]); 4] {
+        [
+            (ReportSection::Largest, &self.largest),
+            (ReportSection::Negative, &self.negative),
+            (ReportSection::Locked, &self.locked),
+            (ReportSection::Disputed, &self.disputed),
+        ]
+    }
+
+    /// Write the report in the given format.
+    pub fn write<W: io::Write>(
+        &self,
+        format: ReportFormat,
+        mut output_stream: W,
+    ) -> Result<(), Box<dyn Error>> {
+        match format {
+            ReportFormat::Csv => self.write_csv(output_stream),
+            ReportFormat::Json => {
+                serde_json::to_writer_pretty(&mut output_stream, self)?;
+                writeln!(output_stream)?;
+                Ok(())
+            }
+        }
+    }
+
+    fn write_csv<W: io::Write>(&self, output_stream: W) -> Result<(), Box<dyn Error>> {
+        let mut writer = csv::Writer::from_writer(output_stream);
+        for (section, lines) in self.sections() {
+            for line in lines {
+                writer.serialize(ReportRow {
+                    section,
+                    client: line.client,
+                    currency: line.currency,
+                    available: line.available,
+                    held: line.held,
+                    total: line.total,
+                    locked: line.locked,
+                    lock_reason: line.lock_reason,
+                    open_disputes: line.open_disputes,
+                })?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// A row of a CSV report: an account, and the section it's listed in.
+#[derive(Serialize)]
+struct ReportRow {
+    section: ReportSection,
+    client: ClientId,
+    currency: Option<CurrencyCode>,
+    available: Currency,
+    held: Currency,
+    total: Currency,
+    locked: bool,
+    lock_reason: Option<LockReason>,
+    open_disputes: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AccountsReport, ReportFormat};
+    use crate::currency::Currency;
+    use crate::policy::Policies;
+    use crate::state::State;
+    use crate::test_utils::{chargeback, deposit, dispute, withdrawal};
+
+    fn report() -> AccountsReport {
+        let mut policies = Policies::default();
+        policies.credit.default_limit = Currency::from(5.0);
+        let mut state = State::with_policies(policies);
+        for record in [
+            deposit(1, 1, 5.0),
+            deposit(2, 2, 20.0),
+            deposit(3, 3, 10.0),
+            withdrawal(4, 4, 2.0),
+            dispute(3, 3),
+            deposit(5, 5, 1.0),
+            dispute(5, 5),
+            chargeback(5, 5),
+        ] {
+            state.handle(record).unwrap();
+        }
+        AccountsReport::new(&state, 2).unwrap()
+    }
+
+    #[test]
+    fn test_report_sections() {
+        let report = report();
+        let clients = |lines: &[super::ReportLine]| -> Vec<_> {
+            lines.iter().map(|line| line.client).collect()
+        };
+        assert_eq!(clients(&report.largest), vec![2, 3]);
+        assert_eq!(clients(&report.negative), vec![4]);
+        assert_eq!(clients(&report.locked), vec![5]);
+        assert_eq!(clients(&report.disputed), vec![3]);
+        assert_eq!(report.disputed[0].open_disputes, 1);
+    }
+
+    #[test]
+    fn test_report_csv() {
+        let mut output = Vec::new();
+        report().write(ReportFormat::Csv, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "\
+section,client,currency,available,held,total,locked,lock_reason,open_disputes
+largest,2,,20.0,0.0,20.0,false,,0
+largest,3,,0.0,10.0,10.0,false,,1
+negative,4,,-2.0,0.0,-2.0,false,,0
+locked,5,,0.0,0.0,0.0,true,chargeback,0
+disputed,3,,0.0,10.0,10.0,false,,1
+"
+        );
+    }
+}
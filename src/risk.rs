@@ -0,0 +1,244 @@
+//! Fraud scoring of deposits and withdrawals as they're validated, so that
+//! suspicious ones can be rejected, or their accounts flagged for review.
+//!
+//! The `RiskPolicy` is a simple rules-based scorer, configured along with the
+//! other policies. Applications can plug in their own, e.g. a trained model,
+//! by adding it to a state's `risk_scorers` before it's handed to the pipeline,
+//! which calls it from whichever handler thread owns each client, as it does
+//! observers. When there are several, the most severe decision wins.
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::currency::Currency;
+use crate::policy::RiskPolicy;
+use crate::state::AccountView;
+use crate::types::{TransactionRecord, TransactionType};
+
+/// Decides what to do with deposits and withdrawals.
+pub trait RiskScorer: Send + Sync {
+    /// Called with each deposit or withdrawal which passed every other check,
+    /// and its account as it is beforehand, which is empty if it's new.
+    fn score(&self, record: &TransactionRecord, account: &AccountView) -> RiskDecision;
+}
+
+/// What to do with a transaction, given its risk.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RiskDecision {
+    Allow,
+    /// Accept the transaction, but flag its account for review, saying why.
+    Flag(String),
+    /// Reject the transaction with `TransactionError::RiskRejected`, saying why.
+    Reject(String),
+}
+
+impl RiskDecision {
+    fn severity(&self) -> u8 {
+        match self {
+            Self::Allow => 0,
+            Self::Flag(_) => 1,
+            Self::Reject(_) => 2,
+        }
+    }
+}
+
+impl RiskPolicy {
+    /// Whether any transaction could be flagged or rejected.
+    pub fn is_enabled(&self) -> bool {
+        self.flag_score.is_some() || self.reject_score.is_some()
+    }
+}
+
+impl RiskScorer for RiskPolicy {
+    /// Add up the points of each rule the transaction matches, out of 100,
+    /// and compare the score to the thresholds.
+    fn score(&self, record: &TransactionRecord, account: &AccountView) -> RiskDecision {
+        if !self.is_enabled() {
+            return RiskDecision::Allow;
+        }
+        let amount = record.amount.unwrap_or(Currency::ZERO);
+        let is_withdrawal = record.transaction_type == TransactionType::Withdrawal;
+        let rules = [
+            (
+                "large amount",
+                self.large_amount.is_some_and(|large| amount > large),
+                self.large_amount_points,
+            ),
+            (
+                "empty account",
+                account.total() == Ok(Currency::ZERO),
+                self.empty_account_points,
+            ),
+            (
+                "drains account",
+                is_withdrawal && account.available.is_positive() && amount >= account.available,
+                self.draining_points,
+            ),
+            (
+                "flagged account",
+                account.flagged,
+                self.flagged_account_points,
+            ),
+        ];
+        let matched: Vec<_> = rules.iter().filter(|(_, matches, _)| *matches).collect();
+        let score = matched
+            .iter()
+            .map(|(_, _, points)| points)
+            .sum::<u32>()
+            .min(100);
+        let reason = || {
+            let names: Vec<_> = matched.iter().map(|(name, _, _)| *name).collect();
+            format!("scored {}: {}", score, names.join(", "))
+        };
+        if self.reject_score.is_some_and(|reject| score >= reject) {
+            RiskDecision::Reject(reason())
+        } else if self.flag_score.is_some_and(|flag| score >= flag) {
+            RiskDecision::Flag(reason())
+        } else {
+            RiskDecision::Allow
+        }
+    }
+}
+
+/// Scorers plugged into a state, called in the order they were added,
+/// after the state's `RiskPolicy`.
+#[derive(Clone, Default)]
+pub struct RiskScorers(Vec<Arc<dyn RiskScorer>>);
+
+impl RiskScorers {
+    pub fn add(&mut self, scorer: Arc<dyn RiskScorer>) {
+        self.0.push(scorer);
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Whether any scorer, including the policy, might flag or reject a transaction,
+    /// so that the transaction needs scoring at all.
+    pub(crate) fn is_active(&self, policy: &RiskPolicy) -> bool {
+        policy.is_enabled() || !self.is_empty()
+    }
+
+    /// The most severe decision of the policy and every plugged in scorer,
+    /// or the first of those which are equally severe.
+    pub(crate) fn score(
+        &self,
+        policy: &RiskPolicy,
+        record: &TransactionRecord,
+        account: &AccountView,
+    ) -> RiskDecision {
+        let mut decision = policy.score(record, account);
+        for scorer in &self.0 {
+            let other = scorer.score(record, account);
+            if other.severity() > decision.severity() {
+                decision = other;
+            }
+        }
+        decision
+    }
+}
+
+impl fmt::Debug for RiskScorers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RiskScorers({})", self.0.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RiskDecision, RiskScorer, RiskScorers};
+    use crate::currency::Currency;
+    use crate::policy::RiskPolicy;
+    use crate::state::{AccountView, State};
+    use crate::test_utils::{deposit, withdrawal};
+    use crate::types::{TransactionError, TransactionRecord};
+    use std::sync::Arc;
+
+    fn policy() -> RiskPolicy {
+        RiskPolicy {
+            flag_score: Some(50),
+            reject_score: Some(90),
+            large_amount: Some(Currency::from(100.0)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_rules_score() {
+        let policy = policy();
+        let mut state = State::new();
+        state.policies.risk = policy.clone();
+        state.handle(deposit(1, 1, 10.0)).unwrap();
+        let account = state.account(1).unwrap();
+
+        assert_eq!(
+            policy.score(&deposit(1, 2, 5.0), &account),
+            RiskDecision::Allow
+        );
+        assert_eq!(
+            policy.score(&deposit(1, 2, 500.0), &account),
+            RiskDecision::Flag("scored 50: large amount".to_string())
+        );
+        assert_eq!(
+            policy.score(&withdrawal(1, 2, 10.0), &account),
+            RiskDecision::Allow
+        );
+        let flagged = AccountView {
+            flagged: true,
+            ..account
+        };
+        assert_eq!(
+            policy.score(&withdrawal(1, 2, 500.0), &flagged),
+            RiskDecision::Reject(
+                "scored 100: large amount, drains account, flagged account".to_string()
+            )
+        );
+        assert_eq!(
+            RiskPolicy::default().score(&withdrawal(1, 2, 500.0), &flagged),
+            RiskDecision::Allow
+        );
+    }
+
+    /// Rejects every deposit and withdrawal from client 2.
+    struct Blocklist;
+
+    impl RiskScorer for Blocklist {
+        fn score(&self, record: &TransactionRecord, _account: &AccountView) -> RiskDecision {
+            if record.client_id == 2 {
+                RiskDecision::Reject("blocked".to_string())
+            } else {
+                RiskDecision::Allow
+            }
+        }
+    }
+
+    #[test]
+    fn test_plugged_in_scorer() {
+        let mut state = State::new();
+        state.policies.risk = policy();
+        state.risk_scorers.add(Arc::new(Blocklist));
+
+        // Flagged by the policy, which the plugged in scorer doesn't override
+        assert_eq!(state.handle(deposit(1, 1, 500.0)), Ok(()));
+        assert!(state.account(1).unwrap().flagged);
+
+        assert_eq!(
+            state.handle(deposit(2, 2, 5.0)),
+            Err(TransactionError::RiskRejected {
+                client: 2,
+                tx: 2,
+                reason: "blocked".to_string()
+            })
+        );
+        // Rejected deposits don't open accounts
+        assert_eq!(state.account(2), None);
+
+        let scorers = RiskScorers::default();
+        assert!(!scorers.is_active(&RiskPolicy::default()));
+    }
+}
@@ -0,0 +1,105 @@
+//! Scheduled transactions, which take effect at a later time than they're sent,
+//! given by their `effective` column.
+//!
+//! The pipeline's router keeps a clock of the latest timestamp it has read.
+//! A transaction which isn't effective yet is rejected with `NotYetEffective`,
+//! unless the policies say to hold it, in which case it's held until a later
+//! record's timestamp reaches its effective time, and applied just before that record.
+//! Since the router sees every record in order, the outcome is the same
+//! however the clients are divided between handlers.
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::{Timestamp, TransactionRecord};
+
+/// Transactions held until they take effect, and the clock they're held against.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Schedule {
+    /// Latest timestamp read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    now: Option<Timestamp>,
+    /// Held transactions, in the order they take effect, then the order they were read
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    held: Vec<TransactionRecord>,
+}
+
+impl Schedule {
+    /// Latest timestamp read, if any.
+    pub fn now(&self) -> Option<Timestamp> {
+        self.now
+    }
+
+    /// Move the clock forward to a record's timestamp, if it has a later one.
+    pub fn advance(&mut self, timestamp: Option<Timestamp>) {
+        self.now = self.now.max(timestamp);
+    }
+
+    /// Whether a transaction may be applied now: it isn't scheduled,
+    /// or the clock has reached its effective time.
+    pub fn is_effective(&self, record: &TransactionRecord) -> bool {
+        record
+            .effective
+            .is_none_or(|effective| self.now.is_some_and(|now| effective <= now))
+    }
+
+    /// Hold a transaction until it takes effect.
+    pub fn hold(&mut self, record: TransactionRecord) {
+        let index = self
+            .held
+            .partition_point(|held| held.effective <= record.effective);
+        self.held.insert(index, record);
+    }
+
+    /// Take every held transaction which has taken effect, in order.
+    pub fn release(&mut self) -> Vec<TransactionRecord> {
+        let due = self.held.partition_point(|held| self.is_effective(held));
+        self.held.drain(..due).collect()
+    }
+
+    /// Transactions still held, in the order they take effect.
+    pub fn held(&self) -> &[TransactionRecord] {
+        &self.held
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.now.is_none() && self.held.is_empty()
+    }
+
+    /// Combine with another schedule, e.g. restored from a checkpoint.
+    pub fn extend(&mut self, other: Schedule) {
+        self.advance(other.now);
+        for record in other.held {
+            self.hold(record);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Schedule;
+    use crate::test_utils::{deposit, withdrawal};
+
+    #[test]
+    fn test_release_in_effective_order() {
+        let mut schedule = Schedule::default();
+        let scheduled = deposit(1, 1, 5.0).with_effective(200);
+        // Nothing is effective before the clock starts
+        assert!(!schedule.is_effective(&scheduled));
+        assert!(schedule.is_effective(&deposit(1, 2, 5.0)));
+
+        schedule.advance(Some(100));
+        schedule.hold(scheduled);
+        schedule.hold(withdrawal(1, 3, 1.0).with_effective(150));
+        schedule.hold(withdrawal(1, 4, 2.0).with_effective(200));
+        assert!(schedule.release().is_empty());
+
+        // The clock never goes back
+        schedule.advance(Some(50));
+        assert_eq!(schedule.now(), Some(100));
+
+        schedule.advance(Some(200));
+        let released: Vec<_> = schedule.release().iter().map(|r| r.tx_id).collect();
+        assert_eq!(released, vec![3, 1, 4]);
+        assert!(schedule.held().is_empty());
+    }
+}
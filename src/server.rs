@@ -0,0 +1,240 @@
+//! A small HTTP service on top of a shared `State`,
+//! e.g. to stand in for a payments backend in integration testing environments.
+//!
+//! - `POST /transactions` handles a transaction, given as JSON with the same
+//!   fields as a CSV row, and responds with the account's new balances,
+//!   or with the `TransactionError` if it's rejected.
+//! - `GET /accounts` lists every account's balances.
+//! - `GET /accounts/{client}` lists one client's balances, one per currency.
+//! - `GET /locks` lists every locked account, with why and when it was locked.
+//! - `GET /ws` upgrades to a WebSocket, which handles a transaction
+//!   for each JSON frame received, and replies to each with an `Ack`.
+//!
+//! Amounts are strings, e.g. `"1.5"`, so that they're exact.
+
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{self, Json, Path};
+use axum::http::StatusCode;
+use axum::response::Response;
+use axum::routing::{get, post};
+use axum::Router;
+
+pub use crate::service::{Ack, LockRecord, SharedState};
+use crate::types::{ClientId, InvalidAccount, OutputRecord, TransactionError, TransactionRecord};
+
+/// Routes for the service, handling transactions against `state`.
+pub fn router(state: SharedState) -> Router {
+    Router::new()
+        .route("/transactions", post(submit_transaction))
+        .route("/accounts", get(list_accounts))
+        .route("/accounts/{client}", get(client_accounts))
+        .route("/locks", get(list_locks))
+        .route("/ws", get(upgrade_websocket))
+        .with_state(state)
+}
+
+/// Handle a transaction, responding with its account's updated balances.
+async fn submit_transaction(
+    extract::State(state): extract::State<SharedState>,
+    Json(record): Json<TransactionRecord>,
+) -> Result<Json<OutputRecord>, (StatusCode, Json<TransactionError>)> {
+    state.submit(record).map(Json).map_err(|err| {
+        let status = match err {
+            TransactionError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::UNPROCESSABLE_ENTITY,
+        };
+        (status, Json(err))
+    })
+}
+
+/// Balances of every account, by client id.
+async fn list_accounts(
+    extract::State(state): extract::State<SharedState>,
+) -> Result<Json<Vec<OutputRecord>>, StatusCode> {
+    state.balances(None).map(Json).map_err(invalid_account)
+}
+
+/// Balances of each of a client's accounts.
+async fn client_accounts(
+    extract::State(state): extract::State<SharedState>,
+    Path(client): Path<ClientId>,
+) -> Result<Json<Vec<OutputRecord>>, StatusCode> {
+    let records = state.balances(Some(client)).map_err(invalid_account)?;
+    if records.is_empty() {
+        Err(StatusCode::NOT_FOUND)
+    } else {
+        Ok(Json(records))
+    }
+}
+
+/// Accounts are kept so their balances can be written, so one that can't is a server error.
+fn invalid_account(err: InvalidAccount) -> StatusCode {
+    tracing::error!("Could not write balances: {}", err);
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+/// Every locked account, with why and when it was locked, by client id.
+async fn list_locks(extract::State(state): extract::State<SharedState>) -> Json<Vec<LockRecord>> {
+    Json(state.locks(None))
+}
+
+/// Upgrade to a WebSocket for streaming transactions.
+async fn upgrade_websocket(
+    extract::State(state): extract::State<SharedState>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    upgrade.on_upgrade(move |socket| stream_transactions(socket, state))
+}
+
+/// Handle transactions from a WebSocket in order, until it's closed.
+async fn stream_transactions(mut socket: WebSocket, state: SharedState) {
+    while let Some(Ok(message)) = socket.recv().await {
+        let ack = match message {
+            Message::Text(text) => state.acknowledge(text.as_bytes()),
+            Message::Binary(bytes) => state.acknowledge(&bytes),
+            Message::Close(_) => break,
+            // Pings are answered automatically
+            Message::Ping(_) | Message::Pong(_) => continue,
+        };
+        let reply = match serde_json::to_string(&ack) {
+            Ok(reply) => reply,
+            Err(err) => {
+                tracing::error!("error serializing acknowledgement: {}", err);
+                break;
+            }
+        };
+        if socket.send(Message::Text(reply.into())).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{router, SharedState};
+    use crate::state::State;
+    use axum::body::{to_bytes, Body};
+    use axum::http::{Request, StatusCode};
+    use futures_util::{SinkExt, StreamExt};
+    use serde_json::{json, Value};
+    use std::future::IntoFuture;
+    use tokio_tungstenite::connect_async;
+    use tokio_tungstenite::tungstenite::Message;
+    use tower::ServiceExt;
+
+    async fn send(state: &SharedState, request: Request<Body>) -> (StatusCode, Value) {
+        let response = router(state.clone()).oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+        (status, value)
+    }
+
+    fn post(body: Value) -> Request<Body> {
+        Request::post("/transactions")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    fn get(uri: &str) -> Request<Body> {
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_submit_transactions() {
+        let state = SharedState::new(State::new());
+
+        let deposit = json!({"type": "deposit", "client": 1, "tx": 1, "amount": "2.5"});
+        assert_eq!(
+            send(&state, post(deposit)).await,
+            (
+                StatusCode::OK,
+                json!({"client": 1, "available": "2.5", "held": "0.0", "total": "2.5", "locked": false})
+            )
+        );
+
+        let withdrawal = json!({"type": "withdrawal", "client": 1, "tx": 2, "amount": "5.0"});
+        let (status, error) = send(&state, post(withdrawal)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(error["code"], "INSUFFICIENT_FUNDS");
+        assert_eq!(state.lock().rejections.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_get_accounts() {
+        let state = SharedState::new(State::new());
+        for (client, tx) in [(2, 1), (1, 2)] {
+            let deposit = json!({"type": "deposit", "client": client, "tx": tx, "amount": "1.0"});
+            send(&state, post(deposit)).await;
+        }
+
+        let (status, accounts) = send(&state, get("/accounts")).await;
+        assert_eq!(status, StatusCode::OK);
+        let clients: Vec<_> = accounts
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|account| account["client"].as_u64().unwrap())
+            .collect();
+        assert_eq!(clients, vec![1, 2]);
+
+        let (status, accounts) = send(&state, get("/accounts/2")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(accounts.as_array().unwrap().len(), 1);
+
+        let (status, _) = send(&state, get("/accounts/3")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_get_locks() {
+        let state = SharedState::new(State::new());
+        for transaction in [
+            json!({"type": "deposit", "client": 1, "tx": 1, "amount": "1.0"}),
+            json!({"type": "deposit", "client": 2, "tx": 2, "amount": "1.0"}),
+            json!({"type": "dispute", "client": 2, "tx": 2}),
+            json!({"type": "chargeback", "client": 2, "tx": 2, "timestamp": 100}),
+        ] {
+            send(&state, post(transaction)).await;
+        }
+
+        let (status, locks) = send(&state, get("/locks")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            locks,
+            json!([{"client": 2, "reason": "chargeback", "tx": 2, "timestamp": 100}])
+        );
+
+        let (_, accounts) = send(&state, get("/accounts/2")).await;
+        assert_eq!(accounts[0]["lock_reason"], "chargeback");
+    }
+
+    #[tokio::test]
+    async fn test_websocket() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let state = SharedState::new(State::new());
+        tokio::spawn(axum::serve(listener, router(state)).into_future());
+
+        let (mut socket, _) = connect_async(format!("ws://{}/ws", address)).await.unwrap();
+        let frames = [
+            json!({"type": "deposit", "client": 1, "tx": 1, "amount": "2.5"}).to_string(),
+            json!({"type": "withdrawal", "client": 1, "tx": 2, "amount": "5.0"}).to_string(),
+            "not a transaction".to_string(),
+        ];
+        let mut acks = Vec::new();
+        for frame in frames {
+            socket.send(Message::text(frame)).await.unwrap();
+            let reply = socket.next().await.unwrap().unwrap();
+            acks.push(serde_json::from_str::<Value>(reply.to_text().unwrap()).unwrap());
+        }
+
+        assert_eq!(acks[0]["status"], "ack");
+        assert_eq!(acks[0]["balance"]["available"], "2.5");
+        assert_eq!(acks[1]["status"], "nack");
+        assert_eq!(acks[1]["tx"], 2);
+        assert_eq!(acks[1]["code"], "INSUFFICIENT_FUNDS");
+        assert_eq!(acks[2]["status"], "invalid");
+    }
+}
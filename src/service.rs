@@ -0,0 +1,313 @@
+//! Engine state shared between the requests of a long-running service,
+//! which handles transactions one at a time as they arrive,
+//! rather than reading them from files.
+
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockWriteGuard};
+
+use serde::Serialize;
+
+use crate::currency::CurrencyCode;
+use crate::policy::RoundingPolicy;
+use crate::simulate::{simulate_in, ProcessingReport};
+use crate::state::{AccountOrder, AccountsState, State};
+use crate::telemetry;
+use crate::types::{
+    AccountKey, ClientId, InvalidAccount, OutputRecord, Rejection, TransactionError, TransactionId,
+};
+use crate::types::{LockInfo, TransactionRecord};
+
+/// Reply to a transaction given as JSON, e.g. a WebSocket frame or a line of input.
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Ack {
+    /// The transaction succeeded, leaving the account with these balances.
+    Ack {
+        tx: TransactionId,
+        balance: OutputRecord,
+    },
+    /// The transaction was rejected, with the error's `code` and `details`.
+    Nack {
+        tx: TransactionId,
+        #[serde(flatten)]
+        error: TransactionError,
+        message: String,
+    },
+    /// The input couldn't be read as a transaction.
+    Invalid { message: String },
+}
+
+/// A locked account, with why and when it was locked, if that's known.
+#[derive(Debug, Serialize)]
+pub struct LockRecord {
+    pub client: ClientId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<CurrencyCode>,
+    #[serde(flatten)]
+    pub lock_info: Option<LockInfo>,
+}
+
+/// Balances as they were at some point, which readers can take their time over
+/// while transactions carry on being handled.
+#[derive(Clone, Debug, Default)]
+pub struct Snapshot {
+    pub accounts: AccountsState,
+    pub rounding: RoundingPolicy,
+}
+
+impl Snapshot {
+    fn of(state: &State) -> Self {
+        Self {
+            accounts: state.accounts.clone(),
+            rounding: state.policies.rounding,
+        }
+    }
+
+    /// Rounded balances of every account, or only the given client's, by client id,
+    /// or why one of them can't be written.
+    pub fn balances(&self, client: Option<ClientId>) -> Result<Vec<OutputRecord>, InvalidAccount> {
+        self.accounts
+            .ordered(AccountOrder::Client)
+            .into_iter()
+            .filter(|((client_id, _), _)| client.is_none_or(|client| *client_id == client))
+            .map(|(&key, account)| OutputRecord::new(key, account, &self.rounding))
+            .collect()
+    }
+
+    /// Every locked account, or only the given client's, by client id,
+    /// e.g. for operations teams to follow up on.
+    pub fn locks(&self, client: Option<ClientId>) -> Vec<LockRecord> {
+        self.accounts
+            .ordered(AccountOrder::Client)
+            .into_iter()
+            .filter(|((client_id, _), account)| {
+                account.locked() && client.is_none_or(|client| *client_id == client)
+            })
+            .map(|(&(client, currency), account)| LockRecord {
+                client,
+                currency,
+                lock_info: account.lock_info().cloned(),
+            })
+            .collect()
+    }
+}
+
+/// Engine state shared between requests.
+///
+/// Reads of balances go to a snapshot rather than the state itself,
+/// so they never wait for transactions being handled, nor hold them up.
+/// Each transaction updates the snapshot copy-on-write: in place, unless
+/// a reader still has it, in which case the reader keeps the old one.
+#[derive(Clone)]
+pub struct SharedState {
+    state: Arc<Mutex<State>>,
+    snapshot: Arc<RwLock<Arc<Snapshot>>>,
+}
+
+impl SharedState {
+    pub fn new(state: State) -> Self {
+        let snapshot = Arc::new(Snapshot::of(&state));
+        Self {
+            state: Arc::new(Mutex::new(state)),
+            snapshot: Arc::new(RwLock::new(snapshot)),
+        }
+    }
+
+    /// Lock the state itself, e.g. to read its transactions or rejections.
+    /// Changes to accounts made through the lock aren't seen by `snapshot`
+    /// until `publish` is called.
+    pub fn lock(&self) -> MutexGuard<'_, State> {
+        // State is left consistent by every handler, so a poisoned lock is still usable
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Balances as of the latest transaction handled, which stay as they are
+    /// however long they're kept, while later transactions go on to the next snapshot.
+    pub fn snapshot(&self) -> Arc<Snapshot> {
+        self.snapshot
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    /// Replace the snapshot with the state's current balances,
+    /// e.g. after changing accounts through `lock`.
+    pub fn publish(&self) {
+        // Hold the state until the snapshot is replaced, so no transaction comes in between
+        let state = self.lock();
+        *self.write_snapshot() = Arc::new(Snapshot::of(&state));
+    }
+
+    fn write_snapshot(&self) -> RwLockWriteGuard<'_, Arc<Snapshot>> {
+        self.snapshot
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Copy one account from the state into the snapshot.
+    fn publish_account(&self, state: &State, (client_id, currency): AccountKey) {
+        if let Some(account) = state.accounts.get(client_id, currency) {
+            let mut snapshot = self.write_snapshot();
+            Arc::make_mut(&mut snapshot)
+                .accounts
+                .insert((client_id, currency), account.clone());
+        }
+    }
+
+    /// Handle a transaction, returning its account's updated balances.
+    /// Rejected transactions are recorded in the state's `rejections`.
+    pub fn submit(&self, record: TransactionRecord) -> Result<OutputRecord, TransactionError> {
+        let mut state = self.lock();
+        let num_accounts = state.accounts.len();
+        let affected = state.affected_account(&record);
+        let result = state.handle(record.clone());
+        telemetry::record_handled(&record, &result, state.accounts.len() > num_accounts);
+        // Even a rejected transaction may have opened an account
+        self.publish_account(&state, affected);
+
+        if let Err(err) = result {
+            tracing::debug!("Rejected transaction: {}", err);
+            state.rejections.push(Rejection {
+                record,
+                error: err.clone(),
+            });
+            return Err(err);
+        }
+
+        let key = (record.client_id, record.currency);
+        match state.accounts.get(record.client_id, record.currency) {
+            Some(account) => {
+                OutputRecord::new(key, account, &state.policies.rounding).map_err(|err| {
+                    TransactionError::UnexpectedError(format!(
+                        "Account for client {} is invalid: {}",
+                        record.client_id, err
+                    ))
+                })
+            }
+            None => Err(TransactionError::UnexpectedError(format!(
+                "No account for client {} after transaction {}",
+                record.client_id, record.tx_id
+            ))),
+        }
+    }
+
+    /// Rounded balances of every account, or only the given client's, by client id,
+    /// from the latest snapshot, or why one of them can't be written.
+    pub fn balances(&self, client: Option<ClientId>) -> Result<Vec<OutputRecord>, InvalidAccount> {
+        self.snapshot().balances(client)
+    }
+
+    /// Every locked account, or only the given client's, by client id,
+    /// from the latest snapshot.
+    pub fn locks(&self, client: Option<ClientId>) -> Vec<LockRecord> {
+        self.snapshot().locks(client)
+    }
+
+    /// What would happen if transactions were submitted, without submitting them.
+    /// The state is only locked while it's forked, so other requests carry on meanwhile.
+    pub fn simulate(&self, records: Vec<TransactionRecord>) -> ProcessingReport {
+        let fork = self.lock().fork();
+        simulate_in(fork, records.into_iter().map(Ok))
+    }
+
+    /// Handle a transaction given as JSON, with the same fields as a CSV row.
+    pub fn acknowledge(&self, json: &[u8]) -> Ack {
+        let record: TransactionRecord = match serde_json::from_slice(json) {
+            Ok(record) => record,
+            Err(err) => {
+                return Ack::Invalid {
+                    message: err.to_string(),
+                }
+            }
+        };
+        let tx = record.tx_id;
+        match self.submit(record) {
+            Ok(balance) => Ack::Ack { tx, balance },
+            Err(error) => Ack::Nack {
+                tx,
+                message: error.to_string(),
+                error,
+            },
+        }
+    }
+
+    /// Serve a line protocol: each line of `input` is a transaction as JSON,
+    /// answered by a line of `output` with its `Ack` as JSON, until `input` ends.
+    /// Blank lines are skipped.
+    pub fn serve_lines<R: BufRead, W: Write>(&self, input: R, mut output: W) -> io::Result<()> {
+        for line in input.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            serde_json::to_writer(&mut output, &self.acknowledge(line.as_bytes()))?;
+            writeln!(output)?;
+            // The other end is likely waiting for this reply before sending more
+            output.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SharedState;
+    use crate::currency::Currency;
+    use crate::state::State;
+    use crate::test_utils::deposit;
+    use serde_json::Value;
+
+    #[test]
+    fn test_serve_lines() {
+        let input = concat!(
+            r#"{"type":"deposit","client":1,"tx":1,"amount":"2.5"}"#,
+            "\n\n",
+            r#"{"type":"withdrawal","client":1,"tx":2,"amount":"9"}"#,
+            "\nnot json\n",
+        );
+        let mut output = Vec::new();
+        let state = SharedState::new(State::new());
+        state.serve_lines(input.as_bytes(), &mut output).unwrap();
+
+        let replies: Vec<Value> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        // The blank line gets no reply
+        assert_eq!(replies.len(), 3);
+        assert_eq!(replies[0]["status"], "ack");
+        assert_eq!(replies[0]["balance"]["available"], "2.5");
+        assert_eq!(replies[1]["status"], "nack");
+        assert_eq!(replies[1]["code"], "INSUFFICIENT_FUNDS");
+        assert_eq!(replies[2]["status"], "invalid");
+    }
+
+    #[test]
+    fn test_snapshot_isolation() {
+        let state = SharedState::new(State::new());
+        state.submit(deposit(1, 1, 2.0)).unwrap();
+        let before = state.snapshot();
+
+        state.submit(deposit(1, 2, 3.0)).unwrap();
+        state.submit(deposit(2, 3, 1.0)).unwrap();
+        let before = before.balances(None).unwrap();
+        assert_eq!(before.len(), 1);
+        assert_eq!(before[0].available, Currency::from(2.0));
+
+        let after = state.balances(None).unwrap();
+        assert_eq!(after.len(), 2);
+        assert_eq!(after[0].available, Currency::from(5.0));
+    }
+
+    #[test]
+    fn test_publish_changes_made_through_lock() {
+        let state = SharedState::new(State::new());
+        state.lock().handle(deposit(1, 1, 2.0)).unwrap();
+        assert!(state.balances(None).unwrap().is_empty());
+        state.publish();
+        assert_eq!(state.balances(Some(1)).unwrap().len(), 1);
+    }
+}
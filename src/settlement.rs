@@ -0,0 +1,243 @@
+//! Totals of the money a run moved, across all clients, for reconciling
+//! against acquirer and bank statements at the end of a batch.
+//!
+//! Amounts in different currencies can't be added up, so there are
+//! separate totals for each currency. Deposits, withdrawals, chargebacks and
+//! representments are counted as they're applied, regardless of any later reversal,
+//! as they appear on statements, while `held` is what's still held by disputes.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io;
+
+use crate::currency::{Currency, CurrencyCode};
+use crate::ledger::LedgerFormat;
+
+/// Counts and totals of the transactions applied in one currency.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct SettlementTotals {
+    pub currency: Option<CurrencyCode>,
+    pub deposits: usize,
+    /// Deposited, before fees
+    pub deposited: Currency,
+    pub withdrawals: usize,
+    /// Withdrawn, before fees
+    pub withdrawn: Currency,
+    /// Disputes opened, including reopened ones
+    pub disputes: usize,
+    /// Still held by disputes
+    pub held: Currency,
+    pub chargebacks: usize,
+    pub charged_back: Currency,
+    /// Part of the charged back amount which clients couldn't cover
+    pub written_off: Currency,
+    pub representments: usize,
+    pub represented: Currency,
+    pub reversals: usize,
+    /// Net change in clients' funds from reversals, negative for reversed deposits
+    pub reversed: Currency,
+    pub fees: Currency,
+    pub interest: Currency,
+}
+
+impl SettlementTotals {
+    pub(crate) fn deposit(&mut self, amount: Currency, fee: Currency) {
+        self.deposits += 1;
+        self.deposited += amount;
+        self.fees += fee;
+    }
+
+    pub(crate) fn withdraw(&mut self, amount: Currency, fee: Currency) {
+        self.withdrawals += 1;
+        self.withdrawn += amount;
+        self.fees += fee;
+    }
+
+    pub(crate) fn dispute(&mut self, amount: Currency) {
+        self.disputes += 1;
+        self.held += amount;
+    }
+
+    pub(crate) fn resolve(&mut self, amount: Currency) {
+        self.held -= amount;
+    }
+
+    pub(crate) fn charge_back(&mut self, amount: Currency, written_off: Currency) {
+        self.chargebacks += 1;
+        self.held -= amount;
+        self.charged_back += amount;
+        self.written_off += written_off;
+    }
+
+    pub(crate) fn represent(&mut self, amount: Currency) {
+        self.representments += 1;
+        self.held += amount;
+        self.represented += amount;
+    }
+
+    pub(crate) fn reverse(&mut self, change: Currency) {
+        self.reversals += 1;
+        self.reversed += change;
+    }
+
+    pub(crate) fn credit_interest(&mut self, interest: Currency) {
+        self.interest += interest;
+    }
+
+    fn add(&mut self, other: &SettlementTotals) {
+        self.deposits += other.deposits;
+        self.deposited += other.deposited;
+        self.withdrawals += other.withdrawals;
+        self.withdrawn += other.withdrawn;
+        self.disputes += other.disputes;
+        self.held += other.held;
+        self.chargebacks += other.chargebacks;
+        self.charged_back += other.charged_back;
+        self.written_off += other.written_off;
+        self.representments += other.representments;
+        self.represented += other.represented;
+        self.reversals += other.reversals;
+        self.reversed += other.reversed;
+        self.fees += other.fees;
+        self.interest += other.interest;
+    }
+}
+
+/// Totals for each currency used, as a run goes.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Settlement {
+    totals: BTreeMap<Option<CurrencyCode>, SettlementTotals>,
+}
+
+impl Settlement {
+    /// Totals in a currency, to be added to.
+    pub(crate) fn totals_mut(&mut self, currency: Option<CurrencyCode>) -> &mut SettlementTotals {
+        self.totals
+            .entry(currency)
+            .or_insert_with(|| SettlementTotals {
+                currency,
+                ..Default::default()
+            })
+    }
+
+    /// Totals in each currency, the default currency first, then by code.
+    pub fn totals(&self) -> impl Iterator<Item = &SettlementTotals> {
+        self.totals.values()
+    }
+
+    /// Add another settlement's totals, e.g. from another shard.
+    pub fn extend(&mut self, other: Settlement) {
+        for (currency, totals) in other.totals {
+            self.totals_mut(currency).add(&totals);
+        }
+    }
+
+    /// Write the totals in each currency in the given format, as for the ledger.
+    pub fn write<W: io::Write>(
+        &self,
+        format: LedgerFormat,
+        mut output_stream: W,
+    ) -> Result<(), Box<dyn Error>> {
+        match format {
+            LedgerFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(output_stream);
+                for totals in self.totals() {
+                    writer.serialize(totals)?;
+                }
+                writer.flush()?;
+            }
+            LedgerFormat::Jsonl => {
+                for totals in self.totals() {
+                    serde_json::to_writer(&mut output_stream, totals)?;
+                    writeln!(output_stream)?;
+                }
+                output_stream.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Settlement;
+    use crate::currency::Currency;
+    use crate::ledger::LedgerFormat;
+    use crate::policy::{FeePolicy, FeeSchedule, Policies};
+    use crate::state::State;
+    use crate::test_utils::{chargeback, deposit, dispute, withdrawal};
+
+    #[test]
+    fn test_settlement_totals() {
+        let policies = Policies {
+            fees: FeePolicy {
+                withdrawal: FeeSchedule {
+                    flat: Currency::from(0.5),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut state = State::with_policies(policies);
+        state.settlement = Some(Settlement::default());
+        let eur = "EUR".parse().unwrap();
+        for record in [
+            deposit(1, 1, 10.0),
+            deposit(2, 2, 4.0),
+            deposit(2, 3, 2.0).with_currency(eur),
+            withdrawal(1, 4, 3.0),
+            // Rejected, so not counted
+            withdrawal(2, 5, 100.0),
+            dispute(1, 1),
+            dispute(2, 2),
+            chargeback(2, 2),
+        ] {
+            let _ = state.handle(record);
+        }
+
+        let settlement = state.settlement.unwrap();
+        let totals: Vec<_> = settlement.totals().collect();
+        assert_eq!(totals.len(), 2);
+        let default = totals[0];
+        assert_eq!(default.currency, None);
+        assert_eq!(
+            (default.deposits, default.deposited),
+            (2, Currency::from(14.0))
+        );
+        assert_eq!(
+            (default.withdrawals, default.withdrawn, default.fees),
+            (1, Currency::from(3.0), Currency::from(0.5))
+        );
+        assert_eq!((default.disputes, default.held), (2, Currency::from(10.0)));
+        assert_eq!(
+            (default.chargebacks, default.charged_back),
+            (1, Currency::from(4.0))
+        );
+        assert_eq!(totals[1].currency, Some(eur));
+        assert_eq!(totals[1].deposited, Currency::from(2.0));
+
+        // Shards' totals add up
+        let mut combined = Settlement::default();
+        combined.extend(settlement.clone());
+        combined.extend(settlement.clone());
+        let doubled = combined.totals().next().unwrap();
+        assert_eq!(doubled.deposits, 4);
+        assert_eq!(doubled.held, Currency::from(20.0));
+
+        let mut csv = Vec::new();
+        settlement.write(LedgerFormat::Csv, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next(),
+            Some(
+                "currency,deposits,deposited,withdrawals,withdrawn,disputes,held,\
+                 chargebacks,charged_back,written_off,representments,represented,\
+                 reversals,reversed,fees,interest"
+            )
+        );
+        assert_eq!(lines.count(), 2);
+    }
+}
@@ -0,0 +1,170 @@
+//! What-if questions, e.g. "would this withdrawal succeed?", answered by
+//! handling transactions against a copy of the state, leaving the real one as it was.
+
+use crate::source::{ParseError, TransactionSource};
+use crate::state::{AccountView, State};
+use crate::types::{AccountKey, TransactionError, TransactionRecord};
+
+/// What would happen if some transactions were handled, from `State::simulate`.
+#[derive(Debug)]
+pub struct ProcessingReport {
+    /// Whether each transaction would succeed, or why it would be rejected, in order
+    pub outcomes: Vec<Result<(), TransactionError>>,
+    /// Records which couldn't be parsed, so had no outcome, in order
+    pub unreadable: Vec<ParseError>,
+    /// Balances of each account the transactions were for, after all of them,
+    /// in the order the accounts first appeared
+    pub accounts: Vec<AccountView>,
+    /// The copy of the state which the transactions were handled against,
+    /// for any further questions
+    pub state: State,
+}
+
+impl ProcessingReport {
+    /// Whether every record could be parsed, and every transaction would succeed.
+    pub fn succeeded(&self) -> bool {
+        self.unreadable.is_empty() && self.outcomes.iter().all(Result::is_ok)
+    }
+
+    /// Balances of a client's account in the given currency after the transactions,
+    /// or `None` if the client would have no account in that currency.
+    pub fn account(&self, (client_id, currency): AccountKey) -> Option<AccountView> {
+        self.state.account_in(client_id, currency)
+    }
+}
+
+impl State {
+    /// A copy of the state, with the same balances, transactions, disputes and policies,
+    /// which can be changed without affecting this one.
+    ///
+    /// Observers and the event log aren't copied, since they'd report changes
+    /// which never happened. Risk scorers are shared, so the copy rejects the same
+    /// transactions this state would.
+    pub fn fork(&self) -> State {
+        State {
+            accounts: self.accounts.clone(),
+            transactions: self.transactions.clone(),
+            disputes: self.disputes.clone(),
+            activity: self.activity.clone(),
+            policies: self.policies.clone(),
+            rejections: self.rejections.clone(),
+            unreadable_rows: self.unreadable_rows.clone(),
+            lost_shards: self.lost_shards,
+            ledger: self.ledger.clone(),
+            journal: self.journal.clone(),
+            settlement: self.settlement.clone(),
+            violations: self.violations.clone(),
+            schedule: self.schedule.clone(),
+            observers: Default::default(),
+            risk_scorers: self.risk_scorers.clone(),
+            events: None,
+        }
+    }
+
+    /// Handle transactions against a fork of the state, one at a time in order,
+    /// reporting what would happen without changing this one.
+    pub fn simulate<I>(&self, records: I) -> ProcessingReport
+    where
+        I: IntoIterator<Item = TransactionRecord>,
+    {
+        simulate_in(self.fork(), records.into_iter().map(Ok))
+    }
+
+    /// Like `simulate`, but reading transactions from a `TransactionSource`,
+    /// reporting any records which can't be parsed along with their outcomes.
+    pub fn simulate_source<T: TransactionSource>(&self, mut source: T) -> ProcessingReport {
+        simulate_in(self.fork(), std::iter::from_fn(|| source.next_record()))
+    }
+}
+
+/// Handle transactions, or records which couldn't be parsed,
+/// against a state which is already a fork.
+pub(crate) fn simulate_in<I>(mut state: State, records: I) -> ProcessingReport
+where
+    I: IntoIterator<Item = Result<TransactionRecord, ParseError>>,
+{
+    let mut keys: Vec<AccountKey> = Vec::new();
+    let mut outcomes = Vec::new();
+    let mut unreadable = Vec::new();
+    for result in records {
+        let record = match result {
+            Ok(record) => record,
+            Err(err) => {
+                unreadable.push(err);
+                continue;
+            }
+        };
+        let key = state.affected_account(&record);
+        if !keys.contains(&key) {
+            keys.push(key);
+        }
+        outcomes.push(state.handle(record));
+    }
+    let accounts = keys
+        .into_iter()
+        .filter_map(|(client_id, currency)| state.account_in(client_id, currency))
+        .collect();
+
+    ProcessingReport {
+        outcomes,
+        unreadable,
+        accounts,
+        state,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::currency::Currency;
+    use crate::source::CsvSource;
+    use crate::state::State;
+    use crate::test_utils::{deposit, dispute, withdrawal};
+    use crate::types::TransactionError;
+
+    #[test]
+    fn test_simulate_leaves_state_unchanged() {
+        let mut state = State::new();
+        state.handle(deposit(1, 1, 10.0)).unwrap();
+
+        let report = state.simulate([withdrawal(1, 2, 4.0), dispute(1, 1)]);
+        assert!(report.succeeded());
+        assert_eq!(report.accounts.len(), 1);
+        assert_eq!(report.accounts[0].available, Currency::from(-4.0));
+        assert_eq!(report.accounts[0].held, Currency::from(10.0));
+
+        let account = state.account(1).unwrap();
+        assert_eq!(account.available, Currency::from(10.0));
+        assert_eq!(account.held, Currency::from(0.0));
+        assert!(state.transaction(1, 2).is_none());
+    }
+
+    #[test]
+    fn test_simulate_rejection() {
+        let mut state = State::new();
+        state.handle(deposit(1, 1, 10.0)).unwrap();
+
+        let report = state.simulate([withdrawal(1, 2, 20.0)]);
+        assert!(!report.succeeded());
+        assert!(matches!(
+            report.outcomes[0],
+            Err(TransactionError::InsufficientFunds { .. })
+        ));
+        assert_eq!(
+            report.account((1, None)).unwrap().available,
+            Currency::from(10.0)
+        );
+    }
+
+    #[test]
+    fn test_simulate_source_reports_unreadable_rows() {
+        let input = "type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,1,x,1.0\nwithdrawal,1,3,2.0\n";
+        let source = CsvSource::new(input.as_bytes(), false).unwrap();
+        let report = State::new().simulate_source(source);
+        assert_eq!(report.outcomes, vec![Ok(()), Ok(())]);
+        assert!(!report.succeeded());
+        assert_eq!(report.unreadable.len(), 1);
+        assert_eq!(report.unreadable[0].line, Some(3));
+        assert_eq!(report.unreadable[0].content, "deposit,1,x,1.0");
+        assert_eq!(report.accounts[0].available, Currency::from(3.0));
+    }
+}
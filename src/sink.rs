@@ -0,0 +1,956 @@
+//! Where final balances go, in any format.
+//!
+//! The counterpart to `source`: `write_balances` writes CSV through a `CsvSink`,
+//! but balances can just as well go to JSON lines, a database, or memory,
+//! by passing another `BalanceSink` to `sink_balances`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io;
+
+use serde::ser::{Serialize, SerializeTuple, Serializer};
+
+use crate::currency::CurrencyCode;
+use crate::policy::RoundingPolicy;
+use crate::state::{AccountOrder, AccountsState, DisputeStatus, State};
+use crate::types::{Account, TransactionError};
+use crate::types::{AccountKey, ClientId, OutputRecord, Rejection, TransactionContainer};
+
+/// Somewhere balances can be written, one account at a time,
+/// e.g. `CsvSink`, `JsonLinesSink`, `SqlSink` or `MemorySink`.
+pub trait BalanceSink {
+    /// Get ready for balances, `with_currency` if some account has a currency code,
+    /// e.g. by writing a header row.
+    fn start(&mut self, _with_currency: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Write one account's balances.
+    fn write_balance(&mut self, balance: &OutputRecord) -> io::Result<()>;
+
+    /// Flush anything buffered, once all balances have been written.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<S: BalanceSink + ?Sized> BalanceSink for &mut S {
+    fn start(&mut self, with_currency: bool) -> io::Result<()> {
+        (**self).start(with_currency)
+    }
+
+    fn write_balance(&mut self, balance: &OutputRecord) -> io::Result<()> {
+        (**self).write_balance(balance)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        (**self).finish()
+    }
+}
+
+/// Write final account balances to a sink, stopping at the first error.
+pub fn sink_balances<S: BalanceSink>(
+    state: &State,
+    order: AccountOrder,
+    sink: S,
+) -> io::Result<()> {
+    sink_accounts(&state.accounts, &state.policies.rounding, order, sink)
+}
+
+/// Write account balances, rounded as they would be in the output, to a sink.
+pub(crate) fn sink_accounts<S: BalanceSink>(
+    accounts: &AccountsState,
+    rounding: &RoundingPolicy,
+    order: AccountOrder,
+    mut sink: S,
+) -> io::Result<()> {
+    sink.start(accounts.has_currency_codes())?;
+    for (&key, account) in accounts.ordered(order) {
+        sink.write_balance(&output_record(key, account, rounding)?)?;
+    }
+    sink.finish()
+}
+
+/// Output row for an account, failing the write if its total can't be represented.
+fn output_record(
+    key: AccountKey,
+    account: &Account,
+    rounding: &RoundingPolicy,
+) -> io::Result<OutputRecord> {
+    OutputRecord::new(key, account, rounding)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Balances as CSV with a header row, as written by `process`.
+/// The currency column is only included when some account has
+/// a currency code, so single-currency output keeps its usual format,
+/// and the `lock_reason` column only when asked for.
+pub struct CsvSink<W: io::Write> {
+    output: Option<W>,
+    writer: Option<csv::Writer<W>>,
+    with_currency: bool,
+    with_lock_reason: bool,
+}
+
+impl<W: io::Write> CsvSink<W> {
+    pub fn new(output: W) -> Self {
+        Self {
+            output: Some(output),
+            writer: None,
+            with_currency: false,
+            with_lock_reason: false,
+        }
+    }
+
+    /// Add a `lock_reason` column, e.g. `chargeback`,
+    /// empty for unlocked accounts, or when the reason isn't known.
+    pub fn with_lock_reason(mut self, with_lock_reason: bool) -> Self {
+        self.with_lock_reason = with_lock_reason;
+        self
+    }
+
+    /// The CSV writer, created by `start` if it hasn't been called already.
+    fn writer(&mut self) -> &mut csv::Writer<W> {
+        if self.writer.is_none() {
+            self.start_writer();
+        }
+        // Always set above
+        self.writer.as_mut().unwrap()
+    }
+
+    fn start_writer(&mut self) {
+        if let Some(output) = self.output.take() {
+            let with_currency = self.with_currency;
+            let headers: Vec<_> = crate::OUTPUT_HEADERS
+                .iter()
+                .filter(|&&header| with_currency || header != "currency")
+                .chain(self.with_lock_reason.then_some(&"lock_reason"))
+                .copied()
+                .collect();
+            self.writer = Some(crate::account_rows_writer(output, true, &headers));
+        }
+    }
+}
+
+/// One row of balances, with only the optional columns asked for.
+struct CsvRow<'a> {
+    balance: &'a OutputRecord,
+    with_currency: bool,
+    with_lock_reason: bool,
+}
+
+impl Serialize for CsvRow<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let balance = self.balance;
+        let len = 5 + self.with_currency as usize + self.with_lock_reason as usize;
+        let mut row = serializer.serialize_tuple(len)?;
+        row.serialize_element(&balance.client)?;
+        if self.with_currency {
+            row.serialize_element(&balance.currency)?;
+        }
+        row.serialize_element(&balance.available)?;
+        row.serialize_element(&balance.held)?;
+        row.serialize_element(&balance.total)?;
+        row.serialize_element(&balance.locked)?;
+        if self.with_lock_reason {
+            row.serialize_element(&balance.lock_reason)?;
+        }
+        row.end()
+    }
+}
+
+impl<W: io::Write> BalanceSink for CsvSink<W> {
+    fn start(&mut self, with_currency: bool) -> io::Result<()> {
+        self.with_currency = with_currency;
+        self.start_writer();
+        Ok(())
+    }
+
+    fn write_balance(&mut self, balance: &OutputRecord) -> io::Result<()> {
+        let row = CsvRow {
+            balance,
+            with_currency: self.with_currency,
+            with_lock_reason: self.with_lock_reason,
+        };
+        self.writer().serialize(row)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer().flush()
+    }
+}
+
+/// Balances as one JSON object per line, leaving out the currency when it's the default.
+pub struct JsonLinesSink<W> {
+    output: W,
+}
+
+impl<W: io::Write> JsonLinesSink<W> {
+    pub fn new(output: W) -> Self {
+        Self { output }
+    }
+}
+
+impl<W: io::Write> BalanceSink for JsonLinesSink<W> {
+    fn write_balance(&mut self, balance: &OutputRecord) -> io::Result<()> {
+        serde_json::to_writer(&mut self.output, balance)?;
+        self.output.write_all(b"\n")
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// A table filled by `SqlSink` or `write_sql`: its columns with their types,
+/// and the key rows are upserted by, or none if rows are only ever inserted.
+struct SqlTable {
+    /// The quoted name
+    name: String,
+    columns: &'static [(&'static str, &'static str)],
+    key: &'static [&'static str],
+}
+
+const BALANCES_COLUMNS: &[(&str, &str)] = &[
+    ("client", "INTEGER NOT NULL"),
+    ("currency", "TEXT NOT NULL DEFAULT ''"),
+    ("available", "NUMERIC NOT NULL"),
+    ("held", "NUMERIC NOT NULL"),
+    ("total", "NUMERIC NOT NULL"),
+    ("locked", "BOOLEAN NOT NULL"),
+];
+
+const TRANSACTIONS_COLUMNS: &[(&str, &str)] = &[
+    ("client", "INTEGER NOT NULL"),
+    ("tx", "BIGINT NOT NULL"),
+    ("type", "TEXT NOT NULL"),
+    ("currency", "TEXT"),
+    ("amount", "NUMERIC"),
+    ("timestamp", "BIGINT"),
+    ("error", "TEXT"),
+    ("disputed", "NUMERIC"),
+    ("settled", "BOOLEAN NOT NULL"),
+];
+
+const REJECTIONS_COLUMNS: &[(&str, &str)] = &[
+    ("type", "TEXT NOT NULL"),
+    ("client", "INTEGER NOT NULL"),
+    ("tx", "BIGINT NOT NULL"),
+    ("amount", "NUMERIC"),
+    ("timestamp", "BIGINT"),
+    ("currency", "TEXT NOT NULL DEFAULT ''"),
+    ("error", "TEXT NOT NULL"),
+    ("message", "TEXT NOT NULL"),
+];
+
+impl SqlTable {
+    /// A table called `name`, which is quoted, so it can be any name.
+    fn new(
+        name: &str,
+        columns: &'static [(&'static str, &'static str)],
+        key: &'static [&'static str],
+    ) -> Self {
+        Self {
+            name: format!("\"{}\"", name.replace('"', "\"\"")),
+            columns,
+            key,
+        }
+    }
+
+    fn balances(name: &str) -> Self {
+        Self::new(name, BALANCES_COLUMNS, &["client", "currency"])
+    }
+
+    fn transactions() -> Self {
+        Self::new("transactions", TRANSACTIONS_COLUMNS, &["client", "tx"])
+    }
+
+    fn rejections() -> Self {
+        Self::new("rejections", REJECTIONS_COLUMNS, &[])
+    }
+
+    fn create(&self) -> String {
+        let mut columns: Vec<_> = self
+            .columns
+            .iter()
+            .map(|(name, definition)| format!("{} {}", name, definition))
+            .collect();
+        if !self.key.is_empty() {
+            columns.push(format!("PRIMARY KEY ({})", self.key.join(", ")));
+        }
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} ({})",
+            self.name,
+            columns.join(", ")
+        )
+    }
+
+    /// Insert a row of `values`, one for each column, or update the row with the same key.
+    fn insert(&self, values: &[String]) -> String {
+        let names: Vec<_> = self.columns.iter().map(|&(name, _)| name).collect();
+        let mut sql = format!(
+            "INSERT INTO {} ({}) VALUES ({})",
+            self.name,
+            names.join(", "),
+            values.join(", ")
+        );
+        if !self.key.is_empty() {
+            let updates: Vec<_> = names
+                .iter()
+                .filter(|name| !self.key.contains(name))
+                .map(|name| format!("{0} = excluded.{0}", name))
+                .collect();
+            sql += &format!(
+                " ON CONFLICT ({}) DO UPDATE SET {}",
+                self.key.join(", "),
+                updates.join(", ")
+            );
+        }
+        sql
+    }
+
+    /// `insert` with a parameter for each column, numbered from `$1`.
+    /// Amounts are bound as text, to keep every digit, and cast to numbers by the database.
+    #[cfg(any(feature = "sqlite", feature = "postgres"))]
+    fn insert_parameters(&self) -> String {
+        let parameters: Vec<_> = (1..)
+            .zip(self.columns)
+            .map(|(index, (_, definition))| {
+                if definition.starts_with("NUMERIC") {
+                    format!("CAST(${} AS NUMERIC)", index)
+                } else {
+                    format!("${}", index)
+                }
+            })
+            .collect();
+        self.insert(&parameters)
+    }
+}
+
+/// A value in a row of an `SqlTable`, with its type even when it's `NULL`,
+/// so that it can be bound to a parameter.
+#[derive(Clone, Debug, PartialEq)]
+enum SqlValue {
+    Integer(Option<u64>),
+    /// An amount, as text
+    Numeric(Option<String>),
+    Text(Option<String>),
+    Boolean(bool),
+}
+
+impl SqlValue {
+    fn numeric<T: fmt::Display>(value: Option<T>) -> Self {
+        Self::Numeric(value.map(|value| value.to_string()))
+    }
+
+    fn integer<T: Into<u64>>(value: Option<T>) -> Self {
+        Self::Integer(value.map(Into::into))
+    }
+
+    fn text<T: fmt::Display>(value: Option<T>) -> Self {
+        Self::Text(value.map(|value| value.to_string()))
+    }
+
+    /// A currency code as stored in SQL, with the default currency as an empty string,
+    /// so that it can be part of a key.
+    fn currency(currency: Option<CurrencyCode>) -> Self {
+        Self::Text(Some(currency.map_or_else(String::new, |code| code.to_string())))
+    }
+
+    /// The value as an SQL literal.
+    fn literal(&self) -> String {
+        match self {
+            Self::Integer(None) | Self::Numeric(None) | Self::Text(None) => "NULL".to_string(),
+            Self::Integer(Some(value)) => value.to_string(),
+            Self::Numeric(Some(value)) => value.clone(),
+            Self::Text(Some(value)) => format!("'{}'", value.replace('\'', "''")),
+            Self::Boolean(value) => value.to_string(),
+        }
+    }
+}
+
+/// Somewhere `write_sql` sends its statements: written out as text,
+/// or run against a database, one transaction at a time.
+trait SqlTarget {
+    fn begin(&mut self) -> io::Result<()>;
+
+    fn create(&mut self, table: &SqlTable) -> io::Result<()>;
+
+    fn delete_all(&mut self, table: &SqlTable) -> io::Result<()>;
+
+    fn insert(&mut self, table: &SqlTable, row: &[SqlValue]) -> io::Result<()>;
+
+    fn commit(&mut self) -> io::Result<()>;
+}
+
+/// Statements written out as text, ending with semicolons, one per line.
+struct SqlText<W>(W);
+
+impl<W: io::Write> SqlTarget for SqlText<W> {
+    fn begin(&mut self) -> io::Result<()> {
+        writeln!(self.0, "BEGIN;")
+    }
+
+    fn create(&mut self, table: &SqlTable) -> io::Result<()> {
+        writeln!(self.0, "{};", table.create())
+    }
+
+    fn delete_all(&mut self, table: &SqlTable) -> io::Result<()> {
+        writeln!(self.0, "DELETE FROM {};", table.name)
+    }
+
+    fn insert(&mut self, table: &SqlTable, row: &[SqlValue]) -> io::Result<()> {
+        let values: Vec<_> = row.iter().map(SqlValue::literal).collect();
+        writeln!(self.0, "{};", table.insert(&values))
+    }
+
+    fn commit(&mut self) -> io::Result<()> {
+        writeln!(self.0, "COMMIT;")?;
+        self.0.flush()
+    }
+}
+
+/// Balances as SQL statements, e.g. to load later with `sqlite3` or `psql`:
+/// the table is created if need be, and each account's row is inserted,
+/// or replaced if it's already there, all in one transaction.
+/// The default currency is stored as an empty string, so that it's part of the key.
+pub struct SqlSink<W> {
+    output: SqlText<W>,
+    table: SqlTable,
+}
+
+impl<W: io::Write> SqlSink<W> {
+    /// Write balances into `table`, which is quoted, so it can be any name.
+    pub fn new(output: W, table: &str) -> Self {
+        Self {
+            output: SqlText(output),
+            table: SqlTable::balances(table),
+        }
+    }
+}
+
+impl<W: io::Write> BalanceSink for SqlSink<W> {
+    fn start(&mut self, _with_currency: bool) -> io::Result<()> {
+        self.output.begin()?;
+        self.output.create(&self.table)
+    }
+
+    fn write_balance(&mut self, balance: &OutputRecord) -> io::Result<()> {
+        self.output.insert(&self.table, &balance_row(balance))
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.output.commit()
+    }
+}
+
+fn balance_row(balance: &OutputRecord) -> Vec<SqlValue> {
+    vec![
+        SqlValue::integer(Some(balance.client)),
+        SqlValue::currency(balance.currency),
+        SqlValue::numeric(Some(balance.available)),
+        SqlValue::numeric(Some(balance.held)),
+        SqlValue::numeric(Some(balance.total)),
+        SqlValue::Boolean(balance.locked),
+    ]
+}
+
+/// Tables to write with `write_sql` besides `balances`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SqlTables {
+    /// Every stored deposit and withdrawal, in `transactions`,
+    /// with whether it failed and where it stands with respect to disputes
+    pub transactions: bool,
+    /// Every rejected transaction, in `rejections`
+    pub rejections: bool,
+}
+
+/// The final state as SQL statements, all in one transaction:
+/// balances upserted into `balances` as by `SqlSink`, and if requested,
+/// transactions upserted into `transactions` by client and id,
+/// and rejections into `rejections`, replacing any already there,
+/// since nothing tells one run's rejections from another's.
+/// Amounts are `NULL` where they're unknown, e.g. for a failed deposit.
+pub fn write_sql<W: io::Write>(
+    state: &State,
+    order: AccountOrder,
+    tables: SqlTables,
+    output: W,
+) -> io::Result<()> {
+    send_sql(state, order, tables, &mut SqlText(output))
+}
+
+/// Send the statements `write_sql` writes to `target`.
+fn send_sql<T: SqlTarget>(
+    state: &State,
+    order: AccountOrder,
+    tables: SqlTables,
+    target: &mut T,
+) -> io::Result<()> {
+    target.begin()?;
+    let balances = SqlTable::balances("balances");
+    target.create(&balances)?;
+    for (&key, account) in state.accounts.ordered(order) {
+        let balance = output_record(key, account, &state.policies.rounding)?;
+        target.insert(&balances, &balance_row(&balance))?;
+    }
+    if tables.transactions {
+        send_sql_transactions(state, target)?;
+    }
+    if tables.rejections {
+        send_sql_rejections(&state.rejections, target)?;
+    }
+    target.commit()
+}
+
+fn send_sql_transactions<T: SqlTarget>(state: &State, target: &mut T) -> io::Result<()> {
+    let table = SqlTable::transactions();
+    target.create(&table)?;
+    let clients: BTreeSet<ClientId> = state
+        .accounts
+        .iter()
+        .map(|(&(client, _), _)| client)
+        .collect();
+    for client in clients {
+        for entry in state.history(client) {
+            let (amount, timestamp, currency) = match entry.transaction {
+                TransactionContainer::Deposit(Ok(deposit)) => (
+                    Some(deposit.amount),
+                    deposit.timestamp,
+                    Some(deposit.currency),
+                ),
+                TransactionContainer::Withdrawal(Ok(withdrawal)) => (
+                    Some(withdrawal.amount),
+                    withdrawal.timestamp,
+                    Some(withdrawal.currency),
+                ),
+                _ => (None, None, None),
+            };
+            let (disputed, settled) = match entry.dispute_status {
+                DisputeStatus::Undisputed => (None, false),
+                DisputeStatus::Disputed { amount } => (Some(amount), false),
+                DisputeStatus::Settled => (None, true),
+            };
+            let row = [
+                SqlValue::integer(Some(client)),
+                SqlValue::integer(Some(entry.tx)),
+                SqlValue::text(Some(entry.transaction.tx_type().name())),
+                currency.map_or(SqlValue::Text(None), SqlValue::currency),
+                SqlValue::numeric(amount),
+                SqlValue::integer(timestamp),
+                SqlValue::text(
+                    entry
+                        .transaction
+                        .outcome()
+                        .err()
+                        .map(TransactionError::code),
+                ),
+                SqlValue::numeric(disputed),
+                SqlValue::Boolean(settled),
+            ];
+            target.insert(&table, &row)?;
+        }
+    }
+    Ok(())
+}
+
+fn send_sql_rejections<T: SqlTarget>(rejections: &[Rejection], target: &mut T) -> io::Result<()> {
+    let table = SqlTable::rejections();
+    target.create(&table)?;
+    target.delete_all(&table)?;
+    for Rejection { record, error } in rejections {
+        let row = [
+            SqlValue::text(Some(record.transaction_type.name())),
+            SqlValue::integer(Some(record.client_id)),
+            SqlValue::integer(Some(record.tx_id)),
+            SqlValue::numeric(record.amount),
+            SqlValue::integer(record.timestamp),
+            SqlValue::currency(record.currency),
+            SqlValue::text(Some(error.code())),
+            SqlValue::text(Some(error)),
+        ];
+        target.insert(&table, &row)?;
+    }
+    Ok(())
+}
+
+/// A database error, e.g. from `rusqlite` or `sqlx`, as an `io::Error`.
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+fn database_error<E: std::error::Error + Send + Sync + 'static>(err: E) -> io::Error {
+    io::Error::other(err)
+}
+
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::load_sqlite;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use std::io;
+    use std::path::Path;
+
+    use rusqlite::types::ToSqlOutput;
+    use rusqlite::{params_from_iter, Connection, ToSql};
+
+    use super::{database_error, send_sql, SqlTable, SqlTables, SqlTarget, SqlValue};
+    use crate::state::{AccountOrder, State};
+
+    impl ToSql for SqlValue {
+        fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+            match self {
+                Self::Integer(value) => value.to_sql(),
+                Self::Numeric(value) | Self::Text(value) => value.to_sql(),
+                Self::Boolean(value) => value.to_sql(),
+            }
+        }
+    }
+
+    impl SqlTarget for Connection {
+        fn begin(&mut self) -> io::Result<()> {
+            self.execute_batch("BEGIN").map_err(database_error)
+        }
+
+        fn create(&mut self, table: &SqlTable) -> io::Result<()> {
+            self.execute_batch(&table.create()).map_err(database_error)
+        }
+
+        fn delete_all(&mut self, table: &SqlTable) -> io::Result<()> {
+            let sql = format!("DELETE FROM {}", table.name);
+            self.execute_batch(&sql).map_err(database_error)
+        }
+
+        fn insert(&mut self, table: &SqlTable, row: &[SqlValue]) -> io::Result<()> {
+            self.prepare_cached(&table.insert_parameters())
+                .and_then(|mut statement| statement.execute(params_from_iter(row)))
+                .map(drop)
+                .map_err(database_error)
+        }
+
+        fn commit(&mut self) -> io::Result<()> {
+            self.execute_batch("COMMIT").map_err(database_error)
+        }
+    }
+
+    /// Load the final state into the SQLite database at `path`, creating it if need be,
+    /// with the same tables and rows as `write_sql` writes, through prepared statements,
+    /// all in one transaction, which is rolled back if any statement fails.
+    pub fn load_sqlite(
+        path: &Path,
+        state: &State,
+        order: AccountOrder,
+        tables: SqlTables,
+    ) -> io::Result<()> {
+        let mut connection = Connection::open(path).map_err(database_error)?;
+        send_sql(state, order, tables, &mut connection)
+    }
+}
+
+#[cfg(feature = "postgres")]
+pub use self::postgres::load_postgres;
+
+#[cfg(feature = "postgres")]
+mod postgres {
+    use std::convert::TryFrom;
+    use std::io;
+
+    use sqlx::{Connection, Executor, PgConnection};
+
+    use super::{database_error, send_sql, SqlTable, SqlTables, SqlTarget, SqlValue};
+    use crate::state::{AccountOrder, State};
+
+    /// A connection to Postgres, driven by a runtime of its own,
+    /// since everything else here is synchronous.
+    struct Postgres {
+        runtime: tokio::runtime::Runtime,
+        connection: PgConnection,
+    }
+
+    impl Postgres {
+        fn execute(&mut self, sql: &str) -> io::Result<()> {
+            let Self {
+                runtime,
+                connection,
+            } = self;
+            runtime
+                .block_on(connection.execute(sql))
+                .map(drop)
+                .map_err(database_error)
+        }
+    }
+
+    impl SqlTarget for Postgres {
+        fn begin(&mut self) -> io::Result<()> {
+            self.execute("BEGIN")
+        }
+
+        fn create(&mut self, table: &SqlTable) -> io::Result<()> {
+            self.execute(&table.create())
+        }
+
+        fn delete_all(&mut self, table: &SqlTable) -> io::Result<()> {
+            self.execute(&format!("DELETE FROM {}", table.name))
+        }
+
+        fn insert(&mut self, table: &SqlTable, row: &[SqlValue]) -> io::Result<()> {
+            let sql = table.insert_parameters();
+            // Prepared once per connection, then cached by `sqlx`
+            let mut query = sqlx::query(&sql);
+            for value in row {
+                query = match value {
+                    SqlValue::Integer(value) => query.bind(
+                        value
+                            .map(i64::try_from)
+                            .transpose()
+                            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+                    ),
+                    SqlValue::Numeric(value) | SqlValue::Text(value) => {
+                        query.bind(value.as_deref())
+                    }
+                    SqlValue::Boolean(value) => query.bind(*value),
+                };
+            }
+            let Self {
+                runtime,
+                connection,
+            } = self;
+            runtime
+                .block_on(query.execute(connection))
+                .map(drop)
+                .map_err(database_error)
+        }
+
+        fn commit(&mut self) -> io::Result<()> {
+            self.execute("COMMIT")
+        }
+    }
+
+    /// Load the final state into the Postgres database at `url`, e.g. `postgres://user@host/db`,
+    /// with the same tables and rows as `write_sql` writes, through prepared statements,
+    /// all in one transaction, which is rolled back if any statement fails.
+    pub fn load_postgres(
+        url: &str,
+        state: &State,
+        order: AccountOrder,
+        tables: SqlTables,
+    ) -> io::Result<()> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let connection = runtime
+            .block_on(PgConnection::connect(url))
+            .map_err(database_error)?;
+        send_sql(
+            state,
+            order,
+            tables,
+            &mut Postgres {
+                runtime,
+                connection,
+            },
+        )
+    }
+}
+
+/// Balances kept in memory, by account, e.g. to check them in a test.
+#[derive(Debug, Default)]
+pub struct MemorySink {
+    pub balances: BTreeMap<AccountKey, OutputRecord>,
+}
+
+impl MemorySink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl BalanceSink for MemorySink {
+    fn write_balance(&mut self, balance: &OutputRecord) -> io::Result<()> {
+        let key = (balance.client, balance.currency);
+        self.balances.insert(key, balance.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SqlSink, SqlTables};
+    use super::{sink_balances, write_sql, BalanceSink, CsvSink, JsonLinesSink, MemorySink};
+    use crate::handlers::handle_transaction;
+    use crate::state::{AccountOrder, State};
+    use crate::test_utils::{deposit, dispute, withdrawal};
+    use crate::types::{Currency, Rejection};
+    use crate::write_balances;
+
+    fn state() -> State {
+        let mut state = State::new();
+        for record in [
+            deposit(2, 1, 5.0),
+            deposit(1, 2, 3.0),
+            withdrawal(2, 3, 1.5),
+            dispute(1, 2),
+        ] {
+            handle_transaction(record, &mut state).unwrap();
+        }
+        state
+    }
+
+    fn sunk<S: BalanceSink>(mut sink: S) -> S {
+        sink_balances(&state(), AccountOrder::Client, &mut sink).unwrap();
+        sink
+    }
+
+    #[test]
+    fn test_csv_sink_matches_write_balances() {
+        let mut expected = Vec::new();
+        write_balances(&state(), AccountOrder::Client, &mut expected);
+
+        let mut actual = Vec::new();
+        sunk(CsvSink::new(&mut actual));
+        assert_eq!(String::from_utf8(actual), String::from_utf8(expected));
+    }
+
+    #[test]
+    fn test_json_lines_sink() {
+        let mut output = Vec::new();
+        sunk(JsonLinesSink::new(&mut output));
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "{\"client\":1,\"available\":\"0.0\",\"held\":\"3.0\",\"total\":\"3.0\",\"locked\":false}\n\
+             {\"client\":2,\"available\":\"3.5\",\"held\":\"0.0\",\"total\":\"3.5\",\"locked\":false}\n"
+        );
+    }
+
+    #[test]
+    fn test_sql_sink() {
+        let mut output = Vec::new();
+        sunk(SqlSink::new(&mut output, "my \"balances\""));
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], "BEGIN;");
+        assert!(lines[1].starts_with("CREATE TABLE IF NOT EXISTS \"my \"\"balances\"\"\" ("));
+        assert!(lines[2].contains("VALUES (1, '', 0.0, 3.0, 3.0, false)"));
+        assert!(lines[3].contains("VALUES (2, '', 3.5, 0.0, 3.5, false)"));
+        assert_eq!(lines[4], "COMMIT;");
+    }
+
+    #[test]
+    fn test_write_sql_tables() {
+        let state = state_with_rejection();
+        let mut output = Vec::new();
+        write_sql(&state, AccountOrder::Client, ALL_TABLES, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        // Two balances, four transactions, and one rejection
+        assert_eq!(lines.len(), 13);
+        assert!(lines[4].starts_with("CREATE TABLE IF NOT EXISTS \"transactions\" ("));
+        assert!(lines[5].contains("VALUES (1, 2, 'deposit', '', 3.0, NULL, NULL, 3.0, false)"));
+        assert!(lines[6].contains(
+            "VALUES (1, 4, 'withdrawal', NULL, NULL, NULL, 'INSUFFICIENT_FUNDS', NULL, false)"
+        ));
+        assert!(lines[7].contains("VALUES (2, 1, 'deposit', '', 5.0, NULL, NULL, NULL, false)"));
+        assert_eq!(lines[10], "DELETE FROM \"rejections\";");
+        assert!(lines[11].contains(
+            "VALUES ('withdrawal', 1, 4, 9.0, NULL, '', 'INSUFFICIENT_FUNDS', 'InsufficientFunds"
+        ));
+        assert_eq!(lines[12], "COMMIT;");
+    }
+
+    /// The state from `test_write_sql_tables`, with a rejection.
+    fn state_with_rejection() -> State {
+        let mut state = state();
+        let record = withdrawal(1, 4, 9.0);
+        let error = handle_transaction(record.clone(), &mut state).unwrap_err();
+        state.rejections.push(Rejection { record, error });
+        state
+    }
+
+    const ALL_TABLES: SqlTables = SqlTables {
+        transactions: true,
+        rejections: true,
+    };
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_load_sqlite() {
+        use super::load_sqlite;
+
+        let path = std::env::temp_dir().join(format!("sink-{}.db", std::process::id()));
+        let state = state_with_rejection();
+        // Loading again upserts the same rows, and replaces the rejections
+        for _ in 0..2 {
+            load_sqlite(&path, &state, AccountOrder::Client, ALL_TABLES).unwrap();
+        }
+
+        let connection = rusqlite::Connection::open(&path).unwrap();
+        let count = |table: &str| -> i64 {
+            let sql = format!("SELECT COUNT(*) FROM {}", table);
+            connection.query_row(&sql, [], |row| row.get(0)).unwrap()
+        };
+        assert_eq!(count("balances"), 2);
+        assert_eq!(count("transactions"), 4);
+        assert_eq!(count("rejections"), 1);
+
+        let balance: (f64, f64, bool) = connection
+            .query_row(
+                "SELECT available, total, locked FROM balances WHERE client = 2 AND currency = ''",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(balance, (3.5, 3.5, false));
+        let error: String = connection
+            .query_row("SELECT error FROM transactions WHERE tx = 4", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(error, "INSUFFICIENT_FUNDS");
+        drop(connection);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// Run with `--ignored`, and `TEST_POSTGRES_URL` set to a database it may write to.
+    #[cfg(feature = "postgres")]
+    #[test]
+    #[ignore = "needs a Postgres server at TEST_POSTGRES_URL"]
+    fn test_load_postgres() {
+        use super::load_postgres;
+        use sqlx::Connection;
+
+        let url = std::env::var("TEST_POSTGRES_URL").unwrap();
+        let state = state_with_rejection();
+        for _ in 0..2 {
+            load_postgres(&url, &state, AccountOrder::Client, ALL_TABLES).unwrap();
+        }
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let (total, rejections): (String, i64) = runtime.block_on(async {
+            let mut connection = sqlx::PgConnection::connect(&url).await.unwrap();
+            let total = sqlx::query_scalar(
+                "SELECT total::text FROM balances WHERE client = 2 AND currency = ''",
+            )
+            .fetch_one(&mut connection)
+            .await
+            .unwrap();
+            let rejections = sqlx::query_scalar("SELECT COUNT(*) FROM rejections")
+                .fetch_one(&mut connection)
+                .await
+                .unwrap();
+            (total, rejections)
+        });
+        assert_eq!(total, Currency::from(3.5).to_string());
+        assert_eq!(rejections, 1);
+    }
+
+    #[test]
+    fn test_memory_sink() {
+        let sink = sunk(MemorySink::new());
+        assert_eq!(sink.balances.len(), 2);
+        let balance = &sink.balances[&(1, None)];
+        assert_eq!(balance.available, Currency::ZERO);
+        assert_eq!(balance.held, Currency::from(3.0));
+    }
+}
@@ -0,0 +1,288 @@
+//! Transactions read one at a time, already parsed, from any format.
+//!
+//! Unlike a `RecordSource`, whose CSV records are deserialized in parallel
+//! by the pipeline, a `TransactionSource` parses its own records,
+//! so that transactions can come from JSON lines, memory, or anywhere else,
+//! e.g. a message queue, and be handled by `process_source` like any CSV file.
+
+use csv::StringRecord;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::input::readable;
+use crate::types::TransactionRecord;
+
+/// Why a record couldn't be read, where it was, if known, and what it said,
+/// so that it can be reported along with rejected transactions.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ParseError {
+    /// Line of the input, counting from 1
+    pub line: Option<u64>,
+    pub message: String,
+    /// The record as it was read, or empty if it couldn't be,
+    /// e.g. if it wasn't valid UTF-8
+    #[serde(default)]
+    pub content: String,
+}
+
+impl ParseError {
+    pub fn new<E: fmt::Display>(line: Option<u64>, err: E) -> Self {
+        Self {
+            line,
+            message: err.to_string(),
+            content: String::new(),
+        }
+    }
+
+    pub fn with_content<S: Into<String>>(mut self, content: S) -> Self {
+        self.content = content.into();
+        self
+    }
+
+    /// Why a CSV record couldn't be read, with its fields as its content.
+    pub(crate) fn for_record<E: fmt::Display>(record: &StringRecord, err: E) -> Self {
+        let line = record.position().map(csv::Position::line);
+        Self::new(line, err).with_content(record_content(record))
+    }
+}
+
+/// A CSV record's fields, written as a row again.
+fn record_content(record: &StringRecord) -> String {
+    let mut writer = csv::WriterBuilder::new()
+        .terminator(csv::Terminator::Any(b'\n'))
+        .from_writer(Vec::new());
+    let _ = writer.write_record(record);
+    let row = writer.into_inner().unwrap_or_default();
+    String::from_utf8_lossy(&row).trim_end().to_string()
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+/// Somewhere transactions can be read from, one at a time, e.g. `CsvSource`,
+/// `JsonLinesSource`, or a `Vec` of records, with `into_iter()`.
+/// A record which can't be parsed is reported with a `ParseError`,
+/// rather than handled, but reading carries on with the next.
+pub trait TransactionSource {
+    /// The next transaction, or why it couldn't be read, until there are none left.
+    fn next_record(&mut self) -> Option<Result<TransactionRecord, ParseError>>;
+}
+
+impl<S: TransactionSource + ?Sized> TransactionSource for Box<S> {
+    fn next_record(&mut self) -> Option<Result<TransactionRecord, ParseError>> {
+        (**self).next_record()
+    }
+}
+
+/// Records already in memory, e.g. built by a test.
+impl TransactionSource for std::vec::IntoIter<TransactionRecord> {
+    fn next_record(&mut self) -> Option<Result<TransactionRecord, ParseError>> {
+        self.next().map(Ok)
+    }
+}
+
+/// Several sources read one after another, e.g. a file per day.
+impl<S: TransactionSource> TransactionSource for VecDeque<S> {
+    fn next_record(&mut self) -> Option<Result<TransactionRecord, ParseError>> {
+        while let Some(source) = self.front_mut() {
+            if let Some(result) = source.next_record() {
+                return Some(result);
+            }
+            self.pop_front();
+        }
+        None
+    }
+}
+
+/// Transactions read from CSV with a header row, as by `process_transactions`,
+/// but parsed on the current thread.
+pub struct CsvSource<R> {
+    reader: csv::Reader<R>,
+    headers: StringRecord,
+    record: StringRecord,
+    /// Whether reading failed, so nothing more can be read
+    failed: bool,
+}
+
+impl<R: io::Read> CsvSource<R> {
+    /// Read the header row, failing if there isn't one.
+    /// As elsewhere, whitespace around fields is trimmed unless `notrim` is set.
+    pub fn new(input: R, notrim: bool) -> csv::Result<Self> {
+        let mut reader = crate::construct_csv_reader(input, notrim, true);
+        let headers = reader.headers()?.clone();
+        Ok(Self {
+            reader,
+            headers,
+            record: StringRecord::new(),
+            failed: false,
+        })
+    }
+}
+
+impl<R: io::Read> TransactionSource for CsvSource<R> {
+    fn next_record(&mut self) -> Option<Result<TransactionRecord, ParseError>> {
+        if self.failed {
+            return None;
+        }
+        let result = match self.reader.read_record(&mut self.record) {
+            Ok(true) => Ok(&self.record),
+            Ok(false) => return None,
+            Err(err) => {
+                self.failed = err.is_io_error();
+                Err(err)
+            }
+        };
+        Some(readable(result, self.headers.len()).and_then(|record| {
+            record
+                .deserialize(Some(&self.headers))
+                .map_err(|err| ParseError::for_record(record, err))
+        }))
+    }
+}
+
+/// Transactions read as one JSON object per line, e.g. as written by
+/// `generate --format jsonl`. Blank lines are skipped.
+pub struct JsonLinesSource<R> {
+    input: R,
+    buf: Vec<u8>,
+    line: u64,
+    /// Whether reading failed, so nothing more can be read
+    failed: bool,
+}
+
+impl<R: io::BufRead> JsonLinesSource<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            input,
+            buf: Vec::new(),
+            line: 0,
+            failed: false,
+        }
+    }
+}
+
+impl<R: io::BufRead> TransactionSource for JsonLinesSource<R> {
+    fn next_record(&mut self) -> Option<Result<TransactionRecord, ParseError>> {
+        while !self.failed {
+            self.buf.clear();
+            self.line += 1;
+            match self.input.read_until(b'\n', &mut self.buf) {
+                Ok(0) => return None,
+                Ok(_) if self.buf.iter().all(u8::is_ascii_whitespace) => continue,
+                // Parsed as bytes, so that invalid UTF-8 only spoils its own line
+                Ok(_) => {
+                    let result = serde_json::from_slice(&self.buf);
+                    return Some(result.map_err(|err| {
+                        let content = String::from_utf8_lossy(&self.buf);
+                        ParseError::new(Some(self.line), err).with_content(content.trim_end())
+                    }));
+                }
+                Err(err) => {
+                    self.failed = true;
+                    return Some(Err(ParseError::new(Some(self.line), err)));
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CsvSource, JsonLinesSource, TransactionSource};
+    use crate::pipeline::PipelineConfig;
+    use crate::policy::Policies;
+    use crate::test_utils::{deposit, dispute, withdrawal};
+    use crate::{process_source, process_transactions};
+
+    /// Every record the source yields, with parse errors as their line numbers.
+    fn read_all<S: TransactionSource>(mut source: S) -> Vec<Result<String, Option<u64>>> {
+        std::iter::from_fn(|| source.next_record())
+            .map(|result| {
+                result
+                    .map(|record| format!("{} {}", record.transaction_type.name(), record.tx_id))
+                    .map_err(|err| err.line)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_csv_source() {
+        let input =
+            "type, client, tx, amount\ndeposit, 1, 1, 5.0\nnonsense\nwithdrawal, 1, 2, 1.0\n";
+        let source = CsvSource::new(input.as_bytes(), false).unwrap();
+        assert_eq!(
+            read_all(source),
+            vec![
+                Ok("deposit 1".to_string()),
+                Err(Some(3)),
+                Ok("withdrawal 2".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_json_lines_source() {
+        let mut input =
+            b"{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": \"5.0\"}\n\n".to_vec();
+        input.extend_from_slice(b"{\"type\": \"\xff\"}\n");
+        input.extend_from_slice(b"{\"type\": \"dispute\", \"client\": 1, \"tx\": 1}");
+        assert_eq!(
+            read_all(JsonLinesSource::new(&input[..])),
+            vec![
+                Ok("deposit 1".to_string()),
+                Err(Some(3)),
+                Ok("dispute 1".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_process_source_matches_csv() {
+        let records = vec![
+            deposit(1, 1, 5.0),
+            deposit(2, 2, 3.0),
+            withdrawal(1, 3, 1.0),
+            dispute(2, 2),
+        ];
+        let mut csv = csv::Writer::from_writer(Vec::new());
+        for record in &records {
+            csv.serialize(record).unwrap();
+        }
+        let csv = csv.into_inner().unwrap();
+
+        let mut expected = Vec::new();
+        process_transactions(
+            std::io::Cursor::new(csv),
+            &mut expected,
+            100,
+            false,
+            Policies::default(),
+            None,
+            None,
+        );
+        let mut actual = Vec::new();
+        let state = process_source(
+            records.into_iter(),
+            &mut actual,
+            PipelineConfig::default(),
+            Policies::default(),
+            None,
+            None,
+        );
+
+        assert_eq!(String::from_utf8(actual), String::from_utf8(expected));
+        assert!(state.unreadable_rows.is_empty());
+    }
+}
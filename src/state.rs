@@ -1,69 +1,345 @@
-use std::collections::{HashMap, HashSet};
+use indexmap::{IndexMap, IndexSet};
+use rustc_hash::{FxBuildHasher, FxHashMap, FxHashSet};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp::Reverse;
+use std::collections::hash_map::Entry;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
 
-use crate::account::AccountAccess;
-use crate::types::{Account, TransactionContainer, TransactionError};
-use crate::types::{ClientId, TransactionId};
+use crate::account::AccountRef;
+use crate::currency::{Currency, CurrencyCode};
+use crate::digest::accounts_digest;
+use crate::events::EventLog;
+use crate::handlers;
+use crate::invariants::{self, Violation};
+use crate::journal::{Journal, JournalEntry};
+use crate::ledger::Ledger;
+use crate::limits::{Activity, ActivityState};
+use crate::observer::Observers;
+use crate::policy::{Policies, TxIdScope};
+use crate::risk::RiskScorers;
+use crate::schedule::Schedule;
+use crate::settlement::Settlement;
+use crate::source::ParseError;
+use crate::traits::Transaction;
+use crate::types::{Account, InvalidAccount, Rejection, TransactionContainer};
+use crate::types::{
+    AccountKey, ClientId, DisputeAction, DisputeReason, DisputeStep, Timestamp, TransactionId,
+    TransactionType,
+};
+use crate::types::{TransactionError, TransactionRecord};
+
+/// Order in which to list accounts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountOrder {
+    /// By client id, then currency, so that output is the same on every run.
+    #[default]
+    Client,
+    /// In the order that each account first appeared in the input.
+    Insertion,
+}
+
+impl FromStr for AccountOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "client" => Ok(Self::Client),
+            "insertion" => Ok(Self::Insertion),
+            other => Err(format!("unknown account order '{}'", other)),
+        }
+    }
+}
+
+// NOTE: State is keyed by small integer ids, which FxHash hashes several times
+// faster than the default SipHash. It isn't resistant to deliberately colliding
+// keys, but ids come from a trusted processor rather than from clients themselves.
+// See `benches/engine.rs`.
 
 /// Component of application state dealing with accounts: balances and status.
-#[derive(Debug, Default, PartialEq)]
-pub struct AccountsState(HashMap<ClientId, Account>);
+/// Each client has a separate account for each currency they use.
+/// Accounts are kept in the order they were created.
+///
+/// Serialized as a list of accounts, in order, each with its client and currency,
+/// since JSON objects can't be keyed by both.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AccountsState(IndexMap<AccountKey, Account, FxBuildHasher>);
+
+/// An account in a serialized `AccountsState`.
+#[derive(Deserialize, Serialize)]
+struct AccountEntry<A> {
+    client: ClientId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    currency: Option<CurrencyCode>,
+    #[serde(flatten)]
+    account: A,
+}
 
+impl Serialize for AccountsState {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(
+            self.0
+                .iter()
+                .map(|(&(client, currency), account)| AccountEntry {
+                    client,
+                    currency,
+                    account,
+                }),
+        )
+    }
+}
+
+impl<'de> Deserialize<'de> for AccountsState {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let entries = Vec::<AccountEntry<Account>>::deserialize(deserializer)?;
+        Ok(Self(
+            entries
+                .into_iter()
+                .map(|entry| ((entry.client, entry.currency), entry.account))
+                .collect(),
+        ))
+    }
+}
+
+impl From<HashMap<AccountKey, Account>> for AccountsState {
+    fn from(inner: HashMap<AccountKey, Account>) -> Self {
+        Self(inner.into_iter().collect())
+    }
+}
+
+/// Accounts in the default currency
 impl From<HashMap<ClientId, Account>> for AccountsState {
     fn from(inner: HashMap<ClientId, Account>) -> Self {
-        Self(inner)
+        inner
+            .into_iter()
+            .map(|(client_id, account)| ((client_id, None), account))
+            .collect::<HashMap<_, _>>()
+            .into()
     }
 }
 
 impl AccountsState {
-    pub fn get(&self, client_id: ClientId) -> Option<&Account> {
-        self.0.get(&client_id)
+    pub fn get(&self, client_id: ClientId, currency: Option<CurrencyCode>) -> Option<&Account> {
+        self.0.get(&(client_id, currency))
     }
 
-    pub fn get_or_default(&mut self, client_id: ClientId) -> &Account {
-        self.0.entry(client_id).or_default()
+    pub fn get_or_default(
+        &mut self,
+        client_id: ClientId,
+        currency: Option<CurrencyCode>,
+    ) -> &Account {
+        self.0.entry((client_id, currency)).or_default()
     }
 
-    pub fn get_mut<'a>(&'a mut self, client_id: ClientId) -> Option<AccountAccess<'a>> {
-        if let Some(account) = self.0.get_mut(&client_id) {
-            Some(account.access())
-        } else {
-            None
+    pub fn get_mut<'a>(
+        &'a mut self,
+        client_id: ClientId,
+        currency: Option<CurrencyCode>,
+    ) -> Option<AccountRef<'a>> {
+        self.0
+            .get_mut(&(client_id, currency))
+            .map(|account| account.access())
+    }
+
+    pub fn get_mut_or_default<'a>(
+        &'a mut self,
+        client_id: ClientId,
+        currency: Option<CurrencyCode>,
+    ) -> AccountRef<'a> {
+        self.0.entry((client_id, currency)).or_default().access()
+    }
+
+    /// Move all accounts from another state into this one.
+    /// Accounts are assumed to be disjoint: if an account exists in both,
+    /// the account from `other` wins.
+    pub fn extend(&mut self, other: AccountsState) {
+        self.0.extend(other.0);
+    }
+
+    /// Move all accounts from another state into this one,
+    /// unless any account exists in both, in which case neither changes.
+    pub fn merge(&mut self, other: AccountsState) -> Result<(), MergeError> {
+        if let Some(&key) = other.0.keys().find(|key| self.0.contains_key(*key)) {
+            return Err(MergeError::DuplicateAccount(key));
         }
+        self.extend(other);
+        Ok(())
     }
 
-    pub fn get_mut_or_default<'a>(&'a mut self, client_id: ClientId) -> AccountAccess<'a> {
-        self.0.entry(client_id).or_default().access()
+    /// Number of accounts.
+    pub fn len(&self) -> usize {
+        self.0.len()
     }
 
-    /// Iterate over accounts: (client_id, account)
-    pub fn iter(&self) -> impl Iterator<Item = (&ClientId, &Account)> {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Whether any account holds a currency other than the default.
+    pub fn has_currency_codes(&self) -> bool {
+        self.0.keys().any(|(_, currency)| currency.is_some())
+    }
+
+    /// Iterate over accounts: ((client_id, currency), account)
+    pub fn iter(&self) -> impl Iterator<Item = (&AccountKey, &Account)> {
         self.0.iter()
     }
+
+    pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = (&AccountKey, &mut Account)> {
+        self.0.iter_mut()
+    }
+
+    /// Accounts in the given order: ((client_id, currency), account)
+    pub fn ordered(&self, order: AccountOrder) -> Vec<(&AccountKey, &Account)> {
+        let mut accounts: Vec<_> = self.0.iter().collect();
+        if order == AccountOrder::Client {
+            accounts.sort_unstable_by_key(|(&key, _)| key);
+        }
+        accounts
+    }
+
+    /// Add an account as it was, e.g. restored from a checkpoint,
+    /// replacing any existing account with the same key.
+    pub(crate) fn insert(&mut self, key: AccountKey, account: Account) {
+        self.0.insert(key, account);
+    }
+
+    /// Remove an account, e.g. to undo opening it.
+    pub(crate) fn remove(&mut self, key: AccountKey) {
+        self.0.shift_remove(&key);
+    }
+
+    /// Put accounts in the order their keys were first seen.
+    /// Accounts which weren't seen go last.
+    pub(crate) fn sort_by_first_seen(&mut self, first_seen: &IndexSet<AccountKey>) {
+        self.0
+            .sort_by_cached_key(|key, _| first_seen.get_index_of(key).unwrap_or(usize::MAX));
+    }
 }
 
 /// Record of all transactions relevant to engine operation.
 /// This is not intended for logging purposes.
 /// Disputes, resolves, and chargebacks are not stored since
 /// they are never directly referenced by other transactions.
-/// Therefore, this struct contains only deposits and withdrawals.
+/// Therefore, this struct contains only deposits and withdrawals,
+/// and reversals, which each refer to the transaction they undid.
 ///
-/// Both successful and failed transactions are stored
+/// Both successful and failed deposits and withdrawals are stored
 /// within TransactionContainer, which wraps a Result.
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct TransactionsState {
-    by_client: HashMap<ClientId, HashMap<TransactionId, TransactionContainer>>,
-    tx_ids: HashSet<TransactionId>,
+    /// Each client's transactions, in the order they were handled
+    by_client: FxHashMap<ClientId, IndexMap<TransactionId, TransactionContainer, FxBuildHasher>>,
+    /// Client of each transaction id, or of the first to use it,
+    /// so that disputes from the wrong client can be told apart
+    /// from disputes of transactions which don't exist.
+    /// Only kept when ids are unique globally.
+    clients_by_tx: FxHashMap<TransactionId, ClientId>,
+    /// Each client's reversed transactions, with the reversal which undid each
+    #[serde(default)]
+    reversed: FxHashMap<ClientId, FxHashMap<TransactionId, TransactionId>>,
+    /// Each client's transactions which were charged back, and not represented since.
+    /// Unlike the disputes, these are never forgotten, so that a transaction
+    /// can't be reversed after its chargeback has been compacted away.
+    #[serde(default)]
+    charged_back: FxHashMap<ClientId, FxHashSet<TransactionId>>,
+    scope: TxIdScope,
 }
 
 impl TransactionsState {
+    /// No transactions yet, with ids unique among the given scope.
+    pub fn with_scope(scope: TxIdScope) -> Self {
+        Self {
+            scope,
+            ..Default::default()
+        }
+    }
+
+    pub fn scope(&self) -> TxIdScope {
+        self.scope
+    }
+
+    /// Whether the id is already taken for a new transaction of the client's.
+    pub fn is_taken(&self, client_id: ClientId, tx_id: TransactionId) -> bool {
+        match self.scope {
+            TxIdScope::Global => self.tx_exists(tx_id),
+            TxIdScope::Client => self.get(client_id, tx_id).is_some(),
+        }
+    }
+
+    /// Whether any client has a transaction with the given id.
+    /// Always false unless ids are unique globally.
     pub fn tx_exists(&self, tx_id: TransactionId) -> bool {
-        self.tx_ids.contains(&tx_id)
+        self.clients_by_tx.contains_key(&tx_id)
+    }
+
+    /// Client whose transaction has the given id, whichever client refers to it.
+    pub fn client_of(&self, tx_id: TransactionId) -> Option<ClientId> {
+        self.clients_by_tx.get(&tx_id).copied()
     }
 
     pub fn get(&self, client_id: ClientId, tx_id: TransactionId) -> Option<&TransactionContainer> {
         self.by_client.get(&client_id).and_then(|c| c.get(&tx_id))
     }
 
+    /// Id of the reversal which undid a client's transaction, if it was reversed.
+    pub fn reversal_of(&self, client_id: ClientId, tx_id: TransactionId) -> Option<TransactionId> {
+        self.reversed.get(&client_id)?.get(&tx_id).copied()
+    }
+
+    /// Whether a client's transaction was charged back, and not represented since.
+    pub fn is_charged_back(&self, client_id: ClientId, tx_id: TransactionId) -> bool {
+        self.charged_back
+            .get(&client_id)
+            .is_some_and(|charged_back| charged_back.contains(&tx_id))
+    }
+
+    /// Remember that a client's transaction was charged back.
+    pub(crate) fn record_chargeback(&mut self, client_id: ClientId, tx_id: TransactionId) {
+        self.charged_back
+            .entry(client_id)
+            .or_default()
+            .insert(tx_id);
+    }
+
+    /// Forget a client's chargeback, since the merchant contested it.
+    pub(crate) fn clear_chargeback(&mut self, client_id: ClientId, tx_id: TransactionId) {
+        if let Entry::Occupied(mut client_charged_back) = self.charged_back.entry(client_id) {
+            client_charged_back.get_mut().remove(&tx_id);
+            if client_charged_back.get().is_empty() {
+                client_charged_back.remove();
+            }
+        }
+    }
+
+    /// Link a reversal to the transaction it undid.
+    fn link_reversal(
+        reversed: &mut FxHashMap<ClientId, FxHashMap<TransactionId, TransactionId>>,
+        client_id: ClientId,
+        transaction: &TransactionContainer,
+    ) {
+        if let TransactionContainer::Reversal(reversal) = transaction {
+            reversed
+                .entry(client_id)
+                .or_default()
+                .insert(reversal.reverses, reversal.tx_id);
+        }
+    }
+
+    /// The client's transaction with the given id, or failing that, another client's.
+    /// When transaction ids are unique across clients, a dispute from the wrong client
+    /// still finds the transaction, to be reported as a client mismatch.
+    pub fn find(&self, client_id: ClientId, tx_id: TransactionId) -> Option<&TransactionContainer> {
+        self.get(client_id, tx_id).or_else(|| {
+            let owner = self.client_of(tx_id)?;
+            self.get(owner, tx_id)
+        })
+    }
+
     pub fn insert(
         &mut self,
         client_id: ClientId,
@@ -74,16 +350,141 @@ impl TransactionsState {
         let client_txs = self.by_client.entry(client_id).or_default();
 
         // Store transaction id globally to avoid duplicates
-        let success = self.tx_ids.insert(tx_id);
-        if !success {
-            log::warn!(
-                "Storing duplicate tx_id {} - did you forget to validate?",
-                tx_id
-            )
+        let duplicate = match self.scope {
+            TxIdScope::Global => match self.clients_by_tx.entry(tx_id) {
+                Entry::Vacant(entry) => {
+                    entry.insert(client_id);
+                    false
+                }
+                Entry::Occupied(_) => true,
+            },
+            TxIdScope::Client => client_txs.contains_key(&tx_id),
+        };
+        if duplicate {
+            // Only expected if the validation policy allows duplicates
+            tracing::debug!("Storing duplicate tx_id {}", tx_id)
+        }
+
+        // NOTE: Discarding duplicate transactions silently,
+        // so that the first transaction with an id is the one disputed
+        if let indexmap::map::Entry::Vacant(entry) = client_txs.entry(tx_id) {
+            Self::link_reversal(&mut self.reversed, client_id, &transaction);
+            entry.insert(transaction);
+        }
+    }
+
+    /// Store a transaction exactly as it was, e.g. restored from a checkpoint.
+    /// Unlike `insert`, the id only refers to this client's transaction
+    /// if it's the `owner`, i.e. the first client to use the id.
+    pub(crate) fn restore(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        transaction: TransactionContainer,
+        owner: bool,
+    ) {
+        Self::link_reversal(&mut self.reversed, client_id, &transaction);
+        self.by_client
+            .entry(client_id)
+            .or_default()
+            .insert(tx_id, transaction);
+        if owner && self.scope == TxIdScope::Global {
+            self.clients_by_tx.insert(tx_id, client_id);
+        }
+    }
+
+    /// A client's transaction as stored, with the client its id refers to,
+    /// and what else handling it may change, to put back with `put_back`.
+    fn save(
+        &self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        reverses: Option<TransactionId>,
+    ) -> SavedTransaction {
+        SavedTransaction {
+            transaction: self.get(client_id, tx_id).cloned(),
+            owner: self.client_of(tx_id),
+            reversed: reverses.map(|reverses| (reverses, self.reversal_of(client_id, reverses))),
+            charged_back: self.is_charged_back(client_id, tx_id),
+        }
+    }
+
+    /// Put a client's transaction back as it was saved, forgetting it if it wasn't stored.
+    fn put_back(&mut self, client_id: ClientId, tx_id: TransactionId, saved: SavedTransaction) {
+        if let Entry::Occupied(mut client_txs) = self.by_client.entry(client_id) {
+            match saved.transaction {
+                Some(transaction) => {
+                    client_txs.get_mut().insert(tx_id, transaction);
+                }
+                None => {
+                    client_txs.get_mut().shift_remove(&tx_id);
+                    if client_txs.get().is_empty() {
+                        client_txs.remove();
+                    }
+                }
+            }
         }
+        match saved.owner {
+            Some(owner) => self.clients_by_tx.insert(tx_id, owner),
+            None => self.clients_by_tx.remove(&tx_id),
+        };
+        if let Some((reverses, reversal)) = saved.reversed {
+            let client_reversed = self.reversed.entry(client_id).or_default();
+            match reversal {
+                Some(reversal) => client_reversed.insert(reverses, reversal),
+                None => client_reversed.remove(&reverses),
+            };
+            if client_reversed.is_empty() {
+                self.reversed.remove(&client_id);
+            }
+        }
+        if saved.charged_back {
+            self.record_chargeback(client_id, tx_id);
+        } else {
+            self.clear_chargeback(client_id, tx_id);
+        }
+    }
 
-        // NOTE: Discarding duplicate transactions silently
-        client_txs.entry(tx_id).or_insert(transaction);
+    /// A client's transactions in the order they were handled,
+    /// including those which failed.
+    pub fn iter_client(
+        &self,
+        client_id: ClientId,
+    ) -> impl Iterator<Item = (TransactionId, &TransactionContainer)> {
+        self.by_client
+            .get(&client_id)
+            .into_iter()
+            .flat_map(|client_txs| client_txs.iter().map(|(&tx_id, tx)| (tx_id, tx)))
+    }
+
+    /// Every stored (client_id, tx_id) pair, in no particular order.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (ClientId, TransactionId)> + '_ {
+        self.by_client.iter().flat_map(|(&client_id, client_txs)| {
+            client_txs.keys().map(move |&tx_id| (client_id, tx_id))
+        })
+    }
+
+    /// Why another client's transactions can't be merged into these, if they can't.
+    fn merge_conflict(&self, other: &TransactionsState) -> Option<MergeError> {
+        if self.scope != other.scope {
+            return Some(MergeError::ScopeMismatch);
+        }
+        other
+            .by_client
+            .keys()
+            .find(|client_id| self.by_client.contains_key(*client_id))
+            .map(|&client_id| MergeError::DuplicateClient(client_id))
+    }
+
+    /// Move all transactions from another state with different clients into this one.
+    /// Like `insert`, an id used in both keeps its first client in the index.
+    fn absorb(&mut self, other: TransactionsState) {
+        self.by_client.extend(other.by_client);
+        self.reversed.extend(other.reversed);
+        self.charged_back.extend(other.charged_back);
+        for (tx_id, client_id) in other.clients_by_tx {
+            self.clients_by_tx.entry(tx_id).or_insert(client_id);
+        }
     }
 
     /// Get the set of tx ids for this client
@@ -100,27 +501,53 @@ impl TransactionsState {
 /// Current state of all disputes, past and present.
 /// Once a dispute is filed for a transaction, it is
 /// considered actively disputed, and its tx_id is stored
-/// in the `active` field.
+/// in the `active` field along with the disputed amount,
+/// which may be less than the transaction's full amount.
 ///
 /// Once a resolve or chargeback has been filed, it is
-/// considered settled, and can no longer be re-disputed.
-/// These tx_ids are found in the `settled` field.
-#[derive(Debug, Default)]
+/// considered settled, and its tx_id is found in the `settled` field.
+/// A resolved transaction may be disputed again, as many times as
+/// the `DisputePolicy` allows, but a charged back one may not.
+///
+/// The merchant may contest each chargeback once, with a representment,
+/// which makes the dispute active again, holding the charged back amount.
+/// Every step is kept in the transaction's dispute history.
+///
+/// If settled disputes are compacted, each is forgotten once its
+/// transaction is too old to dispute, as of the latest timestamp seen.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct DisputesState {
-    active: HashMap<ClientId, HashSet<TransactionId>>,
-    settled: HashMap<ClientId, HashSet<TransactionId>>,
+    active: FxHashMap<ClientId, FxHashMap<TransactionId, Currency>>,
+    settled: FxHashMap<ClientId, FxHashSet<TransactionId>>,
+    /// Reasons, representments and histories of active and settled disputes
+    #[serde(default)]
+    details: FxHashMap<ClientId, FxHashMap<TransactionId, DisputeDetails>>,
+    /// Settled disputes which may be forgotten, oldest transaction first
+    #[serde(default)]
+    expiries: BinaryHeap<Reverse<(Timestamp, ClientId, TransactionId)>>,
+    /// Latest timestamp seen, if compacting
+    #[serde(default)]
+    latest: Option<Timestamp>,
 }
 
 impl DisputesState {
     /// Determine whether a client's transaction is actively disputed.
     pub fn is_disputed(&self, client_id: ClientId, tx_id: TransactionId) -> bool {
         if let Some(client_active) = self.active.get(&client_id) {
-            client_active.contains(&tx_id)
+            client_active.contains_key(&tx_id)
         } else {
             false
         }
     }
 
+    /// Get the amount held by an active dispute, if any.
+    pub fn disputed_amount(&self, client_id: ClientId, tx_id: TransactionId) -> Option<Currency> {
+        self.active
+            .get(&client_id)
+            .and_then(|client_active| client_active.get(&tx_id))
+            .cloned()
+    }
+
     /// Determine whether a client's transaction has been disputed and settled.
     pub fn is_settled(&self, client_id: ClientId, tx_id: TransactionId) -> bool {
         if let Some(client_settled) = self.settled.get(&client_id) {
@@ -130,87 +557,1215 @@ impl DisputesState {
         }
     }
 
-    /// Mark a transaction as actively disputed.
+    /// Why a client's transaction was last disputed, if the dispute gave a reason,
+    /// while it's active or settled.
+    pub fn reason(&self, client_id: ClientId, tx_id: TransactionId) -> Option<DisputeReason> {
+        self.details(client_id, tx_id)?.reason
+    }
+
+    /// Amount charged back by a settled dispute which may still be represented.
+    pub fn charged_back_amount(
+        &self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+    ) -> Option<Currency> {
+        self.details(client_id, tx_id)?.charged_back
+    }
+
+    /// Determine whether a client's latest dispute was reopened by a representment,
+    /// so it can't be represented again.
+    pub fn is_represented(&self, client_id: ClientId, tx_id: TransactionId) -> bool {
+        self.details(client_id, tx_id)
+            .is_some_and(|details| details.represented)
+    }
+
+    /// Every step of a client's transaction's disputes, in order,
+    /// until the settled dispute is forgotten.
+    pub fn history(&self, client_id: ClientId, tx_id: TransactionId) -> &[DisputeStep] {
+        self.details(client_id, tx_id)
+            .map_or(&[], |details| &details.history)
+    }
+
+    /// Number of times a client's transaction has been disputed, including the first.
+    pub fn times_disputed(&self, client_id: ClientId, tx_id: TransactionId) -> usize {
+        self.history(client_id, tx_id)
+            .iter()
+            .filter(|step| matches!(step.action, DisputeAction::Opened | DisputeAction::Reopened))
+            .count()
+    }
+
+    /// Determine whether a settled dispute may be disputed again: only if it was
+    /// resolved, and hasn't already been disputed again `max_redisputes` times.
+    /// Disputes without a history, e.g. from an older checkpoint, may not.
+    pub fn may_redispute(
+        &self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        max_redisputes: u32,
+    ) -> bool {
+        let resolved = self
+            .history(client_id, tx_id)
+            .last()
+            .is_some_and(|step| step.action == DisputeAction::Resolved);
+        let redisputes = self.times_disputed(client_id, tx_id).saturating_sub(1);
+        resolved && redisputes < max_redisputes as usize
+    }
+
+    pub(crate) fn details(
+        &self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+    ) -> Option<&DisputeDetails> {
+        self.details.get(&client_id)?.get(&tx_id)
+    }
+
+    fn details_mut(&mut self, client_id: ClientId, tx_id: TransactionId) -> &mut DisputeDetails {
+        self.details
+            .entry(client_id)
+            .or_default()
+            .entry(tx_id)
+            .or_default()
+    }
+
+    fn record(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        action: DisputeAction,
+        timestamp: Option<Timestamp>,
+    ) {
+        let step = DisputeStep { action, timestamp };
+        self.details_mut(client_id, tx_id).history.push(step);
+    }
+
+    /// Mark some amount of a transaction as actively disputed, for the given reason, if any,
+    /// at the dispute's time. A settled dispute is reopened, as a new dispute.
     pub fn dispute_tx(
         &mut self,
         client_id: ClientId,
         tx_id: TransactionId,
+        amount: Currency,
+        reason: Option<DisputeReason>,
+        timestamp: Option<Timestamp>,
     ) -> Result<(), TransactionError> {
         // TODO: These things should already be checked.
         // Can we safely avoid checking twice?
-        // NOTE: Not checking whether transaction is already settled
+        // NOTE: Not checking whether the policy allows disputing a settled transaction again
         let client_disputes = self.active.entry(client_id).or_default();
-        let insert_success = client_disputes.insert(tx_id);
-        if insert_success {
-            Ok(())
-        } else {
-            Err(TransactionError::TxAlreadyDisputed {
+        match client_disputes.entry(tx_id) {
+            Entry::Occupied(_) => Err(TransactionError::TxAlreadyDisputed {
                 client: client_id,
                 tx: tx_id,
-            })
+            }),
+            Entry::Vacant(entry) => {
+                entry.insert(amount);
+                let action = if self.unsettle(client_id, tx_id) {
+                    DisputeAction::Reopened
+                } else {
+                    DisputeAction::Opened
+                };
+                let details = self.details_mut(client_id, tx_id);
+                details.reason = reason;
+                details.charged_back = None;
+                details.represented = false;
+                self.record(client_id, tx_id, action, timestamp);
+                Ok(())
+            }
         }
     }
 
-    /// Mark a transaction as settled.
-    pub fn settle_dispute(
+    /// Settle a dispute with a resolve, returning the amount which was disputed.
+    pub fn resolve(
         &mut self,
         client_id: ClientId,
         tx_id: TransactionId,
-    ) -> Result<(), TransactionError> {
+        timestamp: Option<Timestamp>,
+    ) -> Result<Currency, TransactionError> {
+        let amount = self.settle(client_id, tx_id)?;
+        self.record(client_id, tx_id, DisputeAction::Resolved, timestamp);
+        Ok(amount)
+    }
+
+    /// Settle a dispute with a chargeback, returning the amount which was disputed.
+    /// Unless the dispute was already represented, the merchant may still represent it.
+    pub fn charge_back(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        timestamp: Option<Timestamp>,
+    ) -> Result<Currency, TransactionError> {
+        let amount = self.settle(client_id, tx_id)?;
+        if !self.is_represented(client_id, tx_id) {
+            self.details_mut(client_id, tx_id).charged_back = Some(amount);
+        }
+        self.record(client_id, tx_id, DisputeAction::ChargedBack, timestamp);
+        Ok(amount)
+    }
+
+    /// Reopen a charged back dispute, returning the amount to hold again.
+    pub fn represent(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        timestamp: Option<Timestamp>,
+    ) -> Result<Currency, TransactionError> {
+        if self.is_represented(client_id, tx_id) {
+            return Err(TransactionError::AlreadyRepresented {
+                client: client_id,
+                tx: tx_id,
+            });
+        }
+        let amount = self.charged_back_amount(client_id, tx_id).ok_or(
+            TransactionError::TxNotChargedBack {
+                client: client_id,
+                tx: tx_id,
+            },
+        )?;
+        self.unsettle(client_id, tx_id);
+        let details = self.details_mut(client_id, tx_id);
+        details.charged_back = None;
+        details.represented = true;
+        self.record(client_id, tx_id, DisputeAction::Represented, timestamp);
+        self.active
+            .entry(client_id)
+            .or_default()
+            .insert(tx_id, amount);
+        Ok(amount)
+    }
+
+    /// Mark a transaction as settled, returning the amount which was disputed.
+    fn settle(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+    ) -> Result<Currency, TransactionError> {
         // NOTE: When using async, make sure to { remove & insert } atomically.
-        if let Some(client_active) = self.active.get_mut(&client_id) {
-            let remove_success = client_active.remove(&tx_id);
-            if remove_success {
-                let client_settled = self.settled.entry(client_id).or_default();
-                let insert_success = client_settled.insert(tx_id);
-                if insert_success {
-                    return Ok(());
-                } else {
-                    return Err(TransactionError::DisputeAlreadySettled {
-                        tx: tx_id,
-                        client: client_id,
-                    });
+        let amount = self
+            .active
+            .get_mut(&client_id)
+            .and_then(|client_active| client_active.remove(&tx_id))
+            .ok_or(TransactionError::TxNotDisputed {
+                client: client_id,
+                tx: tx_id,
+            })?;
+        self.settled.entry(client_id).or_default().insert(tx_id);
+        Ok(amount)
+    }
+
+    /// Stop remembering a transaction as settled, returning whether it was.
+    fn unsettle(&mut self, client_id: ClientId, tx_id: TransactionId) -> bool {
+        match self.settled.entry(client_id) {
+            Entry::Occupied(mut client_settled) => {
+                let removed = client_settled.get_mut().remove(&tx_id);
+                if client_settled.get().is_empty() {
+                    client_settled.remove();
                 }
+                removed
+            }
+            Entry::Vacant(_) => false,
+        }
+    }
+
+    /// Forget a settled dispute once its transaction, which occurred at the given time,
+    /// is too old to dispute. See `expire_settled`.
+    pub fn schedule_expiry(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        occurred_at: Timestamp,
+    ) {
+        self.expiries.push(Reverse((occurred_at, client_id, tx_id)));
+    }
+
+    /// Advance to the given time, forgetting settled disputes of transactions
+    /// older than `max_age` as of the latest time seen. Returns how many were forgotten.
+    pub fn expire_settled(&mut self, now: Timestamp, max_age: Duration) -> usize {
+        let latest = self.latest.map_or(now, |latest| latest.max(now));
+        self.latest = Some(latest);
+
+        let mut expired = 0;
+        while let Some(&Reverse((occurred_at, client_id, tx_id))) = self.expiries.peek() {
+            if latest.saturating_sub(occurred_at) <= max_age.as_secs() {
+                break;
+            }
+            self.expiries.pop();
+            if self.unsettle(client_id, tx_id) {
+                expired += 1;
+                self.forget_details(client_id, tx_id);
+            }
+        }
+        expired
+    }
+
+    fn forget_details(&mut self, client_id: ClientId, tx_id: TransactionId) {
+        if let Entry::Occupied(mut client_details) = self.details.entry(client_id) {
+            client_details.get_mut().remove(&tx_id);
+            if client_details.get().is_empty() {
+                client_details.remove();
             }
         }
-        Err(TransactionError::TxNotDisputed {
-            client: client_id,
-            tx: tx_id,
+    }
+
+    /// Latest timestamp seen while compacting settled disputes.
+    pub fn latest(&self) -> Option<Timestamp> {
+        self.latest
+    }
+
+    /// Number of active disputes.
+    pub fn num_active(&self) -> usize {
+        self.active.values().map(HashMap::len).sum()
+    }
+
+    /// Number of settled disputes which are still remembered.
+    pub fn num_settled(&self) -> usize {
+        self.settled.values().map(HashSet::len).sum()
+    }
+
+    /// Every active dispute as (client_id, tx_id, amount), in no particular order.
+    pub(crate) fn active(&self) -> impl Iterator<Item = (ClientId, TransactionId, Currency)> + '_ {
+        self.active.iter().flat_map(|(&client_id, client_active)| {
+            client_active
+                .iter()
+                .map(move |(&tx_id, &amount)| (client_id, tx_id, amount))
         })
     }
 
+    /// Every settled dispute which is still remembered
+    /// as (client_id, tx_id), in no particular order.
+    pub(crate) fn settled(&self) -> impl Iterator<Item = (ClientId, TransactionId)> + '_ {
+        self.settled
+            .iter()
+            .flat_map(|(&client_id, client_settled)| {
+                client_settled.iter().map(move |&tx_id| (client_id, tx_id))
+            })
+    }
+
+    /// When each settled dispute which may be forgotten
+    /// was disputed, as (occurred_at, client_id, tx_id), in no particular order.
+    pub(crate) fn expiries(
+        &self,
+    ) -> impl Iterator<Item = (Timestamp, ClientId, TransactionId)> + '_ {
+        self.expiries.iter().map(|&Reverse(expiry)| expiry)
+    }
+
+    /// Mark a transaction as settled without it having been actively disputed,
+    /// e.g. when restoring a checkpoint.
+    pub(crate) fn restore_settled(&mut self, client_id: ClientId, tx_id: TransactionId) {
+        self.settled.entry(client_id).or_default().insert(tx_id);
+    }
+
+    /// Set what's known about a dispute besides its amount,
+    /// e.g. when restoring a checkpoint.
+    pub(crate) fn restore_details(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        details: DisputeDetails,
+    ) {
+        if details == DisputeDetails::default() {
+            self.forget_details(client_id, tx_id);
+        } else {
+            *self.details_mut(client_id, tx_id) = details;
+        }
+    }
+
+    /// Set the latest timestamp seen, e.g. when restoring a checkpoint.
+    pub(crate) fn restore_latest(&mut self, latest: Option<Timestamp>) {
+        self.latest = latest;
+    }
+
+    /// Everything about a transaction's dispute, to put back with `put_back`.
+    fn save(&self, client_id: ClientId, tx_id: TransactionId) -> SavedDispute {
+        SavedDispute {
+            active: self.disputed_amount(client_id, tx_id),
+            settled: self.is_settled(client_id, tx_id),
+            details: self.details(client_id, tx_id).cloned(),
+        }
+    }
+
+    /// Put a transaction's dispute back as it was saved.
+    /// Any expiry scheduled since is harmless, since only settled disputes expire.
+    fn put_back(&mut self, client_id: ClientId, tx_id: TransactionId, saved: SavedDispute) {
+        match saved.active {
+            Some(amount) => {
+                self.active
+                    .entry(client_id)
+                    .or_default()
+                    .insert(tx_id, amount);
+            }
+            None => {
+                if let Entry::Occupied(mut client_active) = self.active.entry(client_id) {
+                    client_active.get_mut().remove(&tx_id);
+                    if client_active.get().is_empty() {
+                        client_active.remove();
+                    }
+                }
+            }
+        }
+        if saved.settled {
+            self.restore_settled(client_id, tx_id);
+        } else {
+            self.unsettle(client_id, tx_id);
+        }
+        self.restore_details(client_id, tx_id, saved.details.unwrap_or_default());
+    }
+
+    /// Move all disputes from another state with different clients into this one.
+    fn absorb(&mut self, other: DisputesState) {
+        self.active.extend(other.active);
+        self.settled.extend(other.settled);
+        self.details.extend(other.details);
+        self.expiries.extend(other.expiries);
+        self.latest = self.latest.max(other.latest);
+    }
+
     /// Get the set of all disputed transaction ids for a client.
     pub fn get_disputed_tx_ids_by_client(&self, client_id: ClientId) -> HashSet<TransactionId> {
         self.active
             .get(&client_id)
-            .cloned()
-            .unwrap_or_else(HashSet::new)
+            .map(|client_active| client_active.keys().cloned().collect())
+            .unwrap_or_default()
     }
 
     /// Get the set of all settled transaction ids for a client.
     pub fn get_settled_tx_ids_by_client(&self, client_id: ClientId) -> HashSet<TransactionId> {
         self.settled
             .get(&client_id)
-            .cloned()
-            .unwrap_or_else(HashSet::new)
+            .map(|client_settled| client_settled.iter().cloned().collect())
+            .unwrap_or_default()
     }
 }
 
-/// Root application state
-#[derive(Debug)]
+/// A transaction as stored before it was handled, and what else handling it may change.
+struct SavedTransaction {
+    transaction: Option<TransactionContainer>,
+    /// Client the id referred to, if any
+    owner: Option<ClientId>,
+    /// For a reversal, the transaction it reverses, and the reversal which had undone it
+    reversed: Option<(TransactionId, Option<TransactionId>)>,
+    charged_back: bool,
+}
+
+/// A transaction's dispute as it was before a transaction was handled.
+struct SavedDispute {
+    active: Option<Currency>,
+    settled: bool,
+    details: Option<DisputeDetails>,
+}
+
+/// What handling a transaction may change, saved beforehand so that, if handling it
+/// panics part way through, the state can be put back as it was. See `State::undo`.
+/// Events already sent, and anything done by observers, can't be taken back.
+pub(crate) struct UndoPoint {
+    key: AccountKey,
+    tx_id: TransactionId,
+    account: Option<Account>,
+    transaction: SavedTransaction,
+    dispute: SavedDispute,
+    activity: Option<Activity>,
+    ledger_len: Option<usize>,
+    journal_len: Option<usize>,
+    settlement: Option<Settlement>,
+}
+
+/// What's known about a dispute besides its amount.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub(crate) struct DisputeDetails {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reason: Option<DisputeReason>,
+    /// Amount charged back, while the chargeback may still be represented
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    charged_back: Option<Currency>,
+    /// Whether the latest dispute was reopened by a representment
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    represented: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    history: Vec<DisputeStep>,
+}
+
+/// Root application state.
+///
+/// Serializable, e.g. for snapshots, test fixtures, or sending it elsewhere,
+/// though not its observers, risk scorers or event log, which belong to the process.
+#[derive(Debug, Deserialize, Serialize)]
 pub struct State {
     pub accounts: AccountsState,
     // TODO: log disputes, resolutions, & chargebacks?
     pub transactions: TransactionsState,
     pub disputes: DisputesState,
+    /// Each client's recent deposits and withdrawals, for checking velocity limits
+    #[serde(default)]
+    pub activity: ActivityState,
+    pub policies: Policies,
+    /// Transactions rejected by the pipeline, for reporting
+    pub rejections: Vec<Rejection>,
+    /// Input rows which couldn't be read or deserialized, for reporting
+    #[serde(default)]
+    pub unreadable_rows: Vec<ParseError>,
+    /// Number of handler shards whose accounts were lost, since their thread panicked,
+    /// so the state is incomplete
+    #[serde(default)]
+    pub lost_shards: usize,
+    /// Every balance change, if the ledger is enabled
+    pub ledger: Option<Ledger>,
+    /// Every account's balance changes, if the journal is enabled
+    pub journal: Option<Journal>,
+    /// Totals of the money moved in each currency, if the settlement report is enabled
+    #[serde(default)]
+    pub settlement: Option<Settlement>,
+    /// Invariant violations found while processing, if checked
+    pub violations: Vec<Violation>,
+    /// Scheduled transactions held by the pipeline until they take effect
+    #[serde(default)]
+    pub schedule: Schedule,
+    /// Called before and after each transaction is handled
+    #[serde(skip)]
+    pub observers: Observers,
+    /// Called with each deposit and withdrawal, besides the `RiskPolicy`
+    #[serde(skip)]
+    pub risk_scorers: RiskScorers,
+    /// Where to send events for every change, if anywhere
+    #[serde(skip)]
+    pub events: Option<EventLog>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl State {
     pub fn new() -> Self {
+        Self::with_policies(Default::default())
+    }
+
+    /// Construct an empty state which handles transactions
+    /// according to the given policies.
+    pub fn with_policies(policies: Policies) -> Self {
         Self {
             accounts: Default::default(),
-            transactions: Default::default(),
+            transactions: TransactionsState::with_scope(policies.validation.tx_id_scope),
             disputes: Default::default(),
+            activity: Default::default(),
+            policies,
+            rejections: Vec::new(),
+            unreadable_rows: Vec::new(),
+            lost_shards: 0,
+            ledger: None,
+            journal: None,
+            settlement: None,
+            violations: Vec::new(),
+            schedule: Schedule::default(),
+            observers: Observers::default(),
+            risk_scorers: RiskScorers::default(),
+            events: None,
+        }
+    }
+
+    /// Handle a single transaction, updating balances if it succeeds.
+    /// Unlike the pipeline, rejections aren't recorded in `rejections`.
+    pub fn handle(&mut self, record: TransactionRecord) -> Result<(), TransactionError> {
+        handlers::handle_transaction(record, self)
+    }
+
+    /// Save what handling a transaction may change, before handling it.
+    /// Its account, transaction and dispute are all the disputed transaction's client's,
+    /// for a dispute, resolve, chargeback or representment.
+    pub(crate) fn undo_point(&self, record: &TransactionRecord) -> UndoPoint {
+        let key = self.affected_account(record);
+        let (client_id, tx_id) = (key.0, record.tx_id);
+        UndoPoint {
+            key,
+            tx_id,
+            account: self.accounts.get(key.0, key.1).cloned(),
+            transaction: self.transactions.save(client_id, tx_id, record.reverses),
+            dispute: self.disputes.save(client_id, tx_id),
+            activity: self.activity.get(client_id).cloned(),
+            ledger_len: self.ledger.as_ref().map(|ledger| ledger.entries().len()),
+            journal_len: self.journal.as_ref().map(|journal| journal.entries().len()),
+            settlement: self.settlement.clone(),
+        }
+    }
+
+    /// Put back everything handling a transaction may have changed, as it was saved,
+    /// e.g. after handling it panicked part way through.
+    pub(crate) fn undo(&mut self, undo: UndoPoint) {
+        let UndoPoint {
+            key,
+            tx_id,
+            account,
+            transaction,
+            dispute,
+            activity,
+            ledger_len,
+            journal_len,
+            settlement,
+        } = undo;
+        let client_id = key.0;
+        match account {
+            Some(account) => self.accounts.insert(key, account),
+            None => self.accounts.remove(key),
+        }
+        self.transactions.put_back(client_id, tx_id, transaction);
+        self.disputes.put_back(client_id, tx_id, dispute);
+        match activity {
+            Some(activity) => self.activity.insert(client_id, activity),
+            None => self.activity.remove(client_id),
+        }
+        if let (Some(ledger), Some(len)) = (&mut self.ledger, ledger_len) {
+            ledger.truncate(len);
+        }
+        if let (Some(journal), Some(len)) = (&mut self.journal, journal_len) {
+            journal.truncate(len);
+        }
+        self.settlement = settlement;
+        if let Some(events) = &mut self.events {
+            events.discard();
+        }
+    }
+
+    /// Current balances of a client's account in the default currency,
+    /// or `None` if the client has no account.
+    pub fn account(&self, client_id: ClientId) -> Option<AccountView> {
+        self.account_in(client_id, None)
+    }
+
+    /// Current balances of a client's account in the given currency,
+    /// or `None` if the client has no account in that currency.
+    pub fn account_in(
+        &self,
+        client_id: ClientId,
+        currency: Option<CurrencyCode>,
+    ) -> Option<AccountView> {
+        let account = self.accounts.get(client_id, currency)?;
+        Some(AccountView::new((client_id, currency), account))
+    }
+
+    /// How each transaction changed a client's balances, in every currency, in order,
+    /// to explain how they came to be. Empty unless the journal is enabled.
+    pub fn journal(&self, client_id: ClientId) -> impl Iterator<Item = &JournalEntry> {
+        self.journal
+            .iter()
+            .flat_map(move |journal| journal.client(client_id))
+    }
+
+    /// A client's deposit or withdrawal, whether it succeeded or failed.
+    /// Disputes, resolves and chargebacks aren't stored;
+    /// see `dispute_status` for their effect.
+    pub fn transaction(
+        &self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+    ) -> Option<&TransactionContainer> {
+        self.transactions.get(client_id, tx_id)
+    }
+
+    /// Where a client's transaction stands with respect to disputes,
+    /// or `None` if the client has no such transaction.
+    pub fn dispute_status(
+        &self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+    ) -> Option<DisputeStatus> {
+        self.transaction(client_id, tx_id)?;
+        Some(
+            if let Some(amount) = self.disputes.disputed_amount(client_id, tx_id) {
+                DisputeStatus::Disputed { amount }
+            } else if self.disputes.is_settled(client_id, tx_id) {
+                DisputeStatus::Settled
+            } else {
+                DisputeStatus::Undisputed
+            },
+        )
+    }
+
+    /// A client's deposits and withdrawals in the order they were handled,
+    /// along with whether each succeeded and where it stands with respect to disputes.
+    pub fn history(&self, client_id: ClientId) -> impl Iterator<Item = HistoryEntry<'_>> {
+        self.transactions
+            .iter_client(client_id)
+            .map(move |(tx_id, transaction)| HistoryEntry {
+                tx: tx_id,
+                transaction,
+                dispute_status: self.dispute_status(client_id, tx_id).unwrap_or_default(),
+            })
+    }
+
+    /// Account changed by a transaction: its own, or for a dispute, resolve,
+    /// chargeback or representment, the disputed transaction's, which may belong to another
+    /// client or currency if the validation policy allows it.
+    pub(crate) fn affected_account(&self, record: &TransactionRecord) -> AccountKey {
+        let own = (record.client_id, record.currency);
+        match record.transaction_type {
+            TransactionType::Dispute
+            | TransactionType::Resolve
+            | TransactionType::Chargeback
+            | TransactionType::Representment => {
+                match self
+                    .transactions
+                    .find(record.client_id, record.tx_id)
+                    .map(TransactionContainer::try_get_disputable)
+                {
+                    Some(Ok(Ok(disputed_tx))) => {
+                        (disputed_tx.get_client_id(), disputed_tx.get_currency())
+                    }
+                    _ => own,
+                }
+            }
+            _ => own,
+        }
+    }
+
+    /// Combine another state into this one, e.g. a shard of clients handled
+    /// on another thread or machine, so that together they make one final report.
+    /// The states must have different clients, and ids unique among the same scope.
+    /// If they don't, neither changes. This state's policies are kept.
+    pub fn merge(&mut self, other: State) -> Result<(), MergeError> {
+        if let Some(conflict) = self.transactions.merge_conflict(&other.transactions) {
+            return Err(conflict);
+        }
+        self.accounts.merge(other.accounts)?;
+        self.transactions.absorb(other.transactions);
+        self.disputes.absorb(other.disputes);
+        self.activity.absorb(other.activity);
+        self.rejections.extend(other.rejections);
+        self.unreadable_rows.extend(other.unreadable_rows);
+        self.lost_shards += other.lost_shards;
+        if let Some(other_ledger) = other.ledger {
+            self.ledger
+                .get_or_insert_with(Default::default)
+                .extend(other_ledger);
+        }
+        if let Some(other_journal) = other.journal {
+            self.journal
+                .get_or_insert_with(Default::default)
+                .extend(other_journal);
         }
+        if let Some(other_settlement) = other.settlement {
+            self.settlement
+                .get_or_insert_with(Default::default)
+                .extend(other_settlement);
+        }
+        self.violations.extend(other.violations);
+        self.schedule.extend(other.schedule);
+        Ok(())
+    }
+
+    /// Split into a state for each client, e.g. so that each can be handed to
+    /// whichever handler is responsible for it, and merged back together with `merge`.
+    /// Rejections, unreadable rows, lost shards, ledger and journal entries, settlement totals, violations
+    /// and the schedule are returned
+    /// in a separate state with no clients, along with the policies.
+    pub(crate) fn split_by_client(self) -> (State, Vec<(ClientId, State)>) {
+        let State {
+            accounts,
+            transactions,
+            disputes,
+            activity,
+            policies,
+            rejections,
+            unreadable_rows,
+            lost_shards,
+            ledger,
+            journal,
+            settlement,
+            violations,
+            schedule,
+            observers,
+            risk_scorers,
+            events,
+        } = self;
+        let scope = transactions.scope;
+        let latest = disputes.latest;
+        let empty = || {
+            let mut state = State::with_policies(policies.clone());
+            state.transactions = TransactionsState::with_scope(scope);
+            state.disputes.latest = latest;
+            state
+        };
+
+        // Keeping the order in which accounts were created
+        let mut clients: IndexMap<ClientId, State, FxBuildHasher> = IndexMap::default();
+        for (key, account) in accounts.0 {
+            let client = clients.entry(key.0).or_insert_with(empty);
+            client.accounts.0.insert(key, account);
+        }
+        for (client_id, client_txs) in transactions.by_client {
+            let client = clients.entry(client_id).or_insert_with(empty);
+            for &tx_id in client_txs.keys() {
+                if transactions.clients_by_tx.get(&tx_id) == Some(&client_id) {
+                    client.transactions.clients_by_tx.insert(tx_id, client_id);
+                }
+            }
+            client.transactions.by_client.insert(client_id, client_txs);
+        }
+        for (client_id, client_reversed) in transactions.reversed {
+            let client = clients.entry(client_id).or_insert_with(empty);
+            client
+                .transactions
+                .reversed
+                .insert(client_id, client_reversed);
+        }
+        for (client_id, client_charged_back) in transactions.charged_back {
+            let client = clients.entry(client_id).or_insert_with(empty);
+            client
+                .transactions
+                .charged_back
+                .insert(client_id, client_charged_back);
+        }
+        for (client_id, client_active) in disputes.active {
+            let client = clients.entry(client_id).or_insert_with(empty);
+            client.disputes.active.insert(client_id, client_active);
+        }
+        for (client_id, client_settled) in disputes.settled {
+            let client = clients.entry(client_id).or_insert_with(empty);
+            client.disputes.settled.insert(client_id, client_settled);
+        }
+        for (client_id, client_details) in disputes.details {
+            let client = clients.entry(client_id).or_insert_with(empty);
+            client.disputes.details.insert(client_id, client_details);
+        }
+        for expiry in disputes.expiries {
+            let Reverse((_, client_id, _)) = expiry;
+            let client = clients.entry(client_id).or_insert_with(empty);
+            client.disputes.expiries.push(expiry);
+        }
+        for (client_id, client_activity) in activity.into_clients() {
+            let client = clients.entry(client_id).or_insert_with(empty);
+            client.activity.insert(client_id, client_activity);
+        }
+
+        let mut reports = empty();
+        reports.rejections = rejections;
+        reports.unreadable_rows = unreadable_rows;
+        reports.lost_shards = lost_shards;
+        reports.ledger = ledger;
+        reports.journal = journal;
+        reports.settlement = settlement;
+        reports.violations = violations;
+        reports.schedule = schedule;
+        reports.observers = observers;
+        reports.risk_scorers = risk_scorers;
+        reports.events = events;
+        (reports, clients.into_iter().collect())
+    }
+
+    /// Check that the state is internally consistent. See `invariants`.
+    pub fn check_invariants(&self) -> Vec<Violation> {
+        invariants::check(self)
+    }
+
+    /// Hex digest of final balances, for comparing runs. See `digest`.
+    pub fn digest(&self) -> Result<String, InvalidAccount> {
+        accounts_digest(&self.accounts, &self.policies.rounding)
+    }
+
+    /// Counts of input which didn't make it into the balances.
+    pub fn summary(&self) -> Summary {
+        Summary {
+            rejected: self.rejections.len(),
+            skipped: self.unreadable_rows.len(),
+        }
+    }
+}
+
+/// Why two states couldn't be merged.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MergeError {
+    /// Both states have an account for this client and currency
+    DuplicateAccount(AccountKey),
+    /// Both states have transactions for this client
+    DuplicateClient(ClientId),
+    /// Transaction ids are unique among different scopes in each state
+    ScopeMismatch,
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DuplicateAccount((client, None)) => {
+                write!(f, "both states have an account for client {}", client)
+            }
+            Self::DuplicateAccount((client, Some(currency))) => write!(
+                f,
+                "both states have a {} account for client {}",
+                currency, client
+            ),
+            Self::DuplicateClient(client) => {
+                write!(f, "both states have transactions for client {}", client)
+            }
+            Self::ScopeMismatch => write!(f, "transaction id scopes differ"),
+        }
+    }
+}
+
+impl Error for MergeError {}
+
+/// A snapshot of an account's balances, e.g. for answering
+/// a support query. Amounts aren't rounded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AccountView {
+    pub client: ClientId,
+    pub currency: Option<CurrencyCode>,
+    pub available: Currency,
+    pub held: Currency,
+    pub locked: bool,
+    /// Total fees charged to the account
+    pub fees: Currency,
+    /// Whether the account was flagged for review, because part
+    /// of a chargeback was written off, or by a risk check
+    pub flagged: bool,
+}
+
+impl AccountView {
+    pub fn new((client, currency): AccountKey, account: &Account) -> Self {
+        Self {
+            client,
+            currency,
+            available: account.available(),
+            held: account.held(),
+            locked: account.locked(),
+            fees: account.fees(),
+            flagged: account.flagged(),
+        }
+    }
+
+    /// Sum of `available` and `held`, checked like `Account::total`.
+    pub fn total(&self) -> Result<Currency, InvalidAccount> {
+        self.available
+            .checked_add(self.held)
+            .ok_or(InvalidAccount::TotalOverflow)
+    }
+}
+
+/// Where a transaction stands with respect to disputes.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DisputeStatus {
+    /// Never disputed, so it can still be
+    #[default]
+    Undisputed,
+    /// Actively disputed, with this amount held
+    Disputed { amount: Currency },
+    /// Disputed and then resolved or charged back,
+    /// so it may only be disputed again if the policy allows
+    Settled,
+}
+
+/// A transaction in a client's history.
+#[derive(Debug)]
+pub struct HistoryEntry<'a> {
+    pub tx: TransactionId,
+    /// The transaction, or why it failed
+    pub transaction: &'a TransactionContainer,
+    pub dispute_status: DisputeStatus,
+}
+
+/// How much of the input was dropped while processing,
+/// e.g. for failing a run which should have been clean.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Summary {
+    /// Transactions which were rejected
+    pub rejected: usize,
+    /// Input rows which couldn't be read or deserialized
+    pub skipped: usize,
+}
+
+impl Summary {
+    /// Whether every input row was read and handled successfully.
+    pub fn is_clean(&self) -> bool {
+        self.rejected == 0 && self.skipped == 0
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} transaction(s) rejected, {} row(s) skipped",
+            self.rejected, self.skipped
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DisputeStatus, DisputesState, MergeError, State};
+    use crate::currency::Currency;
+    use crate::journal::Journal;
+    use crate::ledger::Ledger;
+    use crate::source::ParseError;
+    use crate::test_utils::{deposit, dispute, resolve, withdrawal};
+    use crate::types::{DisputeAction, Rejection, TransactionError, TransactionType};
+    use std::time::Duration;
+
+    #[test]
+    fn test_queries() {
+        let mut state = State::new();
+        assert_eq!(state.account(1), None);
+
+        state.handle(deposit(1, 1, 5.0)).unwrap();
+        state.handle(deposit(1, 2, 3.0)).unwrap();
+        state.handle(dispute(1, 1)).unwrap();
+        state.handle(dispute(1, 2)).unwrap();
+        state.handle(resolve(1, 2)).unwrap();
+
+        let account = state.account(1).unwrap();
+        assert_eq!(account.available, Currency::from(3.0));
+        assert_eq!(account.held, Currency::from(5.0));
+        assert_eq!(account.total(), Ok(Currency::from(8.0)));
+        assert_eq!(state.account(2), None);
+
+        assert_eq!(
+            state.transaction(1, 1).map(|tx| tx.tx_type()),
+            Some(TransactionType::Deposit)
+        );
+        // Transactions are only found under their own client
+        assert!(state.transaction(2, 1).is_none());
+
+        assert_eq!(
+            state.dispute_status(1, 1),
+            Some(DisputeStatus::Disputed {
+                amount: Currency::from(5.0)
+            })
+        );
+        assert_eq!(state.dispute_status(1, 2), Some(DisputeStatus::Settled));
+        assert_eq!(state.dispute_status(1, 3), None);
+    }
+
+    #[test]
+    fn test_history() {
+        let mut state = State::new();
+        // Handled out of tx id order, with a failure in between
+        for record in [
+            deposit(1, 3, 5.0),
+            withdrawal(1, 1, 9.0),
+            deposit(1, 2, 1.0),
+            dispute(1, 3),
+        ] {
+            let _ = state.handle(record);
+        }
+
+        let history: Vec<_> = state
+            .history(1)
+            .map(|entry| {
+                (
+                    entry.tx,
+                    entry.transaction.outcome().is_ok(),
+                    entry.dispute_status,
+                )
+            })
+            .collect();
+        assert_eq!(
+            history,
+            vec![
+                (
+                    3,
+                    true,
+                    DisputeStatus::Disputed {
+                        amount: Currency::from(5.0)
+                    }
+                ),
+                (1, false, DisputeStatus::Undisputed),
+                (2, true, DisputeStatus::Undisputed),
+            ]
+        );
+        assert_eq!(state.history(2).count(), 0);
+    }
+
+    #[test]
+    fn test_expire_settled() {
+        let mut disputes = DisputesState::default();
+        let max_age = Duration::from_secs(100);
+        for (tx_id, occurred_at) in [(1, 1000), (2, 1050), (3, 1100)] {
+            disputes
+                .dispute_tx(1, tx_id, Currency::from(1.0), None, None)
+                .unwrap();
+            disputes.resolve(1, tx_id, None).unwrap();
+            disputes.schedule_expiry(1, tx_id, occurred_at);
+        }
+        disputes
+            .dispute_tx(1, 4, Currency::from(1.0), None, None)
+            .unwrap();
+
+        assert_eq!(disputes.expire_settled(1100, max_age), 0);
+        assert_eq!(disputes.expire_settled(1160, max_age), 2);
+        // Time never goes backwards
+        assert_eq!(disputes.expire_settled(0, max_age), 0);
+        assert_eq!(disputes.latest(), Some(1160));
+
+        assert!(!disputes.is_settled(1, 1));
+        assert!(!disputes.is_settled(1, 2));
+        assert!(disputes.is_settled(1, 3));
+        assert_eq!(disputes.num_settled(), 1);
+        assert_eq!(disputes.num_active(), 1);
+    }
+
+    #[test]
+    fn test_dispute_history() {
+        let mut state = State::new();
+        state.policies.dispute.max_redisputes = 1;
+        for record in [
+            deposit(1, 1, 5.0).with_timestamp(10),
+            dispute(1, 1).with_timestamp(20),
+            resolve(1, 1).with_timestamp(30),
+            dispute(1, 1).with_timestamp(40),
+            resolve(1, 1),
+        ] {
+            state.handle(record).unwrap();
+        }
+        let steps: Vec<_> = state
+            .disputes
+            .history(1, 1)
+            .iter()
+            .map(|step| (step.action, step.timestamp))
+            .collect();
+        assert_eq!(
+            steps,
+            vec![
+                (DisputeAction::Opened, Some(20)),
+                (DisputeAction::Resolved, Some(30)),
+                (DisputeAction::Reopened, Some(40)),
+                (DisputeAction::Resolved, None),
+            ]
+        );
+        assert_eq!(state.disputes.times_disputed(1, 1), 2);
+
+        // Already disputed again as many times as allowed
+        assert_eq!(
+            state.handle(dispute(1, 1)),
+            Err(TransactionError::RedisputeNotAllowed { client: 1, tx: 1 })
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        let records = vec![
+            deposit(1, 1, 5.0),
+            deposit(2, 2, 3.0),
+            dispute(1, 1),
+            withdrawal(2, 3, 1.0),
+        ];
+
+        let mut whole = State::new();
+        let mut shards = vec![State::new(), State::new()];
+        for record in records {
+            let _ = whole.handle(record.clone());
+            let _ = shards[record.client_id as usize - 1].handle(record);
+        }
+        let mut merged = shards.remove(0);
+        merged.merge(shards.remove(0)).unwrap();
+
+        assert_eq!(merged.accounts, whole.accounts);
+        assert_eq!(
+            merged.dispute_status(1, 1),
+            Some(DisputeStatus::Disputed {
+                amount: Currency::from(5.0)
+            })
+        );
+        assert_eq!(merged.history(2).count(), 2);
+        assert!(merged.check_invariants().is_empty());
+
+        // Neither state changes if they share a client
+        let mut other = State::new();
+        other.handle(deposit(2, 4, 1.0)).unwrap();
+        assert_eq!(merged.merge(other), Err(MergeError::DuplicateClient(2)));
+        assert_eq!(merged.accounts, whole.accounts);
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        let mut state = State::new();
+        state.ledger = Some(Ledger::default());
+        state.journal = Some(Journal::default());
+        for record in [
+            deposit(1, 1, 5.0),
+            deposit(1, 2, 2.0).with_currency("EUR".parse().unwrap()),
+            withdrawal(2, 3, 1.0),
+            deposit(2, 4, 3.0).with_timestamp(100),
+            dispute(2, 4),
+            dispute(1, 1),
+            resolve(1, 1),
+        ] {
+            if let Err(error) = state.handle(record.clone()) {
+                state.rejections.push(Rejection { record, error });
+            }
+        }
+        state
+            .unreadable_rows
+            .push(ParseError::new(Some(9), "bad row").with_content("deposit,1"));
+
+        let json = serde_json::to_string(&state).unwrap();
+        let restored: State = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.accounts, state.accounts);
+        assert_eq!(restored.rejections, state.rejections);
+        assert_eq!(restored.ledger, state.ledger);
+        assert_eq!(restored.journal, state.journal);
+        assert_eq!(restored.policies, state.policies);
+        assert_eq!(restored.unreadable_rows, state.unreadable_rows);
+        // Compared as JSON, since hash maps' order depends on how they were built
+        use serde_json::to_value;
+        assert_eq!(
+            to_value(&restored.transactions).unwrap(),
+            to_value(&state.transactions).unwrap()
+        );
+        assert_eq!(
+            to_value(&restored.disputes).unwrap(),
+            to_value(&state.disputes).unwrap()
+        );
+        assert_eq!(
+            restored.dispute_status(2, 4),
+            Some(DisputeStatus::Disputed {
+                amount: Currency::from(3.0)
+            })
+        );
+        assert_eq!(restored.dispute_status(1, 1), Some(DisputeStatus::Settled));
+
+        // Accounts are listed in order, with the currency left out when it's the default
+        let accounts = serde_json::to_value(&state.accounts).unwrap();
+        assert_eq!(
+            accounts[0],
+            serde_json::json!({
+                "client": 1,
+                "available": "5.0",
+                "held": "0.0",
+                "locked": false,
+                "fees": "0.0",
+                "flagged": false,
+            })
+        );
+        assert_eq!(accounts[1]["currency"], "EUR");
+    }
+
+    #[test]
+    fn test_deserialize_without_expiries() {
+        let mut state = State::new();
+        for record in [
+            deposit(1, 1, 5.0),
+            deposit(1, 2, 3.0),
+            dispute(1, 1),
+            resolve(1, 1),
+            dispute(1, 2),
+        ] {
+            state.handle(record).unwrap();
+        }
+
+        // As serialized before settled disputes could be forgotten
+        let mut json = serde_json::to_value(&state).unwrap();
+        let disputes = json["disputes"].as_object_mut().unwrap();
+        assert!(disputes.remove("expiries").is_some());
+        assert!(disputes.remove("latest").is_some());
+
+        let mut restored: State = serde_json::from_value(json).unwrap();
+        assert_eq!(restored.disputes.latest(), None);
+        assert_eq!(restored.dispute_status(1, 1), Some(DisputeStatus::Settled));
+        assert_eq!(
+            restored.dispute_status(1, 2),
+            Some(DisputeStatus::Disputed {
+                amount: Currency::from(3.0)
+            })
+        );
+        restored.handle(resolve(1, 2)).unwrap();
+        assert_eq!(restored.dispute_status(1, 2), Some(DisputeStatus::Settled));
     }
 }
@@ -0,0 +1,245 @@
+//! Account statements: one client's transactions in the order they were
+//! handled, each with the balances it left behind, followed by final totals.
+
+use serde::Serialize;
+use std::error::Error;
+use std::io;
+use std::str::FromStr;
+
+use crate::currency::{Currency, CurrencyCode};
+use crate::policy::Policies;
+use crate::source::ParseError;
+use crate::state::State;
+use crate::types::{ClientId, InvalidAccount, OutputRecord, TransactionId};
+use crate::types::{TransactionRecord, TransactionType};
+
+/// Format to write a statement in.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum StatementFormat {
+    /// One row per transaction, followed by one `closing` row per account.
+    #[default]
+    Csv,
+    /// A single object with the client, its transactions, and final totals.
+    Json,
+}
+
+impl FromStr for StatementFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            other => Err(format!("unknown statement format '{}'", other)),
+        }
+    }
+}
+
+/// A transaction on a statement, with the account's balances right after it.
+/// Rejected transactions are listed too, with the code of the error,
+/// and leave the balances unchanged.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct StatementLine {
+    pub tx: TransactionId,
+    #[serde(rename = "type")]
+    pub transaction_type: TransactionType,
+    pub currency: Option<CurrencyCode>,
+    pub amount: Option<Currency>,
+    /// Error code if the transaction was rejected, e.g. `INSUFFICIENT_FUNDS`
+    pub error: Option<&'static str>,
+    pub available: Currency,
+    pub held: Currency,
+    pub total: Currency,
+    pub locked: bool,
+}
+
+/// A client's transactions and final balances.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Statement {
+    pub client: ClientId,
+    pub transactions: Vec<StatementLine>,
+    /// Final balances of each of the client's accounts
+    pub totals: Vec<OutputRecord>,
+    /// Rows which couldn't be read, any of which may have been the client's
+    pub unreadable_rows: Vec<ParseError>,
+}
+
+impl Statement {
+    /// Handle every transaction in order, keeping track of `client_id`'s,
+    /// and of every row which couldn't be read, e.g. from `read_transactions`.
+    /// Other clients' transactions are handled too, since transaction ids
+    /// are shared between clients. Fails if one of the client's balances can't be written.
+    pub fn new(
+        client_id: ClientId,
+        records: impl IntoIterator<Item = Result<TransactionRecord, ParseError>>,
+        policies: Policies,
+    ) -> Result<Self, InvalidAccount> {
+        let mut state = State::with_policies(policies);
+        let mut transactions = Vec::new();
+        for record in records {
+            let record = match record {
+                Ok(record) => record,
+                Err(err) => {
+                    state.unreadable_rows.push(err);
+                    continue;
+                }
+            };
+            if record.client_id != client_id {
+                // Rejections are only reported for this client
+                let _ = state.handle(record);
+                continue;
+            }
+
+            let key = (client_id, record.currency);
+            let line = StatementLine {
+                tx: record.tx_id,
+                transaction_type: record.transaction_type.clone(),
+                currency: record.currency,
+                amount: record.amount,
+                error: state.handle(record).err().map(|err| err.code()),
+                available: Currency::ZERO,
+                held: Currency::ZERO,
+                total: Currency::ZERO,
+                locked: false,
+            };
+            // The account may not exist if its first transaction was rejected
+            transactions.push(match state.accounts.get(key.0, key.1) {
+                Some(account) => {
+                    let balance = OutputRecord::new(key, account, &state.policies.rounding)?;
+                    StatementLine {
+                        available: balance.available,
+                        held: balance.held,
+                        total: balance.total,
+                        locked: balance.locked,
+                        ..line
+                    }
+                }
+                None => line,
+            });
+        }
+
+        let totals = state
+            .accounts
+            .iter()
+            .filter(|(&(client, _), _)| client == client_id)
+            .map(|(&key, account)| OutputRecord::new(key, account, &state.policies.rounding))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            client: client_id,
+            transactions,
+            totals,
+            unreadable_rows: state.unreadable_rows,
+        })
+    }
+
+    /// Write the statement in the given format.
+    pub fn write<W: io::Write>(
+        &self,
+        format: StatementFormat,
+        mut output_stream: W,
+    ) -> Result<(), Box<dyn Error>> {
+        match format {
+            StatementFormat::Csv => self.write_csv(output_stream),
+            StatementFormat::Json => {
+                serde_json::to_writer_pretty(&mut output_stream, self)?;
+                writeln!(output_stream)?;
+                Ok(())
+            }
+        }
+    }
+
+    fn write_csv<W: io::Write>(&self, output_stream: W) -> Result<(), Box<dyn Error>> {
+        let mut writer = csv::Writer::from_writer(output_stream);
+        for line in &self.transactions {
+            writer.serialize(line)?;
+        }
+        // Final totals share the transaction columns, so that the file
+        // stays rectangular, e.g. for spreadsheets
+        for total in &self.totals {
+            writer.serialize(StatementTotal {
+                tx: None,
+                transaction_type: "closing",
+                currency: total.currency,
+                amount: None,
+                error: None,
+                available: total.available,
+                held: total.held,
+                total: total.total,
+                locked: total.locked,
+            })?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// A `closing` row of a CSV statement.
+#[derive(Serialize)]
+struct StatementTotal {
+    tx: Option<TransactionId>,
+    #[serde(rename = "type")]
+    transaction_type: &'static str,
+    currency: Option<CurrencyCode>,
+    amount: Option<Currency>,
+    error: Option<&'static str>,
+    available: Currency,
+    held: Currency,
+    total: Currency,
+    locked: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Statement, StatementFormat};
+
+    use crate::source::ParseError;
+    use crate::test_utils::{deposit, dispute, resolve, withdrawal};
+
+    fn statement() -> Statement {
+        let records = vec![
+            Ok(deposit(1, 1, 5.0)),
+            Ok(deposit(2, 2, 7.0)),
+            Ok(withdrawal(1, 3, 9.0)),
+            Err(ParseError::new(Some(5), "invalid amount").with_content("deposit,1,5,x")),
+            Ok(dispute(1, 1)),
+            Ok(resolve(1, 1)),
+            Ok(withdrawal(1, 4, 2.0)),
+        ];
+        Statement::new(1, records, Default::default()).unwrap()
+    }
+
+    #[test]
+    fn test_statement_csv() {
+        let mut output = Vec::new();
+        statement()
+            .write(StatementFormat::Csv, &mut output)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "\
+tx,type,currency,amount,error,available,held,total,locked
+1,deposit,,5.0,,5.0,0.0,5.0,false
+3,withdrawal,,9.0,INSUFFICIENT_FUNDS,5.0,0.0,5.0,false
+1,dispute,,,,0.0,5.0,5.0,false
+1,resolve,,,,5.0,0.0,5.0,false
+4,withdrawal,,2.0,,3.0,0.0,3.0,false
+,closing,,,,3.0,0.0,3.0,false
+"
+        );
+    }
+
+    #[test]
+    fn test_statement_json() {
+        let mut output = Vec::new();
+        statement()
+            .write(StatementFormat::Json, &mut output)
+            .unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(value["client"], 1);
+        assert_eq!(value["transactions"].as_array().unwrap().len(), 5);
+        assert_eq!(value["transactions"][1]["error"], "INSUFFICIENT_FUNDS");
+        assert_eq!(value["totals"][0]["available"], "3.0");
+        assert_eq!(value["unreadable_rows"][0]["line"], 5);
+    }
+}
@@ -0,0 +1,199 @@
+//! Profile of an input, gathered without handling any transactions,
+//! e.g. to sanity check a file before committing to a full run.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::io;
+use std::sync::mpsc::channel;
+
+use crate::construct_csv_reader;
+use crate::currency::Currency;
+use crate::input::{tagged_records, Inputs};
+use crate::types::{ClientId, TransactionId, TransactionRecord, TransactionType};
+
+/// Distribution of deposit and withdrawal amounts.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct AmountPercentiles {
+    pub min: Currency,
+    pub p50: Currency,
+    pub p90: Currency,
+    pub p99: Currency,
+    pub max: Currency,
+}
+
+impl AmountPercentiles {
+    /// Nearest-rank percentiles of sorted amounts, if there are any.
+    fn new(sorted: &[Currency]) -> Option<Self> {
+        let last = sorted.len().checked_sub(1)?;
+        let percentile = |p: f64| sorted[(p * last as f64).round() as usize];
+        Some(Self {
+            min: sorted[0],
+            p50: percentile(0.5),
+            p90: percentile(0.9),
+            p99: percentile(0.99),
+            max: sorted[last],
+        })
+    }
+}
+
+/// Counts and distributions describing an input.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct InputStats {
+    /// Rows which were read and deserialized
+    pub rows: usize,
+    /// Rows which couldn't be read or deserialized
+    pub malformed: usize,
+    /// Number of rows of each transaction type
+    pub types: BTreeMap<&'static str, usize>,
+    /// Number of distinct clients
+    pub clients: usize,
+    /// Deposits and withdrawals whose tx id was already taken
+    pub duplicate_tx_ids: usize,
+    /// Distribution of deposit and withdrawal amounts, if there were any
+    pub amounts: Option<AmountPercentiles>,
+}
+
+/// Gathers `InputStats` one record at a time.
+#[derive(Default)]
+struct StatsBuilder {
+    stats: InputStats,
+    clients: HashSet<ClientId>,
+    tx_ids: HashSet<TransactionId>,
+    amounts: Vec<Currency>,
+}
+
+impl StatsBuilder {
+    fn add(&mut self, record: &TransactionRecord) {
+        self.stats.rows += 1;
+        *self
+            .stats
+            .types
+            .entry(record.transaction_type.name())
+            .or_default() += 1;
+        self.clients.insert(record.client_id);
+
+        // Only deposits and withdrawals claim their tx id
+        if let TransactionType::Deposit | TransactionType::Withdrawal = record.transaction_type {
+            if !self.tx_ids.insert(record.tx_id) {
+                self.stats.duplicate_tx_ids += 1;
+            }
+            if let Some(amount) = record.amount {
+                self.amounts.push(amount);
+            }
+        }
+    }
+
+    fn finish(mut self) -> InputStats {
+        self.stats.clients = self.clients.len();
+        self.amounts.sort_unstable();
+        self.stats.amounts = AmountPercentiles::new(&self.amounts);
+        self.stats
+    }
+}
+
+impl InputStats {
+    /// Read every input in order, without handling any transactions.
+    /// Fails only if an input's headers can't be read.
+    pub fn scan<R: io::Read + Send + 'static>(
+        inputs: Inputs<R>,
+        notrim: bool,
+    ) -> csv::Result<Self> {
+        let has_headers = inputs.headers.has_header_row();
+        let mut readers: Vec<_> = inputs
+            .streams
+            .into_iter()
+            .map(|input| construct_csv_reader(input, notrim, has_headers))
+            .collect();
+        let mapping = &inputs.headers;
+        let headers = readers
+            .iter_mut()
+            .map(|reader| mapping.read_headers(reader))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let (errors_snd, errors_rcv) = channel();
+        let mut builder = StatsBuilder::default();
+        let mut undeserializable = 0;
+        for (input, record) in tagged_records(readers, &headers, inputs.order, errors_snd) {
+            match record.deserialize(Some(&headers[input])) {
+                Ok(record) => builder.add(&record),
+                Err(err) => {
+                    tracing::error!("Error while deserializing: {}", err);
+                    undeserializable += 1;
+                }
+            }
+        }
+
+        let mut stats = builder.finish();
+        stats.malformed = errors_rcv.try_iter().count() + undeserializable;
+        Ok(stats)
+    }
+}
+
+impl fmt::Display for InputStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "rows: {}", self.rows)?;
+        writeln!(f, "malformed rows: {}", self.malformed)?;
+        writeln!(f, "clients: {}", self.clients)?;
+        writeln!(f, "duplicate tx ids: {}", self.duplicate_tx_ids)?;
+        writeln!(f, "types:")?;
+        for (name, count) in &self.types {
+            writeln!(f, "  {}: {}", name, count)?;
+        }
+        if let Some(amounts) = &self.amounts {
+            writeln!(f, "deposit and withdrawal amounts:")?;
+            writeln!(f, "  min: {}", amounts.min)?;
+            writeln!(f, "  p50: {}", amounts.p50)?;
+            writeln!(f, "  p90: {}", amounts.p90)?;
+            writeln!(f, "  p99: {}", amounts.p99)?;
+            writeln!(f, "  max: {}", amounts.max)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InputStats;
+    use crate::input::Inputs;
+
+    #[test]
+    fn test_scan() {
+        let input = "\
+type,client,tx,amount
+deposit,1,1,1.0
+deposit,2,2,2.0
+withdrawal,1,3,4.0
+deposit,3,3,3.0
+dispute,1,1,
+deposit,not a client,4,1.0
+";
+        let stats = InputStats::scan(Inputs::single(input.as_bytes()), false).unwrap();
+        assert_eq!(
+            stats.to_string(),
+            "\
+rows: 5
+malformed rows: 1
+clients: 3
+duplicate tx ids: 1
+types:
+  deposit: 3
+  dispute: 1
+  withdrawal: 1
+deposit and withdrawal amounts:
+  min: 1.0
+  p50: 3.0
+  p90: 4.0
+  p99: 4.0
+  max: 4.0
+"
+        );
+    }
+
+    #[test]
+    fn test_scan_empty() {
+        let stats =
+            InputStats::scan(Inputs::single("type,client,tx,amount\n".as_bytes()), false).unwrap();
+        assert_eq!(stats, InputStats::default());
+    }
+}
@@ -0,0 +1,184 @@
+//! Metrics recorded through the [`metrics`] facade.
+//!
+//! Nothing is recorded unless the application installs a recorder,
+//! e.g. the Prometheus exporter enabled by `--metrics-address`,
+//! so instrumentation costs next to nothing otherwise.
+
+use std::time::Duration;
+
+use metrics::{
+    counter, describe_counter, describe_gauge, describe_histogram, gauge, histogram, Unit,
+};
+
+use crate::types::{TransactionError, TransactionRecord, TransactionType};
+
+/// Transactions handled successfully, labelled by `type`.
+pub const TRANSACTIONS: &str = "payments_transactions_total";
+/// Transactions rejected, labelled by `type` and error `code`.
+pub const ERRORS: &str = "payments_transaction_errors_total";
+/// Accounts created.
+pub const ACCOUNTS_CREATED: &str = "payments_accounts_created_total";
+/// Disputes opened.
+pub const DISPUTES_OPENED: &str = "payments_disputes_opened_total";
+/// Disputes settled, labelled by `outcome`: `resolve` or `chargeback`.
+pub const DISPUTES_SETTLED: &str = "payments_disputes_settled_total";
+/// Disputes currently open.
+pub const DISPUTES_ACTIVE: &str = "payments_disputes_active";
+/// Settled disputes still remembered, so that they can't be disputed again.
+pub const DISPUTES_REMEMBERED: &str = "payments_disputes_remembered";
+/// Settled disputes forgotten once their transaction aged out of the dispute window.
+pub const DISPUTES_EXPIRED: &str = "payments_disputes_expired_total";
+/// Time taken to deserialize and dispatch each batch of records.
+pub const BATCH_DURATION: &str = "payments_batch_duration_seconds";
+
+/// Register descriptions of every metric with the installed recorder.
+pub fn describe_metrics() {
+    describe_counter!(TRANSACTIONS, "Transactions handled successfully");
+    describe_counter!(ERRORS, "Transactions rejected");
+    describe_counter!(ACCOUNTS_CREATED, "Accounts created");
+    describe_counter!(DISPUTES_OPENED, "Disputes opened");
+    describe_counter!(DISPUTES_SETTLED, "Disputes resolved or charged back");
+    describe_gauge!(DISPUTES_ACTIVE, "Disputes currently open");
+    describe_gauge!(DISPUTES_REMEMBERED, "Settled disputes still remembered");
+    describe_counter!(
+        DISPUTES_EXPIRED,
+        "Settled disputes forgotten after aging out of the dispute window"
+    );
+    describe_histogram!(
+        BATCH_DURATION,
+        Unit::Seconds,
+        "Time taken to deserialize and dispatch each batch of records"
+    );
+}
+
+/// Record the outcome of handling a transaction.
+pub(crate) fn record_handled(
+    record: &TransactionRecord,
+    result: &Result<(), TransactionError>,
+    account_created: bool,
+) {
+    let transaction_type = record.transaction_type.name();
+    match result {
+        Ok(()) => {
+            counter!(TRANSACTIONS, "type" => transaction_type).increment(1);
+            match record.transaction_type {
+                TransactionType::Dispute => {
+                    counter!(DISPUTES_OPENED).increment(1);
+                    gauge!(DISPUTES_ACTIVE).increment(1);
+                }
+                TransactionType::Resolve | TransactionType::Chargeback => {
+                    counter!(DISPUTES_SETTLED, "outcome" => transaction_type).increment(1);
+                    gauge!(DISPUTES_ACTIVE).decrement(1);
+                    gauge!(DISPUTES_REMEMBERED).increment(1);
+                }
+                // Reopens a settled dispute
+                TransactionType::Representment => {
+                    gauge!(DISPUTES_ACTIVE).increment(1);
+                    gauge!(DISPUTES_REMEMBERED).decrement(1);
+                }
+                _ => {}
+            }
+        }
+        Err(err) => record_rejected(record, err),
+    }
+    if account_created {
+        counter!(ACCOUNTS_CREATED).increment(1);
+    }
+}
+
+/// Record a transaction rejected with the given error.
+pub(crate) fn record_rejected(record: &TransactionRecord, err: &TransactionError) {
+    counter!(
+        ERRORS,
+        "type" => record.transaction_type.name(),
+        "code" => err.code()
+    )
+    .increment(1);
+}
+
+/// Record a settled dispute being disputed again, so it's active rather than remembered.
+pub(crate) fn record_dispute_reopened() {
+    gauge!(DISPUTES_REMEMBERED).decrement(1);
+}
+
+/// Record settled disputes being forgotten.
+pub(crate) fn record_disputes_expired(count: usize) {
+    counter!(DISPUTES_EXPIRED).increment(count as u64);
+    gauge!(DISPUTES_REMEMBERED).decrement(count as f64);
+}
+
+/// Record the time taken to process a batch.
+pub(crate) fn record_batch(duration: Duration) {
+    histogram!(BATCH_DURATION).record(duration);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TRANSACTIONS;
+    use super::{record_handled, ACCOUNTS_CREATED, DISPUTES_ACTIVE, DISPUTES_OPENED, ERRORS};
+    use crate::types::{TransactionError, TransactionRecord, TransactionType};
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+    fn record(transaction_type: TransactionType) -> TransactionRecord {
+        TransactionRecord {
+            transaction_type,
+            client_id: 1,
+            tx_id: 1,
+            amount: None,
+            timestamp: None,
+            currency: None,
+            reason: None,
+            reverses: None,
+            effective: None,
+        }
+    }
+
+    #[test]
+    fn test_record_handled() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        metrics::with_local_recorder(&recorder, || {
+            record_handled(&record(TransactionType::Dispute), &Ok(()), true);
+            let err = TransactionError::UnexpectedError("oops".to_string());
+            record_handled(&record(TransactionType::Withdrawal), &Err(err), false);
+        });
+
+        let mut counters: Vec<_> = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .map(|(key, _, _, value)| {
+                let key = key.key();
+                let labels: Vec<_> = key
+                    .labels()
+                    .map(|label| format!("{}={}", label.key(), label.value()))
+                    .collect();
+                (key.name().to_string(), labels.join(","), value)
+            })
+            .collect();
+        counters.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+
+        assert_eq!(
+            counters,
+            vec![
+                (ACCOUNTS_CREATED.into(), "".into(), DebugValue::Counter(1)),
+                (
+                    DISPUTES_ACTIVE.into(),
+                    "".into(),
+                    DebugValue::Gauge(1.0.into())
+                ),
+                (DISPUTES_OPENED.into(), "".into(), DebugValue::Counter(1)),
+                (
+                    ERRORS.into(),
+                    "type=withdrawal,code=UNEXPECTED_ERROR".into(),
+                    DebugValue::Counter(1)
+                ),
+                (
+                    TRANSACTIONS.into(),
+                    "type=dispute".into(),
+                    DebugValue::Counter(1)
+                ),
+            ]
+        );
+    }
+}
@@ -1,17 +1,213 @@
-use std::collections::HashMap;
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
 
+use crate::checkpoint::read_initial_state;
 use crate::handlers::handle_transaction;
-use crate::state::{AccountsState, State};
-use crate::types::{Account, ClientId, TransactionError, TransactionRecord};
+use crate::policy::Policies;
+use crate::state::{AccountOrder, AccountsState, State};
+use crate::types::{Account, AccountKey, ClientId, Currency, CurrencyCode, Timestamp};
+use crate::types::{DisputeReason, LockInfo, LockReason};
+use crate::types::{TransactionError, TransactionId, TransactionRecord, TransactionType};
+
+fn record(
+    transaction_type: TransactionType,
+    client_id: ClientId,
+    tx_id: TransactionId,
+    amount: Option<Currency>,
+) -> TransactionRecord {
+    TransactionRecord {
+        transaction_type,
+        client_id,
+        tx_id,
+        amount,
+        timestamp: None,
+        currency: None,
+        reason: None,
+        reverses: None,
+        effective: None,
+    }
+}
+
+/// A deposit in the default currency, e.g. `deposit(1, 1, 5.0)`.
+pub fn deposit<A: Into<Currency>>(
+    client_id: ClientId,
+    tx_id: TransactionId,
+    amount: A,
+) -> TransactionRecord {
+    record(
+        TransactionType::Deposit,
+        client_id,
+        tx_id,
+        Some(amount.into()),
+    )
+}
+
+/// A withdrawal in the default currency, e.g. `withdrawal(1, 2, 3.0)`.
+pub fn withdrawal<A: Into<Currency>>(
+    client_id: ClientId,
+    tx_id: TransactionId,
+    amount: A,
+) -> TransactionRecord {
+    record(
+        TransactionType::Withdrawal,
+        client_id,
+        tx_id,
+        Some(amount.into()),
+    )
+}
+
+/// A dispute of the whole transaction.
+/// For a partial dispute, see `TransactionRecord::with_amount`.
+pub fn dispute(client_id: ClientId, tx_id: TransactionId) -> TransactionRecord {
+    record(TransactionType::Dispute, client_id, tx_id, None)
+}
+
+pub fn resolve(client_id: ClientId, tx_id: TransactionId) -> TransactionRecord {
+    record(TransactionType::Resolve, client_id, tx_id, None)
+}
+
+pub fn chargeback(client_id: ClientId, tx_id: TransactionId) -> TransactionRecord {
+    record(TransactionType::Chargeback, client_id, tx_id, None)
+}
+
+pub fn representment(client_id: ClientId, tx_id: TransactionId) -> TransactionRecord {
+    record(TransactionType::Representment, client_id, tx_id, None)
+}
+
+pub fn lock(client_id: ClientId, tx_id: TransactionId) -> TransactionRecord {
+    record(TransactionType::Lock, client_id, tx_id, None)
+}
+
+pub fn unlock(client_id: ClientId, tx_id: TransactionId) -> TransactionRecord {
+    record(TransactionType::Unlock, client_id, tx_id, None)
+}
+
+/// A reversal, with its own id, of the client's transaction `reverses`.
+pub fn reversal(
+    client_id: ClientId,
+    tx_id: TransactionId,
+    reverses: TransactionId,
+) -> TransactionRecord {
+    TransactionRecord {
+        reverses: Some(reverses),
+        ..record(TransactionType::Reversal, client_id, tx_id, None)
+    }
+}
+
+/// The optional fields, for records made by the constructors above,
+/// e.g. `deposit(1, 1, 5.0).with_currency("EUR".parse()?)`.
+impl TransactionRecord {
+    pub fn with_amount<A: Into<Currency>>(mut self, amount: A) -> Self {
+        self.amount = Some(amount.into());
+        self
+    }
+
+    pub fn with_timestamp(mut self, timestamp: Timestamp) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    pub fn with_currency(mut self, currency: CurrencyCode) -> Self {
+        self.currency = Some(currency);
+        self
+    }
+
+    pub fn with_reason(mut self, reason: DisputeReason) -> Self {
+        self.reason = Some(reason);
+        self
+    }
+
+    pub fn with_effective(mut self, effective: Timestamp) -> Self {
+        self.effective = Some(effective);
+        self
+    }
+}
+
+/// An account's expected state, starting from a new account's,
+/// e.g. `AccountBuilder::new().available(3.0).held(2.0).locked().build()`.
+#[derive(Clone, Debug, Default)]
+pub struct AccountBuilder {
+    account: Account,
+}
+
+impl AccountBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn available<A: Into<Currency>>(mut self, amount: A) -> Self {
+        self.account.available = amount.into();
+        self
+    }
+
+    pub fn held<A: Into<Currency>>(mut self, amount: A) -> Self {
+        self.account.held = amount.into();
+        self
+    }
+
+    pub fn fees<A: Into<Currency>>(mut self, amount: A) -> Self {
+        self.account.fees = amount.into();
+        self
+    }
+
+    pub fn locked(mut self) -> Self {
+        self.account.locked = true;
+        self
+    }
+
+    /// Locked by transaction `tx`, which had no timestamp.
+    pub fn locked_by(mut self, reason: LockReason, tx: TransactionId) -> Self {
+        self.account.locked = true;
+        self.account.lock_info = Some(LockInfo {
+            reason,
+            tx,
+            timestamp: None,
+        });
+        self
+    }
+
+    pub fn flagged(mut self) -> Self {
+        self.account.flagged = true;
+        self
+    }
+
+    pub fn build(self) -> Account {
+        self.account
+    }
+}
 
 /// Given an initial state and a set of transactions,
 /// test that the final account states and generated errors
 /// both match their expected values.
-pub fn run_test_scenario(
+/// Final accounts may be keyed by client id alone,
+/// for accounts in the default currency.
+pub fn run_test_scenario<A: Into<AccountsState>>(
     initial_state: State,
     transactions: Vec<TransactionRecord>,
-    final_accounts: HashMap<ClientId, Account>,
+    final_accounts: A,
     expected_errors: Vec<TransactionError>,
+) {
+    run_test_scenario_with_state(
+        initial_state,
+        transactions,
+        final_accounts,
+        expected_errors,
+        ExpectedState::default(),
+    )
+}
+
+/// Like `run_test_scenario`, but also checking the stored transactions
+/// and disputes, so that a change in, say, which disputes are settled
+/// is caught even when the balances don't change.
+pub fn run_test_scenario_with_state<A: Into<AccountsState>>(
+    initial_state: State,
+    transactions: Vec<TransactionRecord>,
+    final_accounts: A,
+    expected_errors: Vec<TransactionError>,
+    expected_state: ExpectedState,
 ) {
     let mut state = initial_state;
     let mut actual_errors = Vec::new();
@@ -26,4 +222,293 @@ pub fn run_test_scenario(
 
     assert_eq!(final_accounts_state, state.accounts);
     assert_eq!(expected_errors, actual_errors);
+    expected_state.check(&state, "");
+}
+
+/// A transaction by client and id, e.g. `{ client = 1, tx = 2 }`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TxRef {
+    pub client: ClientId,
+    pub tx: TransactionId,
+}
+
+/// A stored deposit or withdrawal, and whether it failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StoredTransaction {
+    pub client: ClientId,
+    pub tx: TransactionId,
+    #[serde(default)]
+    pub failed: bool,
+}
+
+/// An active dispute, and the amount it holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ActiveDispute {
+    pub client: ClientId,
+    pub tx: TransactionId,
+    pub amount: Currency,
+}
+
+/// Transactions and disputes expected after a scenario, each in any order.
+/// Whatever is left as `None` isn't checked.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExpectedState {
+    /// Every stored transaction, including those which failed
+    pub stored: Option<Vec<StoredTransaction>>,
+    /// Every active dispute
+    pub disputed: Option<Vec<ActiveDispute>>,
+    /// Every settled dispute which is still remembered
+    pub settled: Option<Vec<TxRef>>,
+}
+
+impl ExpectedState {
+    /// Assert that the state's transactions and disputes are as expected,
+    /// with `description` naming the case in the message if they aren't.
+    pub fn check(&self, state: &State, description: &str) {
+        if let Some(expected) = &self.stored {
+            let actual = state.transactions.iter().map(|(client, tx)| {
+                let failed = state
+                    .transactions
+                    .get(client, tx)
+                    .is_some_and(|stored| stored.outcome().is_err());
+                StoredTransaction { client, tx, failed }
+            });
+            assert_eq!(
+                sorted(expected.iter().copied()),
+                sorted(actual),
+                "{}: stored transactions",
+                description
+            );
+        }
+        if let Some(expected) = &self.disputed {
+            let actual = state
+                .disputes
+                .active()
+                .map(|(client, tx, amount)| ActiveDispute { client, tx, amount });
+            assert_eq!(
+                sorted(expected.iter().copied()),
+                sorted(actual),
+                "{}: active disputes",
+                description
+            );
+        }
+        if let Some(expected) = &self.settled {
+            let actual = state
+                .disputes
+                .settled()
+                .map(|(client, tx)| TxRef { client, tx });
+            assert_eq!(
+                sorted(expected.iter().copied()),
+                sorted(actual),
+                "{}: settled disputes",
+                description
+            );
+        }
+    }
+}
+
+fn sorted<T: Ord, I: IntoIterator<Item = T>>(items: I) -> Vec<T> {
+    let mut items: Vec<_> = items.into_iter().collect();
+    items.sort();
+    items
+}
+
+/// A test case written as TOML rather than Rust, so that cases can be added
+/// without touching any code, e.g.
+///
+/// ```toml
+/// description = "Withdrawals can't exceed available funds"
+///
+/// transactions = [
+///     { type = "deposit", client = 1, tx = 1, amount = 5.0 },
+///     { type = "withdrawal", client = 1, tx = 2, amount = 8.0 },
+/// ]
+///
+/// accounts = [
+///     { client = 1, available = 5.0 },
+/// ]
+///
+/// errors = ["INSUFFICIENT_FUNDS"]
+/// ```
+///
+/// Policies are given as in a policy file, and any accounts to start with
+/// as `initial`, like `accounts`. Account fields which are left out take
+/// their defaults, e.g. no funds held, and the account not locked.
+/// Accounts may also start from `initial_file`, relative to the scenario,
+/// e.g. a prior day's closing balances (see `read_initial_state`).
+/// Stored transactions, active disputes and settled disputes
+/// may be checked too, as `stored`, `disputed` and `settled` (see `ExpectedState`).
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scenario {
+    /// What the scenario checks
+    pub description: String,
+    /// Errors expected from the transactions, in order
+    pub errors: Vec<ExpectedError>,
+    pub policies: Policies,
+    /// File to read the state before any transactions from
+    pub initial_file: Option<PathBuf>,
+    /// Accounts before any transactions, besides those in `initial_file`
+    pub initial: Vec<ScenarioAccount>,
+    pub transactions: Vec<TransactionRecord>,
+    /// Every account after the transactions, in any order
+    pub accounts: Vec<ScenarioAccount>,
+    pub stored: Option<Vec<StoredTransaction>>,
+    pub disputed: Option<Vec<ActiveDispute>>,
+    pub settled: Option<Vec<TxRef>>,
+}
+
+/// An account's balances, with fields left out taking their defaults.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScenarioAccount {
+    pub client: ClientId,
+    #[serde(default)]
+    pub currency: Option<CurrencyCode>,
+    #[serde(default)]
+    pub available: Currency,
+    #[serde(default)]
+    pub held: Currency,
+    #[serde(default)]
+    pub locked: bool,
+    #[serde(default)]
+    pub fees: Currency,
+    #[serde(default)]
+    pub flagged: bool,
+    /// Why and when the account was locked,
+    /// e.g. `lock_info = { reason = "chargeback", tx = 1 }`
+    #[serde(default)]
+    pub lock_info: Option<LockInfo>,
+}
+
+/// An expected error, either as just its code, e.g. `"INSUFFICIENT_FUNDS"`,
+/// or in full, e.g. `{ code = "DUPLICATE_TX", details = { tx = 1 } }`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExpectedError {
+    Code(String),
+    Error(TransactionError),
+}
+
+impl<'de> Deserialize<'de> for ExpectedError {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Read as TOML first, rather than as an untagged enum,
+        // so that amounts may still be given as numbers
+        match toml::Value::deserialize(deserializer)? {
+            toml::Value::String(code) => Ok(Self::Code(code)),
+            error => error.try_into().map(Self::Error).map_err(de::Error::custom),
+        }
+    }
+}
+
+impl ExpectedError {
+    fn matches(&self, err: &TransactionError) -> bool {
+        match self {
+            Self::Code(code) => code == err.code(),
+            Self::Error(expected) => expected == err,
+        }
+    }
+}
+
+impl ScenarioAccount {
+    fn into_entry(self) -> (AccountKey, Account) {
+        let account = Account {
+            available: self.available,
+            held: self.held,
+            locked: self.locked,
+            fees: self.fees,
+            flagged: self.flagged,
+            lock_info: self.lock_info,
+        };
+        ((self.client, self.currency), account)
+    }
+}
+
+fn accounts_state(accounts: Vec<ScenarioAccount>) -> AccountsState {
+    accounts
+        .into_iter()
+        .map(ScenarioAccount::into_entry)
+        .collect::<std::collections::HashMap<_, _>>()
+        .into()
+}
+
+impl Scenario {
+    /// Read a scenario from a TOML file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)?;
+        let mut scenario: Self = toml::from_str(&contents)?;
+        if let (Some(initial_file), Some(directory)) = (&mut scenario.initial_file, path.parent()) {
+            *initial_file = directory.join(&initial_file);
+        }
+        Ok(scenario)
+    }
+
+    /// Run the transactions, checking that the final accounts
+    /// and the errors along the way are as expected.
+    pub fn run(self) {
+        let mut state = match &self.initial_file {
+            Some(path) => read_initial_state(path, self.policies).unwrap_or_else(|err| {
+                panic!("Could not read '{}': {}", path.display(), err);
+            }),
+            None => State::with_policies(self.policies),
+        };
+        for (key, account) in self.initial.into_iter().map(ScenarioAccount::into_entry) {
+            state.accounts.insert(key, account);
+        }
+
+        let mut actual_errors = Vec::new();
+        for transaction in self.transactions {
+            if let Err(err) = handle_transaction(transaction, &mut state) {
+                actual_errors.push(err);
+            }
+        }
+
+        let expected_accounts = accounts_state(self.accounts);
+        assert_eq!(
+            expected_accounts.ordered(AccountOrder::Client),
+            state.accounts.ordered(AccountOrder::Client),
+            "{}",
+            self.description
+        );
+        let errors_match = self.errors.len() == actual_errors.len()
+            && self
+                .errors
+                .iter()
+                .zip(&actual_errors)
+                .all(|(expected, actual)| expected.matches(actual));
+        assert!(
+            errors_match,
+            "{}: expected errors {:?}, but got {:?}",
+            self.description, self.errors, actual_errors
+        );
+
+        let expected_state = ExpectedState {
+            stored: self.stored,
+            disputed: self.disputed,
+            settled: self.settled,
+        };
+        expected_state.check(&state, &self.description);
+    }
+}
+
+/// Run every scenario in a directory, i.e. each `.toml` file,
+/// naming the file of any which fails.
+pub fn run_scenario_files<P: AsRef<Path>>(directory: P) -> Result<(), Box<dyn Error>> {
+    let mut paths = fs::read_dir(directory)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<Vec<_>, std::io::Error>>()?;
+    paths.retain(|path| path.extension().is_some_and(|ext| ext == "toml"));
+    paths.sort();
+
+    for path in paths {
+        println!("Running scenario: {}", path.display());
+        let scenario =
+            Scenario::from_file(&path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        scenario.run();
+    }
+
+    Ok(())
 }
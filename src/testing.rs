@@ -0,0 +1,182 @@
+//! [Proptest](https://docs.rs/proptest) strategies for sequences of transactions,
+//! so that code embedding the engine can be tested against realistic input,
+//! both well-behaved and hostile. Enabled by the `testing` feature.
+//!
+//! ```ignore
+//! use payments_engine_example::testing::valid_transactions;
+//! use proptest::prelude::*;
+//!
+//! proptest! {
+//!     #[test]
+//!     fn my_service_accepts_valid_transactions(records in valid_transactions(10, 100)) {
+//!         // Feed `records` through the integration under test
+//!     }
+//! }
+//! ```
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+use crate::rand::{GeneratedTransaction, TransactionGenerator, TransactionWeights};
+use crate::types::{ClientId, Currency, TransactionId, TransactionRecord, TransactionType};
+
+/// Largest deposit in generated sequences.
+const MAX_DEPOSIT: f64 = 1000.0;
+
+/// Attempts at each generated transaction before the sequence ends early.
+const MAX_ATTEMPTS: usize = 1000;
+
+/// Generated sequences of between zero and `max_len` transactions,
+/// with roughly `invalid_ratio` of them breaking a rule.
+/// Sequences shrink to shorter prefixes of themselves, and then to other seeds.
+fn generated_transactions(
+    max_client: ClientId,
+    max_len: TransactionId,
+    invalid_ratio: f64,
+) -> impl Strategy<Value = Vec<GeneratedTransaction>> {
+    (0..=max_len, any::<u64>()).prop_map(move |(len, seed)| {
+        let mix = TransactionWeights::default().distribution().unwrap();
+        TransactionGenerator::new(
+            Some(len),
+            max_client,
+            Currency::from(MAX_DEPOSIT),
+            MAX_ATTEMPTS,
+            mix,
+            invalid_ratio,
+        )
+        .with_seed(seed)
+        .collect()
+    })
+}
+
+/// Sequences of up to `max_len` transactions among clients `1..=max_client`,
+/// each of which the engine accepts given those before it, with default policies.
+pub fn valid_transactions(
+    max_client: ClientId,
+    max_len: TransactionId,
+) -> impl Strategy<Value = Vec<TransactionRecord>> {
+    generated_transactions(max_client, max_len, 0.0).prop_map(|generated| {
+        generated
+            .into_iter()
+            .map(|generated| generated.record)
+            .collect()
+    })
+}
+
+/// Like `valid_transactions`, but with around a fifth of transactions breaking a rule,
+/// e.g. amounts which aren't positive, reused ids, or withdrawals of more than is available,
+/// each along with the error the engine rejects it with.
+pub fn transactions_with_errors(
+    max_client: ClientId,
+    max_len: TransactionId,
+) -> impl Strategy<Value = Vec<GeneratedTransaction>> {
+    generated_transactions(max_client, max_len, 0.2)
+}
+
+/// Any type of transaction, including administrative ones.
+pub fn transaction_type() -> impl Strategy<Value = TransactionType> {
+    prop_oneof![
+        Just(TransactionType::Deposit),
+        Just(TransactionType::Withdrawal),
+        Just(TransactionType::Dispute),
+        Just(TransactionType::Resolve),
+        Just(TransactionType::Chargeback),
+        Just(TransactionType::Lock),
+        Just(TransactionType::Unlock),
+        Just(TransactionType::Representment),
+        Just(TransactionType::Reversal),
+    ]
+}
+
+/// Amounts as they might appear in hostile input: usually ordinary,
+/// but sometimes missing, zero, negative, or as large as they can be.
+pub fn amount() -> impl Strategy<Value = Option<Currency>> {
+    prop_oneof![
+        6 => (1..10_000_000i64).prop_map(|units| Some(Currency::from_minor_units(units))),
+        1 => Just(None),
+        1 => Just(Some(Currency::ZERO)),
+        1 => (-10_000_000..0i64).prop_map(|units| Some(Currency::from_minor_units(units))),
+        1 => any::<i64>().prop_map(|units| Some(Currency::from_minor_units(units))),
+    ]
+}
+
+/// A single transaction with no regard for the rules, e.g. a withdrawal with no amount,
+/// or a dispute by the wrong client. Ids are drawn from `1..=max_tx`
+/// so that transactions often refer to one another.
+pub fn arbitrary_transaction(
+    max_client: ClientId,
+    max_tx: TransactionId,
+) -> impl Strategy<Value = TransactionRecord> {
+    (
+        transaction_type(),
+        1..=max_client,
+        1..=max_tx,
+        amount(),
+        proptest::option::of(1..=max_tx),
+    )
+        .prop_map(
+            |(transaction_type, client_id, tx_id, amount, reverses)| TransactionRecord {
+                transaction_type,
+                client_id,
+                tx_id,
+                amount,
+                timestamp: None,
+                currency: None,
+                reason: None,
+                reverses,
+                effective: None,
+            },
+        )
+}
+
+/// Sequences of up to `max_len` arbitrary transactions, which the engine
+/// should reject or apply without ever panicking or breaking an invariant.
+pub fn arbitrary_transactions(
+    max_client: ClientId,
+    max_len: TransactionId,
+) -> impl Strategy<Value = Vec<TransactionRecord>> {
+    vec(
+        arbitrary_transaction(max_client, max_len.max(1)),
+        0..=max_len as usize,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{arbitrary_transactions, transactions_with_errors, valid_transactions};
+    use crate::handlers::handle_transaction;
+    use crate::invariants;
+    use crate::state::State;
+    use proptest::prelude::*;
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn test_valid_transactions_are_accepted(records in valid_transactions(5, 200)) {
+            let mut state = State::new();
+            for record in records {
+                prop_assert_eq!(handle_transaction(record, &mut state), Ok(()));
+            }
+        }
+
+        #[test]
+        fn test_errors_are_as_expected(generated in transactions_with_errors(5, 200)) {
+            let mut state = State::new();
+            for generated in generated {
+                let result = handle_transaction(generated.record, &mut state);
+                prop_assert_eq!(result.err(), generated.expected_error);
+            }
+        }
+
+        #[test]
+        fn test_arbitrary_transactions_keep_invariants(records in arbitrary_transactions(5, 50)) {
+            let mut state = State::new();
+            for record in records {
+                let _ = handle_transaction(record, &mut state);
+                let violations = invariants::check(&state);
+                prop_assert!(violations.is_empty(), "{:?}", violations);
+            }
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! Pacing transactions to a steady rate, e.g. to soak-test downstream consumers
+//! at a controlled throughput rather than as fast as possible.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How far behind schedule a throttle may fall before giving up on catching up.
+/// Within this, a short stall is made up for by going a little faster afterwards,
+/// which keeps the average rate on target despite imprecise sleeps.
+/// Beyond it, the schedule restarts, rather than bursting to catch up.
+const MAX_LAG: Duration = Duration::from_millis(100);
+
+/// Check that a rate in transactions per second is positive and finite.
+pub fn validate_rate(rate: f64) -> Result<f64, String> {
+    if rate.is_finite() && rate > 0.0 {
+        Ok(rate)
+    } else {
+        Err(format!(
+            "rate must be a positive number of transactions per second, not {}",
+            rate
+        ))
+    }
+}
+
+/// Limits a loop to a number of iterations per second.
+pub struct Throttle {
+    interval: Duration,
+    next: Instant,
+}
+
+impl Throttle {
+    /// Allow `rate` iterations per second, which must be positive and finite.
+    pub fn new(rate: f64) -> Self {
+        Self {
+            interval: Duration::from_secs_f64(1.0 / rate),
+            next: Instant::now(),
+        }
+    }
+
+    /// Wait until the next iteration is due.
+    pub fn wait(&mut self) {
+        let now = Instant::now();
+        if self.next > now {
+            thread::sleep(self.next - now);
+        } else if now - self.next > MAX_LAG {
+            self.next = now;
+        }
+        self.next += self.interval;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{validate_rate, Throttle};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_throttle() {
+        let mut throttle = Throttle::new(1000.0);
+        let start = Instant::now();
+        for _ in 0..51 {
+            throttle.wait();
+        }
+        // The first is immediate, the rest a millisecond apart
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_no_burst_after_stall() {
+        let mut throttle = Throttle::new(100.0);
+        throttle.wait();
+        thread::sleep(Duration::from_millis(300));
+
+        let start = Instant::now();
+        for _ in 0..6 {
+            throttle.wait();
+        }
+        // Rather than 30 immediately to make up for the stall
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_validate_rate() {
+        assert_eq!(validate_rate(0.5), Ok(0.5));
+        assert!(validate_rate(0.0).is_err());
+        assert!(validate_rate(-1.0).is_err());
+        assert!(validate_rate(f64::INFINITY).is_err());
+        assert!(validate_rate(f64::NAN).is_err());
+    }
+}
@@ -1,10 +1,15 @@
+use crate::account::Overflow;
 use crate::types::{Account, TransactionContainer, TransactionError, TransactionType};
-use crate::types::{Chargeback, Deposit, Dispute, Resolve, Withdrawal};
-use crate::types::{ClientId, TransactionId};
+use crate::types::{
+    Chargeback, Deposit, Dispute, Lock, Representment, Resolve, Reversal, Unlock, Withdrawal,
+};
+use crate::types::{ClientId, Currency, CurrencyCode, Timestamp, TransactionId};
 
 pub trait Transaction {
     fn get_tx_id(&self) -> TransactionId;
     fn get_client_id(&self) -> ClientId;
+    fn get_timestamp(&self) -> Option<Timestamp>;
+    fn get_currency(&self) -> Option<CurrencyCode>;
 }
 
 impl Transaction for Deposit {
@@ -17,6 +22,16 @@ impl Transaction for Deposit {
     fn get_client_id(&self) -> ClientId {
         self.client_id
     }
+
+    #[inline]
+    fn get_timestamp(&self) -> Option<Timestamp> {
+        self.timestamp
+    }
+
+    #[inline]
+    fn get_currency(&self) -> Option<CurrencyCode> {
+        self.currency
+    }
 }
 
 impl Transaction for Withdrawal {
@@ -29,6 +44,16 @@ impl Transaction for Withdrawal {
     fn get_client_id(&self) -> ClientId {
         self.client_id
     }
+
+    #[inline]
+    fn get_timestamp(&self) -> Option<Timestamp> {
+        self.timestamp
+    }
+
+    #[inline]
+    fn get_currency(&self) -> Option<CurrencyCode> {
+        self.currency
+    }
 }
 
 impl Transaction for Dispute {
@@ -41,6 +66,16 @@ impl Transaction for Dispute {
     fn get_client_id(&self) -> ClientId {
         self.client_id
     }
+
+    #[inline]
+    fn get_timestamp(&self) -> Option<Timestamp> {
+        self.timestamp
+    }
+
+    #[inline]
+    fn get_currency(&self) -> Option<CurrencyCode> {
+        self.currency
+    }
 }
 
 impl Transaction for Resolve {
@@ -53,6 +88,16 @@ impl Transaction for Resolve {
     fn get_client_id(&self) -> ClientId {
         self.client_id
     }
+
+    #[inline]
+    fn get_timestamp(&self) -> Option<Timestamp> {
+        self.timestamp
+    }
+
+    #[inline]
+    fn get_currency(&self) -> Option<CurrencyCode> {
+        self.currency
+    }
 }
 
 impl Transaction for Chargeback {
@@ -65,28 +110,175 @@ impl Transaction for Chargeback {
     fn get_client_id(&self) -> ClientId {
         self.client_id
     }
+
+    #[inline]
+    fn get_timestamp(&self) -> Option<Timestamp> {
+        self.timestamp
+    }
+
+    #[inline]
+    fn get_currency(&self) -> Option<CurrencyCode> {
+        self.currency
+    }
+}
+
+impl Transaction for Lock {
+    #[inline]
+    fn get_tx_id(&self) -> TransactionId {
+        self.tx_id
+    }
+
+    #[inline]
+    fn get_client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    #[inline]
+    fn get_timestamp(&self) -> Option<Timestamp> {
+        self.timestamp
+    }
+
+    #[inline]
+    fn get_currency(&self) -> Option<CurrencyCode> {
+        self.currency
+    }
+}
+
+impl Transaction for Reversal {
+    #[inline]
+    fn get_tx_id(&self) -> TransactionId {
+        self.tx_id
+    }
+
+    #[inline]
+    fn get_client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    #[inline]
+    fn get_timestamp(&self) -> Option<Timestamp> {
+        self.timestamp
+    }
+
+    #[inline]
+    fn get_currency(&self) -> Option<CurrencyCode> {
+        self.currency
+    }
+}
+
+impl Transaction for Unlock {
+    #[inline]
+    fn get_tx_id(&self) -> TransactionId {
+        self.tx_id
+    }
+
+    #[inline]
+    fn get_client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    #[inline]
+    fn get_timestamp(&self) -> Option<Timestamp> {
+        self.timestamp
+    }
+
+    #[inline]
+    fn get_currency(&self) -> Option<CurrencyCode> {
+        self.currency
+    }
+}
+
+impl Transaction for Representment {
+    #[inline]
+    fn get_tx_id(&self) -> TransactionId {
+        self.tx_id
+    }
+
+    #[inline]
+    fn get_client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    #[inline]
+    fn get_timestamp(&self) -> Option<Timestamp> {
+        self.timestamp
+    }
+
+    #[inline]
+    fn get_currency(&self) -> Option<CurrencyCode> {
+        self.currency
+    }
 }
 
 /// This trait indicates whether and how a transaction can be disputed.
 /// To enable new types of transactions to be disputed, implement this
 /// trait for that type, and update TransactionContainer::try_get_disputable.
+///
+/// Disputes may cover only part of a transaction, so each balance
+/// modification takes the disputed amount explicitly.
 pub trait Disputable: Transaction {
-    fn modify_balances_for_dispute(&self, account: &mut Account);
-    fn modify_balances_for_resolve(&self, account: &mut Account);
-    fn modify_balances_for_chargeback(&self, account: &mut Account);
+    /// Maximum amount which may be disputed.
+    fn disputable_amount(&self) -> Currency;
+    fn modify_balances_for_dispute(
+        &self,
+        account: &mut Account,
+        amount: Currency,
+    ) -> Result<(), Overflow>;
+    fn modify_balances_for_resolve(
+        &self,
+        account: &mut Account,
+        amount: Currency,
+    ) -> Result<(), Overflow>;
+    fn modify_balances_for_chargeback(
+        &self,
+        account: &mut Account,
+        amount: Currency,
+    ) -> Result<(), Overflow>;
+    /// Undo a chargeback, reopening the dispute.
+    fn modify_balances_for_representment(
+        &self,
+        account: &mut Account,
+        amount: Currency,
+    ) -> Result<(), Overflow>;
 }
 
 impl Disputable for Deposit {
-    fn modify_balances_for_dispute(&self, account: &mut Account) {
-        account.available -= self.amount;
-        account.held += self.amount;
+    fn disputable_amount(&self) -> Currency {
+        self.amount
     }
-    fn modify_balances_for_resolve(&self, account: &mut Account) {
-        account.available += self.amount;
-        account.held -= self.amount;
+    fn modify_balances_for_dispute(
+        &self,
+        account: &mut Account,
+        amount: Currency,
+    ) -> Result<(), Overflow> {
+        let available = account.available.checked_sub(amount).ok_or(Overflow)?;
+        let held = account.held.checked_add(amount).ok_or(Overflow)?;
+        account.set_balances(available, held)
+    }
+    fn modify_balances_for_resolve(
+        &self,
+        account: &mut Account,
+        amount: Currency,
+    ) -> Result<(), Overflow> {
+        let available = account.available.checked_add(amount).ok_or(Overflow)?;
+        let held = account.held.checked_sub(amount).ok_or(Overflow)?;
+        account.set_balances(available, held)
     }
-    fn modify_balances_for_chargeback(&self, account: &mut Account) {
-        account.held -= self.amount;
+    fn modify_balances_for_chargeback(
+        &self,
+        account: &mut Account,
+        amount: Currency,
+    ) -> Result<(), Overflow> {
+        let held = account.held.checked_sub(amount).ok_or(Overflow)?;
+        account.set_balances(account.available, held)
+    }
+    fn modify_balances_for_representment(
+        &self,
+        account: &mut Account,
+        amount: Currency,
+    ) -> Result<(), Overflow> {
+        let held = account.held.checked_add(amount).ok_or(Overflow)?;
+        account.set_balances(account.available, held)
     }
 }
 
@@ -119,6 +311,7 @@ impl TransactionContainer {
             TransactionContainer::Withdrawal(result) => {
                 result.clone().map(|t| Box::new(t) as Box<dyn Transaction>)
             }
+            TransactionContainer::Reversal(reversal) => Ok(Box::new(reversal.clone())),
         }
     }
 }
@@ -1,64 +1,136 @@
 use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
 use std::error::Error;
 use std::fmt::{Debug, Display};
+use std::str::FromStr;
+use std::time::Duration;
 
-use crate::currency::round_currency;
-pub use crate::currency::CurrencyFloat;
+pub use crate::currency::{Currency, CurrencyCode, RoundingMode};
+use crate::policy::RoundingPolicy;
 
+/// Client ids are `u16` by default, or `u32` with the `wide-client-ids` feature.
+#[cfg(not(feature = "wide-client-ids"))]
 pub type ClientId = u16;
+#[cfg(feature = "wide-client-ids")]
+pub type ClientId = u32;
+/// Transaction ids are `u32` by default, or `u64` with the `wide-tx-ids` feature.
+#[cfg(not(feature = "wide-tx-ids"))]
 pub type TransactionId = u32;
+#[cfg(feature = "wide-tx-ids")]
+pub type TransactionId = u64;
+/// Seconds since the Unix epoch
+pub type Timestamp = u64;
+/// Each account holds a single currency, so a client has one account
+/// per currency they transact in. Transactions without a currency code
+/// use the default currency, `None`.
+pub type AccountKey = (ClientId, Option<CurrencyCode>);
 
 /// A single row in the final output CSV
-#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub struct OutputRecord {
     /// Id for client's account
     pub client: ClientId,
+    /// Currency of this account, if not the default.
+    /// Only written when some account has a currency code.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<CurrencyCode>,
     /// Total funds available: should equal `total` - `held`
-    pub available: CurrencyFloat,
+    pub available: Currency,
     /// Total disputed funds: should equal `total` - `available`
-    pub held: CurrencyFloat,
+    pub held: Currency,
     /// Total funds, available or otherwise: should equal `available` + `held`
-    pub total: CurrencyFloat,
+    pub total: Currency,
     /// Whether the account is locked: should be lock if a charge-back has occurred
     pub locked: bool,
+    /// Why the account was locked, if it's known.
+    /// Only written to CSV when asked for, e.g. for operations teams.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lock_reason: Option<LockReason>,
 }
 
 impl OutputRecord {
-    pub fn new(client_id: ClientId, account: &Account) -> Self {
-        OutputRecord {
+    /// Output row for an account, rounded according to the rounding policy.
+    /// The total is the sum of the rounded parts, so that the row adds up,
+    /// unless that sum can't be represented.
+    pub fn new(
+        (client_id, currency): AccountKey,
+        account: &Account,
+        rounding: &RoundingPolicy,
+    ) -> Result<Self, InvalidAccount> {
+        let available = rounding.round(account.available());
+        let held = rounding.round(account.held());
+        Ok(OutputRecord {
+            client: client_id,
+            currency,
+            available,
+            held,
+            total: available
+                .checked_add(held)
+                .ok_or(InvalidAccount::TotalOverflow)?,
+            locked: account.locked(),
+            lock_reason: account.lock_info().map(|lock| lock.reason),
+        })
+    }
+}
+
+/// An account's balances right after a transaction was applied,
+/// for following balances as they change.
+#[derive(Debug, PartialEq)]
+pub struct BalanceUpdate {
+    /// Transaction which caused the update
+    pub tx: TransactionId,
+    pub balance: OutputRecord,
+}
+
+/// A single row in the fees report
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct FeesRecord {
+    pub client: ClientId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<CurrencyCode>,
+    /// Total fees charged to the client's account
+    pub fees: Currency,
+}
+
+impl FeesRecord {
+    pub fn new(
+        (client_id, currency): AccountKey,
+        account: &Account,
+        rounding: &RoundingPolicy,
+    ) -> Self {
+        FeesRecord {
             client: client_id,
-            // NOTE: Rounding just in case some strange floating point phemonenon added extra digits
-            // It's still possible that this would still format to more than four digits,
-            // but it's a lot easier than writing a custom serializer / deserializer
-            available: round_currency(account.available),
-            held: round_currency(account.held),
-            total: round_currency(account.available + account.held),
-            locked: account.locked,
+            currency,
+            fees: rounding.round(account.fees),
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+/// Errors are serialized with their `code` as a tag,
+/// and their fields as `details`, e.g.
+/// `{"code": "DUPLICATE_TX", "details": {"tx": 1}}`.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "code", content = "details", rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum TransactionError {
-    /// Client attempted to withdraw more than their available funds.
+    /// Client attempted to withdraw more than their available funds,
+    /// including any credit line.
     InsufficientFunds {
         client: ClientId,
         tx: TransactionId,
-        requested: CurrencyFloat,
-        available: CurrencyFloat,
+        requested: Currency,
+        available: Currency,
     },
     /// This account is locked, and cannot deposit or withdraw.
     AccountLocked { client: ClientId, tx: TransactionId },
     /// Transaction IDs must be globally unique.
+    #[serde(rename = "DUPLICATE_TX")]
     DuplicateTxId { tx: TransactionId },
     /// Deposits and withdrawals must have positive amounts.
-    AmountNotPositive {
-        tx: TransactionId,
-        amount: CurrencyFloat,
-    },
+    AmountNotPositive { tx: TransactionId, amount: Currency },
     /// Cannot dispute an actively disputed transaction.
     TxAlreadyDisputed { client: ClientId, tx: TransactionId },
     /// Dispute refers to nonexistent transaction.
+    #[serde(rename = "TX_NOT_FOUND")]
     TxDoesNotExist { client: ClientId, tx: TransactionId },
     /// Only deposits can be disputed.
     InvalidDispute {
@@ -71,8 +143,9 @@ pub enum TransactionError {
     /// The disputed transaction didn't succeed,
     /// so there's no point in disputing it.
     DisputedTxFailed { tx: TransactionId },
-    /// Transaction has already been disputed and settled - cannot redispute.
-    DisputeAlreadySettled { client: ClientId, tx: TransactionId },
+    /// Transaction has already been disputed and settled, and the dispute policy
+    /// doesn't allow disputing it again - e.g. it was charged back.
+    RedisputeNotAllowed { client: ClientId, tx: TransactionId },
     /// The client_id on this transaction does not
     /// match the client_id on the referenced transaction.
     ClientMismatch {
@@ -80,12 +153,153 @@ pub enum TransactionError {
         tx_client: ClientId,
         dispute_client: ClientId,
     },
+    /// The currency on this transaction does not
+    /// match the currency of the referenced transaction.
+    CurrencyMismatch {
+        tx: TransactionId,
+        tx_currency: Option<CurrencyCode>,
+        dispute_currency: Option<CurrencyCode>,
+    },
     /// Transaction had unknown type or missing required fields.
     ImproperTransaction(TransactionRecord),
+    /// The disputed transaction is older than the dispute policy allows.
+    DisputeWindowExpired {
+        client: ClientId,
+        tx: TransactionId,
+        age: Duration,
+        max_age: Duration,
+    },
+    /// This client already has too many transactions waiting to be handled.
+    ClientQueueFull { client: ClientId, tx: TransactionId },
+    /// A partial dispute can't hold more than the disputed transaction's amount.
+    DisputeExceedsTransaction {
+        client: ClientId,
+        tx: TransactionId,
+        requested: Currency,
+        disputable: Currency,
+    },
+    /// Lock, unlock and reversal transactions are only accepted when explicitly enabled.
+    AdminTransactionsDisabled { client: ClientId, tx: TransactionId },
+    /// Only locked accounts can be unlocked.
+    AccountNotLocked { client: ClientId, tx: TransactionId },
+    /// The deposit isn't large enough to cover its own fee.
+    FeeExceedsAmount {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Currency,
+        fee: Currency,
+    },
+    /// The chargeback would take the account's total funds below zero.
+    ChargebackExceedsFunds {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Currency,
+        shortfall: Currency,
+    },
+    /// The transaction would take the account's total funds above
+    /// the maximum balance, or any of its balances beyond what can be represented.
+    BalanceOverflow {
+        client: ClientId,
+        tx: TransactionId,
+        total: Currency,
+        amount: Currency,
+    },
+    /// The deposit or withdrawal is larger than the client's limit for a single one.
+    LimitExceeded {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Currency,
+        max: Currency,
+    },
+    /// The withdrawal would take the amount withdrawn that day
+    /// above the client's daily limit.
+    DailyLimitExceeded {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Currency,
+        withdrawn: Currency,
+        max: Currency,
+    },
+    /// The client has already made as many deposits and withdrawals
+    /// that minute as their limit allows.
+    RateLimitExceeded {
+        client: ClientId,
+        tx: TransactionId,
+        max_per_minute: u32,
+    },
+    /// Only a charged back dispute can be represented.
+    TxNotChargedBack { client: ClientId, tx: TransactionId },
+    /// A dispute can only be represented once.
+    AlreadyRepresented { client: ClientId, tx: TransactionId },
+    /// Only deposits and withdrawals can be reversed.
+    InvalidReversal {
+        tx: TransactionId,
+        tx_type: TransactionType,
+    },
+    /// The reversed transaction didn't succeed, so there's nothing to undo.
+    ReversedTxFailed { tx: TransactionId },
+    /// The transaction was already reversed, or charged back.
+    AlreadyReversed { client: ClientId, tx: TransactionId },
+    /// The transaction takes effect after the latest timestamp read,
+    /// and scheduled transactions aren't being held until then.
+    NotYetEffective {
+        client: ClientId,
+        tx: TransactionId,
+        effective: Timestamp,
+    },
+    /// A risk check judged the deposit or withdrawal too likely to be fraudulent.
+    RiskRejected {
+        client: ClientId,
+        tx: TransactionId,
+        reason: String,
+    },
     /// Didn't think we'd ever get here, but here we are.
     UnexpectedError(String),
 }
 
+impl TransactionError {
+    /// Stable, machine-readable name for this kind of error,
+    /// e.g. for a column in a rejects file. Unlike the message,
+    /// codes won't change between versions.
+    /// This is also the tag errors are serialized with.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InsufficientFunds { .. } => "INSUFFICIENT_FUNDS",
+            Self::AccountLocked { .. } => "ACCOUNT_LOCKED",
+            Self::DuplicateTxId { .. } => "DUPLICATE_TX",
+            Self::AmountNotPositive { .. } => "AMOUNT_NOT_POSITIVE",
+            Self::TxAlreadyDisputed { .. } => "TX_ALREADY_DISPUTED",
+            Self::TxDoesNotExist { .. } => "TX_NOT_FOUND",
+            Self::InvalidDispute { .. } => "INVALID_DISPUTE",
+            Self::TxNotDisputed { .. } => "TX_NOT_DISPUTED",
+            Self::DisputedTxFailed { .. } => "DISPUTED_TX_FAILED",
+            Self::RedisputeNotAllowed { .. } => "REDISPUTE_NOT_ALLOWED",
+            Self::ClientMismatch { .. } => "CLIENT_MISMATCH",
+            Self::CurrencyMismatch { .. } => "CURRENCY_MISMATCH",
+            Self::ImproperTransaction(_) => "IMPROPER_TRANSACTION",
+            Self::DisputeWindowExpired { .. } => "DISPUTE_WINDOW_EXPIRED",
+            Self::ClientQueueFull { .. } => "CLIENT_QUEUE_FULL",
+            Self::DisputeExceedsTransaction { .. } => "DISPUTE_EXCEEDS_TRANSACTION",
+            Self::AdminTransactionsDisabled { .. } => "ADMIN_TRANSACTIONS_DISABLED",
+            Self::AccountNotLocked { .. } => "ACCOUNT_NOT_LOCKED",
+            Self::FeeExceedsAmount { .. } => "FEE_EXCEEDS_AMOUNT",
+            Self::ChargebackExceedsFunds { .. } => "CHARGEBACK_EXCEEDS_FUNDS",
+            Self::BalanceOverflow { .. } => "BALANCE_OVERFLOW",
+            Self::LimitExceeded { .. } => "LIMIT_EXCEEDED",
+            Self::DailyLimitExceeded { .. } => "DAILY_LIMIT_EXCEEDED",
+            Self::RateLimitExceeded { .. } => "RATE_LIMIT_EXCEEDED",
+            Self::TxNotChargedBack { .. } => "TX_NOT_CHARGED_BACK",
+            Self::AlreadyRepresented { .. } => "ALREADY_REPRESENTED",
+            Self::InvalidReversal { .. } => "INVALID_REVERSAL",
+            Self::ReversedTxFailed { .. } => "REVERSED_TX_FAILED",
+            Self::AlreadyReversed { .. } => "ALREADY_REVERSED",
+            Self::NotYetEffective { .. } => "NOT_YET_EFFECTIVE",
+            Self::RiskRejected { .. } => "RISK_REJECTED",
+            Self::UnexpectedError(_) => "UNEXPECTED_ERROR",
+        }
+    }
+}
+
 impl Display for TransactionError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         Debug::fmt(&self, f)
@@ -104,6 +318,34 @@ pub enum TransactionType {
     Dispute,
     Resolve,
     Chargeback,
+    /// Administrative: lock an account
+    Lock,
+    /// Administrative: unlock a locked account
+    Unlock,
+    /// The merchant contests a chargeback, reopening the dispute
+    Representment,
+    /// Administrative: undo a deposit or withdrawal made in error
+    Reversal,
+    /// Interest credited by the engine itself, which input can't contain
+    Interest,
+}
+
+impl TransactionType {
+    /// Name of the type, as it appears in the `type` column.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Deposit => "deposit",
+            Self::Withdrawal => "withdrawal",
+            Self::Dispute => "dispute",
+            Self::Resolve => "resolve",
+            Self::Chargeback => "chargeback",
+            Self::Lock => "lock",
+            Self::Unlock => "unlock",
+            Self::Representment => "representment",
+            Self::Reversal => "reversal",
+            Self::Interest => "interest",
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
@@ -114,45 +356,137 @@ pub struct TransactionRecord {
     pub client_id: ClientId,
     #[serde(rename = "tx")]
     pub tx_id: TransactionId,
-    pub amount: Option<CurrencyFloat>,
+    pub amount: Option<Currency>,
+    /// Optional time at which the transaction occurred
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<Timestamp>,
+    /// Optional ISO 4217 currency code, or the default currency if omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<CurrencyCode>,
+    /// Optional reason for a dispute, ignored for other types
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<DisputeReason>,
+    /// Transaction a reversal undoes, ignored for other types
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reverses: Option<TransactionId>,
+    /// When a scheduled transaction takes effect, if not as soon as it's read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effective: Option<Timestamp>,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct Deposit {
+    #[serde(rename = "client")]
     pub client_id: ClientId,
+    #[serde(rename = "tx")]
     pub tx_id: TransactionId,
-    pub amount: CurrencyFloat,
+    pub amount: Currency,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<Timestamp>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<CurrencyCode>,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct Withdrawal {
+    #[serde(rename = "client")]
     pub client_id: ClientId,
+    #[serde(rename = "tx")]
     pub tx_id: TransactionId,
-    pub amount: CurrencyFloat,
+    pub amount: Currency,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<Timestamp>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<CurrencyCode>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Dispute {
     pub client_id: ClientId,
     pub tx_id: TransactionId,
+    /// Portion of the transaction to dispute, or all of it if `None`.
+    pub amount: Option<Currency>,
+    pub timestamp: Option<Timestamp>,
+    pub currency: Option<CurrencyCode>,
+    /// Why the transaction was disputed, if that's given
+    pub reason: Option<DisputeReason>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Resolve {
     pub client_id: ClientId,
     pub tx_id: TransactionId,
+    pub timestamp: Option<Timestamp>,
+    pub currency: Option<CurrencyCode>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Chargeback {
     pub client_id: ClientId,
     pub tx_id: TransactionId,
+    pub timestamp: Option<Timestamp>,
+    pub currency: Option<CurrencyCode>,
 }
 
-#[derive(Debug, PartialEq)]
+/// The merchant contesting a chargeback, which reopens the dispute,
+/// holding the charged back funds again until it's resolved or charged back.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Representment {
+    pub client_id: ClientId,
+    pub tx_id: TransactionId,
+    pub timestamp: Option<Timestamp>,
+    pub currency: Option<CurrencyCode>,
+}
+
+/// Administrative action to lock an account.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lock {
+    pub client_id: ClientId,
+    pub tx_id: TransactionId,
+    pub timestamp: Option<Timestamp>,
+    pub currency: Option<CurrencyCode>,
+}
+
+/// Administrative action to unlock an account,
+/// e.g. after a chargeback has been investigated.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Unlock {
+    pub client_id: ClientId,
+    pub tx_id: TransactionId,
+    pub timestamp: Option<Timestamp>,
+    pub currency: Option<CurrencyCode>,
+}
+
+/// Administrative correction undoing a deposit or withdrawal made in error,
+/// with its own id, and the id of the transaction it `reverses`.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Reversal {
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    #[serde(rename = "tx")]
+    pub tx_id: TransactionId,
+    pub reverses: TransactionId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<Timestamp>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<CurrencyCode>,
+}
+
+/// A transaction which was rejected by the engine, and why.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Rejection {
+    pub record: TransactionRecord,
+    pub error: TransactionError,
+}
+
+/// Serialized as e.g. `{"deposit": {"Ok": {...}}}`.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum TransactionContainer {
     Deposit(Result<Deposit, TransactionError>),
     Withdrawal(Result<Withdrawal, TransactionError>),
+    /// Only successful reversals are stored
+    Reversal(Reversal),
 }
 
 impl TransactionContainer {
@@ -160,27 +494,378 @@ impl TransactionContainer {
         match &self {
             TransactionContainer::Deposit(_) => TransactionType::Deposit,
             TransactionContainer::Withdrawal(_) => TransactionType::Withdrawal,
+            TransactionContainer::Reversal(_) => TransactionType::Reversal,
         }
     }
-}
 
+    /// Whether the transaction succeeded, or why it failed.
+    pub fn outcome(&self) -> Result<(), &TransactionError> {
+        match self {
+            TransactionContainer::Deposit(result) => result.as_ref().map(|_| ()),
+            TransactionContainer::Withdrawal(result) => result.as_ref().map(|_| ()),
+            TransactionContainer::Reversal(_) => Ok(()),
+        }
+    }
+}
 
 // Internal state
 
-#[derive(Debug, PartialEq)]
+/// A client's balances in one currency, and its status.
+///
+/// Only the handlers change accounts, so outside the crate they're read
+/// through accessors, and made with `Account::new`, which refuses balances
+/// no sequence of transactions could lead to.
+/// Fields left out when deserializing take their default, e.g. in test fixtures,
+/// and the result is checked the same way.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "AccountFields")]
 pub struct Account {
-    pub available: CurrencyFloat,
-    pub held: CurrencyFloat,
-    pub locked: bool,
+    pub(crate) available: Currency,
+    pub(crate) held: Currency,
+    pub(crate) locked: bool,
+    /// Total fees charged to this account
+    pub(crate) fees: Currency,
+    /// Whether the account was flagged for review, because part of a chargeback
+    /// was written off, or by a risk check
+    pub(crate) flagged: bool,
+    /// Why and when the account was locked, while it is, if that's known,
+    /// which it isn't for accounts read from a balances file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) lock_info: Option<LockInfo>,
+}
+
+impl Account {
+    /// An unlocked account with the given balances, and no fees charged.
+    /// Available funds may be negative, e.g. under a credit limit, but held funds
+    /// can't be, since they're only held by disputes, and the total must be representable.
+    pub fn new(available: Currency, held: Currency) -> Result<Self, InvalidAccount> {
+        if held.is_negative() {
+            return Err(InvalidAccount::NegativeHeld(held));
+        }
+        if available.checked_add(held).is_none() {
+            return Err(InvalidAccount::TotalOverflow);
+        }
+        Ok(Self {
+            available,
+            held,
+            ..Default::default()
+        })
+    }
+
+    pub fn available(&self) -> Currency {
+        self.available
+    }
+
+    pub fn held(&self) -> Currency {
+        self.held
+    }
+
+    /// Available and held funds together, which `Account::new` and the handlers
+    /// keep representable, but which is checked rather than assumed.
+    pub fn total(&self) -> Result<Currency, InvalidAccount> {
+        self.available
+            .checked_add(self.held)
+            .ok_or(InvalidAccount::TotalOverflow)
+    }
+
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Total fees charged to this account
+    pub fn fees(&self) -> Currency {
+        self.fees
+    }
+
+    /// Whether the account was flagged for review, because part of a chargeback
+    /// was written off, or by a risk check
+    pub fn flagged(&self) -> bool {
+        self.flagged
+    }
+
+    /// Why and when the account was locked, if it is, and that's known.
+    pub fn lock_info(&self) -> Option<&LockInfo> {
+        self.lock_info.as_ref()
+    }
+}
+
+/// Why an account was locked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LockReason {
+    /// One of its transactions was charged back
+    Chargeback,
+    /// An administrator locked it, with a `lock` transaction
+    Admin,
+    /// It was flagged as fraudulent, e.g. by a risk check
+    Fraud,
+}
+
+/// Why a transaction was disputed, as one of the categories
+/// card networks group their reason codes into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisputeReason {
+    /// The cardholder didn't make the transaction
+    Fraud,
+    /// The transaction wasn't properly authorized
+    Authorization,
+    /// The transaction was processed incorrectly, e.g. twice, or for the wrong amount
+    ProcessingError,
+    /// The cardholder disputes the goods or services, e.g. they never arrived
+    ConsumerDispute,
+}
+
+impl DisputeReason {
+    /// Name of the reason, as it appears in the `reason` column.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Fraud => "fraud",
+            Self::Authorization => "authorization",
+            Self::ProcessingError => "processing_error",
+            Self::ConsumerDispute => "consumer_dispute",
+        }
+    }
+}
+
+impl FromStr for DisputeReason {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [
+            Self::Fraud,
+            Self::Authorization,
+            Self::ProcessingError,
+            Self::ConsumerDispute,
+        ]
+        .iter()
+        .copied()
+        .find(|reason| reason.name() == s)
+        .ok_or_else(|| format!("unknown dispute reason {:?}", s))
+    }
+}
+
+/// What happened to a transaction's dispute.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisputeAction {
+    /// Disputed for the first time
+    Opened,
+    Resolved,
+    ChargedBack,
+    /// The merchant contested the chargeback, reopening the dispute
+    Represented,
+    /// Disputed again after being resolved
+    Reopened,
+}
+
+/// One step in a transaction's dispute history.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DisputeStep {
+    pub action: DisputeAction,
+    /// Time of the transaction which took the step, if it had one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<Timestamp>,
+}
+
+/// Why and when an account was locked.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct LockInfo {
+    pub reason: LockReason,
+    /// The transaction which locked it, e.g. the chargeback
+    pub tx: TransactionId,
+    /// When it was locked, if the transaction had a timestamp
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<Timestamp>,
+}
+
+/// Why balances can't make up an account.
+#[derive(Clone, Debug, PartialEq)]
+pub enum InvalidAccount {
+    /// Funds are only held by disputes, so can't be negative
+    NegativeHeld(Currency),
+    /// Available and held funds add up to more than can be represented
+    TotalOverflow,
+}
+
+impl Display for InvalidAccount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NegativeHeld(held) => write!(f, "held funds can't be negative: {}", held),
+            Self::TotalOverflow => write!(f, "total balance is too large"),
+        }
+    }
+}
+
+impl Error for InvalidAccount {}
+
+/// An account as serialized, checked by `Account::new` when it's deserialized.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct AccountFields {
+    available: Currency,
+    held: Currency,
+    locked: bool,
+    fees: Currency,
+    flagged: bool,
+    lock_info: Option<LockInfo>,
+}
+
+impl TryFrom<AccountFields> for Account {
+    type Error = InvalidAccount;
+
+    fn try_from(fields: AccountFields) -> Result<Self, Self::Error> {
+        Ok(Self {
+            locked: fields.locked,
+            fees: fields.fees,
+            flagged: fields.flagged,
+            lock_info: fields.lock_info,
+            ..Account::new(fields.available, fields.held)?
+        })
+    }
 }
 
 // Default state for a new account
 impl Default for Account {
     fn default() -> Self {
         Self {
-            available: 0.0,
-            held: 0.0,
+            available: Currency::ZERO,
+            held: Currency::ZERO,
             locked: false,
+            fees: Currency::ZERO,
+            flagged: false,
+            lock_info: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Account, InvalidAccount};
+    use super::{Currency, TransactionError, TransactionRecord, TransactionType};
+    use std::time::Duration;
+
+    #[test]
+    fn test_serialize_error() {
+        let err = TransactionError::DuplicateTxId { tx: 1 };
+        assert_eq!(
+            serde_json::to_string(&err).unwrap(),
+            r#"{"code":"DUPLICATE_TX","details":{"tx":1}}"#
+        );
+    }
+
+    #[test]
+    fn test_error_round_trip() {
+        let errors = vec![
+            TransactionError::InsufficientFunds {
+                client: 1,
+                tx: 2,
+                requested: Currency::from(3.5),
+                available: Currency::from(1.25),
+            },
+            TransactionError::TxDoesNotExist { client: 1, tx: 2 },
+            TransactionError::CurrencyMismatch {
+                tx: 2,
+                tx_currency: "USD".parse().ok(),
+                dispute_currency: None,
+            },
+            TransactionError::ImproperTransaction(TransactionRecord {
+                transaction_type: TransactionType::Deposit,
+                client_id: 1,
+                tx_id: 2,
+                amount: None,
+                timestamp: Some(100),
+                currency: None,
+                reason: None,
+                reverses: None,
+                effective: None,
+            }),
+            TransactionError::DisputeWindowExpired {
+                client: 1,
+                tx: 2,
+                age: Duration::from_secs(90),
+                max_age: Duration::from_secs(60),
+            },
+            TransactionError::UnexpectedError("oops".into()),
+        ];
+
+        for err in errors {
+            let json = serde_json::to_value(&err).unwrap();
+            // Serialized codes match `code`
+            assert_eq!(json["code"], err.code(), "{}", json);
+            assert_eq!(
+                serde_json::from_value::<TransactionError>(json).unwrap(),
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_new_account() {
+        let account = Account::new(Currency::from(-2.0), Currency::from(5.0)).unwrap();
+        assert_eq!(account.available(), Currency::from(-2.0));
+        assert_eq!(account.held(), Currency::from(5.0));
+        assert_eq!(account.total(), Ok(Currency::from(3.0)));
+        assert!(!account.locked());
+
+        assert_eq!(
+            Account::new(Currency::from(1.0), Currency::from(-1.0)),
+            Err(InvalidAccount::NegativeHeld(Currency::from(-1.0)))
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "decimal"))]
+    fn test_new_account_overflow() {
+        let max = Currency::from_minor_units(i64::MAX);
+        assert_eq!(
+            Account::new(max, Currency::from_minor_units(1)),
+            Err(InvalidAccount::TotalOverflow)
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "decimal"))]
+    fn test_total_overflow() {
+        use super::{OutputRecord, RoundingMode};
+        use crate::policy::RoundingPolicy;
+
+        let max = Currency::from_minor_units(i64::MAX);
+        let account = Account {
+            available: max,
+            held: Currency::from_minor_units(1),
+            ..Default::default()
+        };
+        assert_eq!(account.total(), Err(InvalidAccount::TotalOverflow));
+        let rounding = RoundingPolicy::default();
+        assert_eq!(
+            OutputRecord::new((1, None), &account, &rounding),
+            Err(InvalidAccount::TotalOverflow)
+        );
+
+        // The account adds up, but its rounded parts don't
+        let account = Account::new(
+            Currency::from_minor_units(i64::MAX - 50),
+            Currency::from_minor_units(50),
+        )
+        .unwrap();
+        let cents = RoundingPolicy {
+            mode: RoundingMode::HalfUp,
+            precision: 2,
+        };
+        assert_eq!(account.total(), Ok(max));
+        assert_eq!(
+            OutputRecord::new((1, None), &account, &cents),
+            Err(InvalidAccount::TotalOverflow)
+        );
+    }
+
+    #[test]
+    fn test_deserialize_account_checks_balances() {
+        let account: Account = serde_json::from_str(r#"{"held": "1.5", "locked": true}"#).unwrap();
+        assert_eq!(account.total(), Ok(Currency::from(1.5)));
+        assert!(account.locked());
+
+        assert!(serde_json::from_str::<Account>(r#"{"held": "-1.5"}"#).is_err());
+    }
+}
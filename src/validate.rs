@@ -1,16 +1,25 @@
-use crate::account::{AccountAccess, BaseAccountFeatures, UnlockedAccountFeatures};
-use crate::currency::CurrencyFloat;
-use crate::state::{AccountsState, DisputesState, TransactionsState};
+use crate::account::{AccountRef, LockedAccount, UnlockedAccount};
+use crate::currency::Currency;
+use crate::limits::ActivityState;
+use crate::policy::{
+    ChargebackPolicy, DisputePolicy, Limits, Policies, RiskPolicy, ValidationPolicy,
+};
+use crate::risk::{RiskDecision, RiskScorers};
+use crate::state::{AccountView, AccountsState, DisputesState, TransactionsState};
 use crate::traits::{Disputable, PostDispute, Transaction};
-use crate::types::{Deposit, Dispute, Withdrawal};
-use crate::types::{TransactionError, TransactionId};
+use crate::types::{Account, Deposit, Dispute, Lock, Representment, Reversal, Unlock, Withdrawal};
+use crate::types::{AccountKey, ClientId, Timestamp, TransactionError, TransactionId};
+use crate::types::{DisputeAction, TransactionContainer, TransactionRecord, TransactionType};
+use std::time::Duration;
 
 fn check_for_duplicate_tx_id(
+    client_id: ClientId,
     tx_id: TransactionId,
     transactions: &TransactionsState,
+    policy: &ValidationPolicy,
 ) -> Result<(), TransactionError> {
     // TODO: Efficiently record duplicate transactions?
-    if transactions.tx_exists(tx_id) {
+    if transactions.is_taken(client_id, tx_id) && !policy.allow_duplicate_tx_ids {
         // Duplicate transactions are a bad sign
         Err(TransactionError::DuplicateTxId { tx: tx_id })
     } else {
@@ -18,91 +27,324 @@ fn check_for_duplicate_tx_id(
     }
 }
 
+/// An account's total funds, or zero for one that doesn't exist yet.
+/// The handlers keep totals representable, so one that isn't is unexpected.
+pub(crate) fn account_total(
+    client_id: ClientId,
+    account: Option<&Account>,
+) -> Result<Currency, TransactionError> {
+    account.map_or(Ok(Currency::ZERO), |account| {
+        account.total().map_err(|err| {
+            TransactionError::UnexpectedError(format!(
+                "Account for client {} is invalid: {}",
+                client_id, err
+            ))
+        })
+    })
+}
+
+/// Why a transaction was rejected, when it would take the account's balances
+/// beyond what can be represented.
+pub(crate) fn balance_overflow(
+    client: ClientId,
+    tx: TransactionId,
+    account: Option<&Account>,
+    amount: Currency,
+) -> TransactionError {
+    match account_total(client, account) {
+        Ok(total) => TransactionError::BalanceOverflow {
+            client,
+            tx,
+            total,
+            amount,
+        },
+        Err(err) => err,
+    }
+}
+
 fn check_for_positive_amount(
     tx: TransactionId,
-    amount: CurrencyFloat,
+    amount: Currency,
+    policy: &ValidationPolicy,
 ) -> Result<(), TransactionError> {
-    if amount > 0.0 {
+    if amount.is_positive() || (policy.allow_zero_amounts && amount == Currency::ZERO) {
         Ok(())
     } else {
         Err(TransactionError::AmountNotPositive { tx, amount })
     }
 }
 
-/// If the transaction is valid, return the transaction and a &mut to the associated account.
+fn check_dispute_window<D: Disputable>(
+    dispute: &Dispute,
+    disputed_tx: &D,
+    latest: Option<Timestamp>,
+    policy: &DisputePolicy,
+) -> Result<(), TransactionError> {
+    // If settled disputes are being forgotten, age the dispute as of the latest
+    // timestamp seen, so that a forgotten transaction can't be disputed again
+    let disputed_at = match (dispute.get_timestamp(), latest) {
+        (Some(disputed_at), Some(latest)) => Some(disputed_at.max(latest)),
+        (disputed_at, latest) => disputed_at.or(latest),
+    };
+    // Transactions without timestamps can't be aged, so let them through
+    if let (Some(max_age), Some(disputed_at), Some(occurred_at)) =
+        (policy.max_age, disputed_at, disputed_tx.get_timestamp())
+    {
+        let age = Duration::from_secs(disputed_at.saturating_sub(occurred_at));
+        if age > max_age {
+            return Err(TransactionError::DisputeWindowExpired {
+                client: dispute.client_id,
+                tx: dispute.tx_id,
+                age,
+                max_age,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Check that crediting the account won't take its total funds
+/// above the maximum balance, or overflow.
+fn check_balance_limit(
+    deposit: &Deposit,
+    credited: Currency,
+    accounts: &AccountsState,
+    max_balance: Option<Currency>,
+) -> Result<(), TransactionError> {
+    let total = account_total(
+        deposit.client_id,
+        accounts.get(deposit.client_id, deposit.currency),
+    )?;
+    match total.checked_add(credited) {
+        Some(new_total) if max_balance.is_none_or(|max| new_total <= max) => Ok(()),
+        _ => Err(TransactionError::BalanceOverflow {
+            client: deposit.client_id,
+            tx: deposit.tx_id,
+            total,
+            amount: deposit.amount,
+        }),
+    }
+}
+
+/// Rules a deposit or withdrawal is checked against, besides its account's funds.
+pub struct Checks<'a> {
+    pub validation: &'a ValidationPolicy,
+    pub limits: &'a Limits,
+    /// The client's recent activity, counted towards their limits
+    pub activity: &'a ActivityState,
+    pub risk: &'a RiskPolicy,
+    pub risk_scorers: &'a RiskScorers,
+}
+
+/// Score a deposit or withdrawal which passed every other check,
+/// returning why to flag its account, if it should be.
+fn check_risk<T: Transaction + Clone + Into<TransactionRecord>>(
+    tx: &T,
+    account: Option<&Account>,
+    checks: &Checks,
+) -> Result<Option<String>, TransactionError> {
+    if !checks.risk_scorers.is_active(checks.risk) {
+        return Ok(None);
+    }
+    let key = (tx.get_client_id(), tx.get_currency());
+    let view = AccountView::new(key, account.unwrap_or(&Account::default()));
+    let record: TransactionRecord = tx.clone().into();
+    match checks.risk_scorers.score(checks.risk, &record, &view) {
+        RiskDecision::Allow => Ok(None),
+        RiskDecision::Flag(reason) => Ok(Some(reason)),
+        RiskDecision::Reject(reason) => Err(TransactionError::RiskRejected {
+            client: tx.get_client_id(),
+            tx: tx.get_tx_id(),
+            reason,
+        }),
+    }
+}
+
+/// Whether a deposit or withdrawal repeats one which already succeeded, with the
+/// same id, type, client, currency and amount, e.g. because a message queue
+/// delivered it twice. Timestamps may differ between deliveries.
+pub fn is_resubmission(
+    tx_type: TransactionType,
+    (client_id, currency): AccountKey,
+    tx_id: TransactionId,
+    amount: Currency,
+    transactions: &TransactionsState,
+) -> bool {
+    let original = match transactions.get(client_id, tx_id) {
+        Some(TransactionContainer::Deposit(Ok(deposit))) if tx_type == TransactionType::Deposit => {
+            (deposit.currency, deposit.amount)
+        }
+        Some(TransactionContainer::Withdrawal(Ok(withdrawal)))
+            if tx_type == TransactionType::Withdrawal =>
+        {
+            (withdrawal.currency, withdrawal.amount)
+        }
+        _ => return false,
+    };
+    original == (currency, amount)
+}
+
+/// If the transaction is valid, return the transaction, a &mut to the associated account,
+/// and why to flag the account for review, if a risk check says to.
 /// Otherwise, return an Err(TransactionError).
-pub fn validate_deposit<'a, 't>(
+#[tracing::instrument(name = "validate", level = "trace", skip_all)]
+pub fn validate_deposit<'a>(
     deposit: Deposit,
+    fee: Currency,
+    max_balance: Option<Currency>,
     accounts: &'a mut AccountsState,
-    transactions: &'t TransactionsState,
-) -> Result<(Deposit, impl UnlockedAccountFeatures + 'a), TransactionError> {
-    check_for_duplicate_tx_id(deposit.tx_id, transactions)?;
-    check_for_positive_amount(deposit.tx_id, deposit.amount)?;
+    transactions: &TransactionsState,
+    checks: Checks,
+) -> Result<(Deposit, UnlockedAccount<'a>, Option<String>), TransactionError> {
+    let policy = checks.validation;
+    check_for_duplicate_tx_id(deposit.client_id, deposit.tx_id, transactions, policy)?;
+    check_for_positive_amount(deposit.tx_id, deposit.amount, policy)?;
+    checks.activity.check_deposit(&deposit, checks.limits)?;
+    if fee > deposit.amount {
+        return Err(TransactionError::FeeExceedsAmount {
+            client: deposit.client_id,
+            tx: deposit.tx_id,
+            amount: deposit.amount,
+            fee,
+        });
+    }
+    let credited = deposit.amount.checked_sub(fee).ok_or_else(|| {
+        balance_overflow(
+            deposit.client_id,
+            deposit.tx_id,
+            accounts.get(deposit.client_id, deposit.currency),
+            deposit.amount,
+        )
+    })?;
+    check_balance_limit(&deposit, credited, accounts, max_balance)?;
+    // Locked accounts aren't scored, since they can't deposit anyway
+    let flag = match accounts.get(deposit.client_id, deposit.currency) {
+        Some(account) if account.locked() => None,
+        account => check_risk(&deposit, account, &checks)?,
+    };
 
-    match accounts.get_mut_or_default(deposit.client_id) {
-        AccountAccess::Unlocked(account) => Ok((deposit, account)),
-        AccountAccess::Locked(_) => Err(TransactionError::AccountLocked {
+    match accounts
+        .get_mut_or_default(deposit.client_id, deposit.currency)
+        .try_unlocked()
+    {
+        Ok(account) => Ok((deposit, account, flag)),
+        Err(_) => Err(TransactionError::AccountLocked {
             client: deposit.client_id,
             tx: deposit.tx_id,
         }),
     }
 }
 
-pub fn validate_withdrawal<'a, 't>(
+#[tracing::instrument(name = "validate", level = "trace", skip_all)]
+pub fn validate_withdrawal<'a>(
     withdrawal: Withdrawal,
+    fee: Currency,
+    credit_limit: Currency,
     accounts: &'a mut AccountsState,
-    transactions: &'t TransactionsState,
-) -> Result<(Withdrawal, impl UnlockedAccountFeatures + 'a), TransactionError> {
-    check_for_duplicate_tx_id(withdrawal.tx_id, transactions)?;
-    check_for_positive_amount(withdrawal.tx_id, withdrawal.amount)?;
-
-    match accounts.get_mut(withdrawal.client_id) {
-        // unlocked accounts can withdraw if they have enough funds
-        Some(AccountAccess::Unlocked(account)) => {
-            let view = account.view();
-            if view.available >= withdrawal.amount {
-                return Ok((withdrawal, account));
-            } else {
-                return Err(TransactionError::InsufficientFunds {
-                    client: withdrawal.client_id,
-                    tx: withdrawal.tx_id,
-                    requested: withdrawal.amount,
-                    available: view.available,
-                });
-            }
-        }
-        // Locked accounts cannot withdraw
-        Some(AccountAccess::Locked(_)) => Err(TransactionError::AccountLocked {
+    transactions: &TransactionsState,
+    checks: Checks,
+) -> Result<(Withdrawal, UnlockedAccount<'a>, Option<String>), TransactionError> {
+    let policy = checks.validation;
+    check_for_duplicate_tx_id(withdrawal.client_id, withdrawal.tx_id, transactions, policy)?;
+    check_for_positive_amount(withdrawal.tx_id, withdrawal.amount, policy)?;
+    checks
+        .activity
+        .check_withdrawal(&withdrawal, checks.limits)?;
+
+    let currency = withdrawal.currency;
+    let account = accounts.get(withdrawal.client_id, currency);
+    // Locked accounts cannot withdraw
+    if account.is_some_and(Account::locked) {
+        return Err(TransactionError::AccountLocked {
             client: withdrawal.client_id,
             tx: withdrawal.tx_id,
-        }),
-        // New accounts cannot withdraw
-        None => Err(TransactionError::InsufficientFunds {
+        });
+    }
+    let overflow = || {
+        balance_overflow(
+            withdrawal.client_id,
+            withdrawal.tx_id,
+            account,
+            withdrawal.amount,
+        )
+    };
+    // The fee is taken from the same available funds
+    let requested = withdrawal.amount.checked_add(fee).ok_or_else(overflow)?;
+    // Unlocked accounts, or new ones, can withdraw if they have enough funds,
+    // including their credit line
+    let available = account
+        .map_or(Currency::ZERO, Account::available)
+        .checked_add(credit_limit)
+        .ok_or_else(overflow)?;
+    if available < requested {
+        return Err(TransactionError::InsufficientFunds {
+            client: withdrawal.client_id,
+            tx: withdrawal.tx_id,
+            requested,
+            available,
+        });
+    }
+    let flag = check_risk(&withdrawal, account, &checks)?;
+
+    // New accounts with a large enough credit line can start by borrowing
+    match accounts
+        .get_mut_or_default(withdrawal.client_id, currency)
+        .try_unlocked()
+    {
+        Ok(account) => Ok((withdrawal, account, flag)),
+        Err(_) => Err(TransactionError::AccountLocked {
             client: withdrawal.client_id,
             tx: withdrawal.tx_id,
-            requested: withdrawal.amount,
-            available: 0.0,
         }),
     }
 }
 
-fn validate_dispute_for_successful_tx<'a, 't, 'd, D: Disputable>(
+fn check_client_match<T: Transaction, D: Disputable>(
+    tx: &T,
+    disputed_tx: &D,
+    policy: &ValidationPolicy,
+) -> Result<(), TransactionError> {
+    if tx.get_client_id() == disputed_tx.get_client_id() || !policy.require_matching_client {
+        Ok(())
+    } else {
+        Err(TransactionError::ClientMismatch {
+            tx: tx.get_tx_id(),
+            tx_client: disputed_tx.get_client_id(),
+            dispute_client: tx.get_client_id(),
+        })
+    }
+}
+
+fn check_currency_match<T: Transaction, D: Disputable>(
+    tx: &T,
+    disputed_tx: &D,
+    policy: &ValidationPolicy,
+) -> Result<(), TransactionError> {
+    if tx.get_currency() == disputed_tx.get_currency() || !policy.require_matching_currency {
+        Ok(())
+    } else {
+        Err(TransactionError::CurrencyMismatch {
+            tx: tx.get_tx_id(),
+            tx_currency: disputed_tx.get_currency(),
+            dispute_currency: tx.get_currency(),
+        })
+    }
+}
+
+fn validate_dispute_for_successful_tx<'a, 't, D: Disputable>(
     dispute: Dispute,
     disputed_tx: &'t D,
     accounts: &'a mut AccountsState,
-    disputes: &'d DisputesState,
-) -> Result<(&'t impl Disputable, Box<dyn BaseAccountFeatures + 'a>), TransactionError> {
-    // NOTE: CHECK 3: dispute client_id must match disputed transaction client_id
-    if dispute.client_id != disputed_tx.get_client_id() {
-        return Err(TransactionError::ClientMismatch {
-            tx: dispute.tx_id,
-            tx_client: disputed_tx.get_client_id(),
-            dispute_client: dispute.client_id,
-        });
-    }
+    disputes: &DisputesState,
+    (policy, validation): (&DisputePolicy, &ValidationPolicy),
+) -> Result<(&'t impl Disputable, AccountRef<'a>), TransactionError> {
+    // NOTE: CHECK 3: dispute client_id and currency must match disputed transaction
+    check_client_match(&dispute, disputed_tx, validation)?;
+    check_currency_match(&dispute, disputed_tx, validation)?;
 
+    // The transaction's client, in case the policy allows others to dispute it
     let tx_id = dispute.get_tx_id();
-    let client_id = dispute.get_client_id();
+    let client_id = disputed_tx.get_client_id();
 
     // NOTE: CHECK 4: Cannot dispute an actively disputed transaction
     if disputes.is_disputed(client_id, tx_id) {
@@ -112,24 +354,49 @@ fn validate_dispute_for_successful_tx<'a, 't, 'd, D: Disputable>(
         });
     }
 
-    // NOTE: CHECK 5: Cannot dispute a settled transaction
-    if disputes.is_settled(client_id, tx_id) {
-        return Err(TransactionError::DisputeAlreadySettled {
+    // NOTE: CHECK 5: Cannot dispute a settled transaction again, unless the policy allows
+    if disputes.is_settled(client_id, tx_id)
+        && !disputes.may_redispute(client_id, tx_id, policy.max_redisputes)
+    {
+        return Err(TransactionError::RedisputeNotAllowed {
             client: client_id,
             tx: tx_id,
         });
     }
 
-    if let Some(access) = accounts.get_mut(client_id) {
+    // NOTE: CHECK 6: Cannot dispute a transaction older than the policy allows
+    check_dispute_window(&dispute, disputed_tx, disputes.latest(), policy)?;
+
+    // NOTE: CHECK 7: A partial dispute must be positive and can't exceed the transaction
+    if let Some(amount) = dispute.amount {
+        check_for_positive_amount(tx_id, amount, validation)?;
+        if amount > disputed_tx.disputable_amount() {
+            return Err(TransactionError::DisputeExceedsTransaction {
+                client: client_id,
+                tx: tx_id,
+                requested: amount,
+                disputable: disputed_tx.disputable_amount(),
+            });
+        }
+    }
+
+    match accounts.get_mut(client_id, disputed_tx.get_currency()) {
+        // NOTE: CHECK 8: Locked accounts may only dispute if the policy allows
+        Some(access) if access.is_locked() && !validation.allow_locked_disputes => {
+            Err(TransactionError::AccountLocked {
+                client: client_id,
+                tx: tx_id,
+            })
+        }
         // Get access to the referenced account (don't need unlocked access here)
-        let account = access.inner();
-        return Ok((disputed_tx, account));
-    } else {
-        // This should never happen, but catch it just in case
-        return Err(TransactionError::UnexpectedError(format!(
-            "Disputed transaction {} refers to nonexistent client {}",
-            tx_id, client_id
-        )));
+        Some(access) => Ok((disputed_tx, access)),
+        None => {
+            // This should never happen, but catch it just in case
+            Err(TransactionError::UnexpectedError(format!(
+                "Disputed transaction {} refers to nonexistent client {}",
+                tx_id, client_id
+            )))
+        }
     }
 }
 
@@ -140,26 +407,48 @@ fn validate_dispute_for_successful_tx<'a, 't, 'd, D: Disputable>(
 ///
 /// Need to check:
 /// 1. transaction is of a disputable type
-/// 2. transaction initially succeeded
-/// 3. transaction refers to same client
+/// 2. transaction initially succeeded, and hasn't been reversed
+/// 3. transaction refers to same client and currency
 /// 4. transaction is not actively disputed
-/// 5. transaction is not already settled
+/// 5. transaction is not already settled, unless it was resolved and may be disputed again
+/// 6. transaction is recent enough to be disputed
+/// 7. disputed amount, if given, is positive and doesn't exceed the transaction
+/// 8. account is unlocked, unless locked accounts may dispute
+///
+/// Checks 3, 7, and 8 can be loosened by the `ValidationPolicy`.
+#[tracing::instrument(name = "validate", level = "trace", skip_all)]
 pub fn validate_dispute<'a, 't, 'd>(
     dispute: Dispute,
     accounts: &'a mut AccountsState,
     transactions: &'t TransactionsState,
     disputes: &'d DisputesState,
-) -> Result<(&'t impl Disputable, Box<dyn BaseAccountFeatures + 'a>), TransactionError> {
+    policies: (&DisputePolicy, &ValidationPolicy),
+) -> Result<(&'t impl Disputable, AccountRef<'a>), TransactionError> {
     // NOTE: disputes do not have their own transaction id, they refer to a deposit or withdrawal
-    // NOTE: locked accounts are still allowed to dispute, just not deposit or withdraw
+    // NOTE: by default, locked accounts are still allowed to dispute, just not deposit or withdraw
 
     // Get disputed transaction from log
-    if let Some(disputed_tx_container) = transactions.get(dispute.client_id, dispute.tx_id) {
+    if let Some(disputed_tx_container) = transactions.find(dispute.client_id, dispute.tx_id) {
         match disputed_tx_container.try_get_disputable() {
-            // Transaction is of a disputable type and initially succeeded
-            Ok(Ok(disputed_tx)) => {
-                validate_dispute_for_successful_tx(dispute, disputed_tx, accounts, disputes)
+            // NOTE: CHECK 2: Cannot dispute a transaction which was undone by a reversal
+            Ok(Ok(disputed_tx))
+                if transactions
+                    .reversal_of(disputed_tx.get_client_id(), dispute.tx_id)
+                    .is_some() =>
+            {
+                Err(TransactionError::AlreadyReversed {
+                    client: disputed_tx.get_client_id(),
+                    tx: dispute.tx_id,
+                })
             }
+            // Transaction is of a disputable type and initially succeeded
+            Ok(Ok(disputed_tx)) => validate_dispute_for_successful_tx(
+                dispute,
+                disputed_tx,
+                accounts,
+                disputes,
+                policies,
+            ),
             // Transaction is of a disputable type but initially failed
             Ok(Err(_)) => {
                 // NOTE: CHECK 2: Cannot dispute a transaction that didn't succeed in the first place
@@ -179,23 +468,20 @@ pub fn validate_dispute<'a, 't, 'd>(
     }
 }
 
-fn validate_post_dispute_for_existing_tx<'a, 't, 'd, D: Disputable, P: PostDispute>(
+fn validate_post_dispute_for_existing_tx<'a, 't, D: Disputable, P: PostDispute>(
     post: P,
     disputed_tx: &'t D,
     accounts: &'a mut AccountsState,
-    disputes: &'d DisputesState,
-) -> Result<(&'t impl Disputable, AccountAccess<'a>), TransactionError> {
-    // NOTE: CHECK 1: client_id must match disputed transaction client_id
-    if post.get_client_id() != disputed_tx.get_client_id() {
-        return Err(TransactionError::ClientMismatch {
-            tx: post.get_tx_id(),
-            tx_client: disputed_tx.get_client_id(),
-            dispute_client: post.get_client_id(),
-        });
-    }
+    disputes: &DisputesState,
+    policy: &ValidationPolicy,
+) -> Result<(&'t impl Disputable, AccountRef<'a>), TransactionError> {
+    // NOTE: CHECK 1: client_id and currency must match disputed transaction
+    check_client_match(&post, disputed_tx, policy)?;
+    check_currency_match(&post, disputed_tx, policy)?;
 
+    // The transaction's client, in case the policy allows others to settle it
     let tx_id = post.get_tx_id();
-    let client_id = post.get_client_id();
+    let client_id = disputed_tx.get_client_id();
 
     let disputed = disputes.is_disputed(client_id, tx_id);
     // NOTE: CHECK 2: Cannot dispute an actively disputed transaction
@@ -206,14 +492,14 @@ fn validate_post_dispute_for_existing_tx<'a, 't, 'd, D: Disputable, P: PostDispu
         });
     }
 
-    if let Some(access) = accounts.get_mut(client_id) {
-        return Ok((disputed_tx, access));
+    if let Some(access) = accounts.get_mut(client_id, disputed_tx.get_currency()) {
+        Ok((disputed_tx, access))
     } else {
         // This should never happen, but catch it just in case
-        return Err(TransactionError::UnexpectedError(format!(
+        Err(TransactionError::UnexpectedError(format!(
             "Disputed transaction {} refers to nonexistent client {}",
             tx_id, client_id
-        )));
+        )))
     }
 }
 
@@ -223,14 +509,18 @@ fn validate_post_dispute_for_existing_tx<'a, 't, 'd, D: Disputable, P: PostDispu
 /// 1.transaction exists
 ///
 /// Need to check:
-/// 1. transaction refers to same client
+/// 1. transaction refers to same client and currency
 /// 2. transaction is actively disputed
-pub fn validate_post_dispute<'a, 't, 'd, T: PostDispute>(
+///
+/// Check 1 can be loosened by the `ValidationPolicy`.
+#[tracing::instrument(name = "validate", level = "trace", skip_all)]
+pub fn validate_post_dispute<'a, 't, 'd, T: PostDispute + 't>(
     post: T,
     accounts: &'a mut AccountsState,
     transactions: &'t TransactionsState,
     disputes: &'d DisputesState,
-) -> Result<(&'t impl Disputable, AccountAccess<'a>), TransactionError> {
+    policy: &ValidationPolicy,
+) -> Result<(&'t impl Disputable, AccountRef<'a>), TransactionError> {
     // NOTE: disputes and resolves do not have their own transaction id,
     // they refer to a deposit or withdrawal
     // NOTE: locked accounts are still allowed to dispute and resolve,
@@ -240,9 +530,9 @@ pub fn validate_post_dispute<'a, 't, 'd, T: PostDispute>(
     let tx_id = post.get_tx_id();
 
     // Get disputed transaction from log
-    if let Some(disputed_tx_container) = transactions.get(client_id, tx_id) {
+    if let Some(disputed_tx_container) = transactions.find(client_id, tx_id) {
         if let Ok(Ok(disputed_tx)) = disputed_tx_container.try_get_disputable() {
-            validate_post_dispute_for_existing_tx(post, disputed_tx, accounts, disputes)
+            validate_post_dispute_for_existing_tx(post, disputed_tx, accounts, disputes, policy)
         } else {
             // NOTE: Actively disputed transaction should have already been validated
             Err(TransactionError::UnexpectedError(format!(
@@ -257,3 +547,283 @@ pub fn validate_post_dispute<'a, 't, 'd, T: PostDispute>(
         })
     }
 }
+
+/// Validate a representment, returning the disputed transaction,
+/// access to its account, and the amount which was charged back.
+///
+/// Need to check:
+/// 1. transaction exists, and can be disputed
+/// 2. transaction refers to same client and currency
+/// 3. dispute hasn't been represented already
+/// 4. dispute was charged back
+///
+/// Check 2 can be loosened by the `ValidationPolicy`.
+/// Like resolves and chargebacks, representments are allowed on locked accounts.
+#[tracing::instrument(name = "validate", level = "trace", skip_all)]
+pub fn validate_representment<'a, 't>(
+    representment: Representment,
+    accounts: &'a mut AccountsState,
+    transactions: &'t TransactionsState,
+    disputes: &DisputesState,
+    policy: &ValidationPolicy,
+) -> Result<(&'t impl Disputable, AccountRef<'a>, Currency), TransactionError> {
+    let tx_id = representment.tx_id;
+    // NOTE: CHECK 1: Only a disputable transaction can have been charged back
+    let disputed_tx = match transactions
+        .find(representment.client_id, tx_id)
+        .map(TransactionContainer::try_get_disputable)
+    {
+        Some(Ok(Ok(disputed_tx))) => disputed_tx,
+        _ => {
+            return Err(TransactionError::TxDoesNotExist {
+                client: representment.client_id,
+                tx: tx_id,
+            })
+        }
+    };
+
+    // NOTE: CHECK 2: client_id and currency must match disputed transaction
+    check_client_match(&representment, disputed_tx, policy)?;
+    check_currency_match(&representment, disputed_tx, policy)?;
+    let client_id = disputed_tx.get_client_id();
+
+    // NOTE: CHECK 3: A dispute can only be represented once
+    if disputes.is_represented(client_id, tx_id) {
+        return Err(TransactionError::AlreadyRepresented {
+            client: client_id,
+            tx: tx_id,
+        });
+    }
+
+    // NOTE: CHECK 4: Only a chargeback can be represented
+    let amount = match disputes.charged_back_amount(client_id, tx_id) {
+        Some(amount) => amount,
+        None => {
+            return Err(TransactionError::TxNotChargedBack {
+                client: client_id,
+                tx: tx_id,
+            })
+        }
+    };
+
+    match accounts.get_mut(client_id, disputed_tx.get_currency()) {
+        Some(access) => Ok((disputed_tx, access, amount)),
+        // This should never happen, but catch it just in case
+        None => Err(TransactionError::UnexpectedError(format!(
+            "Represented transaction {} refers to nonexistent client {}",
+            tx_id, client_id
+        ))),
+    }
+}
+
+/// Check whether the account can cover a chargeback of the given amount.
+/// Returns the shortfall to write off, which is only nonzero
+/// for the `Clamp` policy.
+pub fn check_chargeback_funds(
+    client_id: ClientId,
+    tx_id: TransactionId,
+    account: &Account,
+    amount: Currency,
+    policy: ChargebackPolicy,
+) -> Result<Currency, TransactionError> {
+    // Only the part of the chargeback which would take the total below zero
+    // counts, so that an account that was already negative can still be charged back
+    // for funds it does have.
+    let total = account_total(client_id, Some(account))?;
+    let covered = total.max(Currency::ZERO).min(amount);
+    let shortfall = amount - covered;
+
+    if !shortfall.is_positive() {
+        return Ok(Currency::ZERO);
+    }
+
+    match policy {
+        ChargebackPolicy::AllowNegative => Ok(Currency::ZERO),
+        ChargebackPolicy::Block => Err(TransactionError::ChargebackExceedsFunds {
+            client: client_id,
+            tx: tx_id,
+            amount,
+            shortfall,
+        }),
+        ChargebackPolicy::Clamp => Ok(shortfall),
+    }
+}
+
+fn check_admin_allowed<T: Transaction>(tx: &T, allow_admin: bool) -> Result<(), TransactionError> {
+    if allow_admin {
+        Ok(())
+    } else {
+        Err(TransactionError::AdminTransactionsDisabled {
+            client: tx.get_client_id(),
+            tx: tx.get_tx_id(),
+        })
+    }
+}
+
+/// Validate an administrative reversal, returning the account and how much
+/// its available funds change: down by a reversed deposit's amount,
+/// or up by a reversed withdrawal's. Fees aren't refunded.
+///
+/// Need to check:
+/// 1. administrative transactions are allowed
+/// 2. the reversal's own id isn't taken
+/// 3. the reversed transaction is one of the client's
+/// 4. it's a deposit or withdrawal, which succeeded
+/// 5. it has the reversal's currency
+/// 6. it hasn't already been reversed, or charged back
+/// 7. it isn't actively disputed
+/// 8. the account can cover a reversed deposit, including its credit line,
+///    and a reversed withdrawal doesn't take it above the maximum balance
+///
+/// Locked accounts may be corrected too.
+#[tracing::instrument(name = "validate", level = "trace", skip_all)]
+pub fn validate_reversal<'a>(
+    reversal: &Reversal,
+    accounts: &'a mut AccountsState,
+    transactions: &TransactionsState,
+    disputes: &DisputesState,
+    policies: &Policies,
+    credit_limit: Currency,
+) -> Result<(AccountRef<'a>, Currency), TransactionError> {
+    check_admin_allowed(reversal, policies.allow_admin)?;
+    check_for_duplicate_tx_id(
+        reversal.client_id,
+        reversal.tx_id,
+        transactions,
+        &policies.validation,
+    )?;
+
+    let client_id = reversal.client_id;
+    let reversed_id = reversal.reverses;
+    let (change, currency) = match transactions.get(client_id, reversed_id) {
+        Some(TransactionContainer::Deposit(Ok(deposit))) => (-deposit.amount, deposit.currency),
+        Some(TransactionContainer::Withdrawal(Ok(withdrawal))) => {
+            (withdrawal.amount, withdrawal.currency)
+        }
+        Some(TransactionContainer::Deposit(Err(_)))
+        | Some(TransactionContainer::Withdrawal(Err(_))) => {
+            return Err(TransactionError::ReversedTxFailed { tx: reversed_id })
+        }
+        Some(other) => {
+            return Err(TransactionError::InvalidReversal {
+                tx: reversed_id,
+                tx_type: other.tx_type(),
+            })
+        }
+        None => {
+            return Err(TransactionError::TxDoesNotExist {
+                client: client_id,
+                tx: reversed_id,
+            })
+        }
+    };
+    if currency != reversal.currency {
+        return Err(TransactionError::CurrencyMismatch {
+            tx: reversed_id,
+            tx_currency: currency,
+            dispute_currency: reversal.currency,
+        });
+    }
+
+    // A chargeback already reversed the deposit. Its dispute may since have been
+    // forgotten, but the transaction remembers it
+    let charged_back = transactions.is_charged_back(client_id, reversed_id)
+        || disputes.is_settled(client_id, reversed_id)
+            && disputes
+                .history(client_id, reversed_id)
+                .last()
+                .is_some_and(|step| step.action == DisputeAction::ChargedBack);
+    if charged_back || transactions.reversal_of(client_id, reversed_id).is_some() {
+        return Err(TransactionError::AlreadyReversed {
+            client: client_id,
+            tx: reversed_id,
+        });
+    }
+    if disputes.is_disputed(client_id, reversed_id) {
+        return Err(TransactionError::TxAlreadyDisputed {
+            client: client_id,
+            tx: reversed_id,
+        });
+    }
+
+    let access = accounts.get_mut(client_id, currency).ok_or_else(|| {
+        // This should never happen, since the transaction succeeded
+        TransactionError::UnexpectedError(format!(
+            "Reversed transaction {} refers to nonexistent client {}",
+            reversed_id, client_id
+        ))
+    })?;
+    let account = access.view();
+    if change.is_negative() {
+        let available = account
+            .available()
+            .checked_add(credit_limit)
+            .ok_or_else(|| balance_overflow(client_id, reversal.tx_id, Some(account), change))?;
+        if available < -change {
+            return Err(TransactionError::InsufficientFunds {
+                client: client_id,
+                tx: reversal.tx_id,
+                requested: -change,
+                available,
+            });
+        }
+    } else {
+        let total = account_total(client_id, Some(account))?;
+        match total.checked_add(change) {
+            Some(new_total) if policies.max_balance.is_none_or(|max| new_total <= max) => {}
+            _ => {
+                return Err(TransactionError::BalanceOverflow {
+                    client: client_id,
+                    tx: reversal.tx_id,
+                    total,
+                    amount: change,
+                })
+            }
+        }
+    }
+    Ok((access, change))
+}
+
+/// Validate an administrative lock.
+/// Locking a new account creates it, so that it starts out locked.
+#[tracing::instrument(name = "validate", level = "trace", skip_all)]
+pub fn validate_lock<'a>(
+    lock: &Lock,
+    accounts: &'a mut AccountsState,
+    allow_admin: bool,
+) -> Result<UnlockedAccount<'a>, TransactionError> {
+    check_admin_allowed(lock, allow_admin)?;
+
+    match accounts
+        .get_mut_or_default(lock.client_id, lock.currency)
+        .try_unlocked()
+    {
+        Ok(account) => Ok(account),
+        Err(_) => Err(TransactionError::AccountLocked {
+            client: lock.client_id,
+            tx: lock.tx_id,
+        }),
+    }
+}
+
+/// Validate an administrative unlock.
+#[tracing::instrument(name = "validate", level = "trace", skip_all)]
+pub fn validate_unlock<'a>(
+    unlock: &Unlock,
+    accounts: &'a mut AccountsState,
+    allow_admin: bool,
+) -> Result<LockedAccount<'a>, TransactionError> {
+    check_admin_allowed(unlock, allow_admin)?;
+
+    match accounts
+        .get_mut(unlock.client_id, unlock.currency)
+        .map(AccountRef::try_locked)
+    {
+        Some(Ok(account)) => Ok(account),
+        // Nonexistent accounts are never locked
+        Some(Err(_)) | None => Err(TransactionError::AccountNotLocked {
+            client: unlock.client_id,
+            tx: unlock.tx_id,
+        }),
+    }
+}
@@ -0,0 +1,199 @@
+//! Compare final balances against expected ones, e.g. a known-good
+//! `accounts.csv`, reporting every account which differs.
+
+use std::fmt;
+use std::io;
+
+use crate::state::{AccountOrder, State};
+use crate::types::{InvalidAccount, OutputRecord};
+
+/// An account whose balances weren't as expected.
+#[derive(Debug, PartialEq)]
+pub enum BalanceDiff {
+    /// Expected, but the account doesn't exist
+    Missing(OutputRecord),
+    /// The account exists, but wasn't expected
+    Unexpected(OutputRecord),
+    /// The account exists, with different balances
+    Mismatch {
+        expected: OutputRecord,
+        actual: OutputRecord,
+    },
+}
+
+/// One row of balances, in the same format as the output CSV.
+struct Row<'a>(&'a OutputRecord);
+
+impl fmt::Display for Row<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let record = self.0;
+        write!(f, "{},", record.client)?;
+        if let Some(currency) = record.currency {
+            write!(f, "{}", currency)?;
+        }
+        write!(
+            f,
+            ",{},{},{},{}",
+            record.available, record.held, record.total, record.locked
+        )
+    }
+}
+
+/// Written like a unified diff: expected rows start with `-`,
+/// and actual rows with `+`.
+impl fmt::Display for BalanceDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing(expected) => write!(f, "-{}", Row(expected)),
+            Self::Unexpected(actual) => write!(f, "+{}", Row(actual)),
+            Self::Mismatch { expected, actual } => {
+                write!(f, "-{}\n+{}", Row(expected), Row(actual))
+            }
+        }
+    }
+}
+
+/// Write differences under a header row, like a diff of two output files.
+pub fn write_diffs<W: io::Write>(diffs: &[BalanceDiff], mut output_stream: W) -> io::Result<()> {
+    writeln!(output_stream, "client,currency,available,held,total,locked")?;
+    for diff in diffs {
+        writeln!(output_stream, "{}", diff)?;
+    }
+    output_stream.flush()
+}
+
+/// Read balances from a CSV file in the output format.
+/// Whitespace is trimmed, and the `currency` and `lock_reason` columns are optional.
+pub fn read_balances<R: io::Read>(input: R) -> csv::Result<Vec<OutputRecord>> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(input)
+        .into_deserialize()
+        .collect()
+}
+
+/// Final balances of every account, rounded as they would be written,
+/// or why one of them can't be.
+pub fn balances(state: &State) -> Result<Vec<OutputRecord>, InvalidAccount> {
+    state
+        .accounts
+        .ordered(AccountOrder::Client)
+        .into_iter()
+        .map(|(&key, account)| OutputRecord::new(key, account, &state.policies.rounding))
+        .collect()
+}
+
+/// Whether an account's balances are as expected. Lock reasons are only compared
+/// when one is expected, since they're only written when asked for.
+fn balance_matches(expected: &OutputRecord, actual: &OutputRecord) -> bool {
+    match expected.lock_reason {
+        Some(_) => expected == actual,
+        None => {
+            *expected
+                == OutputRecord {
+                    lock_reason: None,
+                    ..actual.clone()
+                }
+        }
+    }
+}
+
+/// Every account which differs between the expected and actual balances,
+/// by client and currency. The order of rows doesn't matter.
+pub fn compare_balances(
+    mut expected: Vec<OutputRecord>,
+    mut actual: Vec<OutputRecord>,
+) -> Vec<BalanceDiff> {
+    expected.sort_by_key(|record| (record.client, record.currency));
+    actual.sort_by_key(|record| (record.client, record.currency));
+
+    let mut diffs = Vec::new();
+    let mut expected = expected.into_iter().peekable();
+    let mut actual = actual.into_iter().peekable();
+    loop {
+        let key = |record: &OutputRecord| (record.client, record.currency);
+        let diff = match (expected.peek(), actual.peek()) {
+            (None, None) => break,
+            (Some(_), None) => BalanceDiff::Missing(expected.next().unwrap()),
+            (None, Some(_)) => BalanceDiff::Unexpected(actual.next().unwrap()),
+            (Some(e), Some(a)) if key(e) < key(a) => BalanceDiff::Missing(expected.next().unwrap()),
+            (Some(e), Some(a)) if key(e) > key(a) => {
+                BalanceDiff::Unexpected(actual.next().unwrap())
+            }
+            (Some(e), Some(a)) if balance_matches(e, a) => {
+                expected.next();
+                actual.next();
+                continue;
+            }
+            (Some(_), Some(_)) => BalanceDiff::Mismatch {
+                expected: expected.next().unwrap(),
+                actual: actual.next().unwrap(),
+            },
+        };
+        diffs.push(diff);
+    }
+    diffs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compare_balances, read_balances, write_diffs, BalanceDiff};
+
+    #[test]
+    fn test_compare_balances() {
+        let expected = read_balances(
+            "client,available,held,total,locked
+            3,1.0,0.0,1.0,false
+            1,1.5,0.0,1.5,false
+            2,2.0,0.0,2.0,false"
+                .as_bytes(),
+        )
+        .unwrap();
+        let actual = read_balances(
+            "client,available,held,total,locked
+            1,1.5,0.0,1.5,false
+            2,2.0,1.0,3.0,true
+            4,4.0,0.0,4.0,false"
+                .as_bytes(),
+        )
+        .unwrap();
+
+        let diffs = compare_balances(expected, actual);
+        assert!(matches!(diffs[0], BalanceDiff::Mismatch { .. }));
+        let mut report = Vec::new();
+        write_diffs(&diffs, &mut report).unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "\
+client,currency,available,held,total,locked
+-2,,2.0,0.0,2.0,false
++2,,2.0,1.0,3.0,true
+-3,,1.0,0.0,1.0,false
++4,,4.0,0.0,4.0,false
+"
+        );
+    }
+
+    #[test]
+    fn test_compare_equal_balances() {
+        let balances = "client,available,held,total,locked\n1,1.5,0.0,1.5,false\n";
+        let expected = read_balances(balances.as_bytes()).unwrap();
+        let actual = read_balances(balances.as_bytes()).unwrap();
+        assert_eq!(compare_balances(expected, actual), vec![]);
+    }
+
+    #[test]
+    fn test_compare_lock_reasons_only_when_expected() {
+        let actual =
+            "client,available,held,total,locked,lock_reason\n1,0.0,0.0,0.0,true,chargeback\n";
+        let actual = || read_balances(actual.as_bytes()).unwrap();
+
+        let without_reason = "client,available,held,total,locked\n1,0.0,0.0,0.0,true\n";
+        let expected = read_balances(without_reason.as_bytes()).unwrap();
+        assert_eq!(compare_balances(expected, actual()), vec![]);
+
+        let admin = "client,available,held,total,locked,lock_reason\n1,0.0,0.0,0.0,true,admin\n";
+        let expected = read_balances(admin.as_bytes()).unwrap();
+        assert_eq!(compare_balances(expected, actual()).len(), 1);
+    }
+}
@@ -0,0 +1,134 @@
+//! JavaScript API for running the engine in a browser, e.g. for demos, or for
+//! validating a file of transactions before it's sent anywhere.
+//!
+//! Built for `wasm32-unknown-unknown` with `wasm-pack build --features wasm`.
+//! There are no threads or files there, so transactions are read from a string,
+//! and handled one at a time on the calling thread, as by `service::SharedState`,
+//! rather than by the pipeline.
+
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+use crate::policy::Policies;
+use crate::source::{CsvSource, ParseError, TransactionSource};
+use crate::state::{AccountOrder, State};
+use crate::types::{OutputRecord, Rejection, TransactionError, TransactionRecord};
+
+/// What became of a CSV string's transactions.
+#[derive(Serialize)]
+struct Processed<'a> {
+    /// Final balances, by client
+    balances: Vec<OutputRecord>,
+    rejections: Vec<ProcessedRejection<'a>>,
+    /// Number of rows which couldn't be read
+    skipped: usize,
+    /// Why each of them couldn't be, and what they said
+    unreadable: &'a [ParseError],
+}
+
+#[derive(Serialize)]
+struct ProcessedRejection<'a> {
+    transaction: &'a TransactionRecord,
+    error: &'a TransactionError,
+}
+
+/// Handle every transaction in a CSV string, with a header row, as `process` would,
+/// optionally under policies given as TOML, as in a policy file.
+/// Returns the state, or why the CSV or policies couldn't be read.
+pub fn process_csv_str(input: &str, policies: Option<&str>) -> Result<State, String> {
+    let policies = match policies {
+        Some(policies) => toml::from_str(policies).map_err(|err| err.to_string())?,
+        None => Policies::default(),
+    };
+    let mut state = State::with_policies(policies);
+    let mut source = CsvSource::new(input.as_bytes(), false).map_err(|err| err.to_string())?;
+    while let Some(result) = source.next_record() {
+        match result {
+            Ok(record) => {
+                if let Err(error) = state.handle(record.clone()) {
+                    state.rejections.push(Rejection { record, error });
+                }
+            }
+            Err(err) => state.unreadable_rows.push(err),
+        }
+    }
+    Ok(state)
+}
+
+/// The state's balances, rejections and unreadable rows, as JSON,
+/// or why one of the balances can't be written.
+fn to_json(state: &State) -> Result<String, String> {
+    let processed = Processed {
+        balances: state
+            .accounts
+            .ordered(AccountOrder::Client)
+            .into_iter()
+            .map(|(&key, account)| OutputRecord::new(key, account, &state.policies.rounding))
+            .collect::<Result<_, _>>()
+            .map_err(|err| err.to_string())?,
+        rejections: state
+            .rejections
+            .iter()
+            .map(|Rejection { record, error }| ProcessedRejection {
+                transaction: record,
+                error,
+            })
+            .collect(),
+        skipped: state.unreadable_rows.len(),
+        unreadable: &state.unreadable_rows,
+    };
+    // Only strings and numbers, so can't fail
+    Ok(serde_json::to_string(&processed).unwrap())
+}
+
+/// Handle every transaction in a CSV string, returning JSON like
+/// `{"balances": [{"client": 1, "available": "1.5", ...}], "rejections": [], "skipped": 0,
+/// "unreadable": []}`,
+/// or throwing if the CSV has no header row, the policies can't be read,
+/// or a balance can't be written.
+#[wasm_bindgen(js_name = processCsv)]
+pub fn process_csv(input: &str, policies: Option<String>) -> Result<String, JsValue> {
+    process_csv_str(input, policies.as_deref())
+        .and_then(|state| to_json(&state))
+        .map_err(|err| JsValue::from_str(&err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{process_csv_str, to_json};
+
+    const INPUT: &str = "type, client, tx, amount
+deposit, 2, 1, 5.0
+withdrawal, 2, 2, 9.0
+nonsense
+deposit, 1, 3, 1.5
+";
+
+    #[test]
+    fn test_process_csv() {
+        let state = process_csv_str(INPUT, None).unwrap();
+        assert_eq!(
+            to_json(&state).unwrap(),
+            "{\"balances\":[\
+             {\"client\":1,\"available\":\"1.5\",\"held\":\"0.0\",\"total\":\"1.5\",\"locked\":false},\
+             {\"client\":2,\"available\":\"5.0\",\"held\":\"0.0\",\"total\":\"5.0\",\"locked\":false}],\
+             \"rejections\":[{\"transaction\":\
+             {\"type\":\"withdrawal\",\"client\":2,\"tx\":2,\"amount\":\"9.0\"},\
+             \"error\":{\"code\":\"INSUFFICIENT_FUNDS\",\
+             \"details\":{\"client\":2,\"tx\":2,\"requested\":\"9.0\",\"available\":\"5.0\"}}}],\
+             \"skipped\":1,\"unreadable\":[{\"line\":4,\
+             \"message\":\"found record with 1 fields, but the headers have 4\",\
+             \"content\":\"nonsense\"}]}"
+        );
+    }
+
+    #[test]
+    fn test_process_csv_with_policies() {
+        let policies = "[fees.deposit]\nflat = 0.5\n";
+        let state = process_csv_str(INPUT, Some(policies)).unwrap();
+        let account = state.accounts.get(1, None).unwrap();
+        assert_eq!(account.available.to_string(), "1.0");
+
+        assert!(process_csv_str(INPUT, Some("fees = 1")).is_err());
+    }
+}
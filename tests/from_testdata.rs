@@ -1,59 +1,195 @@
-use payments_engine_example::process_transactions;
-use payments_engine_example::types::OutputRecord;
+use payments_engine_example::input::Inputs;
+use payments_engine_example::pipeline::{ExecutionMode, PipelineConfig};
+use payments_engine_example::policy::Policies;
+use payments_engine_example::rand::{generate_random_transaction_sequence, TransactionWeights};
+use payments_engine_example::state::AccountOrder;
+use payments_engine_example::state::State;
+use payments_engine_example::types::{ClientId, Currency, OutputRecord, TransactionId};
+use payments_engine_example::{process_inputs, run_inputs, stream_inputs, write_balances};
+use serde::{Deserialize, Serialize};
+use std::env;
 use std::error::Error;
 use std::fs;
 use std::io;
 use std::path;
 
-fn run_test_from_directory(directory: path::PathBuf) -> Result<(), Box<dyn Error>> {
+/// A row of a test case's optional `errors.csv`, identifying a rejected transaction
+/// and its error code. Other columns are ignored, so the file may be written
+/// with `process --errors-output` or `generate --expected-errors`.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+struct ExpectedRejection {
+    #[serde(rename = "type")]
+    transaction_type: String,
+    client: ClientId,
+    tx: TransactionId,
+    error_code: String,
+}
+
+/// Set to regenerate each test case's expected output from the engine's, e.g.
+/// `BLESS=1 cargo test --test from_testdata`, after an intended change in behavior.
+const BLESS_VAR: &str = "BLESS";
+
+fn bless_requested() -> bool {
+    env::var_os(BLESS_VAR).is_some_and(|value| !value.is_empty() && value != "0")
+}
+
+/// Accounts from a CSV file, sorted by client id and currency,
+/// since the order of rows is not significant.
+fn read_accounts<R: io::Read>(reader: R) -> csv::Result<Vec<OutputRecord>> {
+    let mut accounts = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader)
+        .into_deserialize()
+        .collect::<Result<Vec<OutputRecord>, _>>()?;
+    accounts.sort_by_key(|rec| (rec.client, rec.currency));
+    Ok(accounts)
+}
+
+fn read_rejections(path: &path::Path) -> csv::Result<Vec<ExpectedRejection>> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)?
+        .into_deserialize()
+        .collect()
+}
+
+/// Every rejected transaction in the state, in the order they were rejected.
+fn rejections(state: &State) -> Vec<ExpectedRejection> {
+    state
+        .rejections
+        .iter()
+        .map(|rejection| ExpectedRejection {
+            transaction_type: rejection.record.transaction_type.name().to_string(),
+            client: rejection.record.client_id,
+            tx: rejection.record.tx_id,
+            error_code: rejection.error.code().to_string(),
+        })
+        .collect()
+}
+
+/// Overwrite a test case's `accounts.csv` with the engine's output, and its `errors.csv`
+/// with the engine's rejections if it has one, where they differ in more than formatting,
+/// so that unchanged files keep their layout.
+fn bless_directory(directory: &path::Path) -> Result<(), Box<dyn Error>> {
+    let accounts_path = directory.join("accounts.csv");
+    let errors_path = directory.join("errors.csv");
+
+    let transactions_file = fs::File::open(directory.join("transactions.csv"))?;
+    let config = PipelineConfig {
+        execution: ExecutionMode::Sequential,
+        ..Default::default()
+    };
+    let state = run_inputs(
+        Inputs::single(transactions_file),
+        config,
+        Policies::default(),
+        None,
+        None,
+    );
+
+    let mut accounts = Vec::new();
+    write_balances(&state, AccountOrder::Client, &mut accounts);
+    let expected = fs::File::open(&accounts_path)
+        .ok()
+        .and_then(|file| read_accounts(file).ok());
+    if expected != Some(read_accounts(&accounts[..])?) {
+        println!("Blessing {}", accounts_path.display());
+        fs::write(&accounts_path, accounts)?;
+    }
+
+    if errors_path.exists() {
+        let actual = rejections(&state);
+        let mut sorted_actual = actual.clone();
+        sorted_actual.sort();
+        let mut expected = read_rejections(&errors_path)?;
+        expected.sort();
+        if expected != sorted_actual {
+            println!("Blessing {}", errors_path.display());
+            let mut writer = csv::Writer::from_path(&errors_path)?;
+            for rejection in actual {
+                writer.serialize(rejection)?;
+            }
+            writer.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+fn run_test_from_directory(
+    directory: &path::Path,
+    execution: ExecutionMode,
+) -> Result<(), Box<dyn Error>> {
     let transactions_path = directory.join("transactions.csv");
     let accounts_path = directory.join("accounts.csv");
+    let errors_path = directory.join("errors.csv");
 
-    let transactions_file = fs::File::open(&transactions_path).expect(&format!(
-        "Failed to open transactions file '{}'",
-        transactions_path.to_str().unwrap_or("<invalid path>")
-    ));
+    let transactions_file = fs::File::open(&transactions_path).unwrap_or_else(|_| {
+        panic!(
+            "Failed to open transactions file '{}'",
+            transactions_path.to_str().unwrap_or("<invalid path>")
+        )
+    });
 
     // Write results to in-memory buffer
     let mut output_buf = io::Cursor::new(Vec::new());
     let batch_size = 1000;
     let notrim = false;
-    process_transactions(transactions_file, &mut output_buf, batch_size, notrim);
+    let policies = Policies::default();
+    let config = PipelineConfig {
+        batch_size,
+        notrim,
+        execution,
+        ..Default::default()
+    };
+    let state = process_inputs(
+        Inputs::single(transactions_file),
+        &mut output_buf,
+        config,
+        policies,
+        None,
+        None,
+    );
 
     // Re-deserialize actual results from output buffer
     output_buf.set_position(0);
-    let actual_accounts_reader = csv::ReaderBuilder::new()
-        .trim(csv::Trim::All)
-        .from_reader(&mut output_buf);
+    let actual_accounts = read_accounts(&mut output_buf)?;
 
     // Read expected results from file
-    let expected_accounts_reader = csv::ReaderBuilder::new()
-        .trim(csv::Trim::All)
-        .from_path(&accounts_path)
-        .expect(&format!(
+    let accounts_file = fs::File::open(&accounts_path).unwrap_or_else(|_| {
+        panic!(
             "Failed to open accounts file '{}'",
             accounts_path.to_str().unwrap_or("<invalid path>")
-        ));
-
-    // Be reckless: serialize whole files into memory, failing if any error is encountered
-    let mut expected_accounts: Vec<OutputRecord> = expected_accounts_reader
-        .into_deserialize()
-        .collect::<Result<Vec<_>, _>>()?;
-    let mut actual_accounts: Vec<OutputRecord> = actual_accounts_reader
-        .into_deserialize()
-        .collect::<Result<Vec<_>, _>>()?;
-
-    // Sort values by client id before comparing since the order of rows is not significant
-    expected_accounts.sort_by_key(|rec| rec.client);
-    actual_accounts.sort_by_key(|rec| rec.client);
+        )
+    });
+    let expected_accounts = read_accounts(accounts_file)?;
 
     assert_eq!(
         expected_accounts,
         actual_accounts,
-        "test failure in {:?}",
-        directory.to_str().unwrap_or("<invalid path>")
+        "test failure in {:?} with {:?} execution",
+        directory.to_str().unwrap_or("<invalid path>"),
+        execution
     );
 
+    if errors_path.exists() {
+        let mut expected_rejections = read_rejections(&errors_path)?;
+        let mut actual_rejections = rejections(&state);
+
+        // Some rejections, e.g. of duplicate ids, may be reported before others
+        // depending on the execution mode, so only which are rejected matters
+        expected_rejections.sort();
+        actual_rejections.sort();
+
+        assert_eq!(
+            expected_rejections,
+            actual_rejections,
+            "rejections differ in {:?} with {:?} execution",
+            directory.to_str().unwrap_or("<invalid path>"),
+            execution
+        );
+    }
+
     Ok(())
 }
 
@@ -67,8 +203,75 @@ fn run_tests_from_testdata() -> Result<(), Box<dyn Error>> {
             "Running test from directory: {}",
             test_path.to_str().unwrap_or("<invalid path>")
         );
-        run_test_from_directory(test_path)?;
+        if bless_requested() {
+            bless_directory(&test_path)?;
+        }
+        // Every execution mode handles each client's transactions in order,
+        // so they should all agree
+        for execution in [
+            ExecutionMode::Sharded,
+            ExecutionMode::Actors,
+            ExecutionMode::Sequential,
+        ] {
+            run_test_from_directory(&test_path, execution)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn sequential_runs_identical() {
+    let run = |transactions_path: &path::Path| {
+        let config = PipelineConfig {
+            execution: ExecutionMode::Sequential,
+            ..Default::default()
+        };
+        let mut updates_buf = Vec::new();
+        let transactions_file = fs::File::open(transactions_path).unwrap();
+        stream_inputs(
+            Inputs::single(transactions_file),
+            &mut updates_buf,
+            config,
+            Policies::default(),
+            None,
+            None,
+        );
+        updates_buf
+    };
+
+    for directory in fs::read_dir("testdata").unwrap() {
+        let transactions_path = directory.unwrap().path().join("transactions.csv");
+        // Every balance update, in the same order, down to the byte
+        assert_eq!(run(&transactions_path), run(&transactions_path));
+    }
+}
+
+#[test]
+fn generated_testdata() -> Result<(), Box<dyn Error>> {
+    let directory = env::temp_dir().join(format!("generated-testdata-{}", std::process::id()));
+    fs::create_dir_all(&directory)?;
+
+    let mix = TransactionWeights::default().distribution()?;
+    let mut generator =
+        generate_random_transaction_sequence(Some(2000), 20, Currency::from(100.0), 1000, mix, 0.1);
+    let mut transactions = csv::Writer::from_path(directory.join("transactions.csv"))?;
+    for generated in generator.by_ref() {
+        transactions.serialize(generated.record)?;
+    }
+    transactions.flush()?;
+    let accounts = fs::File::create(directory.join("accounts.csv"))?;
+    write_balances(generator.state(), AccountOrder::Client, accounts);
+
+    // The generator's own balances are what processing should end with
+    for execution in [
+        ExecutionMode::Sharded,
+        ExecutionMode::Actors,
+        ExecutionMode::Sequential,
+    ] {
+        run_test_from_directory(&directory, execution)?;
     }
+    fs::remove_dir_all(&directory)?;
 
     Ok(())
 }
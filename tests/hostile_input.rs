@@ -0,0 +1,94 @@
+use payments_engine_example::invariants;
+use payments_engine_example::policy::Policies;
+use payments_engine_example::state::AccountOrder;
+use payments_engine_example::types::Currency;
+use payments_engine_example::{process_bytes, process_bytes_with_policies};
+
+/// Inputs a fuzzer might come up with, none of which should cause a panic.
+const HOSTILE_INPUTS: &[&[u8]] = &[
+    b"",
+    b"\n\n\n",
+    b"type,client,tx,amount",
+    b"type,client,tx,amount\n,,,\n",
+    b"\xff\xfe\x00type,client\n\x80\x81",
+    b"type,client,tx,amount\ndeposit,1,1,1.0,extra,columns\n",
+    b"type,client,tx,amount\ndeposit,1\n",
+    b"type,client,tx,amount\ndeposit,-1,1,1.0\n",
+    b"type,client,tx,amount\ndeposit,65536,4294967296,1.0\n",
+    b"type,client,tx,amount\ndeposit,1,1,NaN\n",
+    b"type,client,tx,amount\ndeposit,1,1,inf\n",
+    b"type,client,tx,amount\ndeposit,1,1,-inf\n",
+    b"type,client,tx,amount\ndeposit,1,1,1e400\n",
+    b"type,client,tx,amount\ndeposit,1,1,0.000000000000000000000000000001\n",
+    b"type,client,tx,amount\ndeposit,1,1,99999999999999999999999999999999\n",
+    b"type,client,tx,amount\ndeposit,1,1,922337203685477.5807\ndeposit,1,2,922337203685477.5807\n",
+    b"type,client,tx,amount\nwithdrawal,1,1,922337203685477.5807\nwithdrawal,1,2,922337203685477.5807\n",
+    b"type,client,tx,amount\ndeposit,1,1,5\ndispute,1,1,\ndispute,1,1,\nchargeback,1,1,\nresolve,1,1,\n",
+    b"type,client,tx,amount,timestamp,currency\ndeposit,1,1,5,-1,XXXX\n",
+    b"type,client,tx,amount,timestamp,currency\ndeposit,1,1,5,18446744073709551615,\xe2\x82\xac\n",
+    b"\"type\",\"client\nTX\",\"amount\ndeposit,1,1,5\n",
+    b"amount,tx,client,type\n5,1,1,deposit\n",
+    // Disputes, chargebacks, representments, reversals and interest
+    // once balances are already near the largest representable amount
+    b"type,client,tx,amount\ndeposit,1,1,900000000000000\nwithdrawal,1,2,900000000000000\ndeposit,1,3,900000000000000\nwithdrawal,1,4,900000000000000\ndispute,1,1,\ndispute,1,3,\n",
+    b"type,client,tx,amount\ndeposit,1,1,900000000000000\nwithdrawal,1,2,900000000000000\ndeposit,1,3,900000000000000\ndispute,1,1,\nchargeback,1,1,\nrepresentment,1,1,\ndispute,1,3,\nchargeback,1,3,\n",
+    b"type,client,tx,amount\ndeposit,1,1,900000000000000\nwithdrawal,1,2,900000000000000\ndeposit,1,3,900000000000000\nreversal,1,2,\nreversal,1,1,\nreversal,1,3,\n",
+    b"type,client,tx,amount\ndeposit,1,1,900000000000000\nwithdrawal,1,2,900000000000000\nwithdrawal,1,3,900000000000000\ndispute,1,2,\ndispute,1,3,\nchargeback,1,3,\n",
+    b"type,client,tx,amount\ndeposit,1,1,922337203685477.5807\nwithdrawal,1,2,0.0001\n",
+    b"type,client,tx,amount\nwithdrawal,1,1,922337203685477.5807\nwithdrawal,1,2,922337203685477.5807\nreversal,1,1,\nreversal,1,2,\n",
+];
+
+/// Policies allowing everything the defaults don't: admin transactions,
+/// unlimited credit, redisputes, and interest on every balance.
+fn permissive_policies() -> Policies {
+    let mut policies = Policies {
+        allow_admin: true,
+        ..Policies::default()
+    };
+    policies.credit.default_limit = Currency::from(922337203685477.0);
+    policies.dispute.max_redisputes = u32::MAX;
+    policies.interest.percent = 100.0;
+    policies
+}
+
+#[test]
+fn hostile_inputs_are_handled() {
+    for input in HOSTILE_INPUTS {
+        let state = process_bytes(input);
+        let violations = invariants::check(&state);
+        assert!(
+            violations.is_empty(),
+            "{:?}: {:?}",
+            String::from_utf8_lossy(input),
+            violations
+        );
+    }
+}
+
+#[test]
+fn hostile_inputs_are_handled_under_permissive_policies() {
+    for input in HOSTILE_INPUTS {
+        let state = process_bytes_with_policies(input, permissive_policies());
+        let violations = invariants::check(&state);
+        assert!(
+            violations.is_empty(),
+            "{:?}: {:?}",
+            String::from_utf8_lossy(input),
+            violations
+        );
+    }
+}
+
+#[test]
+fn readable_transactions_are_handled() {
+    let input = b"type,client,tx,amount\ndeposit,1,1,5.0\nnonsense\nwithdrawal,1,2,2.0\n";
+    let state = process_bytes(input);
+
+    let accounts = state.accounts.ordered(AccountOrder::Client);
+    assert_eq!(accounts.len(), 1);
+    let (&(client, _), account) = accounts[0];
+    assert_eq!(client, 1);
+    assert_eq!(account.available(), Currency::from(3.0));
+    assert_eq!(state.unreadable_rows.len(), 1);
+    assert_eq!(state.unreadable_rows[0].line, Some(3));
+}
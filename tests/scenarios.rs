@@ -0,0 +1,7 @@
+use payments_engine_example::test_utils::run_scenario_files;
+use std::error::Error;
+
+#[test]
+fn run_scenarios() -> Result<(), Box<dyn Error>> {
+    run_scenario_files("tests/scenarios")
+}